@@ -12,11 +12,16 @@
 //   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
 //
 
+// Without a backend only the fallback `main` below is built.
+#![cfg_attr(
+    not(any(feature = "async_tokio", feature = "sync", feature = "async_smol")),
+    allow(unused)
+)]
+
 use clap::Parser;
-use ctrlc;
-use hls_lfcd_lds_driver::{LFCDLaser, DEFAULT_BAUD_RATE, DEFAULT_PORT};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use hls_lfcd_lds_driver::error::Result;
+use hls_lfcd_lds_driver::run::RunPolicy;
+use hls_lfcd_lds_driver::{DEFAULT_BAUD_RATE, DEFAULT_PORT};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -28,72 +33,71 @@ struct Args {
 
 #[cfg(feature = "async_tokio")]
 #[tokio::main]
-async fn main() -> tokio_serial::Result<()> {
-    let flag = Arc::new(AtomicBool::new(true));
-    let c_flag = flag.clone();
-    ctrlc::set_handler(move || c_flag.store(false, Ordering::Relaxed))
-        .expect("Error when setting Ctrl-C handler");
-
+async fn main() -> Result<()> {
     let args = Args::parse();
     println!(
         "Going to open LDS01 on {} with {}",
         args.port, args.baud_rate
     );
 
-    let mut port = LFCDLaser::new(args.port, args.baud_rate)?;
+    let port = hls_lfcd_lds_driver::tokio::LFCDLaser::new(args.port, args.baud_rate)?;
 
-    while flag.load(Ordering::Relaxed) {
-        let reading = port.read().await?;
-        println!("Reading: {reading:?}")
-    }
-
-    Ok(())
+    port.run(
+        |reading| println!("Reading: {reading:?}"),
+        RunPolicy {
+            handle_sigint: true,
+            ..Default::default()
+        },
+    )
+    .await
 }
 
-#[cfg(feature = "sync")]
-fn main() -> serialport::Result<()> {
+#[cfg(all(feature = "sync", not(feature = "async_tokio")))]
+fn main() -> Result<()> {
     let args = Args::parse();
 
-    let flag = Arc::new(AtomicBool::new(true));
-    let c_flag = flag.clone();
-    ctrlc::set_handler(move || c_flag.store(false, Ordering::Relaxed))
-        .expect("Error when setting Ctrl-C handler");
-
     println!(
         "Going to open LDS01 on {} with {}",
         args.port, args.baud_rate
     );
 
-    let mut port = LFCDLaser::new(args.port, args.baud_rate)?;
+    let port = hls_lfcd_lds_driver::sync::LFCDLaser::new(args.port, args.baud_rate)?;
 
-    while flag.load(Ordering::Relaxed) {
-        let reading = port.read()?;
-        println!("Reading: {reading:?}")
-    }
-
-    Ok(())
+    port.run(
+        |reading| println!("Reading: {reading:?}"),
+        RunPolicy {
+            handle_sigint: true,
+            ..Default::default()
+        },
+    )
 }
 
-#[cfg(feature = "async_smol")]
+#[cfg(all(
+    feature = "async_smol",
+    not(any(feature = "async_tokio", feature = "sync"))
+))]
 #[async_std::main]
-async fn main() -> mio_serial::Result<()> {
+async fn main() -> Result<()> {
     let args = Args::parse();
-    let flag = Arc::new(AtomicBool::new(true));
-    let c_flag = flag.clone();
-    ctrlc::set_handler(move || c_flag.store(false, Ordering::Relaxed))
-        .expect("Error when setting Ctrl-C handler");
-
     println!(
         "Going to open LDS01 on {} with {}",
         args.port, args.baud_rate
     );
 
-    let mut port = LFCDLaser::new(args.port, args.baud_rate)?;
+    let port = hls_lfcd_lds_driver::smol::LFCDLaser::new(args.port, args.baud_rate)?;
 
-    while flag.load(Ordering::Relaxed) {
-        let reading = port.read().await?;
-        println!("Reading: {reading:?}")
-    }
+    port.run(
+        |reading| println!("Reading: {reading:?}"),
+        RunPolicy {
+            handle_sigint: true,
+            ..Default::default()
+        },
+    )
+    .await
+}
 
-    Ok(())
+// Built with none of the backends above, e.g. with the `defmt` feature.
+#[cfg(not(any(feature = "async_tokio", feature = "sync", feature = "async_smol")))]
+fn main() {
+    eprintln!("lds_read needs the `async_tokio`, `sync` or `async_smol` feature");
 }
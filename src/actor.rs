@@ -0,0 +1,579 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Actor-style drivers, enabled by the `actor` feature.
+//!
+//! The driver is moved into a dedicated task (tokio) or thread (sync) that
+//! owns the serial port, it is controlled through a command channel and
+//! publishes every scan, or read error, to its subscribers.
+//!
+//! Subscribers get their scans through bounded queues, see [`crate::queue`]:
+//! a slow subscriber loses scans, or with `Overflow::Block` holds the
+//! actor back, but never makes it buffer without limits.
+//!
+//! Read errors are published as well. After a timeout or a lost
+//! synchronization the actor keeps reading, after any other error, that
+//! the next read would most likely repeat, it pauses until `start`.
+
+/// Commands accepted by the actors, `S` is the reply channel for subscriptions.
+pub(crate) enum Command<S> {
+    Start,
+    Stop,
+    /// Only recorded, the LDS-01 does not accept speed commands
+    SetSpeed(u16),
+    Subscribe(S),
+    Shutdown,
+}
+
+#[cfg(feature = "async_tokio")]
+pub use self::tokio_actor::TokioActor;
+
+/// Checks if the actor keeps reading after `error`.
+#[cfg(any(feature = "async_tokio", feature = "sync"))]
+fn keeps_reading(error: &crate::Error) -> bool {
+    matches!(error, crate::Error::Timeout | crate::Error::SyncLost)
+}
+
+#[cfg(feature = "sync")]
+pub use self::sync_actor::{SyncActor, ThreadOptions};
+
+#[cfg(feature = "async_tokio")]
+mod tokio_actor {
+    use super::{keeps_reading, Command};
+    use crate::queue::{self, Overflow};
+    use crate::tokio::LFCDLaser;
+    use crate::{Error, LaserReading};
+    use ::tokio::sync::{broadcast, mpsc, oneshot};
+    use ::tokio::task::JoinHandle;
+    use std::future::{poll_fn, Future};
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::task::Poll;
+
+    // Shared by the subscribers, the I/O errors cannot be cloned.
+    type Event = Result<LaserReading, Arc<Error>>;
+
+    enum Subscription {
+        Broadcast(oneshot::Sender<broadcast::Receiver<Event>>),
+        Queue(queue::Sender<Event>),
+    }
+
+    /// Number of scans buffered for each subscriber before it starts lagging.
+    const SUBSCRIBERS_CAPACITY: usize = 16;
+    /// Number of pending commands.
+    const MAILBOX_CAPACITY: usize = 16;
+
+    /// Handle to a driver running in its own tokio task.
+    ///
+    /// Commands are applied once the ongoing read, if any, completes, within
+    /// a revolution, but `shutdown` that drops it. Dropping every handle
+    /// shuts the task down, stopping the lidar.
+    #[derive(Clone)]
+    pub struct TokioActor {
+        mailbox: mpsc::Sender<Command<Subscription>>,
+    }
+
+    impl TokioActor {
+        pub(crate) fn spawn(laser: LFCDLaser) -> (Self, JoinHandle<LFCDLaser>) {
+            let (tx, rx) = mpsc::channel(MAILBOX_CAPACITY);
+            let task = ::tokio::spawn(run(laser, rx));
+            (Self { mailbox: tx }, task)
+        }
+
+        async fn send(&self, cmd: Command<Subscription>) -> bool {
+            self.mailbox.send(cmd).await.is_ok()
+        }
+
+        /// Starts the lidar and the publication of scans.
+        /// Returns `false` if the actor is no longer running.
+        pub async fn start(&self) -> bool {
+            self.send(Command::Start).await
+        }
+
+        /// Stops the lidar, scans are no longer published until `start`.
+        /// Returns `false` if the actor is no longer running.
+        pub async fn stop(&self) -> bool {
+            self.send(Command::Stop).await
+        }
+
+        /// Sets the requested motor speed, see `LFCDLaser::set_speed`.
+        /// The LDS-01 does not accept speed commands, this only updates the
+        /// value reported by `LFCDLaser::speed`, the motor keeps its speed.
+        /// Returns `false` if the actor is no longer running.
+        pub async fn set_speed(&self, speed: u16) -> bool {
+            self.send(Command::SetSpeed(speed)).await
+        }
+
+        /// Subscribes to the scans published by the actor, a subscriber
+        /// that falls behind by more than 16 scans loses the oldest ones.
+        /// Returns `None` if the actor is no longer running.
+        pub async fn subscribe(&self) -> Option<broadcast::Receiver<Event>> {
+            let (tx, rx) = oneshot::channel();
+            if !self
+                .send(Command::Subscribe(Subscription::Broadcast(tx)))
+                .await
+            {
+                return None;
+            }
+            rx.await.ok()
+        }
+
+        /// Subscribes to the scans published by the actor through a queue
+        /// of `capacity` scans, handling a full queue according to `overflow`.
+        /// Returns `None` if the actor is no longer running.
+        ///
+        /// With `Overflow::Block` the actor waits for the subscriber, neither
+        /// reading the lidar nor applying commands in the meantime, the
+        /// other subscribers get their scans without waiting.
+        pub async fn subscribe_with(
+            &self,
+            capacity: usize,
+            overflow: Overflow,
+        ) -> Option<queue::Receiver<Event>> {
+            let (tx, rx) = queue::channel(capacity, overflow);
+            self.send(Command::Subscribe(Subscription::Queue(tx)))
+                .await
+                .then_some(rx)
+        }
+
+        /// Asks the actor to stop the lidar and terminate.
+        /// The driver is given back by the `JoinHandle` returned by `spawn`.
+        pub async fn shutdown(&self) {
+            self.send(Command::Shutdown).await;
+        }
+    }
+
+    /// Publication of the scans.
+    struct Publisher {
+        broadcast: broadcast::Sender<Event>,
+        subscribers: Vec<queue::Sender<Event>>,
+    }
+
+    impl Publisher {
+        fn subscribe(&mut self, subscription: Subscription) {
+            match subscription {
+                Subscription::Broadcast(reply) => {
+                    let _ = reply.send(self.broadcast.subscribe());
+                }
+                Subscription::Queue(tx) => self.subscribers.push(tx),
+            }
+        }
+
+        /// Sends `event` to all the subscribers at once, a full `Block` queue
+        /// does not delay the others, and removes the ones that went away.
+        async fn publish(&mut self, event: Event) {
+            // No subscribers is not an error.
+            let _ = self.broadcast.send(event.clone());
+            let mut sends: Vec<_> = self
+                .subscribers
+                .iter()
+                .map(|subscriber| Some(Box::pin(subscriber.send_async(event.clone()))))
+                .collect();
+            let mut alive = vec![true; sends.len()];
+            poll_fn(|cx| {
+                let mut done = true;
+                for (send, alive) in sends.iter_mut().zip(alive.iter_mut()) {
+                    if let Some(future) = send {
+                        match future.as_mut().poll(cx) {
+                            Poll::Ready(res) => {
+                                *alive = res.is_ok();
+                                *send = None;
+                            }
+                            Poll::Pending => done = false,
+                        }
+                    }
+                }
+                if done {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
+            drop(sends);
+
+            let mut alive = alive.into_iter();
+            self.subscribers.retain(|_| alive.next().unwrap_or(false));
+        }
+    }
+
+    async fn run(
+        mut laser: LFCDLaser,
+        mut mailbox: mpsc::Receiver<Command<Subscription>>,
+    ) -> LFCDLaser {
+        let mut publisher = Publisher {
+            broadcast: broadcast::channel(SUBSCRIBERS_CAPACITY).0,
+            subscribers: Vec::new(),
+        };
+        let mut running = true;
+        // Commands received during a read, applied once it completes.
+        let mut deferred = Vec::new();
+
+        loop {
+            if running {
+                // A read dropped halfway loses its frame, so it is never
+                // dropped but to shut down.
+                let res = {
+                    let mut read = pin!(laser.read());
+                    loop {
+                        ::tokio::select! {
+                            res = &mut read => break Some(res),
+                            cmd = mailbox.recv() => match cmd {
+                                Some(Command::Subscribe(subscription)) => {
+                                    publisher.subscribe(subscription)
+                                }
+                                Some(Command::Shutdown) | None => break None,
+                                Some(cmd) => deferred.push(cmd),
+                            },
+                        }
+                    }
+                };
+                let Some(res) = res else {
+                    laser.close();
+                    return laser;
+                };
+                running = res.as_ref().map_or_else(keeps_reading, |_| true);
+                publisher.publish(res.map_err(Arc::new)).await;
+            } else {
+                deferred.push(mailbox.recv().await.unwrap_or(Command::Shutdown));
+            }
+
+            for cmd in deferred.drain(..) {
+                match cmd {
+                    Command::Start => {
+                        laser.start();
+                        running = true;
+                    }
+                    Command::Stop => {
+                        laser.close();
+                        running = false;
+                    }
+                    Command::SetSpeed(speed) => laser.set_speed(speed),
+                    Command::Subscribe(subscription) => publisher.subscribe(subscription),
+                    Command::Shutdown => {
+                        laser.close();
+                        return laser;
+                    }
+                }
+            }
+        }
+    }
+
+    impl LFCDLaser {
+        /// Moves the driver into a new tokio task, returning a handle to control it
+        /// and the `JoinHandle` of the task, which gives back the driver on shutdown.
+        ///
+        /// Must be called from within a tokio runtime.
+        pub fn spawn(self) -> (TokioActor, JoinHandle<LFCDLaser>) {
+            TokioActor::spawn(self)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::testing::{assert_scan_eq, fixtures};
+
+        #[tokio::test]
+        async fn publishes_past_a_full_blocking_queue() {
+            let (blocked, blocked_rx) = queue::channel(1, Overflow::Block);
+            let (fast, fast_rx) = queue::channel(4, Overflow::DropOldest);
+            let (gone, _) = queue::channel(1, Overflow::Block);
+            let mut publisher = Publisher {
+                broadcast: broadcast::channel(SUBSCRIBERS_CAPACITY).0,
+                subscribers: vec![blocked, fast, gone],
+            };
+            let mut broadcast_rx = publisher.broadcast.subscribe();
+
+            let room = fixtures()[0].expected.clone();
+            publisher.publish(Ok(room.clone())).await;
+            assert_eq!(publisher.subscribers.len(), 2);
+
+            let mut second = pin!(publisher.publish(Err(Arc::new(Error::Timeout))));
+            assert!(poll_fn(|cx| Poll::Ready(second.as_mut().poll(cx)))
+                .await
+                .is_pending());
+            assert_eq!(fast_rx.len(), 2);
+            assert!(broadcast_rx.try_recv().unwrap().is_ok());
+            assert!(broadcast_rx.try_recv().unwrap().is_err());
+
+            assert_scan_eq(&blocked_rx.try_recv().unwrap().unwrap(), &room);
+            second.await;
+            assert!(blocked_rx.try_recv().unwrap().is_err());
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+mod sync_actor {
+    use super::{keeps_reading, Command};
+    use crate::queue::{self, Overflow};
+    use crate::sync::LFCDLaser;
+    use crate::{Error, LaserReading};
+    use std::io;
+    use std::sync::{mpsc, Arc};
+    use std::thread::{self, JoinHandle};
+
+    // Shared by the subscribers, the I/O errors cannot be cloned.
+    type Event = Result<LaserReading, Arc<Error>>;
+    type Subscription = queue::Sender<Event>;
+
+    /// Scheduling of the thread reading the lidar, so that acquisition is not
+    /// starved on busy single-board computers. Only supported on unix, the
+    /// affinity only on Linux and FreeBSD.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct ThreadOptions {
+        /// `SCHED_FIFO` priority, from 1 to 99 on Linux, requires `CAP_SYS_NICE` there and root elsewhere
+        pub priority: Option<i32>,
+        /// CPUs the thread runs on, empty for any
+        pub affinity: Vec<usize>,
+        /// Keeps the default scheduling when the options cannot be applied
+        pub best_effort: bool,
+    }
+
+    impl ThreadOptions {
+        /// Checks if the options leave the default scheduling.
+        fn is_default(&self) -> bool {
+            self.priority.is_none() && self.affinity.is_empty()
+        }
+
+        /// Applies the options to the calling thread.
+        #[cfg(unix)]
+        fn apply(&self) -> io::Result<()> {
+            if !self.affinity.is_empty() {
+                set_affinity(&self.affinity)?;
+            }
+
+            if let Some(priority) = self.priority {
+                // SAFETY: `sched_param` is plain data, some platforms add
+                // padding fields to it; `param` outlives the call, which
+                // only reads it.
+                let rc = unsafe {
+                    let mut param: libc::sched_param = std::mem::zeroed();
+                    param.sched_priority = priority;
+                    libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param)
+                };
+                if rc != 0 {
+                    return Err(io::Error::from_raw_os_error(rc));
+                }
+            }
+            Ok(())
+        }
+
+        #[cfg(not(unix))]
+        fn apply(&self) -> io::Result<()> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Thread scheduling is only supported on unix",
+            ))
+        }
+    }
+
+    /// Set of CPUs of `sched_setaffinity`.
+    #[cfg(target_os = "linux")]
+    type CpuSet = libc::cpu_set_t;
+    #[cfg(target_os = "freebsd")]
+    type CpuSet = libc::cpuset_t;
+
+    /// Pins the calling thread to the given CPUs.
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+        // SAFETY: `CpuSet` is plain data and every CPU is checked to fit in it.
+        unsafe {
+            let mut set: CpuSet = std::mem::zeroed();
+            let size = std::mem::size_of::<CpuSet>();
+            for &cpu in cpus {
+                if cpu >= size * 8 {
+                    return Err(io::Error::from_raw_os_error(libc::EINVAL));
+                }
+                libc::CPU_SET(cpu, &mut set);
+            }
+            if libc::sched_setaffinity(0, size, &set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(all(unix, not(any(target_os = "linux", target_os = "freebsd"))))]
+    fn set_affinity(_: &[usize]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "CPU affinity is only supported on Linux and FreeBSD",
+        ))
+    }
+
+    /// Handle to a driver running in its own thread.
+    ///
+    /// Commands are applied between two reads. Dropping every handle
+    /// shuts the thread down, stopping the lidar.
+    #[derive(Clone)]
+    pub struct SyncActor {
+        mailbox: mpsc::Sender<Command<Subscription>>,
+    }
+
+    impl SyncActor {
+        pub(crate) fn spawn(
+            laser: LFCDLaser,
+            options: ThreadOptions,
+        ) -> io::Result<(Self, JoinHandle<LFCDLaser>)> {
+            let (tx, rx) = mpsc::channel();
+            let (status_tx, status_rx) = mpsc::channel();
+            let best_effort = options.best_effort;
+            let thread = thread::Builder::new()
+                .name(format!("lds-{}", laser.port().display()))
+                .spawn(move || {
+                    let status = if options.is_default() {
+                        Ok(())
+                    } else {
+                        options.apply()
+                    };
+                    let failed = status.is_err() && !options.best_effort;
+                    let _ = status_tx.send(status);
+                    if failed {
+                        return laser;
+                    }
+                    run(laser, rx)
+                })?;
+
+            match status_rx.recv() {
+                Ok(Err(e)) if !best_effort => {
+                    // The thread returns right away, dropping the driver.
+                    let _ = thread.join();
+                    Err(e)
+                }
+                _ => Ok((Self { mailbox: tx }, thread)),
+            }
+        }
+
+        fn send(&self, cmd: Command<Subscription>) -> bool {
+            self.mailbox.send(cmd).is_ok()
+        }
+
+        /// Starts the lidar and the publication of scans.
+        /// Returns `false` if the actor is no longer running.
+        pub fn start(&self) -> bool {
+            self.send(Command::Start)
+        }
+
+        /// Stops the lidar, scans are no longer published until `start`.
+        /// Returns `false` if the actor is no longer running.
+        pub fn stop(&self) -> bool {
+            self.send(Command::Stop)
+        }
+
+        /// Sets the requested motor speed, see `LFCDLaser::set_speed`.
+        /// The LDS-01 does not accept speed commands, this only updates the
+        /// value reported by `LFCDLaser::speed`, the motor keeps its speed.
+        /// Returns `false` if the actor is no longer running.
+        pub fn set_speed(&self, speed: u16) -> bool {
+            self.send(Command::SetSpeed(speed))
+        }
+
+        /// Subscribes to the scans published by the actor, a subscriber
+        /// that falls behind by more than 16 scans loses the oldest ones.
+        /// Returns `None` if the actor is no longer running.
+        pub fn subscribe(&self) -> Option<queue::Receiver<Event>> {
+            self.subscribe_with(queue::DEFAULT_CAPACITY, Overflow::DropOldest)
+        }
+
+        /// Subscribes to the scans published by the actor through a queue
+        /// of `capacity` scans, handling a full queue according to `overflow`.
+        /// Returns `None` if the actor is no longer running.
+        ///
+        /// With `Overflow::Block` the actor waits for the subscriber, neither
+        /// reading the lidar nor applying commands in the meantime.
+        pub fn subscribe_with(
+            &self,
+            capacity: usize,
+            overflow: Overflow,
+        ) -> Option<queue::Receiver<Event>> {
+            let (tx, rx) = queue::channel(capacity, overflow);
+            self.send(Command::Subscribe(tx)).then_some(rx)
+        }
+
+        /// Asks the actor to stop the lidar and terminate.
+        /// The driver is given back by the `JoinHandle` returned by `spawn`.
+        pub fn shutdown(&self) {
+            self.send(Command::Shutdown);
+        }
+    }
+
+    fn run(mut laser: LFCDLaser, mailbox: mpsc::Receiver<Command<Subscription>>) -> LFCDLaser {
+        let mut subscribers: Vec<Subscription> = Vec::new();
+        let mut running = true;
+
+        loop {
+            let cmd = if running {
+                match mailbox.try_recv() {
+                    Ok(cmd) => Some(cmd),
+                    Err(mpsc::TryRecvError::Empty) => None,
+                    Err(mpsc::TryRecvError::Disconnected) => Some(Command::Shutdown),
+                }
+            } else {
+                Some(mailbox.recv().unwrap_or(Command::Shutdown))
+            };
+
+            match cmd {
+                None => {
+                    let res = laser.read();
+                    running = res.as_ref().map_or_else(keeps_reading, |_| true);
+                    let res = res.map_err(Arc::new);
+                    // Subscribers that went away are removed.
+                    subscribers.retain(|s| s.send(res.clone()).is_ok());
+                }
+                Some(Command::Start) => {
+                    laser.start();
+                    running = true;
+                }
+                Some(Command::Stop) => {
+                    laser.close();
+                    running = false;
+                }
+                Some(Command::SetSpeed(speed)) => laser.set_speed(speed),
+                Some(Command::Subscribe(tx)) => subscribers.push(tx),
+                Some(Command::Shutdown) => {
+                    laser.close();
+                    return laser;
+                }
+            }
+        }
+    }
+
+    impl LFCDLaser {
+        /// Moves the driver into a new thread, returning a handle to control it
+        /// and the `JoinHandle` of the thread, which gives back the driver on shutdown.
+        ///
+        /// # Errors
+        /// An error variant is returned if the thread cannot be spawned.
+        pub fn spawn(self) -> io::Result<(SyncActor, JoinHandle<LFCDLaser>)> {
+            SyncActor::spawn(self, ThreadOptions::default())
+        }
+
+        /// Like `spawn`, setting the priority and the affinity of the thread.
+        ///
+        /// # Errors
+        /// An error variant is returned in case of:
+        /// - unable to spawn the thread
+        /// - unable to apply the options, unless `best_effort` is set
+        ///
+        /// When the options cannot be applied the driver is dropped, stopping the lidar.
+        pub fn spawn_with(
+            self,
+            options: ThreadOptions,
+        ) -> io::Result<(SyncActor, JoinHandle<LFCDLaser>)> {
+            SyncActor::spawn(self, options)
+        }
+    }
+}
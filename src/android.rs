@@ -0,0 +1,293 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Driver of the lidar on Android, through the USB host API, enabled by the
+//! `android_usb` feature.
+//!
+//! Android apps cannot open `/dev/ttyUSB0`: the app asks the user for the
+//! permission to use the adapter with `UsbManager.requestPermission`, opens
+//! it with `UsbManager.openDevice` and hands the descriptor of
+//! `UsbDeviceConnection.getFileDescriptor()` to the native code. The driver
+//! then talks to the CP2102 adapter of the LDS-01 directly, through the
+//! `usbdevfs` requests of the kernel. The connection must stay open on the
+//! Java side while the driver is in use.
+//!
+//! The module is also built on Linux, where the descriptor of a device node
+//! in `/dev/bus/usb` works the same way, once the `cp210x` kernel driver is
+//! detached from it.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "android_usb", any(target_os = "android", target_os = "linux")))]
+//! # fn main() -> Result<(), hls_lfcd_lds_driver::Error> {
+//! use hls_lfcd_lds_driver::android::AndroidLaser;
+//!
+//! // From `UsbDeviceConnection.getFileDescriptor()`, through JNI.
+//! let fd = 42;
+//! let mut laser = AndroidLaser::from_fd(fd)?;
+//! let scan = laser.read()?;
+//! println!("{} rpm", scan.rpms);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(all(feature = "android_usb", any(target_os = "android", target_os = "linux"))))]
+//! # fn main() {}
+//! ```
+
+use crate::transport::{cp210x, Transport, TransportLaser, BAUD_RATE};
+use crate::Error;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::Duration;
+
+/// Timeout of a USB transfer, by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Size of the USB reads, a multiple of the 64 bytes packets of the adapter.
+const READ_SIZE: usize = 512;
+/// Vendor request to an interface, from the host.
+const REQUEST_TYPE: u8 = 0x41;
+
+/// `struct usbdevfs_ctrltransfer` in `linux/usbdevice_fs.h`.
+#[repr(C)]
+struct CtrlTransfer {
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+    timeout: u32,
+    data: *mut libc::c_void,
+}
+
+/// `struct usbdevfs_bulktransfer` in `linux/usbdevice_fs.h`.
+#[repr(C)]
+struct BulkTransfer {
+    endpoint: u32,
+    length: u32,
+    timeout: u32,
+    data: *mut libc::c_void,
+}
+
+/// Encodes an ioctl request of the `usbdevfs` ('U') type, `_IOC` in
+/// `asm-generic/ioctl.h`.
+const fn usbdevfs(dir: libc::c_ulong, nr: libc::c_ulong, size: usize) -> libc::c_ulong {
+    (dir << 30) | ((size as libc::c_ulong) << 16) | ((b'U' as libc::c_ulong) << 8) | nr
+}
+
+/// `_IOWR('U', 0, struct usbdevfs_ctrltransfer)`.
+const USBDEVFS_CONTROL: libc::c_ulong = usbdevfs(3, 0, mem::size_of::<CtrlTransfer>());
+/// `_IOWR('U', 2, struct usbdevfs_bulktransfer)`.
+const USBDEVFS_BULK: libc::c_ulong = usbdevfs(3, 2, mem::size_of::<BulkTransfer>());
+/// `_IOR('U', 15, unsigned int)`.
+const USBDEVFS_CLAIMINTERFACE: libc::c_ulong = usbdevfs(2, 15, mem::size_of::<libc::c_uint>());
+/// `_IOR('U', 16, unsigned int)`.
+const USBDEVFS_RELEASEINTERFACE: libc::c_ulong = usbdevfs(2, 16, mem::size_of::<libc::c_uint>());
+
+/// Serial port of a CP2102 adapter driven through `usbdevfs`.
+///
+/// The descriptor is borrowed: it is neither closed on drop nor by
+/// `AndroidLaser`, the `UsbDeviceConnection` owning it closes it.
+pub struct UsbSerial {
+    fd: RawFd,
+    timeout: Duration,
+    buf: Box<[u8; READ_SIZE]>,
+    start: usize,
+    end: usize,
+}
+
+impl UsbSerial {
+    /// Claims the serial interface of the adapter behind `fd` and configures
+    /// it to `baud_rate`, 8N1.
+    ///
+    /// # Errors
+    /// An error variant is returned if the interface cannot be claimed, e.g.
+    /// a kernel driver still holds it, or the adapter refuses the configuration.
+    pub fn new(fd: RawFd, baud_rate: u32) -> io::Result<Self> {
+        let mut interface = libc::c_uint::from(cp210x::INTERFACE);
+        // SAFETY: the request reads an `unsigned int` living through the call.
+        if unsafe { libc::ioctl(fd, USBDEVFS_CLAIMINTERFACE as _, &mut interface) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut serial = Self {
+            fd,
+            timeout: DEFAULT_TIMEOUT,
+            buf: Box::new([0; READ_SIZE]),
+            start: 0,
+            end: 0,
+        };
+        serial.control(cp210x::IFC_ENABLE, 1, &mut [])?;
+        serial.control(cp210x::SET_BAUDRATE, 0, &mut baud_rate.to_le_bytes())?;
+        serial.control(cp210x::SET_LINE_CTL, cp210x::LINE_8N1, &mut [])?;
+        Ok(serial)
+    }
+
+    /// Gets the timeout of the USB transfers.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the timeout of the USB transfers, a read receiving nothing for
+    /// this long fails with `io::ErrorKind::TimedOut`.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn timeout_ms(&self) -> u32 {
+        // 0 would wait forever.
+        self.timeout.as_millis().clamp(1, u32::MAX as u128) as u32
+    }
+
+    fn control(&mut self, request: u8, value: u16, data: &mut [u8]) -> io::Result<()> {
+        let mut transfer = CtrlTransfer {
+            request_type: REQUEST_TYPE,
+            request,
+            value,
+            index: cp210x::INTERFACE.into(),
+            length: data.len() as u16,
+            timeout: self.timeout_ms(),
+            data: data.as_mut_ptr().cast(),
+        };
+        // SAFETY: `data` outlives the synchronous transfer and is as long as
+        // `transfer.length`.
+        if unsafe { libc::ioctl(self.fd, USBDEVFS_CONTROL as _, &mut transfer) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn bulk(&mut self, endpoint: u8, data: *mut u8, length: usize) -> io::Result<usize> {
+        let mut transfer = BulkTransfer {
+            endpoint: endpoint.into(),
+            length: length as u32,
+            timeout: self.timeout_ms(),
+            data: data.cast(),
+        };
+        // SAFETY: the callers pass a buffer of `length` bytes outliving the
+        // synchronous transfer.
+        let n = unsafe { libc::ioctl(self.fd, USBDEVFS_BULK as _, &mut transfer) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            return Err(match e.raw_os_error() {
+                Some(libc::ETIMEDOUT) => io::ErrorKind::TimedOut.into(),
+                _ => e,
+            });
+        }
+        Ok(n as usize)
+    }
+}
+
+impl Read for UsbSerial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // A bulk read shorter than the packet of the adapter would overflow,
+        // so the reads always go through the internal buffer.
+        if self.start == self.end {
+            let data = self.buf.as_mut_ptr();
+            self.end = self.bulk(cp210x::BULK_IN, data, READ_SIZE)?;
+            self.start = 0;
+        }
+        let n = buf.len().min(self.end - self.start);
+        buf[..n].copy_from_slice(&self.buf[self.start..][..n]);
+        self.start += n;
+        Ok(n)
+    }
+}
+
+impl Write for UsbSerial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The kernel only reads from the buffer of an OUT transfer.
+        self.bulk(cp210x::BULK_OUT, buf.as_ptr().cast_mut(), buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for UsbSerial {
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.start = 0;
+        self.end = 0;
+        self.control(cp210x::PURGE, cp210x::PURGE_ALL, &mut [])
+    }
+}
+
+impl AsRawFd for UsbSerial {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for UsbSerial {
+    fn drop(&mut self) {
+        self.control(cp210x::IFC_ENABLE, 0, &mut []).ok();
+        let mut interface = libc::c_uint::from(cp210x::INTERFACE);
+        // SAFETY: the request reads an `unsigned int` living through the call.
+        unsafe { libc::ioctl(self.fd, USBDEVFS_RELEASEINTERFACE as _, &mut interface) };
+    }
+}
+
+/// Driver of the LDS-01 on Android, over a `UsbSerial`.
+pub type AndroidLaser = TransportLaser<UsbSerial>;
+
+impl AndroidLaser {
+    /// Opens the lidar behind the descriptor of a `UsbDeviceConnection` and
+    /// starts it.
+    ///
+    /// # Errors
+    /// An error variant is returned if the adapter cannot be configured, see
+    /// `UsbSerial::new`.
+    pub fn from_fd(fd: RawFd) -> Result<Self, Error> {
+        Ok(Self::new(UsbSerial::new(fd, BAUD_RATE)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn matches_the_ioctls_of_the_kernel() {
+        // From `linux/usbdevice_fs.h` on 64-bit targets.
+        assert_eq!(USBDEVFS_CONTROL, 0xC018_5500);
+        assert_eq!(USBDEVFS_BULK, 0xC018_5502);
+        assert_eq!(USBDEVFS_CLAIMINTERFACE, 0x8004_550F);
+        assert_eq!(USBDEVFS_RELEASEINTERFACE, 0x8004_5510);
+    }
+
+    #[test]
+    fn fails_on_a_descriptor_not_of_a_usb_device() {
+        let file = std::fs::File::open("/dev/null").unwrap();
+        assert!(UsbSerial::new(file.as_raw_fd(), BAUD_RATE).is_err());
+    }
+
+    #[test]
+    fn reads_through_the_buffer_of_the_transfers() {
+        let mut serial = UsbSerial {
+            fd: -1,
+            timeout: DEFAULT_TIMEOUT,
+            buf: Box::new([0; READ_SIZE]),
+            start: 0,
+            end: 5,
+        };
+        serial.buf[..5].copy_from_slice(b"LDS01");
+        let mut buf = [0u8; 3];
+        assert_eq!(serial.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"LDS");
+        assert_eq!(serial.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"01");
+        // Empty, the next read is a transfer, failing on this descriptor.
+        assert!(serial.read(&mut buf).is_err());
+    }
+}
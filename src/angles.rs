@@ -0,0 +1,183 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Beam angles in radians, in the convention of the caller.
+//!
+//! The readings are indexed by degree, counter-clockwise from the front of
+//! the lidar. An `AngleFrame` moves the zero and the direction of the
+//! angles, by default it follows REP-103: counter-clockwise from the front,
+//! in `[-π, π)`.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::angles::AngleFrame;
+//! use std::f32::consts::FRAC_PI_2;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let frame = AngleFrame::default();
+//! // The beam on the right of the lidar.
+//! assert_eq!(reading.index_at(-FRAC_PI_2, &frame), 270);
+//! assert!((reading.angle_in(270, &frame) + FRAC_PI_2).abs() < 1e-5);
+//! ```
+
+use crate::LaserReading;
+use std::f32::consts::{PI, TAU};
+
+/// Convention of the angles: where 0 points, which way they grow and
+/// their range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "ser_de", derive(serde::Serialize, serde::Deserialize))]
+pub struct AngleFrame {
+    /// Direction of the angle 0, in radians counter-clockwise from the front of the lidar
+    pub zero: f32,
+    /// Angles grow clockwise
+    pub clockwise: bool,
+    /// Angles are in `[-π, π)` instead of `[0, 2π)`
+    pub signed: bool,
+}
+
+impl AngleFrame {
+    /// The REP-103 convention: counter-clockwise from the front, in `[-π, π)`.
+    pub const REP103: Self = Self {
+        zero: 0.0,
+        clockwise: false,
+        signed: true,
+    };
+
+    /// The convention of the readings: counter-clockwise from the front, in `[0, 2π)`.
+    pub const LIDAR: Self = Self {
+        zero: 0.0,
+        clockwise: false,
+        signed: false,
+    };
+
+    /// Creates a counter-clockwise frame, in `[-π, π)`, whose 0 points to `zero`
+    /// radians from the front of the lidar, e.g. for a lidar mounted rotated.
+    pub fn new(zero: f32) -> Self {
+        Self {
+            zero,
+            ..Self::REP103
+        }
+    }
+
+    /// Converts an angle of the lidar, in radians counter-clockwise from its
+    /// front, to this frame.
+    pub fn from_lidar(&self, angle: f32) -> f32 {
+        let angle = angle - self.zero;
+        let angle = if self.clockwise { -angle } else { angle };
+        self.wrap(angle)
+    }
+
+    /// Converts an angle of this frame to the lidar one, in radians
+    /// counter-clockwise from its front, in `[0, 2π)`.
+    pub fn to_lidar(&self, angle: f32) -> f32 {
+        let angle = if self.clockwise { -angle } else { angle };
+        (angle + self.zero).rem_euclid(TAU)
+    }
+
+    /// Wraps an angle to the range of this frame.
+    pub fn wrap(&self, angle: f32) -> f32 {
+        if self.signed {
+            (angle + PI).rem_euclid(TAU) - PI
+        } else {
+            angle.rem_euclid(TAU)
+        }
+    }
+}
+
+impl Default for AngleFrame {
+    fn default() -> Self {
+        Self::REP103
+    }
+}
+
+impl<const N: usize> LaserReading<N> {
+    /// Gets the angle, in radians in `frame`, of the given beam.
+    pub fn angle_in(&self, index: usize, frame: &AngleFrame) -> f32 {
+        frame.from_lidar(Self::beam_angle(index))
+    }
+
+    /// Gets the index of the beam closest to an angle, in radians in `frame`.
+    pub fn index_at(&self, angle: f32, frame: &AngleFrame) -> usize {
+        let step = TAU / N as f32;
+        (frame.to_lidar(angle) / step).round() as usize % N
+    }
+
+    /// Gets the range, in meters, of the beam closest to an angle, in radians
+    /// in `frame`, `None` if the beam is not valid.
+    pub fn range_at(&self, angle: f32, frame: &AngleFrame) -> Option<f32> {
+        let index = self.index_at(angle, frame);
+        self.is_valid(index)
+            .then(|| f32::from(self.ranges[index]) / 1000.0)
+    }
+
+    /// Gets the angle, in radians in `frame`, and the range, in meters, of
+    /// all the valid beams.
+    pub fn polar(&self, frame: &AngleFrame) -> Vec<(f32, f32)> {
+        (0..N)
+            .filter(|&i| self.is_valid(i))
+            .map(|i| (self.angle_in(i, frame), f32::from(self.ranges[i]) / 1000.0))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn converts_between_frames() {
+        // Left of the lidar.
+        let left = FRAC_PI_2;
+        assert!(close(
+            AngleFrame::REP103.from_lidar(3.0 * FRAC_PI_2),
+            -FRAC_PI_2
+        ));
+        assert!(close(
+            AngleFrame::LIDAR.from_lidar(-FRAC_PI_2),
+            3.0 * FRAC_PI_2
+        ));
+
+        let rotated = AngleFrame::new(FRAC_PI_2);
+        assert!(close(rotated.from_lidar(left), 0.0));
+        let clockwise = AngleFrame {
+            clockwise: true,
+            ..AngleFrame::REP103
+        };
+        assert!(close(clockwise.from_lidar(left), -FRAC_PI_2));
+
+        for frame in [AngleFrame::REP103, AngleFrame::LIDAR, rotated, clockwise] {
+            assert!(close(frame.to_lidar(frame.from_lidar(1.0)), 1.0));
+        }
+    }
+
+    #[test]
+    fn finds_the_beam_at_an_angle() {
+        let mut reading = LaserReading::new();
+        reading.ranges[90] = 1500;
+        let frame = AngleFrame::new(FRAC_PI_2);
+        assert_eq!(reading.index_at(0.0, &frame), 90);
+        assert_eq!(reading.range_at(0.0, &frame), Some(1.5));
+        assert_eq!(reading.range_at(PI, &frame), None);
+        assert_eq!(reading.index_at(-0.001, &AngleFrame::REP103), 0);
+
+        let polar = reading.polar(&frame);
+        assert_eq!(polar.len(), 1);
+        assert!(close(polar[0].0, 0.0) && close(polar[0].1, 1.5));
+    }
+}
@@ -0,0 +1,352 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Runtime selection of the backend, among the ones enabled at compile time.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use hls_lfcd_lds_driver::any::{AnyLaser, Backend};
+//!
+//! let backend: Backend = "tokio".parse()?;
+//! let mut lidar = AnyLaser::new(backend, "/dev/ttyUSB0", 230400)?;
+//! let reading = lidar.read().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::{Error, Result};
+use crate::options::OpenOptions;
+use crate::state::{DriverEvent, DriverState};
+use crate::{AsyncLidarDriver, Hooks, LaserReading};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The backends that can be selected at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// Blocking backend, `sync::LFCDLaser`
+    #[cfg(feature = "sync")]
+    Sync,
+    /// Tokio backend, `tokio::LFCDLaser`
+    #[cfg(feature = "async_tokio")]
+    Tokio,
+    /// Smol backend, `smol::LFCDLaser`
+    #[cfg(feature = "async_smol")]
+    Smol,
+}
+
+impl Backend {
+    /// Gets the name of the backend, as accepted by `FromStr`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "sync")]
+            Backend::Sync => "sync",
+            #[cfg(feature = "async_tokio")]
+            Backend::Tokio => "tokio",
+            #[cfg(feature = "async_smol")]
+            Backend::Smol => "smol",
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Backend {
+    type Err = UnknownBackend;
+
+    fn from_str(s: &str) -> std::result::Result<Self, UnknownBackend> {
+        match s.to_ascii_lowercase().as_str() {
+            #[cfg(feature = "sync")]
+            "sync" => Ok(Backend::Sync),
+            #[cfg(feature = "async_tokio")]
+            "tokio" | "async_tokio" => Ok(Backend::Tokio),
+            #[cfg(feature = "async_smol")]
+            "smol" | "async_smol" => Ok(Backend::Smol),
+            _ => Err(UnknownBackend(s.to_string())),
+        }
+    }
+}
+
+/// Error parsing a `Backend`, holds the name that is unknown, or whose
+/// feature is disabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownBackend(pub String);
+
+impl fmt::Display for UnknownBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown or disabled backend: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownBackend {}
+
+/// A driver whose backend is chosen at runtime.
+///
+/// Reads are always exposed as `async`. The `Sync` backend reads on the
+/// threads of the `blocking` crate, through a `sync::LFCDLaserHandle`, so
+/// it does not block the executor, whatever it is. Its other methods lock
+/// the driver, they only wait when a read was dropped before completing,
+/// until the end of that read.
+pub enum AnyLaser {
+    #[cfg(feature = "sync")]
+    Sync(crate::sync::LFCDLaserHandle),
+    #[cfg(feature = "async_tokio")]
+    Tokio(crate::tokio::LFCDLaser),
+    #[cfg(feature = "async_smol")]
+    Smol(crate::smol::LFCDLaser),
+}
+
+/// Dispatches the same expression to every enabled variant.
+macro_rules! dispatch {
+    ($self:expr, $l:ident => $e:expr) => {
+        match $self {
+            #[cfg(feature = "sync")]
+            AnyLaser::Sync(handle) => handle.with(|$l| $e),
+            #[cfg(feature = "async_tokio")]
+            AnyLaser::Tokio($l) => $e,
+            #[cfg(feature = "async_smol")]
+            AnyLaser::Smol($l) => $e,
+        }
+    };
+}
+
+impl AnyLaser {
+    /// Creates a new `AnyLaser` using the given backend.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn new<P: AsRef<Path>>(backend: Backend, port: P, baud_rate: u32) -> Result<Self> {
+        match backend {
+            #[cfg(feature = "sync")]
+            Backend::Sync => Ok(AnyLaser::Sync(
+                crate::sync::LFCDLaser::new(port, baud_rate)?.into_handle(),
+            )),
+            #[cfg(feature = "async_tokio")]
+            Backend::Tokio => Ok(AnyLaser::Tokio(crate::tokio::LFCDLaser::new(
+                port, baud_rate,
+            )?)),
+            #[cfg(feature = "async_smol")]
+            Backend::Smol => Ok(AnyLaser::Smol(crate::smol::LFCDLaser::new(
+                port, baud_rate,
+            )?)),
+        }
+    }
+
+    /// Creates a new `AnyLaser` using the given backend, as the options say,
+    /// see `options::OpenOptions`.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn with_options(backend: Backend, options: &OpenOptions) -> Result<Self> {
+        match backend {
+            #[cfg(feature = "sync")]
+            Backend::Sync => Ok(AnyLaser::Sync(
+                crate::sync::LFCDLaser::with_options(options)?.into_handle(),
+            )),
+            #[cfg(feature = "async_tokio")]
+            Backend::Tokio => Ok(AnyLaser::Tokio(crate::tokio::LFCDLaser::with_options(
+                options,
+            )?)),
+            #[cfg(feature = "async_smol")]
+            Backend::Smol => Ok(AnyLaser::Smol(crate::smol::LFCDLaser::with_options(
+                options,
+            )?)),
+        }
+    }
+
+    /// Gets the backend in use.
+    pub fn backend(&self) -> Backend {
+        match self {
+            #[cfg(feature = "sync")]
+            AnyLaser::Sync(_) => Backend::Sync,
+            #[cfg(feature = "async_tokio")]
+            AnyLaser::Tokio(_) => Backend::Tokio,
+            #[cfg(feature = "async_smol")]
+            AnyLaser::Smol(_) => Backend::Smol,
+        }
+    }
+
+    /// Gets a reading from the lidar, returing a `LaserReading` object.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub async fn read(&mut self) -> Result<LaserReading> {
+        match self {
+            #[cfg(feature = "sync")]
+            AnyLaser::Sync(l) => {
+                let handle = l.clone();
+                blocking::unblock(move || handle.read()).await
+            }
+            #[cfg(feature = "async_tokio")]
+            AnyLaser::Tokio(l) => l.read().await,
+            #[cfg(feature = "async_smol")]
+            AnyLaser::Smol(l) => l.read().await,
+        }
+    }
+
+    /// Gets the scan of the newest complete revolution, dropping the ones
+    /// waiting, see `LFCDLaser::read_latest`.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub async fn read_latest(&mut self) -> Result<LaserReading> {
+        match self {
+            #[cfg(feature = "sync")]
+            AnyLaser::Sync(l) => {
+                let handle = l.clone();
+                blocking::unblock(move || handle.with(|l| l.read_latest())).await
+            }
+            #[cfg(feature = "async_tokio")]
+            AnyLaser::Tokio(l) => l.read_latest().await,
+            #[cfg(feature = "async_smol")]
+            AnyLaser::Smol(l) => l.read_latest().await,
+        }
+    }
+
+    /// Re-opens the serial port and starts the lidar again.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn reconnect(&mut self) -> Result<()> {
+        dispatch!(self, l => l.reconnect())
+    }
+
+    /// Starts the Lidar
+    pub fn start(&mut self) {
+        match self {
+            #[cfg(feature = "sync")]
+            AnyLaser::Sync(l) => l.start(),
+            #[cfg(feature = "async_tokio")]
+            AnyLaser::Tokio(l) => l.start(),
+            #[cfg(feature = "async_smol")]
+            AnyLaser::Smol(l) => l.start(),
+        }
+    }
+
+    /// Stops the lidar and marks the driver as closed.
+    pub fn close(&mut self) {
+        match self {
+            #[cfg(feature = "sync")]
+            AnyLaser::Sync(l) => l.stop(),
+            #[cfg(feature = "async_tokio")]
+            AnyLaser::Tokio(l) => l.close(),
+            #[cfg(feature = "async_smol")]
+            AnyLaser::Smol(l) => l.close(),
+        }
+    }
+
+    /// Discards the bytes received and not read yet, see `LFCDLaser::purge_input`.
+    ///
+    /// # Errors
+    /// An error variant is returned if the OS buffer cannot be cleared.
+    pub fn purge_input(&mut self) -> std::io::Result<()> {
+        dispatch!(self, l => l.purge_input())
+    }
+
+    /// Gets lidar speed.
+    pub fn speed(&self) -> u16 {
+        dispatch!(self, l => l.speed())
+    }
+
+    /// Gets the state of the driver.
+    pub fn state(&self) -> DriverState {
+        dispatch!(self, l => l.state())
+    }
+
+    /// Gets the configured baud rate
+    pub fn baud_rate(&self) -> u32 {
+        dispatch!(self, l => l.baud_rate())
+    }
+
+    /// Gets the configured serial port
+    pub fn port(&self) -> PathBuf {
+        dispatch!(self, l => l.port().to_path_buf())
+    }
+
+    /// Gets the lidars rmp from the last reading
+    pub fn rpms(&self) -> u16 {
+        dispatch!(self, l => l.rpms())
+    }
+
+    /// Gets the time of a revolution, in seconds, from the last reading
+    pub fn scan_time(&self) -> f32 {
+        dispatch!(self, l => l.scan_time())
+    }
+
+    /// Runs `f` with the callbacks registered on this driver.
+    pub fn with_hooks<R>(&mut self, f: impl FnOnce(&mut Hooks) -> R) -> R {
+        dispatch!(self, l => f(l.hooks()))
+    }
+
+    /// Gets a channel receiving the changes of the state of the driver,
+    /// see `LFCDLaser::subscribe`.
+    pub fn subscribe(&mut self) -> std::sync::mpsc::Receiver<DriverEvent> {
+        dispatch!(self, l => l.subscribe())
+    }
+}
+
+impl AsyncLidarDriver for AnyLaser {
+    type Error = Error;
+
+    async fn read(&mut self) -> Result<LaserReading> {
+        AnyLaser::read(self).await
+    }
+
+    fn start(&mut self) {
+        AnyLaser::start(self)
+    }
+
+    fn close(&mut self) {
+        AnyLaser::close(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_enabled_backends() {
+        #[cfg(feature = "sync")]
+        assert_eq!("sync".parse(), Ok(Backend::Sync));
+        #[cfg(feature = "async_tokio")]
+        assert_eq!("Async_Tokio".parse(), Ok(Backend::Tokio));
+        #[cfg(feature = "async_smol")]
+        assert_eq!("SMOL".parse(), Ok(Backend::Smol));
+        for backend in ["sync", "tokio", "smol"] {
+            if let Ok(parsed) = backend.parse::<Backend>() {
+                assert_eq!(parsed.name(), backend);
+            }
+        }
+        assert_eq!(
+            "serial".parse::<Backend>(),
+            Err(UnknownBackend("serial".into()))
+        );
+    }
+}
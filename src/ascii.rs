@@ -0,0 +1,83 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Text-mode polar plot of a scan, for serial consoles and test failures.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::LaserReading;
+//!
+//! let mut reading = LaserReading::new();
+//! // A wall 1 m in front of the lidar.
+//! for degree in (0..30).chain(330..360) {
+//!     reading.ranges[degree] = (1000.0 / LaserReading::angle(degree).cos()) as u16;
+//! }
+//! let plot = reading.to_ascii_plot(40, 20);
+//! assert_eq!(plot.lines().count(), 20);
+//! println!("{plot}");
+//! ```
+
+use crate::LaserReading;
+
+/// Character of the lidar, at the center of the plot.
+const LIDAR: char = '@';
+/// Character of a return.
+const POINT: char = '*';
+/// Character showing the front of the lidar, next to it.
+const FRONT: char = '^';
+
+impl<const N: usize> LaserReading<N> {
+    /// Renders the scan as `height` lines of `width` characters, seen from
+    /// above with the front of the lidar pointing up.
+    ///
+    /// The plot is scaled to the farthest valid beam, a character being
+    /// twice as tall as wide. Invalid beams are not drawn.
+    pub fn to_ascii_plot(&self, width: usize, height: usize) -> String {
+        if width == 0 || height == 0 {
+            return String::new();
+        }
+        let mut grid = vec![vec![' '; width]; height];
+        let (cx, cy) = ((width - 1) / 2, (height - 1) / 2);
+
+        let points = self.points();
+        let max_range = points
+            .iter()
+            .map(|(x, y)| x.hypot(*y))
+            .fold(0.0f32, f32::max);
+        if max_range > 0.0 {
+            // Meters per row, a column being half of it.
+            let row = (max_range / cy.max(1) as f32).max(2.0 * max_range / cx.max(1) as f32);
+            for (x, y) in points {
+                let col = cx as f32 - y / (row / 2.0);
+                let line = cy as f32 - x / row;
+                if (0.0..width as f32).contains(&col.round())
+                    && (0.0..height as f32).contains(&line.round())
+                {
+                    grid[line.round() as usize][col.round() as usize] = POINT;
+                }
+            }
+        }
+
+        if cy > 0 {
+            grid[cy - 1][cx] = FRONT;
+        }
+        grid[cy][cx] = LIDAR;
+
+        let mut plot = String::with_capacity((width + 1) * height);
+        for line in grid {
+            plot.extend(line);
+            plot.push('\n');
+        }
+        plot
+    }
+}
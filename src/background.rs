@@ -0,0 +1,216 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Background subtraction for a statically mounted lidar, e.g. to count
+//! people or vehicles.
+//!
+//! The model learns the range of every beam while the scene is empty, then
+//! keeps only the beams closer than their background: the foreground. The
+//! background keeps adapting to slow changes, and an object standing still
+//! long enough ends up in it.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::background::BackgroundModel;
+//! use hls_lfcd_lds_driver::legs::LegDetector;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let mut model = BackgroundModel::new().with_training_scans(50);
+//! let foreground = model.update(&reading);
+//! let people = LegDetector::default().detect_people(&foreground);
+//! # assert!(people.is_empty());
+//! ```
+
+use crate::{LaserReading, RANGE_MAX};
+
+/// Learned range of every beam, see the module documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackgroundModel {
+    learning_rate: f32,
+    absorption_rate: f32,
+    threshold: f32,
+    training_scans: usize,
+    scans: usize,
+    // Meters, `None` while the beam gets no return.
+    background: Vec<Option<f32>>,
+}
+
+impl BackgroundModel {
+    /// Creates a model learning from the first 25 scans, five seconds at
+    /// 300 rpm, with a threshold of 0.1 m.
+    pub fn new() -> Self {
+        Self {
+            learning_rate: 0.05,
+            absorption_rate: 0.001,
+            threshold: 0.1,
+            training_scans: 25,
+            scans: 0,
+            background: Vec::new(),
+        }
+    }
+
+    /// Sets how fast the background follows the beams not in the foreground,
+    /// from 0 to 1.
+    pub fn with_learning_rate(mut self, rate: f32) -> Self {
+        self.learning_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets how fast an object standing still joins the background, from 0
+    /// to 1, 0 to never.
+    pub fn with_absorption_rate(mut self, rate: f32) -> Self {
+        self.absorption_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets how much closer than the background, in meters, a beam must be
+    /// to be in the foreground.
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets the number of scans learned before reporting any foreground.
+    pub fn with_training_scans(mut self, scans: usize) -> Self {
+        self.training_scans = scans;
+        self
+    }
+
+    /// Checks if the training is over.
+    pub fn is_trained(&self) -> bool {
+        self.scans >= self.training_scans
+    }
+
+    /// Gets the background range, in meters, of the given beam, `None` if
+    /// it had no return during the training and nothing showed up since.
+    pub fn background(&self, index: usize) -> Option<f32> {
+        self.background.get(index).copied().flatten()
+    }
+
+    /// Forgets the background, the training starts over.
+    pub fn reset(&mut self) {
+        self.scans = 0;
+        self.background.clear();
+    }
+
+    /// Checks if the given beam of a scan is in the foreground, always
+    /// `false` during the training.
+    pub fn is_foreground(&self, reading: &LaserReading, index: usize) -> bool {
+        if !self.is_trained() || !reading.is_valid(index) {
+            return false;
+        }
+        let range = f32::from(reading.ranges[index]) / 1000.0;
+        match self.background(index) {
+            Some(background) => range < background - self.threshold,
+            // Something appeared where the beam used to get no return.
+            None => true,
+        }
+    }
+
+    /// Learns a scan and returns its foreground: a copy where the beams of
+    /// the background are set to 0, empty during the training.
+    pub fn update(&mut self, reading: &LaserReading) -> LaserReading {
+        let n = reading.ranges.len();
+        self.background.resize(n, None);
+
+        let mut foreground = LaserReading::new();
+        foreground.rpms = reading.rpms;
+        for i in 0..n {
+            let fg = self.is_foreground(reading, i);
+            if fg {
+                foreground.ranges[i] = reading.ranges[i];
+                foreground.intensities[i] = reading.intensities[i];
+            }
+            if !reading.is_valid(i) {
+                continue;
+            }
+
+            let range = f32::from(reading.ranges[i]) / 1000.0;
+            let rate = if !self.is_trained() {
+                // Averages the training scans evenly.
+                1.0 / (self.scans + 1) as f32
+            } else if fg {
+                self.absorption_rate
+            } else {
+                self.learning_rate
+            };
+            let background = match self.background[i] {
+                Some(b) => b,
+                None if !self.is_trained() => range,
+                // No return until now, the object joins the background slowly.
+                None => f32::from(RANGE_MAX) / 1000.0,
+            };
+            self.background[i] = Some(background + rate * (range - background));
+        }
+
+        self.scans = self.scans.saturating_add(1);
+        foreground
+    }
+}
+
+impl Default for BackgroundModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wall() -> LaserReading {
+        let mut reading = LaserReading::new();
+        reading.ranges[..180].fill(2000);
+        reading
+    }
+
+    #[test]
+    fn reports_what_stands_before_the_background() {
+        let mut model = BackgroundModel::new().with_training_scans(3);
+        for _ in 0..3 {
+            let foreground = model.update(&wall());
+            assert!(foreground.ranges.iter().all(|&r| r == 0));
+        }
+        assert!(model.is_trained());
+        assert_eq!(model.background(10), Some(2.0));
+        assert_eq!(model.background(200), None);
+
+        let mut scan = wall();
+        // A person before the wall, something where there was no return.
+        scan.ranges[10] = 1000;
+        scan.ranges[11] = 1950;
+        scan.ranges[200] = 3000;
+        let foreground = model.update(&scan);
+        let seen: Vec<_> = (0..360).filter(|&i| foreground.ranges[i] != 0).collect();
+        assert_eq!(seen, [10, 200]);
+    }
+
+    #[test]
+    fn absorbs_what_stands_still() {
+        let mut model = BackgroundModel::new()
+            .with_training_scans(1)
+            .with_absorption_rate(0.5);
+        model.update(&wall());
+        let mut scan = wall();
+        scan.ranges[10] = 1000;
+        assert_eq!(model.update(&scan).ranges[10], 1000);
+        for _ in 0..10 {
+            model.update(&scan);
+        }
+        assert_eq!(model.update(&scan).ranges[10], 0);
+
+        model.reset();
+        assert!(!model.is_trained());
+        assert_eq!(model.background(10), None);
+    }
+}
@@ -0,0 +1,372 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Always-on recorder of the last seconds of scans, and optionally of the
+//! raw frames, to dump to disk when something goes wrong in the field.
+//!
+//! A dump starts with the `LDSB` magic and a version byte, followed by the
+//! records in time order. Every record is its kind, `SCAN` or `FRAME`, its
+//! timestamp as `u64` ns since the epoch, the length of the payload as
+//! `u32` and the payload. A scan is its rpms, its ranges and its
+//! intensities, a frame is the bytes read from the lidar; all little-endian.
+//!
+//! ```no_run
+//! use hls_lfcd_lds_driver::blackbox::BlackBox;
+//! use hls_lfcd_lds_driver::Hooks;
+//! use std::time::Duration;
+//!
+//! let recorder = BlackBox::new(Duration::from_secs(30)).with_raw_frames();
+//! let mut hooks = Hooks::new();
+//! recorder.attach(&mut hooks);
+//!
+//! // ... when an incident happens
+//! recorder.dump("incident.ldsb")?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::sink::ScanSink;
+use crate::{Hooks, LaserReading};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Magic bytes at the beginning of a dump.
+pub const MAGIC: &[u8; 4] = b"LDSB";
+/// Version of the format.
+pub const VERSION: u8 = 1;
+/// Kind of a record holding a scan.
+pub const SCAN: u8 = 0;
+/// Kind of a record holding a raw frame.
+pub const FRAME: u8 = 1;
+
+/// A recorded scan or raw frame.
+#[derive(Debug, Clone)]
+pub enum Record {
+    Scan(SystemTime, Box<LaserReading>),
+    Frame(SystemTime, Vec<u8>),
+}
+
+impl Record {
+    /// Gets the time the record was taken at.
+    pub fn timestamp(&self) -> SystemTime {
+        match self {
+            Record::Scan(t, _) | Record::Frame(t, _) => *t,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    window: Duration,
+    raw_frames: bool,
+    records: VecDeque<Record>,
+    dump_dir: Option<PathBuf>,
+    last_dump: Option<SystemTime>,
+}
+
+impl Inner {
+    fn push(&mut self, record: Record) {
+        let now = record.timestamp();
+        self.records.push_back(record);
+        while self.records.front().is_some_and(|r| {
+            now.duration_since(r.timestamp())
+                .is_ok_and(|age| age > self.window)
+        }) {
+            self.records.pop_front();
+        }
+    }
+}
+
+/// Ring recorder keeping the records of the last `window`.
+///
+/// Cloning it gives another handle to the same recorder, so it can be
+/// attached to a driver and dumped from elsewhere.
+#[derive(Debug, Clone)]
+pub struct BlackBox {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl BlackBox {
+    /// Creates a recorder keeping the scans of the last `window`.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                window,
+                raw_frames: false,
+                records: VecDeque::new(),
+                dump_dir: None,
+                last_dump: None,
+            })),
+        }
+    }
+
+    /// Records also the raw frames, doubling the memory used.
+    pub fn with_raw_frames(self) -> Self {
+        self.lock().raw_frames = true;
+        self
+    }
+
+    /// Dumps the recorder into `dir` whenever the driver reports a decode
+    /// error, at most once per window.
+    ///
+    /// The dump is written by the thread reading the lidar, in a file named
+    /// after the time of the error.
+    pub fn with_dump_on_error<P: AsRef<Path>>(self, dir: P) -> Self {
+        self.lock().dump_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers the callbacks feeding the recorder on a driver.
+    pub fn attach(&self, hooks: &mut Hooks) {
+        let recorder = self.clone();
+        hooks.on_scan(move |reading| recorder.record(reading));
+
+        if self.lock().raw_frames {
+            let recorder = self.clone();
+            hooks.on_frame(move |frame| recorder.record_frame(frame));
+        }
+
+        let recorder = self.clone();
+        hooks.on_decode_error(move |_| {
+            // Errors cannot be reported from a callback.
+            let _ = recorder.dump_on_error();
+        });
+    }
+
+    /// Records a scan, taken now.
+    pub fn record(&self, reading: &LaserReading) {
+        self.record_at(reading, SystemTime::now());
+    }
+
+    /// Records a scan taken at `timestamp`.
+    pub fn record_at(&self, reading: &LaserReading, timestamp: SystemTime) {
+        self.lock()
+            .push(Record::Scan(timestamp, Box::new(reading.clone())));
+    }
+
+    /// Records a raw frame, read now.
+    pub fn record_frame(&self, frame: &[u8]) {
+        self.lock()
+            .push(Record::Frame(SystemTime::now(), frame.to_vec()));
+    }
+
+    /// Gets the number of records.
+    pub fn len(&self) -> usize {
+        self.lock().records.len()
+    }
+
+    /// Checks if nothing has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.lock().records.is_empty()
+    }
+
+    /// Removes all the records.
+    pub fn clear(&self) {
+        self.lock().records.clear();
+    }
+
+    /// Gets a copy of the records, oldest first.
+    pub fn snapshot(&self) -> Vec<Record> {
+        self.lock().records.iter().cloned().collect()
+    }
+
+    /// Dumps the records to a file, replacing it if it exists.
+    ///
+    /// # Errors
+    /// An error variant is returned if the file cannot be written.
+    pub fn dump<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.dump_to(&mut w)?;
+        w.flush()
+    }
+
+    /// Dumps the records to a writer.
+    ///
+    /// # Errors
+    /// An error variant is returned if the writer fails.
+    pub fn dump_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        // Copies the records so the driver is not blocked while writing.
+        let records = self.snapshot();
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        for record in &records {
+            write_record(w, record)?;
+        }
+        Ok(())
+    }
+
+    fn dump_on_error(&self) -> io::Result<()> {
+        let now = SystemTime::now();
+        let dir = {
+            let mut inner = self.lock();
+            let Some(dir) = inner.dump_dir.clone() else {
+                return Ok(());
+            };
+            let window = inner.window;
+            if inner
+                .last_dump
+                .is_some_and(|t| now.duration_since(t).unwrap_or_default() < window)
+            {
+                return Ok(());
+            }
+            inner.last_dump = Some(now);
+            dir
+        };
+        let ms = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        self.dump(dir.join(format!("blackbox-{ms}.ldsb")))
+    }
+
+    /// Reads back the records of a dump.
+    ///
+    /// # Errors
+    /// An error variant is returned if the dump cannot be read or is corrupted.
+    pub fn load<R: Read>(mut r: R) -> io::Result<Vec<Record>> {
+        let mut header = [0u8; 5];
+        r.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(invalid("not a black-box dump"));
+        }
+
+        let mut records = Vec::new();
+        let mut head = [0u8; 13];
+        loop {
+            match r.read_exact(&mut head) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(records),
+                Err(e) => return Err(e),
+            }
+            let mut nanos = [0u8; 8];
+            nanos.copy_from_slice(&head[1..9]);
+            let timestamp = UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(nanos));
+            let len = u32::from_le_bytes([head[9], head[10], head[11], head[12]]) as usize;
+            let mut payload = vec![0u8; len];
+            r.read_exact(&mut payload)?;
+
+            records.push(match head[0] {
+                SCAN => Record::Scan(timestamp, Box::new(decode_scan(&payload)?)),
+                FRAME => Record::Frame(timestamp, payload),
+                _ => return Err(invalid("unknown record kind")),
+            });
+        }
+    }
+}
+
+impl ScanSink for BlackBox {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        self.record(reading);
+        Ok(())
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_record<W: Write>(w: &mut W, record: &Record) -> io::Result<()> {
+    let (kind, payload) = match record {
+        Record::Scan(_, reading) => {
+            let mut payload = Vec::with_capacity(2 + 4 * reading.ranges.len());
+            payload.extend_from_slice(&reading.rpms.to_le_bytes());
+            for v in reading.ranges.iter().chain(reading.intensities.iter()) {
+                payload.extend_from_slice(&v.to_le_bytes());
+            }
+            (SCAN, payload)
+        }
+        Record::Frame(_, frame) => (FRAME, frame.clone()),
+    };
+    let nanos = record
+        .timestamp()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    w.write_all(&[kind])?;
+    w.write_all(&nanos.to_le_bytes())?;
+    w.write_all(&(payload.len() as u32).to_le_bytes())?;
+    w.write_all(&payload)
+}
+
+fn decode_scan(payload: &[u8]) -> io::Result<LaserReading> {
+    let mut reading = LaserReading::new();
+    let n = reading.ranges.len();
+    if payload.len() != 2 + 4 * n {
+        return Err(invalid("bad scan record"));
+    }
+    let u16_at = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+    reading.rpms = u16_at(0);
+    for i in 0..n {
+        reading.ranges[i] = u16_at(2 + 2 * i);
+        reading.intensities[i] = u16_at(2 + 2 * (n + i));
+    }
+    Ok(reading)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_scan_eq;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn keeps_only_the_last_window() {
+        let recorder = BlackBox::new(Duration::from_secs(2));
+        for secs in 0..5 {
+            recorder.record_at(&LaserReading::new(), at(secs));
+        }
+        let stamps: Vec<_> = recorder.snapshot().iter().map(Record::timestamp).collect();
+        assert_eq!(stamps, [at(2), at(3), at(4)]);
+
+        recorder.clear();
+        assert!(recorder.is_empty());
+    }
+
+    #[test]
+    fn loads_back_a_dump() {
+        let recorder = BlackBox::new(Duration::from_secs(60));
+        let mut reading = LaserReading::new();
+        reading.rpms = 300;
+        reading.ranges[10] = 1234;
+        reading.intensities[10] = 56;
+        recorder.record(&reading);
+        recorder.record_frame(&[0xFA, 0xA0]);
+
+        let mut dump = Vec::new();
+        recorder.dump_to(&mut dump).unwrap();
+        let records = BlackBox::load(dump.as_slice()).unwrap();
+        assert_eq!(records.len(), 2);
+        match &records[0] {
+            Record::Scan(_, scan) => assert_scan_eq(scan, &reading),
+            r => panic!("expected a scan, got {r:?}"),
+        }
+        assert!(matches!(&records[1], Record::Frame(_, f) if f == &[0xFA, 0xA0]));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let err = BlackBox::load(&b"LDSZ\x01"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
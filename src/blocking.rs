@@ -0,0 +1,162 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Blocking facade over the tokio driver, enabled by the `blocking` feature.
+//!
+//! Useful for applications that do not use async but want the
+//! tokio-serial based reading path.
+
+use crate::error::{Error, Result};
+use crate::options::{Model, Open, OpenOptions};
+use crate::tokio::LFCDLaser;
+use crate::{Hooks, LaserReading, LidarDriver};
+use ::tokio::runtime::{Builder, Runtime};
+use std::path::Path;
+
+/// A driver with a synchronous `read`, running the tokio driver
+/// on a private single-threaded runtime.
+pub struct BlockingLaser {
+    // Declared before the runtime so that it is dropped (and the lidar stopped)
+    // while the runtime is still alive.
+    laser: LFCDLaser,
+    runtime: Runtime,
+}
+
+impl BlockingLaser {
+    /// Creates a new `BlockingLaser` with the given parameters.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to create the runtime
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn new<P: AsRef<Path>>(port: P, baud_rate: u32) -> Result<Self> {
+        let runtime = Builder::new_current_thread().enable_io().build()?;
+        let laser = {
+            let _guard = runtime.enter();
+            LFCDLaser::new(port, baud_rate)?
+        };
+
+        Ok(Self { laser, runtime })
+    }
+
+    /// Creates a new `BlockingLaser` as the options say, see `OpenOptions::open`.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - invalid options, the port is not touched
+    /// - unable to create the runtime
+    /// - unable to open the specified serial port
+    /// - unable to set the exclusivity of the port (only on unix)
+    pub fn with_options(options: &OpenOptions) -> Result<Self> {
+        options.open()
+    }
+
+    /// Gets a reading from the lidar, blocking the current thread.
+    ///
+    /// Must not be called from within an async context.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub fn read(&mut self) -> Result<LaserReading> {
+        self.runtime.block_on(self.laser.read())
+    }
+
+    /// Re-opens the serial port and starts the lidar again.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn reconnect(&mut self) -> Result<()> {
+        let _guard = self.runtime.enter();
+        self.laser.reconnect()
+    }
+
+    /// Starts the Lidar
+    pub fn start(&mut self) {
+        self.laser.start()
+    }
+
+    /// Stops the lidar and marks the driver as closed.
+    pub fn close(&mut self) {
+        self.laser.close()
+    }
+
+    /// Gets lidar speed.
+    pub fn speed(&self) -> u16 {
+        self.laser.speed()
+    }
+
+    /// Gets the configured baud rate
+    pub fn baud_rate(&self) -> u32 {
+        self.laser.baud_rate()
+    }
+
+    /// Gets the configured serial port
+    pub fn port(&self) -> &Path {
+        self.laser.port()
+    }
+
+    /// Gets the lidars rmp from the last reading
+    pub fn rpms(&self) -> u16 {
+        self.laser.rpms()
+    }
+
+    /// Gets the callbacks registered on this driver.
+    pub fn hooks(&mut self) -> &mut Hooks {
+        self.laser.hooks()
+    }
+
+    /// Gets the underlying async driver.
+    pub fn inner(&mut self) -> &mut LFCDLaser {
+        &mut self.laser
+    }
+}
+
+impl Open for BlockingLaser {
+    type Error = Error;
+
+    const DRIVER: &'static str = "blocking";
+
+    const MODELS: &'static [Model] = &[Model::Lds01];
+
+    fn open_unchecked(options: &OpenOptions) -> Result<Self> {
+        let runtime = Builder::new_current_thread().enable_io().build()?;
+        let laser = {
+            let _guard = runtime.enter();
+            LFCDLaser::open_unchecked(options)?
+        };
+
+        Ok(Self { laser, runtime })
+    }
+}
+
+impl LidarDriver for BlockingLaser {
+    type Error = Error;
+
+    fn read(&mut self) -> Result<LaserReading> {
+        BlockingLaser::read(self)
+    }
+
+    fn start(&mut self) {
+        BlockingLaser::start(self)
+    }
+
+    fn close(&mut self) {
+        BlockingLaser::close(self)
+    }
+}
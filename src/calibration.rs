@@ -0,0 +1,125 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Per-degree range correction, compensating the systematic bias of an
+//! individual unit.
+//!
+//! The offsets are indexed by the degree the lidar reports the beam at,
+//! before the scan is mirrored, so a table measured on a unit stays valid
+//! whichever way it is mounted. With the `ser_de` feature the table can be
+//! stored in any serde format, e.g. JSON as `{"offsets": [...]}`.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::calibration::Calibration;
+//!
+//! let mut calibration = Calibration::new();
+//! // The unit reads 15 mm too far straight ahead.
+//! calibration.set_offset(0, -15);
+//! assert_eq!(calibration.correct(0, 1000), 985);
+//! // No return stays no return.
+//! assert_eq!(calibration.correct(0, 0), 0);
+//! ```
+
+#[cfg(feature = "ser_de")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "ser_de")]
+use serde_big_array::BigArray;
+
+/// Offsets, in mm, added to the range of every degree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ser_de", derive(Serialize, Deserialize))]
+pub struct Calibration {
+    /// Offset of every degree, in mm
+    #[cfg_attr(feature = "ser_de", serde(with = "BigArray"))]
+    pub offsets: [i16; 360],
+}
+
+impl Calibration {
+    /// Creates a table that leaves the ranges untouched.
+    pub fn new() -> Self {
+        Self { offsets: [0; 360] }
+    }
+
+    /// Creates a table from the offsets of every degree.
+    pub fn from_offsets(offsets: [i16; 360]) -> Self {
+        Self { offsets }
+    }
+
+    /// Gets the offset of the given degree.
+    pub fn offset(&self, degree: usize) -> i16 {
+        self.offsets[degree]
+    }
+
+    /// Sets the offset of the given degree.
+    pub fn set_offset(&mut self, degree: usize, offset: i16) {
+        self.offsets[degree] = offset;
+    }
+
+    /// Gets the corrected range of the given degree.
+    ///
+    /// A range of 0, no return, is left as is, the others are saturated to
+    /// the range of `u16`.
+    pub fn correct(&self, degree: usize, range: u16) -> u16 {
+        if range == 0 {
+            return 0;
+        }
+        let corrected = i32::from(range) + i32::from(self.offsets[degree]);
+        corrected.clamp(0, i32::from(u16::MAX)) as u16
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{decode_frame_with, DecodeOptions};
+    use crate::testing::{encode_frame, fixtures};
+
+    #[test]
+    fn corrects_and_saturates_the_ranges() {
+        let mut calibration = Calibration::new();
+        calibration.set_offset(0, 25);
+        calibration.set_offset(1, -300);
+        calibration.set_offset(2, i16::MAX);
+        assert_eq!(calibration.correct(0, 1000), 1025);
+        assert_eq!(calibration.correct(1, 200), 0);
+        assert_eq!(calibration.correct(2, u16::MAX - 10), u16::MAX);
+        // No return stays no return.
+        assert_eq!(calibration.correct(0, 0), 0);
+        assert_eq!(calibration.correct(3, 1000), 1000);
+    }
+
+    #[test]
+    fn follows_the_lidar_when_mirrored() {
+        let room = &fixtures()[0];
+        let mut calibration = Calibration::new();
+        calibration.set_offset(90, 10);
+        let options = DecodeOptions {
+            mirrored: true,
+            calibration: Some(calibration),
+        };
+        let scan = decode_frame_with(&encode_frame(&room.expected), &options, |_| {});
+        let expected = room.expected.ranges[90];
+        assert_eq!(
+            scan.ranges[270],
+            if expected == 0 { 0 } else { expected + 10 }
+        );
+        assert_eq!(scan.ranges[90], room.expected.ranges[270]);
+    }
+}
@@ -0,0 +1,71 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Segmentation of a scan in clusters of neighbouring beams, shared by the
+//! feature detectors.
+
+use crate::LaserReading;
+
+/// Splits the valid beams of a scan in clusters, in angle order.
+///
+/// Two consecutive beams belong to the same cluster when both are valid and
+/// their points are at most `max_gap` meters apart. The cluster crossing the
+/// front of the lidar is not split in two.
+pub(crate) fn clusters(reading: &LaserReading, max_gap: f32) -> Vec<Vec<usize>> {
+    let n = reading.ranges.len();
+    let joined = |a: usize, b: usize| {
+        reading.is_valid(a) && reading.is_valid(b) && {
+            let (pa, pb) = (reading.point(a), reading.point(b));
+            (pa.0 - pb.0).hypot(pa.1 - pb.1) <= max_gap
+        }
+    };
+
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    for i in (0..n).filter(|&i| reading.is_valid(i)) {
+        match clusters.last_mut() {
+            Some(c) if i > 0 && c.last() == Some(&(i - 1)) && joined(i - 1, i) => c.push(i),
+            _ => clusters.push(vec![i]),
+        }
+    }
+
+    if clusters.len() > 1 && clusters[0][0] == 0 && joined(n - 1, 0) {
+        let mut last = clusters.pop().unwrap_or_default();
+        if last.last() == Some(&(n - 1)) {
+            last.append(&mut clusters[0]);
+            clusters[0] = last;
+        } else {
+            clusters.push(last);
+        }
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_the_cluster_crossing_the_front() {
+        let mut reading = LaserReading::new();
+        reading.ranges[358..].fill(1000);
+        reading.ranges[..3].fill(1000);
+        reading.ranges[90..93].fill(1000);
+        // Too far from its neighbour.
+        reading.ranges[93] = 2000;
+        assert_eq!(
+            clusters(&reading, 0.1),
+            [vec![358, 359, 0, 1, 2], vec![90, 91, 92], vec![93]]
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! `tokio_util` codecs, enabled by the `codec` feature.
+//!
+//! They allow to wrap any `AsyncRead` in a `FramedRead`:
+//!
+//! ```no_run
+//! use hls_lfcd_lds_driver::codec::LdsCodec;
+//! use tokio_util::codec::FramedRead;
+//!
+//! # fn run<R>(serial: R) {
+//! // `scans` is a `Stream` of `LaserReading`
+//! let scans = FramedRead::new(serial, LdsCodec::new());
+//! # }
+//! ```
+
+use crate::protocol::{
+    decode_frame_with, decode_packet, DecodeOptions, Packet, FIRST_INDEX, FRAME_SIZE,
+    PACKETS_PER_FRAME, PACKET_SIZE, SYNC_BYTE,
+};
+use crate::{Error, LaserReading};
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::Decoder;
+
+/// Drops the bytes before the first occurrence of `SYNC_BYTE` followed by a
+/// byte accepted by `is_index`, returns `true` if the header has been found.
+fn sync(src: &mut BytesMut, is_index: impl Fn(u8) -> bool) -> bool {
+    match src
+        .windows(2)
+        .position(|w| w[0] == SYNC_BYTE && is_index(w[1]))
+    {
+        Some(pos) => {
+            src.advance(pos);
+            true
+        }
+        None => {
+            // Keeps a trailing sync byte, its index may still be on the way.
+            let keep = usize::from(src.last() == Some(&SYNC_BYTE));
+            let len = src.len();
+            src.advance(len - keep);
+            false
+        }
+    }
+}
+
+/// Decoder producing a `LaserReading` for every revolution.
+///
+/// Fails with the errors of the drivers: `Error::Io` for the errors of the
+/// underlying reader, `Error::ShortRead` when it ends in the middle of a
+/// frame.
+#[derive(Debug, Clone)]
+pub struct LdsCodec {
+    decode_errors: u64,
+    options: DecodeOptions,
+    decimation: usize,
+    decimated: usize,
+}
+
+impl Default for LdsCodec {
+    fn default() -> Self {
+        Self {
+            decode_errors: 0,
+            options: DecodeOptions::default(),
+            decimation: 1,
+            decimated: 0,
+        }
+    }
+}
+
+impl LdsCodec {
+    /// Creates a new `LdsCodec`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the decimation, only one scan out of `n` is produced, 1 for
+    /// every scan. Every frame is still parsed to stay in sync.
+    pub fn with_decimation(mut self, n: usize) -> Self {
+        self.decimation = n.max(1);
+        self
+    }
+
+    /// Creates a new `LdsCodec` decoding the frames with `options`.
+    pub fn with_options(options: DecodeOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// Gets the number of packets skipped, since the creation of the codec,
+    /// because of a bad header.
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors
+    }
+}
+
+impl Decoder for LdsCodec {
+    type Item = LaserReading;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<LaserReading>, Error> {
+        loop {
+            if !sync(src, |b| b == FIRST_INDEX) {
+                return Ok(None);
+            }
+
+            if src.len() < FRAME_SIZE {
+                src.reserve(FRAME_SIZE - src.len());
+                return Ok(None);
+            }
+
+            let frame = src.split_to(FRAME_SIZE);
+            // The length has just been checked.
+            let frame: &[u8; FRAME_SIZE] = frame[..].try_into().unwrap();
+            let errors = &mut self.decode_errors;
+            let scan = decode_frame_with(frame, &self.options, |_| *errors += 1);
+
+            let skip = self.decimated != 0;
+            self.decimated = (self.decimated + 1) % self.decimation;
+            if !skip {
+                return Ok(Some(scan));
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<LaserReading>, Error> {
+        match self.decode(src)? {
+            None if !src.is_empty() => Err(Error::ShortRead),
+            scan => Ok(scan),
+        }
+    }
+}
+
+/// Decoder producing every single `Packet`, six degrees at a time.
+#[derive(Debug, Default, Clone)]
+pub struct LdsPacketCodec;
+
+impl LdsPacketCodec {
+    /// Creates a new `LdsPacketCodec`.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Decoder for LdsPacketCodec {
+    type Item = Packet;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, Error> {
+        let is_index = |b: u8| {
+            b.checked_sub(FIRST_INDEX)
+                .is_some_and(|i| usize::from(i) < PACKETS_PER_FRAME)
+        };
+        if !sync(src, is_index) {
+            return Ok(None);
+        }
+
+        if src.len() < PACKET_SIZE {
+            src.reserve(PACKET_SIZE - src.len());
+            return Ok(None);
+        }
+
+        let packet = src.split_to(PACKET_SIZE);
+        // The length has just been checked and the header found by `sync`.
+        let packet: &[u8; PACKET_SIZE] = packet[..].try_into().unwrap();
+        // A bad header is reported as `Error::InvalidHeader`.
+        Ok(Some(decode_packet(packet)?))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Packet>, Error> {
+        match self.decode(src)? {
+            None if !src.is_empty() => Err(Error::ShortRead),
+            packet => Ok(packet),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_scan_eq, fixtures};
+
+    #[test]
+    fn decodes_the_fixtures() {
+        let mut codec = LdsCodec::new();
+        let mut src = BytesMut::new();
+        // Garbage first, as when opening the port mid-frame.
+        src.extend_from_slice(&[0x00, SYNC_BYTE, 0x12, 0xA0]);
+        for fixture in fixtures() {
+            src.extend_from_slice(&fixture.frame);
+        }
+        for fixture in fixtures() {
+            let scan = codec.decode(&mut src).unwrap().unwrap();
+            assert_scan_eq(&scan, &fixture.expected);
+        }
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+        assert_eq!(codec.decode_errors(), 1);
+    }
+
+    #[test]
+    fn fails_on_a_frame_cut_short() {
+        let room = &fixtures()[0];
+        let mut src = BytesMut::from(&room.frame[..FRAME_SIZE - 1]);
+        let mut codec = LdsCodec::new();
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert!(matches!(codec.decode_eof(&mut src), Err(Error::ShortRead)));
+
+        let mut src = BytesMut::from(&room.frame[..PACKET_SIZE + 1]);
+        let mut codec = LdsPacketCodec::new();
+        assert!(codec.decode(&mut src).unwrap().is_some());
+        assert!(matches!(codec.decode_eof(&mut src), Err(Error::ShortRead)));
+    }
+}
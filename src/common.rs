@@ -0,0 +1,1178 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! State shared by all the backends, independent from the serial port type.
+
+use crate::diagnostics::{ResyncStats, RpmTracker, SyncEvent};
+use crate::duty::Schedule;
+use crate::error::{Error, Result, SYNC_LIMIT};
+use crate::idle::IdleMonitor;
+use crate::protocol::{
+    self, decode_frame_with, DecodeOptions, FIRST_INDEX, FRAME_SIZE, PACKETS_PER_FRAME,
+    PACKET_SIZE, SYNC_BYTE,
+};
+use crate::state::{DriverEvent, DriverState};
+use crate::watchdog::Watchdog;
+use crate::{Hooks, LaserReading};
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+pub(crate) struct Core {
+    pub(crate) port: PathBuf,
+    pub(crate) baud_rate: u32,
+    /// Keeps other processes from opening the port, only on unix.
+    pub(crate) exclusive: bool,
+    pub(crate) state: DriverState,
+    /// The last scan had packets failing to decode.
+    degraded: bool,
+    pub(crate) motor_speed: u16,
+    pub(crate) rpms: u16,
+    /// When the last frame was read.
+    pub(crate) frame_end: Option<SystemTime>,
+    pub(crate) buff: [u8; FRAME_SIZE],
+    pub(crate) hooks: Hooks,
+    pub(crate) decode: DecodeOptions,
+    pub(crate) resync: ResyncStats,
+    pub(crate) rpm: RpmTracker,
+    /// Bytes skipped so far while searching the header of the next frame.
+    skipped: usize,
+    /// Beginning of the next frame, found at the end of a corrupted one.
+    pub(crate) carry: Vec<u8>,
+    /// Stops the motor when the driver is not read.
+    pub(crate) idle: Option<IdleMonitor>,
+    /// Restarts the motor when it stalls.
+    pub(crate) watchdog: Option<Watchdog>,
+    /// Stops the motor out of the scanning windows.
+    pub(crate) duty: Option<Schedule>,
+    /// Scans still to drop while the motor speeds up.
+    pub(crate) warmup: usize,
+    /// Only one scan out of `decimation` is returned.
+    pub(crate) decimation: usize,
+    /// Scans decoded since the last one returned.
+    decimated: usize,
+    /// Commands sent through the handles of the driver, if shared.
+    pub(crate) commands: Option<Arc<Mutex<Commands>>>,
+}
+
+impl Core {
+    pub(crate) fn new(port: PathBuf, baud_rate: u32) -> Self {
+        Self {
+            port,
+            baud_rate,
+            exclusive: false,
+            state: DriverState::Stopped,
+            degraded: false,
+            motor_speed: 0,
+            rpms: 0,
+            frame_end: None,
+            buff: [0u8; FRAME_SIZE],
+            hooks: Hooks::new(),
+            decode: DecodeOptions::default(),
+            resync: ResyncStats::new(),
+            rpm: RpmTracker::default(),
+            skipped: 0,
+            carry: Vec::new(),
+            idle: None,
+            watchdog: None,
+            duty: None,
+            warmup: 0,
+            decimation: 1,
+            decimated: 0,
+            commands: None,
+        }
+    }
+
+    /// Gets the timeout of the reads, derived from the last RPMs so that a
+    /// stopped motor is told apart from slow data, `fallback` until the
+    /// lidar spins.
+    pub(crate) fn read_timeout(&self, fallback: Duration) -> Duration {
+        protocol::read_timeout(self.rpms).unwrap_or(fallback)
+    }
+
+    /// Passes the bytes of the buffer just read, from `start` to `end`, to
+    /// the `on_bytes` callbacks.
+    pub(crate) fn tee(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.hooks.emit_bytes(&self.buff[start..end]);
+        }
+    }
+
+    /// Checks if the driver has been closed.
+    pub(crate) fn closed(&self) -> bool {
+        self.state == DriverState::Closed
+    }
+
+    /// Records a failed read, returns the error.
+    pub(crate) fn fail(&mut self, e: Error) -> Error {
+        if !self.closed() {
+            self.state = DriverState::Error(e.kind());
+        }
+        if matches!(e, Error::SyncLost) {
+            self.hooks.emit_event(DriverEvent::SyncLost);
+        }
+        e
+    }
+
+    /// Moves the bytes carried over from the previous frame at the beginning
+    /// of the buffer, returns their number.
+    pub(crate) fn resume(&mut self) -> usize {
+        let n = self.carry.len();
+        self.buff[..n].copy_from_slice(&self.carry);
+        self.carry.clear();
+        n
+    }
+
+    /// Forgets the partial frame and the bytes skipped, the stream restarts.
+    pub(crate) fn discard(&mut self) {
+        self.carry.clear();
+        self.skipped = 0;
+    }
+
+    /// Keeps the first `n` bytes of the buffer, a frame interrupted by an
+    /// error, for the next read to complete.
+    pub(crate) fn keep(&mut self, n: usize) {
+        self.carry.clear();
+        self.carry.extend_from_slice(&self.buff[..n]);
+    }
+
+    /// Checks the byte just read at `buff[*start_count]` against the frame
+    /// header, moving `start_count` to 2 once the whole header has been read.
+    ///
+    /// # Errors
+    /// `Error::SyncLost` is returned once `SYNC_LIMIT` bytes have been skipped.
+    pub(crate) fn sync(&mut self, start_count: &mut usize) -> Result<()> {
+        let expected = if *start_count == 0 {
+            SYNC_BYTE
+        } else {
+            FIRST_INDEX
+        };
+        if self.buff[*start_count] != expected {
+            self.skipped += *start_count + 1;
+            *start_count = 0;
+            if self.skipped >= SYNC_LIMIT {
+                #[cfg(feature = "defmt")]
+                defmt::error!("sync lost after {} bytes", self.skipped);
+                self.resync.record(SyncEvent::Resync(self.skipped));
+                self.skipped = 0;
+                return Err(Error::SyncLost);
+            }
+            return Ok(());
+        }
+        *start_count += 1;
+
+        if *start_count == 2 && self.skipped > 0 {
+            #[cfg(feature = "defmt")]
+            defmt::debug!("resynced after {} bytes", self.skipped);
+            self.resync.record(SyncEvent::Resync(self.skipped));
+            self.skipped = 0;
+        }
+        Ok(())
+    }
+
+    /// Realigns a frame where bytes have been lost or inserted: the packets
+    /// found after the corruption are moved back to their place, the missing
+    /// ones are left zeroed, and the beginning of the next frame, if read,
+    /// is carried over to the next read.
+    fn realign(&mut self) {
+        let header = |i: usize| [SYNC_BYTE, FIRST_INDEX + i as u8];
+        let Some(bad) =
+            (0..PACKETS_PER_FRAME).find(|&i| self.buff[i * PACKET_SIZE..][..2] != header(i))
+        else {
+            return;
+        };
+
+        let from = bad * PACKET_SIZE;
+        // A lost byte moves the next header into the previous packet.
+        let search = from.saturating_sub(PACKET_SIZE - 2);
+        // The next frame starts where its headers line up until the end of
+        // the buffer, a payload can hold a single header.
+        let starts_frame = |p: usize| {
+            (p..FRAME_SIZE)
+                .step_by(PACKET_SIZE)
+                .zip(FIRST_INDEX..)
+                .all(|(q, index)| {
+                    self.buff[q] == SYNC_BYTE && self.buff.get(q + 1).is_none_or(|&b| b == index)
+                })
+        };
+        let end = (search..FRAME_SIZE)
+            .find(|&p| starts_frame(p))
+            .unwrap_or(FRAME_SIZE);
+        self.carry.extend_from_slice(&self.buff[end..]);
+
+        let mut frame = [0u8; FRAME_SIZE];
+        frame[..from].copy_from_slice(&self.buff[..from]);
+        let (mut pos, mut next) = (search, bad);
+        while next < PACKETS_PER_FRAME && pos + PACKET_SIZE <= end {
+            let index = usize::from(self.buff[pos + 1].wrapping_sub(FIRST_INDEX));
+            if self.buff[pos] == SYNC_BYTE && (next..PACKETS_PER_FRAME).contains(&index) {
+                frame[index * PACKET_SIZE..][..PACKET_SIZE]
+                    .copy_from_slice(&self.buff[pos..pos + PACKET_SIZE]);
+                pos += PACKET_SIZE;
+                next = index + 1;
+            } else {
+                pos += 1;
+            }
+        }
+        self.buff = frame;
+    }
+
+    /// Moves the newest complete frame of `bytes`, received in a row, to the
+    /// buffer, and carries the beginning of the next one over to the next
+    /// read; the older frames are dropped. Returns `false` if there is no
+    /// complete frame, the bytes are dropped all the same.
+    pub(crate) fn latest(&mut self, bytes: &[u8]) -> bool {
+        self.discard();
+        let is_frame = |frame: &[u8]| {
+            (0..PACKETS_PER_FRAME)
+                .all(|i| frame[i * PACKET_SIZE..][..2] == [SYNC_BYTE, FIRST_INDEX + i as u8])
+        };
+        let Some(start) = (0..(bytes.len() + 1).saturating_sub(FRAME_SIZE))
+            .rev()
+            .find(|&p| is_frame(&bytes[p..p + FRAME_SIZE]))
+        else {
+            return false;
+        };
+
+        self.buff.copy_from_slice(&bytes[start..start + FRAME_SIZE]);
+        let rest = &bytes[start + FRAME_SIZE..];
+        if rest.first() == Some(&SYNC_BYTE) && rest.get(1).is_none_or(|&b| b == FIRST_INDEX) {
+            self.carry.extend_from_slice(rest);
+        }
+        true
+    }
+
+    /// Decodes the frame currently stored in the buffer, `None` while
+    /// warming up and for the scans dropped by the decimation.
+    pub(crate) fn decode(&mut self) -> Option<LaserReading> {
+        self.frame_end = Some(SystemTime::now());
+        self.hooks.emit_frame(&self.buff);
+        self.realign();
+
+        let hooks = &mut self.hooks;
+        let resync = &mut self.resync;
+        let mut bad_sets = 0;
+        let scan = decode_frame_with(&self.buff, &self.decode, |e| {
+            bad_sets += 1;
+            resync.record(SyncEvent::HeaderMismatch);
+            hooks.emit_decode_error(&e);
+        });
+
+        if bad_sets < PACKETS_PER_FRAME {
+            self.rpms = scan.rpms;
+            if let Some(watchdog) = &mut self.watchdog {
+                watchdog.fed();
+            }
+        }
+        if bad_sets > 0 && !self.degraded {
+            self.hooks.emit_event(DriverEvent::HealthDegraded {
+                bad_packets: bad_sets,
+            });
+        }
+        self.degraded = bad_sets > 0;
+
+        // The motor is still speeding up after an idle stop.
+        if self.warmup > 0 {
+            self.warmup -= 1;
+            return None;
+        }
+        self.state = DriverState::Scanning;
+        if bad_sets < PACKETS_PER_FRAME {
+            self.rpm.record(self.rpms);
+        }
+
+        let skip = self.decimated != 0;
+        self.decimated = (self.decimated + 1) % self.decimation;
+        if skip {
+            return None;
+        }
+
+        self.hooks.emit_scan(&scan);
+        Some(scan)
+    }
+}
+
+/// A command sent through a handle of the driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Command {
+    Start,
+    Stop,
+}
+
+/// Commands sent through the handles of a driver, applied by the handle
+/// holding the driver, so that they do not wait for an ongoing read.
+#[derive(Debug, Default)]
+pub(crate) struct Commands {
+    pending: Vec<Command>,
+    /// A handle holds the driver, and applies the pending commands at the
+    /// end of every frame and before releasing it.
+    held: bool,
+}
+
+impl Commands {
+    // Poisoning is ignored, the queue is consistent at any time.
+    fn lock(commands: &Mutex<Self>) -> MutexGuard<'_, Self> {
+        commands.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues `command` for the next handle holding the driver, `false` if
+    /// none holds it yet.
+    pub(crate) fn send(commands: &Mutex<Self>, command: Command) -> bool {
+        let mut commands = Self::lock(commands);
+        commands.pending.push(command);
+        commands.held
+    }
+
+    /// Gets the commands sent so far.
+    pub(crate) fn take(commands: &Mutex<Self>) -> Vec<Command> {
+        std::mem::take(&mut Self::lock(commands).pending)
+    }
+
+    /// Marks the driver as held, gets the commands sent so far.
+    fn acquire(commands: &Mutex<Self>) -> Vec<Command> {
+        let mut commands = Self::lock(commands);
+        commands.held = true;
+        std::mem::take(&mut commands.pending)
+    }
+
+    /// Gets the commands sent so far, marks the driver as released if none.
+    fn release(commands: &Mutex<Self>) -> Vec<Command> {
+        let mut commands = Self::lock(commands);
+        commands.held = !commands.pending.is_empty();
+        std::mem::take(&mut commands.pending)
+    }
+}
+
+/// A driver applying the commands sent through its handles.
+pub(crate) trait Commanded {
+    /// Applies `commands`, in the order they were sent.
+    fn apply(&mut self, commands: Vec<Command>);
+}
+
+/// A driver held by a handle, applying the commands sent meanwhile before
+/// it is released.
+pub(crate) struct Held<'a, G>
+where
+    G: DerefMut,
+    G::Target: Commanded,
+{
+    laser: G,
+    commands: &'a Mutex<Commands>,
+}
+
+impl<'a, G> Held<'a, G>
+where
+    G: DerefMut,
+    G::Target: Commanded,
+{
+    /// Holds the driver locked by `laser`, applying the pending commands.
+    pub(crate) fn new(mut laser: G, commands: &'a Mutex<Commands>) -> Self {
+        laser.apply(Commands::acquire(commands));
+        Self { laser, commands }
+    }
+}
+
+impl<G> Deref for Held<'_, G>
+where
+    G: DerefMut,
+    G::Target: Commanded,
+{
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.laser
+    }
+}
+
+impl<G> DerefMut for Held<'_, G>
+where
+    G: DerefMut,
+    G::Target: Commanded,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.laser
+    }
+}
+
+impl<G> Drop for Held<'_, G>
+where
+    G: DerefMut,
+    G::Target: Commanded,
+{
+    fn drop(&mut self) {
+        loop {
+            let pending = Commands::release(self.commands);
+            if pending.is_empty() {
+                break;
+            }
+            self.laser.apply(pending);
+        }
+    }
+}
+
+/// Implements the methods that do not depend on the serial port type.
+///
+/// The backend must have a `core: Core` field, a `serial` field holding the
+/// port, a `fn write_byte(&mut self, byte: u8)` method and a
+/// `fn clear_input(&mut self) -> std::io::Result<()>` method discarding the
+/// input buffer of the OS.
+macro_rules! impl_common {
+    ($laser:ty) => {
+        impl $crate::common::Commanded for $laser {
+            fn apply(&mut self, commands: Vec<$crate::common::Command>) {
+                for command in commands {
+                    match command {
+                        $crate::common::Command::Start => self.start(),
+                        $crate::common::Command::Stop => self.close(),
+                    }
+                }
+            }
+        }
+
+        impl $laser {
+            /// Stops the lidar and marks the driver as closed.
+            pub fn close(&mut self) {
+                if self.core.state != $crate::state::DriverState::Closed {
+                    self.core.state = $crate::state::DriverState::Closed;
+                    self.core
+                        .hooks
+                        .emit_event($crate::state::DriverEvent::Stopped);
+                }
+
+                // Stopping the Lidar, ignoring the result.
+                self.write_byte($crate::protocol::STOP_BYTE);
+            }
+
+            /// Gets lidar speed.
+            pub fn speed(&self) -> u16 {
+                self.core.motor_speed
+            }
+
+            /// Sets the requested motor speed.
+            ///
+            /// The LDS-01 does not accept speed commands over the serial port,
+            /// the value is only recorded and returned by `speed`.
+            pub fn set_speed(&mut self, speed: u16) {
+                self.core.motor_speed = speed;
+            }
+
+            /// Gets the configured baud rate
+            pub fn baud_rate(&self) -> u32 {
+                self.core.baud_rate
+            }
+
+            /// Gets the configured serial port
+            pub fn port(&self) -> &std::path::Path {
+                &self.core.port
+            }
+
+            /// Gets the persistent `/dev/serial/by-id` or `by-path` link of the port,
+            /// to store in the configuration instead of the port name.
+            ///
+            /// # Errors
+            /// An error variant is returned if the port or the links cannot be read.
+            #[cfg(target_os = "linux")]
+            pub fn stable_path(&self) -> std::io::Result<Option<std::path::PathBuf>> {
+                $crate::devices::stable_path(&self.core.port)
+            }
+
+            /// Checks if the scans are mirrored, for a lidar mounted upside down.
+            pub fn mirrored(&self) -> bool {
+                self.core.decode.mirrored
+            }
+
+            /// Sets whether the scans are mirrored, reversing the angular ordering
+            /// of the beams so that a lidar mounted upside down still gives
+            /// counter-clockwise angles.
+            pub fn set_mirrored(&mut self, mirrored: bool) {
+                self.core.decode.mirrored = mirrored;
+            }
+
+            /// Gets the per-degree correction applied to the ranges, if any.
+            pub fn calibration(&self) -> Option<&$crate::calibration::Calibration> {
+                self.core.decode.calibration.as_ref()
+            }
+
+            /// Sets the per-degree correction applied to the ranges while parsing,
+            /// `None` to disable it.
+            pub fn set_calibration(
+                &mut self,
+                calibration: Option<$crate::calibration::Calibration>,
+            ) {
+                self.core.decode.calibration = calibration;
+            }
+
+            /// Gets the reserved bytes of every packet of the last frame read,
+            /// which the driver does not interpret, for protocol investigations.
+            pub fn reserved_bytes(
+                &self,
+            ) -> [[u8; $crate::protocol::RESERVED_PER_PACKET]; $crate::protocol::PACKETS_PER_FRAME]
+            {
+                $crate::protocol::frame_reserved_bytes(&self.core.buff)
+            }
+
+            /// Gets the RPMs reported by every packet of the last frame read,
+            /// from the first to the last, 0 for the skipped packets.
+            pub fn packet_rpms(&self) -> [u16; $crate::protocol::PACKETS_PER_FRAME] {
+                $crate::protocol::frame_rpms(&self.core.buff)
+            }
+
+            /// Gets the RPMs of the last frame read interpolated to every
+            /// beam, to detect and compensate the speed changing within a
+            /// revolution, see `protocol::degree_rpms`.
+            pub fn degree_rpms(&self) -> [f32; 360] {
+                let rpms = $crate::protocol::degree_rpms(&self.packet_rpms());
+                if !self.core.decode.mirrored {
+                    return rpms;
+                }
+                let mut mirrored = [0f32; 360];
+                for (degree, speed) in rpms.into_iter().enumerate() {
+                    mirrored[(360 - degree) % 360] = speed;
+                }
+                mirrored
+            }
+
+            /// Gets the estimated capture time of the first beam of the last
+            /// frame read, a revolution before it was read; `None` before
+            /// the first frame or while the motor is stopped.
+            pub fn scan_start(&self) -> Option<std::time::SystemTime> {
+                let period = $crate::protocol::scan_time(self.core.rpms);
+                if period == 0.0 {
+                    return None;
+                }
+                self.core
+                    .frame_end?
+                    .checked_sub(std::time::Duration::from_secs_f32(period))
+            }
+
+            /// Gets the estimated capture time of every beam of the last
+            /// frame read, indexed like `LaserReading::ranges`, following
+            /// the speed of the motor within the revolution, see `timing`.
+            pub fn beam_times(&self) -> Option<[std::time::SystemTime; 360]> {
+                let start = self.scan_start()?;
+                let offsets = $crate::timing::beam_offsets_with(
+                    &self.degree_rpms(),
+                    self.core.decode.mirrored,
+                );
+                Some(offsets.map(|offset| start + offset))
+            }
+
+            /// Gets the number of times the driver lost the stream, and when.
+            pub fn resync_stats(&self) -> $crate::diagnostics::ResyncStats {
+                self.core.resync.clone()
+            }
+
+            /// Resets the resync statistics.
+            pub fn reset_resync_stats(&mut self) {
+                self.core.resync = $crate::diagnostics::ResyncStats::new();
+            }
+
+            /// Gets the statistics of the RPMs over the last scans, `None`
+            /// before the first scan.
+            pub fn rpm_stats(&self) -> Option<$crate::diagnostics::RpmStats> {
+                self.core.rpm.stats()
+            }
+
+            /// Sets the number of scans the RPM statistics are computed
+            /// over, by default `diagnostics::DEFAULT_RPM_WINDOW`.
+            pub fn set_rpm_window(&mut self, scans: usize) {
+                self.core.rpm.set_window(scans);
+            }
+
+            /// Resets the RPM statistics.
+            pub fn reset_rpm_stats(&mut self) {
+                self.core.rpm.clear();
+            }
+
+            /// Gets the decimation, one scan out of this number is returned.
+            pub fn decimation(&self) -> usize {
+                self.core.decimation
+            }
+
+            /// Returns only one scan out of `n`, 1 for every scan. Every frame is
+            /// still parsed, so the driver stays in sync and the scans returned
+            /// are always the latest ones.
+            pub fn set_decimation(&mut self, n: usize) {
+                self.core.decimation = n.max(1);
+            }
+
+            /// Creates a driver configured by a TOML file, see `config::Config`.
+            ///
+            /// # Errors
+            /// An error variant is returned if the file cannot be loaded, or
+            /// the port opened and configured.
+            #[cfg(feature = "config")]
+            pub fn from_config<P: AsRef<std::path::Path>>(
+                path: P,
+            ) -> std::result::Result<Self, $crate::config::ConfigError> {
+                Self::with_config(&$crate::config::Config::load(path)?)
+            }
+
+            /// Creates a driver from a configuration.
+            ///
+            /// # Errors
+            /// An error variant is returned if the port cannot be opened and configured.
+            #[cfg(feature = "config")]
+            pub fn with_config(
+                config: &$crate::config::Config,
+            ) -> std::result::Result<Self, $crate::config::ConfigError> {
+                let mut laser = Self::open_config(config)?;
+                laser.apply_config(config)?;
+                Ok(laser)
+            }
+
+            /// Applies the filters, the calibration and the idle stop of a
+            /// configuration, the port and the timeouts need a new driver.
+            ///
+            /// # Errors
+            /// An error variant is returned if the calibration cannot be loaded
+            /// or the idle stop enabled.
+            #[cfg(feature = "config")]
+            pub fn apply_config(
+                &mut self,
+                config: &$crate::config::Config,
+            ) -> std::result::Result<(), $crate::config::ConfigError> {
+                self.set_mirrored(config.filters.mirrored);
+                self.set_decimation(config.filters.decimation);
+                self.set_calibration(config.load_calibration()?);
+                #[cfg(unix)]
+                self.set_idle_stop(config.idle_options())?;
+                Ok(())
+            }
+
+            /// Applies the filters and the calibration of a reloaded configuration,
+            /// between two reads; the changes needing a new driver are ignored,
+            /// see `Reload::needs_restart`.
+            ///
+            /// # Errors
+            /// An error variant is returned if the calibration cannot be loaded,
+            /// the driver is left unchanged.
+            #[cfg(feature = "config_watch")]
+            pub fn reload_config(
+                &mut self,
+                reload: &$crate::config::Reload,
+            ) -> std::result::Result<(), $crate::config::ConfigError> {
+                let calibration = reload.config.load_calibration()?;
+                if reload.filters_changed() {
+                    self.set_mirrored(reload.config.filters.mirrored);
+                    self.set_decimation(reload.config.filters.decimation);
+                }
+                self.set_calibration(calibration);
+                Ok(())
+            }
+
+            /// Gets the state of the driver.
+            pub fn state(&self) -> $crate::state::DriverState {
+                use $crate::state::DriverState;
+
+                match self.core.state {
+                    DriverState::Stopped | DriverState::Closed => self.core.state,
+                    _ if self.core.idle.as_ref().is_some_and(|i| i.is_stopped()) => {
+                        DriverState::Paused
+                    }
+                    state => state,
+                }
+            }
+
+            /// Gets the lidars rmp from the last reading
+            pub fn rpms(&self) -> u16 {
+                self.core.rpms
+            }
+
+            /// Gets the time of a revolution, in seconds, from the last reading
+            pub fn scan_time(&self) -> f32 {
+                $crate::protocol::scan_time(self.core.rpms)
+            }
+
+            /// Starts the Lidar
+            pub fn start(&mut self) {
+                // Bytes received before the motor stopped would corrupt the first scan.
+                self.purge_input().ok();
+                // Starting the Lidar
+                self.write_byte($crate::protocol::START_BYTE);
+                if let Some(idle) = &self.core.idle {
+                    idle.started();
+                }
+                if let Some(watchdog) = &mut self.core.watchdog {
+                    watchdog.started();
+                }
+                // Stopped again by the next read if out of the window.
+                if let Some(duty) = &mut self.core.duty {
+                    duty.paused = false;
+                }
+
+                self.core.state = $crate::state::DriverState::Starting;
+                self.core
+                    .hooks
+                    .emit_event($crate::state::DriverEvent::Started);
+            }
+
+            /// Discards the bytes received and not read yet, by the OS and by
+            /// the driver, so that the next scan is made of fresh data only.
+            /// Done by `start` and when the idle stop starts the motor again.
+            ///
+            /// # Errors
+            /// An error variant is returned if the OS buffer cannot be cleared.
+            pub fn purge_input(&mut self) -> std::io::Result<()> {
+                self.core.discard();
+                self.clear_input()
+            }
+
+            /// Stops the motor once the driver has not been read for
+            /// `options.timeout`, the next read starts it again and skips the
+            /// scans of the warm-up. `None` keeps the motor running.
+            /// Only on unix.
+            ///
+            /// # Errors
+            /// An error variant is returned if the port cannot be shared with
+            /// the thread stopping the motor.
+            #[cfg(unix)]
+            pub fn set_idle_stop(
+                &mut self,
+                options: Option<$crate::idle::IdleOptions>,
+            ) -> std::io::Result<()> {
+                use std::os::fd::{AsRawFd, BorrowedFd};
+
+                // Stops the previous monitor first.
+                self.core.idle = None;
+                if let Some(options) = options {
+                    // SAFETY: the descriptor is open as long as `serial`, and
+                    // duplicated right away.
+                    let port = unsafe { BorrowedFd::borrow_raw(self.serial.as_raw_fd()) }
+                        .try_clone_to_owned()?;
+                    self.core.idle = Some($crate::idle::IdleMonitor::spawn(
+                        std::fs::File::from(port),
+                        options,
+                    )?);
+                }
+                Ok(())
+            }
+
+            /// Gets the options of the idle stop, `None` if disabled.
+            pub fn idle_stop(&self) -> Option<$crate::idle::IdleOptions> {
+                self.core.idle.as_ref().map(|idle| idle.options())
+            }
+
+            /// Restarts the motor once no frame has been decoded for
+            /// `options.interval`, instead of waiting for it forever, see
+            /// `watchdog`. `None` disables the watchdog.
+            pub fn set_watchdog(&mut self, options: Option<$crate::watchdog::WatchdogOptions>) {
+                self.core.watchdog = options.map($crate::watchdog::Watchdog::new);
+            }
+
+            /// Gets the options of the watchdog, `None` if disabled.
+            pub fn watchdog(&self) -> Option<$crate::watchdog::WatchdogOptions> {
+                self.core.watchdog.as_ref().map(|watchdog| watchdog.options)
+            }
+
+            /// Gets the number of times the watchdog restarted the motor.
+            pub fn motor_restarts(&self) -> u64 {
+                self.core
+                    .watchdog
+                    .as_ref()
+                    .map_or(0, |watchdog| watchdog.total)
+            }
+
+            /// Handles a read finding no frame in time: stops the motor if
+            /// the watchdog says so and returns the pause before
+            /// `restart_stalled`, `None` to keep waiting; the error failing
+            /// the read without the watchdog or after too many restarts.
+            fn watchdog_timeout(
+                &mut self,
+            ) -> std::result::Result<Option<std::time::Duration>, $crate::Error> {
+                use $crate::watchdog::Verdict;
+
+                let Some(watchdog) = &mut self.core.watchdog else {
+                    return Err($crate::Error::Timeout);
+                };
+                match watchdog.check() {
+                    Verdict::Wait => Ok(None),
+                    Verdict::GiveUp => Err($crate::Error::Timeout),
+                    Verdict::Restart => {
+                        let pause = watchdog.options.pause;
+                        self.write_byte($crate::protocol::STOP_BYTE);
+                        Ok(Some(pause))
+                    }
+                }
+            }
+
+            /// Starts the motor stopped by `watchdog_timeout` again.
+            fn restart_stalled(&mut self) {
+                self.purge_input().ok();
+                self.write_byte($crate::protocol::START_BYTE);
+                let Some(watchdog) = &mut self.core.watchdog else {
+                    return;
+                };
+                watchdog.started();
+                let restarts = watchdog.restarts;
+                self.core.warmup = watchdog.options.warmup_scans;
+                self.core.state = $crate::state::DriverState::Starting;
+                // Speeding up again, the timeouts fall back until it spins.
+                self.core.rpms = 0;
+                self.core
+                    .hooks
+                    .emit_event($crate::state::DriverEvent::MotorRestarted { restarts });
+            }
+
+            /// Makes the reads return only the scans of the windows of
+            /// `options`, stopping the motor in between, see `duty`. The first
+            /// window starts now. `None` keeps the lidar scanning.
+            pub fn set_duty_cycle(&mut self, options: Option<$crate::duty::DutyCycle>) {
+                self.resume_duty();
+                self.core.duty = options.map($crate::duty::Schedule::new);
+            }
+
+            /// Gets the duty cycle, `None` if the lidar scans all the time.
+            pub fn duty_cycle(&self) -> Option<$crate::duty::DutyCycle> {
+                self.core.duty.as_ref().map(|duty| duty.options)
+            }
+
+            /// Stops the motor out of the window of the duty cycle, returns
+            /// the time left until the next one.
+            fn pause_duty(&mut self) -> Option<std::time::Duration> {
+                let duty = self.core.duty.as_mut()?;
+                let wait = duty.until_window()?;
+                if !duty.paused {
+                    duty.paused = true;
+                    self.write_byte($crate::protocol::STOP_BYTE);
+                    self.core.state = $crate::state::DriverState::Paused;
+                    self.core
+                        .hooks
+                        .emit_event($crate::state::DriverEvent::Paused);
+                }
+                Some(wait)
+            }
+
+            /// Applies the commands sent through the handles of the driver
+            /// since the last frame.
+            ///
+            /// # Errors
+            /// `DriverClosed` once one of them stopped the lidar.
+            fn apply_commands(&mut self) -> $crate::error::Result<()> {
+                if let Some(commands) = &self.core.commands {
+                    let pending = $crate::common::Commands::take(commands);
+                    $crate::common::Commanded::apply(self, pending);
+                }
+                if self.core.closed() {
+                    return Err($crate::error::Error::DriverClosed);
+                }
+                Ok(())
+            }
+
+            /// Starts the motor stopped by `pause_duty` again.
+            fn resume_duty(&mut self) {
+                let Some(duty) = &mut self.core.duty else {
+                    return;
+                };
+                if !std::mem::replace(&mut duty.paused, false) {
+                    return;
+                }
+                self.core.warmup = duty.options.warmup_scans;
+                self.purge_input().ok();
+                self.write_byte($crate::protocol::START_BYTE);
+                if let Some(watchdog) = &mut self.core.watchdog {
+                    watchdog.started();
+                }
+                self.core.state = $crate::state::DriverState::Starting;
+                // Speeding up again, the timeouts fall back until it spins.
+                self.core.rpms = 0;
+                self.core
+                    .hooks
+                    .emit_event($crate::state::DriverEvent::Started);
+            }
+
+            /// Checks if a scan completed now belongs to a window of the duty cycle.
+            fn in_window(&self) -> bool {
+                self.core
+                    .duty
+                    .as_ref()
+                    .is_none_or(|duty| duty.until_window().is_none())
+            }
+
+            /// Starts the motor again if it has been stopped for being idle,
+            /// the returned guard keeps it running until the read completes.
+            fn wake_up(&mut self) -> Option<$crate::idle::Busy> {
+                let (busy, stopped) = self.core.idle.as_ref()?.enter();
+                if stopped {
+                    self.purge_input().ok();
+                    self.write_byte($crate::protocol::START_BYTE);
+                    self.core.state = $crate::state::DriverState::Starting;
+                    self.core
+                        .hooks
+                        .emit_event($crate::state::DriverEvent::Started);
+                    // Speeding up again, the timeouts fall back until it spins.
+                    self.core.rpms = 0;
+                    self.core.warmup = self.idle_stop().map_or(0, |o| o.warmup_scans);
+                    if let Some(watchdog) = self.core.watchdog.as_mut() {
+                        watchdog.started();
+                    }
+                }
+                Some(busy)
+            }
+
+            /// Gets the callbacks registered on this driver.
+            pub fn hooks(&mut self) -> &mut $crate::Hooks {
+                &mut self.core.hooks
+            }
+
+            /// Gets a lock-free snapshot updated with every scan read by this driver.
+            pub fn snapshot(&mut self) -> $crate::snapshot::ScanSnapshot {
+                $crate::snapshot::ScanSnapshot::attach(&mut self.core.hooks)
+            }
+
+            /// Registers a callback invoked for every raw frame, before it is decoded.
+            pub fn on_frame<F>(&mut self, f: F)
+            where
+                F: FnMut(&[u8]) + Send + 'static,
+            {
+                self.core.hooks.on_frame(f);
+            }
+
+            /// Registers a callback invoked with every chunk of bytes read from
+            /// the serial port, before any parsing.
+            pub fn on_bytes<F>(&mut self, f: F)
+            where
+                F: FnMut(&[u8]) + Send + 'static,
+            {
+                self.core.hooks.on_bytes(f);
+            }
+
+            /// Copies every byte read from the serial port to `w`, e.g. to capture
+            /// the raw stream in production while scans are still read. A failed
+            /// write stops the copy, the reads are not affected.
+            pub fn set_tee<W>(&mut self, w: W)
+            where
+                W: std::io::Write + Send + 'static,
+            {
+                let mut w = Some(w);
+                self.core.hooks.on_bytes(move |bytes| {
+                    if let Some(out) = w.as_mut() {
+                        if out.write_all(bytes).is_err() {
+                            w = None;
+                        }
+                    }
+                });
+            }
+
+            /// Registers a callback invoked for every complete scan.
+            pub fn on_scan<F>(&mut self, f: F)
+            where
+                F: FnMut(&$crate::LaserReading) + Send + 'static,
+            {
+                self.core.hooks.on_scan(f);
+            }
+
+            /// Registers a callback invoked for every packet that fails to decode.
+            pub fn on_decode_error<F>(&mut self, f: F)
+            where
+                F: FnMut(&$crate::DecodeError) + Send + 'static,
+            {
+                self.core.hooks.on_decode_error(f);
+            }
+
+            /// Registers a callback invoked after the serial port has been re-opened
+            /// by `reconnect`.
+            pub fn on_reconnect<F>(&mut self, f: F)
+            where
+                F: FnMut(&str) + Send + 'static,
+            {
+                self.core.hooks.on_reconnect(f);
+            }
+
+            /// Registers a callback invoked for every change of the state of
+            /// the driver, see `state::DriverEvent`.
+            pub fn on_event<F>(&mut self, f: F)
+            where
+                F: FnMut(&$crate::state::DriverEvent) + Send + 'static,
+            {
+                self.core.hooks.on_event(f);
+            }
+
+            /// Gets a channel receiving the changes of the state of the
+            /// driver, until the driver is dropped. Dropping the receiver
+            /// unsubscribes it.
+            pub fn subscribe(&mut self) -> std::sync::mpsc::Receiver<$crate::state::DriverEvent> {
+                self.core.hooks.subscribe()
+            }
+        }
+
+        impl Drop for $laser {
+            fn drop(&mut self) {
+                self.close();
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_scan_eq, fixtures};
+
+    fn core() -> Core {
+        Core::new(PathBuf::from("/dev/null"), 230400)
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<Command>);
+
+    impl Commanded for Recorder {
+        fn apply(&mut self, commands: Vec<Command>) {
+            self.0.extend(commands);
+        }
+    }
+
+    #[test]
+    fn applies_the_commands_sent_while_held() {
+        let commands = Mutex::new(Commands::default());
+        let laser = Mutex::new(Recorder::default());
+        assert!(!Commands::send(&commands, Command::Start));
+
+        {
+            // Applied when the driver is taken.
+            let mut held = Held::new(laser.lock().unwrap(), &commands);
+            assert_eq!(held.0, [Command::Start]);
+            assert!(Commands::send(&commands, Command::Stop));
+            // Then at the end of the frame being read.
+            let pending = Commands::take(&commands);
+            held.apply(pending);
+            assert_eq!(held.0, [Command::Start, Command::Stop]);
+            assert!(Commands::send(&commands, Command::Start));
+        }
+        // Then before the driver is released.
+        assert_eq!(
+            laser.lock().unwrap().0,
+            [Command::Start, Command::Stop, Command::Start]
+        );
+        assert!(!Commands::send(&commands, Command::Stop));
+    }
+
+    #[test]
+    fn decodes_the_fixtures() {
+        let mut core = core();
+        for fixture in fixtures() {
+            core.buff.copy_from_slice(&fixture.frame);
+            let scan = core.decode().unwrap();
+            assert_scan_eq(&scan, &fixture.expected);
+            assert_eq!(core.rpms, fixture.expected.rpms);
+            assert_eq!(core.state, DriverState::Scanning);
+        }
+    }
+
+    #[test]
+    fn reports_the_corrupted_packet_once() {
+        let mut core = core();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        core.hooks
+            .on_event(move |event| sink.lock().unwrap().push(*event));
+
+        let corrupted = &fixtures()[3];
+        for _ in 0..2 {
+            core.buff.copy_from_slice(&corrupted.frame);
+            assert_scan_eq(&core.decode().unwrap(), &corrupted.expected);
+        }
+        assert_eq!(
+            *events.lock().unwrap(),
+            [DriverEvent::HealthDegraded { bad_packets: 1 }]
+        );
+        assert_eq!(core.resync.header_mismatches, 2);
+    }
+
+    #[test]
+    fn drops_the_warmup_and_decimated_scans() {
+        let mut core = core();
+        core.warmup = 2;
+        core.decimation = 2;
+        let room = &fixtures()[0];
+        let returned: Vec<bool> = (0..6)
+            .map(|_| {
+                core.buff.copy_from_slice(&room.frame);
+                core.decode().is_some()
+            })
+            .collect();
+        assert_eq!(returned, [false, false, true, false, true, false]);
+    }
+
+    /// Two room frames in a row, edited by `corrupt`, the first frame
+    /// decoded and the bytes carried over returned.
+    fn realign(corrupt: impl FnOnce(&mut Vec<u8>)) -> (LaserReading, Vec<u8>) {
+        let room = &fixtures()[0];
+        let mut stream = room.frame.repeat(2);
+        corrupt(&mut stream);
+        let mut core = core();
+        core.buff.copy_from_slice(&stream[..FRAME_SIZE]);
+        (core.decode().unwrap(), core.carry)
+    }
+
+    /// Asserts that the beams of every packet but `lost` are the ones of the room.
+    #[track_caller]
+    fn assert_packets(scan: &LaserReading, lost: &[usize]) {
+        let room = &fixtures()[0].expected;
+        for packet in (0..PACKETS_PER_FRAME).filter(|p| !lost.contains(p)) {
+            for n in 0..6 {
+                let degree = 359 - (6 * packet + n);
+                assert_eq!(scan.ranges[degree], room.ranges[degree], "packet {packet}");
+            }
+        }
+    }
+
+    #[test]
+    fn realigns_after_a_lost_byte() {
+        let (scan, carry) = realign(|stream| {
+            stream.remove(10 * PACKET_SIZE + 20);
+        });
+        // The packet losing the byte is garbled, the next frame starts with
+        // the last byte of the buffer.
+        assert_packets(&scan, &[10]);
+        assert_eq!(carry, [SYNC_BYTE]);
+    }
+
+    #[test]
+    fn realigns_after_an_inserted_byte() {
+        let (scan, carry) = realign(|stream| {
+            stream.insert(10 * PACKET_SIZE + 20, 0x55);
+        });
+        // The last packet is pushed out of the buffer.
+        assert_packets(&scan, &[10, 59]);
+        assert!((0..6).all(|n| scan.ranges[n] == 0));
+        assert!(carry.is_empty());
+    }
+
+    #[test]
+    fn ignores_a_header_in_the_payload() {
+        let (scan, carry) = realign(|stream| {
+            // The intensity of a beam of packet 30 reads `FA A0`.
+            stream[30 * PACKET_SIZE + 4..][..2].copy_from_slice(&[SYNC_BYTE, FIRST_INDEX]);
+            stream.remove(10 * PACKET_SIZE + 20);
+        });
+        assert_packets(&scan, &[10]);
+        assert_eq!(carry, [SYNC_BYTE]);
+    }
+
+    #[test]
+    fn keeps_the_newest_frame() {
+        let mut core = core();
+        let fixtures = fixtures();
+        let mut bytes = fixtures[0].frame.clone();
+        bytes.extend_from_slice(&fixtures[2].frame);
+        bytes.extend_from_slice(&fixtures[1].frame[..10]);
+
+        assert!(core.latest(&bytes));
+        assert_scan_eq(&core.decode().unwrap(), &fixtures[2].expected);
+        assert_eq!(core.carry, fixtures[1].frame[..10]);
+        assert!(!core.latest(&bytes[..FRAME_SIZE - 1]));
+    }
+}
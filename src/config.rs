@@ -0,0 +1,495 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Configuration of the driver from a TOML file, enabled by the `config`
+//! feature, see `LFCDLaser::from_config`.
+//!
+//! Every key is optional, the missing ones take the default of the driver.
+//! Durations are in seconds, and a relative calibration path is relative to
+//! the configuration file. The sinks are not started by the driver, they
+//! are listed for the application to start, e.g. with `HttpServer::bind`.
+//!
+//! With the `config_watch` feature a `ConfigWatcher` reloads the file when
+//! it changes, and `LFCDLaser::reload_config` applies the filters and the
+//! calibration between two reads, without restarting the driver:
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "config_watch", feature = "sync"))]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use hls_lfcd_lds_driver::config::ConfigWatcher;
+//! use hls_lfcd_lds_driver::sync::LFCDLaser;
+//!
+//! let mut watcher = ConfigWatcher::new("/etc/lds/lds.toml")?;
+//! let mut laser = LFCDLaser::with_config(watcher.config())?;
+//! loop {
+//!     let reading = laser.read()?;
+//!     match watcher.poll() {
+//!         Some(Ok(reload)) => laser.reload_config(&reload)?,
+//!         // The previous configuration stays in use.
+//!         Some(Err(e)) => eprintln!("invalid configuration: {e}"),
+//!         None => {}
+//!     }
+//! }
+//! # }
+//! # #[cfg(not(all(feature = "config_watch", feature = "sync")))]
+//! # fn main() {}
+//! ```
+//!
+//! ```
+//! use hls_lfcd_lds_driver::config::Config;
+//!
+//! let config = Config::from_toml(
+//!     r#"
+//!     port = "/dev/serial/by-id/usb-Silicon_Labs_CP2102-if00-port0"
+//!
+//!     [timeouts]
+//!     read = 0.5
+//!     idle_stop = 30.0
+//!
+//!     [filters]
+//!     mirrored = true
+//!     decimation = 2
+//!
+//!     [sinks]
+//!     http = "0.0.0.0:8080"
+//!     "#,
+//! )?;
+//! assert_eq!(config.baud_rate, 230400);
+//! assert_eq!(config.sinks.http.as_deref(), Some("0.0.0.0:8080"));
+//! # Ok::<(), hls_lfcd_lds_driver::config::ConfigError>(())
+//! ```
+
+use crate::calibration::Calibration;
+use crate::idle::IdleOptions;
+use crate::run::RunPolicy;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Configuration of a driver and of its outputs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Serial port of the lidar
+    pub port: String,
+    /// Baud rate of the serial port
+    pub baud_rate: u32,
+    /// Timeouts of the driver
+    pub timeouts: Timeouts,
+    /// Processing of the scans by the driver
+    pub filters: Filters,
+    /// File holding the per-degree correction, `.toml` or `.json`
+    pub calibration: Option<PathBuf>,
+    /// File holding the zones, see `zones::ZoneMonitor`
+    pub zones: Option<PathBuf>,
+    /// Outputs to start
+    pub sinks: Sinks,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: crate::DEFAULT_PORT.into(),
+            baud_rate: 230400,
+            timeouts: Timeouts::default(),
+            filters: Filters::default(),
+            calibration: None,
+            zones: None,
+            sinks: Sinks::default(),
+        }
+    }
+}
+
+/// Timeouts of the driver, in seconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// Time a read may take until the RPMs are known
+    pub read: f64,
+    /// Delay before every reconnection
+    pub reconnect_delay: f64,
+    /// Time without reads after which the motor is stopped, only on unix
+    pub idle_stop: Option<f64>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            read: 1.0,
+            reconnect_delay: 1.0,
+            idle_stop: None,
+        }
+    }
+}
+
+/// Processing of the scans by the driver.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Filters {
+    /// Reverses the angular ordering, for a lidar mounted upside down
+    pub mirrored: bool,
+    /// Returns only one scan out of `decimation`
+    pub decimation: usize,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Self {
+            mirrored: false,
+            decimation: 1,
+        }
+    }
+}
+
+/// Outputs started by the application, `None` when disabled.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sinks {
+    /// Address of the Foxglove WebSocket server, `foxglove` feature
+    pub foxglove: Option<String>,
+    /// Address of the HTTP server, `http` feature
+    pub http: Option<String>,
+    /// URL of the rosbridge server, `rosbridge` feature
+    pub rosbridge: Option<String>,
+}
+
+/// Errors while loading a configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    /// The calibration file is neither `.toml` nor `.json`
+    UnknownFormat(PathBuf),
+    /// The driver could not be opened
+    Driver(crate::Error),
+    #[cfg(feature = "zones")]
+    Zones(crate::zones::ZoneError),
+    #[cfg(feature = "config_watch")]
+    Watch(notify::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "IO error: {e}"),
+            ConfigError::Toml(e) => write!(f, "TOML error: {e}"),
+            ConfigError::Json(e) => write!(f, "JSON error: {e}"),
+            ConfigError::UnknownFormat(p) => {
+                write!(f, "Unknown calibration file format: {}", p.display())
+            }
+            ConfigError::Driver(e) => write!(f, "Driver error: {e}"),
+            #[cfg(feature = "zones")]
+            ConfigError::Zones(e) => write!(f, "Zones error: {e}"),
+            #[cfg(feature = "config_watch")]
+            ConfigError::Watch(e) => write!(f, "Watch error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Toml(e)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> Self {
+        ConfigError::Json(e)
+    }
+}
+
+impl From<crate::Error> for ConfigError {
+    fn from(e: crate::Error) -> Self {
+        ConfigError::Driver(e)
+    }
+}
+
+#[cfg(feature = "zones")]
+impl From<crate::zones::ZoneError> for ConfigError {
+    fn from(e: crate::zones::ZoneError) -> Self {
+        ConfigError::Zones(e)
+    }
+}
+
+#[cfg(feature = "config_watch")]
+impl From<notify::Error> for ConfigError {
+    fn from(e: notify::Error) -> Self {
+        ConfigError::Watch(e)
+    }
+}
+
+// Negative durations, e.g. a typo in the file, are 0 instead of panicking.
+fn seconds(s: f64) -> Duration {
+    Duration::try_from_secs_f64(s).unwrap_or(Duration::ZERO)
+}
+
+impl Config {
+    /// Parses the content of a TOML file.
+    ///
+    /// # Errors
+    /// An error variant is returned if the content is not a valid configuration.
+    pub fn from_toml(s: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(s)?)
+    }
+
+    /// Loads a TOML file.
+    ///
+    /// # Errors
+    /// An error variant is returned if the file cannot be read or is not a
+    /// valid configuration.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let mut config = Self::from_toml(&std::fs::read_to_string(path)?)?;
+        if let Some(dir) = path.parent() {
+            let files = [&mut config.calibration, &mut config.zones];
+            for file in files.into_iter().flatten() {
+                *file = dir.join(&*file);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Loads the calibration file, if any.
+    ///
+    /// # Errors
+    /// An error variant is returned if the file cannot be read, has another
+    /// extension or is not a valid calibration.
+    pub fn load_calibration(&self) -> Result<Option<Calibration>, ConfigError> {
+        let Some(path) = &self.calibration else {
+            return Ok(None);
+        };
+        let content = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(Some(toml::from_str(&content)?)),
+            Some("json") => Ok(Some(serde_json::from_str(&content)?)),
+            _ => Err(ConfigError::UnknownFormat(path.clone())),
+        }
+    }
+
+    /// Loads the zone file, if any.
+    ///
+    /// # Errors
+    /// An error variant is returned if the file cannot be loaded.
+    #[cfg(feature = "zones")]
+    pub fn load_zones(&self) -> Result<Option<crate::zones::ZoneMonitor>, ConfigError> {
+        match &self.zones {
+            Some(path) => Ok(Some(crate::zones::ZoneMonitor::load(path)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets the read timeout used until the RPMs are known.
+    pub fn read_timeout(&self) -> Duration {
+        seconds(self.timeouts.read)
+    }
+
+    /// Gets the options of the idle stop, if enabled.
+    pub fn idle_options(&self) -> Option<IdleOptions> {
+        self.timeouts.idle_stop.map(|timeout| IdleOptions {
+            timeout: seconds(timeout),
+            ..Default::default()
+        })
+    }
+
+    /// Gets the policy of `LFCDLaser::run` with the configured timeouts.
+    pub fn run_policy(&self) -> RunPolicy {
+        RunPolicy {
+            reconnect_delay: seconds(self.timeouts.reconnect_delay),
+            read_timeout: self.read_timeout(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(feature = "config_watch")]
+pub use self::watch::{ConfigWatcher, Reload};
+
+#[cfg(feature = "config_watch")]
+mod watch {
+    use super::{Config, ConfigError};
+    use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::{self, Receiver};
+
+    /// A configuration reloaded from its file.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Reload {
+        /// The configuration in use before the reload
+        pub previous: Config,
+        /// The new configuration
+        pub config: Config,
+    }
+
+    impl Reload {
+        /// Checks if the filters changed.
+        pub fn filters_changed(&self) -> bool {
+            self.previous.filters != self.config.filters
+        }
+
+        /// Checks if the calibration file changed, only its path is compared.
+        pub fn calibration_changed(&self) -> bool {
+            self.previous.calibration != self.config.calibration
+        }
+
+        /// Checks if the zone file changed, only its path is compared.
+        pub fn zones_changed(&self) -> bool {
+            self.previous.zones != self.config.zones
+        }
+
+        /// Checks if the sinks changed, the application has to restart them.
+        pub fn sinks_changed(&self) -> bool {
+            self.previous.sinks != self.config.sinks
+        }
+
+        /// Checks if the port, the baud rate or the timeouts changed, which
+        /// are only applied by a new driver.
+        pub fn needs_restart(&self) -> bool {
+            self.previous.port != self.config.port
+                || self.previous.baud_rate != self.config.baud_rate
+                || self.previous.timeouts != self.config.timeouts
+        }
+    }
+
+    /// Watches a configuration file, reloading it when it changes.
+    pub struct ConfigWatcher {
+        path: PathBuf,
+        config: Config,
+        events: Receiver<()>,
+        // Stops watching when dropped.
+        _watcher: RecommendedWatcher,
+    }
+
+    impl ConfigWatcher {
+        /// Loads a configuration file and starts watching it.
+        ///
+        /// # Errors
+        /// An error variant is returned if the file cannot be loaded or watched.
+        pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+            let path = path.as_ref().to_path_buf();
+            let config = Config::load(&path)?;
+
+            // Editors often replace the file, so its directory is watched.
+            let name = path.file_name().map(|n| n.to_os_string());
+            let (tx, events) = mpsc::channel();
+            let mut watcher =
+                notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                    if let Ok(event) = res {
+                        if event.paths.iter().any(|p| p.file_name() == name.as_deref()) {
+                            let _ = tx.send(());
+                        }
+                    }
+                })?;
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+            Ok(Self {
+                path,
+                config,
+                events,
+                _watcher: watcher,
+            })
+        }
+
+        /// Gets the configuration in use.
+        pub fn config(&self) -> &Config {
+            &self.config
+        }
+
+        /// Checks, without blocking, if the file changed, `None` if it did
+        /// not or its content is the same.
+        ///
+        /// # Errors
+        /// An error variant is returned if the new file cannot be loaded, the
+        /// previous configuration stays in use and the next change is
+        /// loaded again.
+        pub fn poll(&mut self) -> Option<Result<Reload, ConfigError>> {
+            // A save is usually several events, the file is loaded once.
+            self.events.try_iter().last()?;
+            let config = match Config::load(&self.path) {
+                Ok(config) => config,
+                Err(e) => return Some(Err(e)),
+            };
+            if config == self.config {
+                return None;
+            }
+            let previous = std::mem::replace(&mut self.config, config.clone());
+            Some(Ok(Reload { previous, config }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_the_defaults() {
+        let config = Config::from_toml(
+            "port = \"/dev/ttyACM0\"\n\
+             [timeouts]\n\
+             idle_stop = 30.0\n\
+             [filters]\n\
+             mirrored = true\n",
+        )
+        .unwrap();
+        assert_eq!(config.port, "/dev/ttyACM0");
+        assert_eq!(config.baud_rate, 230400);
+        assert!(config.filters.mirrored);
+        assert_eq!(config.filters.decimation, 1);
+        assert_eq!(
+            config.idle_options().map(|o| o.timeout),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(config.run_policy().read_timeout, Duration::from_secs(1));
+        assert!(Config::from_toml("baud_rate = \"fast\"").is_err());
+    }
+
+    #[test]
+    fn loads_the_calibration_next_to_the_file() {
+        let dir = std::env::temp_dir().join(format!("lds-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut offsets = [0i16; 360];
+        offsets[90] = -15;
+        std::fs::write(
+            dir.join("calibration.json"),
+            serde_json::to_string(&Calibration::from_offsets(offsets)).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(dir.join("lds.toml"), "calibration = \"calibration.json\"").unwrap();
+        std::fs::write(dir.join("other.toml"), "calibration = \"lds.toml\"").unwrap();
+
+        let calibration = Config::load(dir.join("lds.toml"))
+            .and_then(|c| c.load_calibration())
+            .map(|c| c.map(|c| c.offset(90)));
+        let wrong = Config::load(dir.join("other.toml")).and_then(|c| c.load_calibration());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(calibration.unwrap(), Some(-15));
+        // A TOML file, but not a calibration.
+        assert!(matches!(wrong, Err(ConfigError::Toml(_))));
+        assert_eq!(Config::default().load_calibration().unwrap(), None);
+    }
+}
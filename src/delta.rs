@@ -0,0 +1,395 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Compression of scan streams, encoding every scan as the difference
+//! from the previous one, for low bandwidth links.
+//!
+//! Every encoded scan starts with its kind, `KEYFRAME` or `DELTA`, and the
+//! rpms as a little-endian `u16`. The beams follow as runs, each one with
+//! a header byte: when the high bit is clear the next `header + 1` beams
+//! are unchanged, otherwise the next `(header & 0x7F) + 1` beams changed and
+//! the differences of their range and intensity follow, as zigzag varints.
+//! A keyframe is encoded as the difference from an empty scan, so a decoder
+//! can start from it.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::delta::{DeltaDecoder, DeltaEncoder};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let mut encoder = DeltaEncoder::new();
+//! let mut decoder = DeltaDecoder::new();
+//!
+//! let bytes = encoder.encode(&reading);
+//! let decoded = decoder.decode(&bytes).unwrap();
+//! assert_eq!(decoded.ranges, reading.ranges);
+//! ```
+
+use crate::LaserReading;
+use std::fmt;
+
+/// Kind of a self-contained encoded scan.
+pub const KEYFRAME: u8 = 0;
+/// Kind of a scan encoded against the previous one.
+pub const DELTA: u8 = 1;
+/// Default number of scans between two keyframes.
+pub const DEFAULT_KEYFRAME_INTERVAL: usize = 50;
+
+const CHANGED: u8 = 0x80;
+const MAX_RUN: usize = 128;
+
+/// Errors while decoding a compressed scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaError {
+    /// The buffer ended before the scan was complete
+    Truncated,
+    /// A delta arrived before any keyframe
+    MissingKeyframe,
+    /// Unknown kind of scan
+    InvalidKind(u8),
+    /// The runs do not add up to a scan, or a value does not fit in a `u16`
+    Corrupted,
+}
+
+impl fmt::Display for DeltaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeltaError::Truncated => write!(f, "Truncated scan"),
+            DeltaError::MissingKeyframe => write!(f, "Delta received before a keyframe"),
+            DeltaError::InvalidKind(k) => write!(f, "Invalid scan kind: {k}"),
+            DeltaError::Corrupted => write!(f, "Corrupted scan"),
+        }
+    }
+}
+
+impl std::error::Error for DeltaError {}
+
+/// Encoder of a stream of scans.
+#[derive(Debug, Clone)]
+pub struct DeltaEncoder {
+    // What the decoder has reconstructed so far.
+    reference: Option<LaserReading>,
+    keyframe_interval: usize,
+    since_keyframe: usize,
+    range_tolerance: u16,
+    intensity_tolerance: u16,
+}
+
+impl Default for DeltaEncoder {
+    fn default() -> Self {
+        Self {
+            reference: None,
+            keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+            since_keyframe: 0,
+            range_tolerance: 0,
+            intensity_tolerance: 0,
+        }
+    }
+}
+
+impl DeltaEncoder {
+    /// Creates a lossless encoder, with a keyframe every `DEFAULT_KEYFRAME_INTERVAL` scans.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of scans between two keyframes, so a decoder joining
+    /// late, or losing a scan, recovers. 0 disables them after the first one.
+    pub fn with_keyframe_interval(mut self, scans: usize) -> Self {
+        self.keyframe_interval = scans;
+        self
+    }
+
+    /// Considers unchanged the beams whose range, in mm, and intensity differ
+    /// by at most the given tolerances, making the compression lossy.
+    ///
+    /// The error never accumulates, it is bounded by the tolerances.
+    pub fn with_tolerance(mut self, range: u16, intensity: u16) -> Self {
+        self.range_tolerance = range;
+        self.intensity_tolerance = intensity;
+        self
+    }
+
+    /// Makes the next scan a keyframe.
+    pub fn force_keyframe(&mut self) {
+        self.reference = None;
+    }
+
+    /// Encodes a scan into a new buffer.
+    pub fn encode(&mut self, reading: &LaserReading) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(reading, &mut out);
+        out
+    }
+
+    /// Encodes a scan, appending it to `out`.
+    pub fn encode_into(&mut self, reading: &LaserReading, out: &mut Vec<u8>) {
+        let keyframe = self.reference.is_none()
+            || (self.keyframe_interval > 0 && self.since_keyframe >= self.keyframe_interval);
+        if keyframe {
+            self.reference = Some(LaserReading::new());
+            self.since_keyframe = 0;
+        }
+        self.since_keyframe += 1;
+        let reference = self.reference.get_or_insert_with(LaserReading::new);
+
+        out.push(if keyframe { KEYFRAME } else { DELTA });
+        out.extend_from_slice(&reading.rpms.to_le_bytes());
+        reference.rpms = reading.rpms;
+
+        let n = reading.ranges.len();
+        let changed = |i: usize| {
+            reading.ranges[i].abs_diff(reference.ranges[i]) > self.range_tolerance
+                || reading.intensities[i].abs_diff(reference.intensities[i])
+                    > self.intensity_tolerance
+        };
+        let changed: Vec<bool> = (0..n).map(changed).collect();
+
+        let mut i = 0;
+        while i < n {
+            let len = changed[i..]
+                .iter()
+                .take(MAX_RUN)
+                .take_while(|c| **c == changed[i])
+                .count();
+            if changed[i] {
+                out.push(CHANGED | (len - 1) as u8);
+                for j in i..i + len {
+                    let dr = i32::from(reading.ranges[j]) - i32::from(reference.ranges[j]);
+                    let di =
+                        i32::from(reading.intensities[j]) - i32::from(reference.intensities[j]);
+                    write_varint(out, zigzag(dr));
+                    write_varint(out, zigzag(di));
+                    reference.ranges[j] = reading.ranges[j];
+                    reference.intensities[j] = reading.intensities[j];
+                }
+            } else {
+                out.push((len - 1) as u8);
+            }
+            i += len;
+        }
+    }
+}
+
+/// Decoder of a stream of scans.
+#[derive(Debug, Clone, Default)]
+pub struct DeltaDecoder {
+    reference: Option<LaserReading>,
+}
+
+impl DeltaDecoder {
+    /// Creates a decoder, waiting for a keyframe.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes a scan.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - buffer too short
+    /// - delta received before a keyframe
+    /// - unknown kind or corrupted data
+    ///
+    /// After an error the decoder waits for the next keyframe.
+    pub fn decode(&mut self, buf: &[u8]) -> Result<LaserReading, DeltaError> {
+        let result = self.decode_inner(buf);
+        if result.is_err() {
+            self.reference = None;
+        }
+        result
+    }
+
+    fn decode_inner(&mut self, buf: &[u8]) -> Result<LaserReading, DeltaError> {
+        let (&kind, rest) = buf.split_first().ok_or(DeltaError::Truncated)?;
+        let mut reading = match kind {
+            KEYFRAME => LaserReading::new(),
+            DELTA => self.reference.clone().ok_or(DeltaError::MissingKeyframe)?,
+            k => return Err(DeltaError::InvalidKind(k)),
+        };
+        let rpms = rest.get(..2).ok_or(DeltaError::Truncated)?;
+        reading.rpms = u16::from_le_bytes([rpms[0], rpms[1]]);
+
+        let mut pos = 3;
+        let mut i = 0;
+        let n = reading.ranges.len();
+        while i < n {
+            let header = *buf.get(pos).ok_or(DeltaError::Truncated)?;
+            pos += 1;
+            let len = usize::from(header & !CHANGED) + 1;
+            if i + len > n {
+                return Err(DeltaError::Corrupted);
+            }
+            if header & CHANGED != 0 {
+                for j in i..i + len {
+                    let dr = unzigzag(read_varint(buf, &mut pos)?);
+                    let di = unzigzag(read_varint(buf, &mut pos)?);
+                    reading.ranges[j] = apply(reading.ranges[j], dr)?;
+                    reading.intensities[j] = apply(reading.intensities[j], di)?;
+                }
+            }
+            i += len;
+        }
+
+        self.reference = Some(reading.clone());
+        Ok(reading)
+    }
+}
+
+fn apply(value: u16, delta: i32) -> Result<u16, DeltaError> {
+    i32::from(value)
+        .checked_add(delta)
+        .and_then(|v| u16::try_from(v).ok())
+        .ok_or(DeltaError::Corrupted)
+}
+
+fn zigzag(v: i32) -> u32 {
+    ((v << 1) ^ (v >> 31)) as u32
+}
+
+fn unzigzag(v: u32) -> i32 {
+    (v >> 1) as i32 ^ -((v & 1) as i32)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u32) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Result<u32, DeltaError> {
+    let mut v = 0u32;
+    for shift in (0..32).step_by(7) {
+        let b = *buf.get(*pos).ok_or(DeltaError::Truncated)?;
+        *pos += 1;
+        v |= u32::from(b & 0x7F) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(DeltaError::Corrupted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_scan_close, assert_scan_eq, fixtures};
+
+    fn scans() -> Vec<LaserReading> {
+        fixtures().into_iter().map(|f| f.decode()).collect()
+    }
+
+    #[test]
+    fn round_trips_keyframes_and_deltas() {
+        let mut encoder = DeltaEncoder::new().with_keyframe_interval(3);
+        let mut decoder = DeltaDecoder::new();
+        let scans = scans();
+        for (n, scan) in scans.iter().chain(&scans).enumerate() {
+            let bytes = encoder.encode(scan);
+            let kind = if n % 3 == 0 { KEYFRAME } else { DELTA };
+            assert_eq!(bytes[0], kind, "scan {n}");
+            assert_scan_eq(&decoder.decode(&bytes).unwrap(), scan);
+        }
+    }
+
+    #[test]
+    fn encodes_an_unchanged_scan_in_runs() {
+        let room = &scans()[0];
+        let mut encoder = DeltaEncoder::new();
+        encoder.encode(room);
+        // Kind, rpms, then 360 unchanged beams in runs of 128.
+        assert_eq!(encoder.encode(room), [DELTA, 44, 1, 127, 127, 103]);
+    }
+
+    #[test]
+    fn bounds_the_lossy_error() {
+        let scans = scans();
+        let mut encoder = DeltaEncoder::new().with_tolerance(20, u16::MAX);
+        let mut decoder = DeltaDecoder::new();
+        decoder.decode(&encoder.encode(&scans[0])).unwrap();
+        let mut moved = scans[0].clone();
+        for range in moved.ranges.iter_mut().filter(|r| **r > 0) {
+            *range += 15;
+        }
+        let decoded = decoder.decode(&encoder.encode(&moved)).unwrap();
+        assert_scan_close(&decoded, &moved, 20);
+        assert_eq!(decoded.ranges, scans[0].ranges);
+    }
+
+    #[test]
+    fn waits_for_a_keyframe() {
+        let scans = scans();
+        let mut encoder = DeltaEncoder::new();
+        let keyframe = encoder.encode(&scans[0]);
+        let delta = encoder.encode(&scans[3]);
+
+        let mut decoder = DeltaDecoder::new();
+        assert_eq!(
+            decoder.decode(&delta).unwrap_err(),
+            DeltaError::MissingKeyframe
+        );
+        decoder.decode(&keyframe).unwrap();
+        assert_scan_eq(&decoder.decode(&delta).unwrap(), &scans[3]);
+
+        // An error drops the reference, the next delta needs a keyframe.
+        assert_eq!(
+            decoder.decode(&[7]).unwrap_err(),
+            DeltaError::InvalidKind(7)
+        );
+        assert_eq!(
+            decoder.decode(&delta).unwrap_err(),
+            DeltaError::MissingKeyframe
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_streams() {
+        let room = &scans()[0];
+        let keyframe = DeltaEncoder::new().encode(room);
+        let mut decoder = DeltaDecoder::new();
+        assert_eq!(
+            decoder.decode(&keyframe[..keyframe.len() - 1]).unwrap_err(),
+            DeltaError::Truncated
+        );
+
+        // A delta of i32::MAX on a beam must not overflow.
+        let near_and_far = &scans()[2];
+        assert!(near_and_far.ranges[0] > 0);
+        decoder
+            .decode(&DeltaEncoder::new().encode(near_and_far))
+            .unwrap();
+        let mut hostile = vec![DELTA, 0, 0, CHANGED];
+        write_varint(&mut hostile, 0xFFFF_FFFE);
+        write_varint(&mut hostile, 0);
+        hostile.extend([127, 127, 102]);
+        assert_eq!(unzigzag(0xFFFF_FFFE), i32::MAX);
+        assert_eq!(decoder.decode(&hostile).unwrap_err(), DeltaError::Corrupted);
+
+        // A negative range.
+        let mut negative = vec![KEYFRAME, 0, 0, CHANGED];
+        write_varint(&mut negative, zigzag(-1));
+        write_varint(&mut negative, 0);
+        negative.extend([127, 127, 102]);
+        assert_eq!(
+            decoder.decode(&negative).unwrap_err(),
+            DeltaError::Corrupted
+        );
+
+        // Runs longer than a scan.
+        let mut long = vec![KEYFRAME, 0, 0];
+        long.extend([127, 127, 127]);
+        assert_eq!(decoder.decode(&long).unwrap_err(), DeltaError::Corrupted);
+    }
+}
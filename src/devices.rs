@@ -0,0 +1,255 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Persistent names of the serial ports, from the `/dev/serial/by-id` and
+//! `/dev/serial/by-path` links created by udev on Linux.
+//!
+//! `/dev/ttyUSB0` depends on the order the adapters are enumerated in,
+//! while the `by-id` link is named after the adapter and the `by-path` one
+//! after the USB port it is plugged in, so both survive a reboot.
+//!
+//! On macOS IOKit already names the devices after the adapter, e.g.
+//! `/dev/cu.usbserial-0001` or `/dev/cu.SLAB_USBtoUART`, and `resolve`
+//! searches the `/dev/cu.*` call-out devices. On the BSDs it searches the
+//! `/dev/cua*` ones, e.g. `/dev/cuaU0`.
+//!
+//! ```no_run
+//! use hls_lfcd_lds_driver::devices;
+//!
+//! // The USB2LDS adapter of the TurtleBot3 is a CP2102.
+//! let port = devices::resolve("CP2102")?;
+//! println!("lidar on {}", port.display());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory of the links named after the adapters.
+#[cfg(target_os = "linux")]
+pub const BY_ID: &str = "/dev/serial/by-id";
+/// Directory of the links named after the USB ports.
+#[cfg(target_os = "linux")]
+pub const BY_PATH: &str = "/dev/serial/by-path";
+
+/// Prefixes of the dial-in and of the call-out devices.
+#[cfg(target_os = "macos")]
+pub(crate) const DIAL_IN_CALL_OUT: (&str, &str) = ("tty.", "cu.");
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+pub(crate) const DIAL_IN_CALL_OUT: (&str, &str) = ("tty", "cua");
+
+/// A persistent link to a serial port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialLink {
+    /// Name of the link, e.g. `usb-Silicon_Labs_CP2102_..._0001-if00-port0`
+    pub name: String,
+    /// Path of the link
+    pub link: PathBuf,
+    /// Device the link points to, e.g. `/dev/ttyUSB0`
+    pub device: PathBuf,
+}
+
+#[cfg(target_os = "linux")]
+fn list(dir: &str) -> io::Result<Vec<SerialLink>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // udev creates the directory only when a serial adapter is plugged in.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut links = Vec::new();
+    for entry in entries {
+        let link = entry?.path();
+        let Ok(device) = fs::canonicalize(&link) else {
+            continue;
+        };
+        links.push(SerialLink {
+            name: link
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            link,
+            device,
+        });
+    }
+    links.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(links)
+}
+
+/// Gets the links in `/dev/serial/by-id`.
+///
+/// # Errors
+/// An error variant is returned if the directory cannot be read.
+#[cfg(target_os = "linux")]
+pub fn by_id() -> io::Result<Vec<SerialLink>> {
+    list(BY_ID)
+}
+
+/// Gets the links in `/dev/serial/by-path`.
+///
+/// # Errors
+/// An error variant is returned if the directory cannot be read.
+#[cfg(target_os = "linux")]
+pub fn by_path() -> io::Result<Vec<SerialLink>> {
+    list(BY_PATH)
+}
+
+/// Gets the persistent `by-id` link of a serial port, falling back to the
+/// `by-path` one, `None` if it has neither.
+///
+/// # Errors
+/// An error variant is returned if the port or the links cannot be read.
+#[cfg(target_os = "linux")]
+pub fn stable_path<P: AsRef<Path>>(port: P) -> io::Result<Option<PathBuf>> {
+    let device = fs::canonicalize(port)?;
+    Ok(by_id()?
+        .into_iter()
+        .chain(by_path()?)
+        .find(|l| l.device == device)
+        .map(|l| l.link))
+}
+
+/// Gets the call-out devices, `/dev/cu.*` on macOS and `/dev/cua*` on the
+/// BSDs, the ones to open: the dial-in devices block until the carrier is
+/// detected, which the lidar never asserts.
+///
+/// # Errors
+/// An error variant is returned if `/dev` cannot be read.
+#[cfg(not(target_os = "linux"))]
+pub fn callout_devices() -> io::Result<Vec<SerialLink>> {
+    let (_, prefix) = DIAL_IN_CALL_OUT;
+    let mut devices = Vec::new();
+    for entry in fs::read_dir("/dev")? {
+        let path = entry?.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix(prefix))
+            // The BSDs have `.init` and `.lock` devices holding the settings.
+            .filter(|n| !n.ends_with(".init") && !n.ends_with(".lock"))
+        else {
+            continue;
+        };
+        devices.push(SerialLink {
+            name: name.into(),
+            link: path.clone(),
+            device: path,
+        });
+    }
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(devices)
+}
+
+/// Gets the path of a port from its `by-id` or `by-path` link, or on macOS
+/// and the BSDs from the name of its call-out device.
+///
+/// `name` is either a path, returned as is if it exists, or a part of the
+/// name of exactly one link.
+///
+/// # Errors
+/// An error variant is returned if no link, or more than one, matches.
+pub fn resolve(name: &str) -> io::Result<PathBuf> {
+    let path = Path::new(name);
+    if path.is_absolute() && path.exists() {
+        return Ok(path.to_path_buf());
+    }
+
+    #[cfg(target_os = "linux")]
+    let mut matches: Vec<SerialLink> = {
+        let mut matches: Vec<SerialLink> = by_id()?
+            .into_iter()
+            .filter(|l| l.name.contains(name))
+            .collect();
+        if matches.is_empty() {
+            matches = by_path()?
+                .into_iter()
+                .filter(|l| l.name.contains(name))
+                .collect();
+        }
+        matches
+    };
+    #[cfg(not(target_os = "linux"))]
+    let mut matches: Vec<SerialLink> = callout_devices()?
+        .into_iter()
+        .filter(|l| l.name.contains(name))
+        .collect();
+
+    match matches.len() {
+        1 => Ok(matches.remove(0).link),
+        0 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No serial port matches {name}"),
+        )),
+        n => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{n} serial ports match {name}"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn lists_the_links_by_name() {
+        let dir = std::env::temp_dir().join(format!("lds-devices-{}", std::process::id()));
+        let by_id = dir.join("by-id");
+        fs::create_dir_all(&by_id).unwrap();
+        let device = dir.join("ttyUSB0");
+        fs::write(&device, b"").unwrap();
+        for name in ["usb-b-port0", "usb-a-port0", "dangling"] {
+            let target = if name == "dangling" {
+                dir.join("ttyUSB9")
+            } else {
+                device.clone()
+            };
+            std::os::unix::fs::symlink(target, by_id.join(name)).unwrap();
+        }
+
+        let device = fs::canonicalize(&device).unwrap();
+        let links = list(by_id.to_str().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        let links = links.unwrap();
+        let names: Vec<_> = links.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["usb-a-port0", "usb-b-port0"]);
+        assert!(links.iter().all(|l| l.device == device));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn lists_nothing_without_the_directory() {
+        assert!(list("/dev/serial/lds-missing").unwrap().is_empty());
+    }
+
+    #[test]
+    fn resolves_an_existing_path_as_is() {
+        let path = std::env::temp_dir();
+        assert_eq!(resolve(path.to_str().unwrap()).unwrap(), path);
+    }
+
+    #[test]
+    fn fails_on_an_unknown_name() {
+        let err = resolve("lds-no-such-serial-port").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,252 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Statistics on the health of the serial stream, to correlate glitches in
+//! the scans with electrical noise on the robot.
+//!
+//! A resync happens when the bytes following a frame are not the header of
+//! the next one, and the driver has to search for it again. The first
+//! search after opening the port usually counts as one, since the lidar
+//! is already spinning.
+//!
+//! The speed of the motor is tracked over the last scans: a growing
+//! variance of the RPMs is the first sign of a motor wearing out, well
+//! before the scans get bad.
+//!
+//! ```no_run
+//! # #[cfg(feature = "async_tokio")]
+//! # async fn run(laser: &mut hls_lfcd_lds_driver::tokio::LFCDLaser) {
+//! let stats = laser.resync_stats();
+//! println!(
+//!     "{} resyncs, {} bytes skipped, last at {:?}",
+//!     stats.resyncs,
+//!     stats.skipped_bytes,
+//!     stats.last()
+//! );
+//! if let Some(rpms) = laser.rpm_stats() {
+//!     if rpms.std_dev() > 5.0 {
+//!         eprintln!("motor speed unstable: {rpms:?}");
+//!     }
+//! }
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+/// Number of events whose time is kept.
+pub const RECENT_EVENTS: usize = 16;
+
+/// Default number of scans over which the RPM statistics are computed, about
+/// 12 s at 300 rpm.
+pub const DEFAULT_RPM_WINDOW: usize = 60;
+
+/// What made the driver lose the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncEvent {
+    /// The header of the frame had to be searched, skipping the given bytes
+    Resync(usize),
+    /// A packet inside a frame had a bad header
+    HeaderMismatch,
+}
+
+/// Counters of the times the driver lost the stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResyncStats {
+    /// Number of searches of the frame header
+    pub resyncs: u64,
+    /// Number of bytes discarded while searching the frame header
+    pub skipped_bytes: u64,
+    /// Number of packets with a bad header inside a frame
+    pub header_mismatches: u64,
+    /// Last events with their time, oldest first
+    pub recent: VecDeque<(SystemTime, SyncEvent)>,
+}
+
+impl ResyncStats {
+    /// Creates empty statistics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the time of the last event, `None` if the stream never got lost.
+    pub fn last(&self) -> Option<SystemTime> {
+        self.recent.back().map(|(t, _)| *t)
+    }
+
+    /// Records an event happened now.
+    pub fn record(&mut self, event: SyncEvent) {
+        match event {
+            SyncEvent::Resync(skipped) => {
+                self.resyncs += 1;
+                self.skipped_bytes += skipped as u64;
+            }
+            SyncEvent::HeaderMismatch => self.header_mismatches += 1,
+        }
+        if self.recent.len() == RECENT_EVENTS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((SystemTime::now(), event));
+    }
+}
+
+/// Statistics of the RPMs over the last scans, see `RpmTracker`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RpmStats {
+    /// Number of scans in the window
+    pub count: usize,
+    pub mean: f32,
+    pub variance: f32,
+    pub min: u16,
+    pub max: u16,
+    /// Change of the RPMs per scan, the slope of the least squares line
+    pub trend: f32,
+}
+
+impl RpmStats {
+    /// Gets the standard deviation of the RPMs.
+    pub fn std_dev(&self) -> f32 {
+        self.variance.sqrt()
+    }
+}
+
+/// RPMs of the last `window` scans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpmTracker {
+    window: usize,
+    samples: VecDeque<u16>,
+}
+
+impl Default for RpmTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_RPM_WINDOW)
+    }
+}
+
+impl RpmTracker {
+    /// Creates a tracker over the last `window` scans, at least one.
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self {
+            window,
+            samples: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Gets the number of scans of the window.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Sets the number of scans of the window, dropping the oldest ones
+    /// beyond it.
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.max(1);
+        while self.samples.len() > self.window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Records the RPMs of a scan.
+    pub fn record(&mut self, rpms: u16) {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rpms);
+    }
+
+    /// Forgets the scans recorded.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Gets the statistics of the window.
+    ///
+    /// Returns `None` if no scan has been recorded.
+    pub fn stats(&self) -> Option<RpmStats> {
+        let count = self.samples.len();
+        let n = count as f32;
+        let mean = self.samples.iter().map(|r| f32::from(*r)).sum::<f32>() / n;
+        let variance = self
+            .samples
+            .iter()
+            .map(|r| (f32::from(*r) - mean).powi(2))
+            .sum::<f32>()
+            / n;
+
+        // Slope against the scan index, centered on the middle of the window.
+        let middle = (n - 1.0) / 2.0;
+        let (mut covariance, mut spread) = (0.0, 0.0);
+        for (i, r) in self.samples.iter().enumerate() {
+            let x = i as f32 - middle;
+            covariance += x * (f32::from(*r) - mean);
+            spread += x * x;
+        }
+
+        Some(RpmStats {
+            count,
+            mean,
+            variance,
+            min: *self.samples.iter().min()?,
+            max: *self.samples.iter().max()?,
+            trend: if spread > 0.0 {
+                covariance / spread
+            } else {
+                0.0
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_the_rpm_statistics() {
+        let mut tracker = RpmTracker::new(4);
+        assert_eq!(tracker.stats(), None);
+        for rpms in [100, 290, 300, 310, 320] {
+            tracker.record(rpms);
+        }
+        // The first scan left the window.
+        let stats = tracker.stats().unwrap();
+        assert_eq!((stats.count, stats.min, stats.max), (4, 290, 320));
+        assert_eq!(stats.mean, 305.0);
+        assert_eq!(stats.variance, 125.0);
+        assert_eq!(stats.trend, 10.0);
+
+        tracker.set_window(1);
+        let stats = tracker.stats().unwrap();
+        assert_eq!((stats.count, stats.mean, stats.trend), (1, 320.0, 0.0));
+    }
+
+    #[test]
+    fn keeps_the_recent_sync_events() {
+        let mut stats = ResyncStats::new();
+        assert_eq!(stats.last(), None);
+        stats.record(SyncEvent::Resync(7));
+        for _ in 0..RECENT_EVENTS {
+            stats.record(SyncEvent::HeaderMismatch);
+        }
+        assert_eq!((stats.resyncs, stats.skipped_bytes), (1, 7));
+        assert_eq!(stats.header_mismatches, RECENT_EVENTS as u64);
+        assert_eq!(stats.recent.len(), RECENT_EVENTS);
+        assert!(stats
+            .recent
+            .iter()
+            .all(|(_, e)| *e == SyncEvent::HeaderMismatch));
+        assert!(stats.last().is_some());
+    }
+}
@@ -0,0 +1,205 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Backend-agnostic traits, allowing code to be generic over
+//! "some LDS driver", including mocks used in tests.
+
+use crate::LaserReading;
+use std::future::Future;
+
+/// A lidar driver that reads scans in a blocking fashion.
+pub trait LidarDriver {
+    /// Error returned by the driver.
+    type Error;
+
+    /// Gets a reading from the lidar, blocking until a full revolution is available.
+    fn read(&mut self) -> Result<LaserReading, Self::Error>;
+
+    /// Gets `n` consecutive readings, blocking until all of them are available.
+    fn read_batch(&mut self, n: usize) -> Result<Vec<LaserReading>, Self::Error> {
+        (0..n).map(|_| self.read()).collect()
+    }
+
+    /// Starts the lidar.
+    fn start(&mut self);
+
+    /// Stops the lidar, following reads are expected to fail.
+    fn close(&mut self);
+}
+
+/// A lidar driver that reads scans asynchronously.
+pub trait AsyncLidarDriver {
+    /// Error returned by the driver.
+    type Error;
+
+    /// Gets a reading from the lidar, completing when a full revolution is available.
+    fn read(&mut self) -> impl Future<Output = Result<LaserReading, Self::Error>> + Send;
+
+    /// Gets `n` consecutive readings, completing when all of them are available.
+    fn read_batch(
+        &mut self,
+        n: usize,
+    ) -> impl Future<Output = Result<Vec<LaserReading>, Self::Error>> + Send
+    where
+        Self: Send,
+    {
+        async move {
+            let mut scans = Vec::with_capacity(n);
+            for _ in 0..n {
+                scans.push(self.read().await?);
+            }
+            Ok(scans)
+        }
+    }
+
+    /// Starts the lidar.
+    fn start(&mut self);
+
+    /// Stops the lidar, following reads are expected to fail.
+    fn close(&mut self);
+}
+
+impl<T: LidarDriver + ?Sized> LidarDriver for &mut T {
+    type Error = T::Error;
+
+    fn read(&mut self) -> Result<LaserReading, Self::Error> {
+        (**self).read()
+    }
+
+    fn read_batch(&mut self, n: usize) -> Result<Vec<LaserReading>, Self::Error> {
+        (**self).read_batch(n)
+    }
+
+    fn start(&mut self) {
+        (**self).start()
+    }
+
+    fn close(&mut self) {
+        (**self).close()
+    }
+}
+
+impl<T: LidarDriver + ?Sized> LidarDriver for Box<T> {
+    type Error = T::Error;
+
+    fn read(&mut self) -> Result<LaserReading, Self::Error> {
+        (**self).read()
+    }
+
+    fn read_batch(&mut self, n: usize) -> Result<Vec<LaserReading>, Self::Error> {
+        (**self).read_batch(n)
+    }
+
+    fn start(&mut self) {
+        (**self).start()
+    }
+
+    fn close(&mut self) {
+        (**self).close()
+    }
+}
+
+impl<T: AsyncLidarDriver + Send> AsyncLidarDriver for &mut T {
+    type Error = T::Error;
+
+    fn read(&mut self) -> impl Future<Output = Result<LaserReading, Self::Error>> + Send {
+        (**self).read()
+    }
+
+    fn read_batch(
+        &mut self,
+        n: usize,
+    ) -> impl Future<Output = Result<Vec<LaserReading>, Self::Error>> + Send
+    where
+        Self: Send,
+    {
+        (**self).read_batch(n)
+    }
+
+    fn start(&mut self) {
+        (**self).start()
+    }
+
+    fn close(&mut self) {
+        (**self).close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A driver with its own `read_batch`, returning scans with the batch
+    /// size as RPMs.
+    struct Batching;
+
+    impl LidarDriver for Batching {
+        type Error = ();
+
+        fn read(&mut self) -> Result<LaserReading, ()> {
+            Ok(LaserReading::new())
+        }
+
+        fn read_batch(&mut self, n: usize) -> Result<Vec<LaserReading>, ()> {
+            let mut scan = LaserReading::new();
+            scan.rpms = n as u16;
+            Ok(vec![scan; n])
+        }
+
+        fn start(&mut self) {}
+
+        fn close(&mut self) {}
+    }
+
+    impl AsyncLidarDriver for Batching {
+        type Error = ();
+
+        async fn read(&mut self) -> Result<LaserReading, ()> {
+            LidarDriver::read(self)
+        }
+
+        async fn read_batch(&mut self, n: usize) -> Result<Vec<LaserReading>, ()> {
+            LidarDriver::read_batch(self, n)
+        }
+
+        fn start(&mut self) {}
+
+        fn close(&mut self) {}
+    }
+
+    fn batch_rpms<D: LidarDriver<Error = ()>>(mut driver: D) -> Vec<u16> {
+        driver
+            .read_batch(3)
+            .unwrap()
+            .iter()
+            .map(|s| s.rpms)
+            .collect()
+    }
+
+    async fn async_batch_rpms<D: AsyncLidarDriver<Error = ()> + Send>(mut driver: D) -> Vec<u16> {
+        let scans = driver.read_batch(3).await.unwrap();
+        scans.iter().map(|s| s.rpms).collect()
+    }
+
+    #[test]
+    fn forwards_read_batch() {
+        assert_eq!(batch_rpms(&mut Batching), [3, 3, 3]);
+        assert_eq!(batch_rpms(Box::new(Batching)), [3, 3, 3]);
+    }
+
+    #[tokio::test]
+    async fn forwards_async_read_batch() {
+        assert_eq!(async_batch_rpms(&mut Batching).await, [3, 3, 3]);
+    }
+}
@@ -0,0 +1,147 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Scanning in windows, see `LFCDLaser::set_duty_cycle`.
+//!
+//! Battery-powered deployments seldom need the lidar spinning all the time.
+//! With a duty cycle, the reads only return the scans of the first
+//! `scanning` of every `period`: out of the window a read stops the motor
+//! and waits for the next one, then starts it again and drops the scans of
+//! the warm-up. Scans completed after the end of a window are dropped too.
+//!
+//! ```no_run
+//! # #[cfg(feature = "sync")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use hls_lfcd_lds_driver::duty::DutyCycle;
+//! use hls_lfcd_lds_driver::sync::LFCDLaser;
+//! use std::time::Duration;
+//!
+//! let mut laser = LFCDLaser::new("/dev/ttyUSB0", 230400)?;
+//! // 2 s scanning every 10 s.
+//! laser.set_duty_cycle(Some(DutyCycle {
+//!     scanning: Duration::from_secs(2),
+//!     period: Duration::from_secs(10),
+//!     ..Default::default()
+//! }));
+//! loop {
+//!     let reading = laser.read()?;
+//! }
+//! # }
+//! # #[cfg(not(feature = "sync"))]
+//! # fn main() {}
+//! ```
+
+use std::time::Duration;
+
+/// How long the lidar scans, and how often.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ser_de", derive(serde::Serialize, serde::Deserialize))]
+pub struct DutyCycle {
+    /// Time scanning at the beginning of every period, the warm-up included
+    pub scanning: Duration,
+    /// Time between the beginnings of two windows, the lidar never stops
+    /// when not longer than `scanning`
+    pub period: Duration,
+    /// Scans dropped after the motor has been started again
+    pub warmup_scans: usize,
+}
+
+impl Default for DutyCycle {
+    fn default() -> Self {
+        Self {
+            scanning: Duration::from_secs(2),
+            period: Duration::from_secs(10),
+            // A second at 300 rpm.
+            warmup_scans: 5,
+        }
+    }
+}
+
+impl DutyCycle {
+    /// Checks if the lidar never stops.
+    pub fn is_continuous(&self) -> bool {
+        self.period <= self.scanning
+    }
+}
+
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+pub(crate) use self::schedule::Schedule;
+
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+mod schedule {
+    use super::DutyCycle;
+    use std::time::{Duration, Instant};
+
+    /// Windows of a duty cycle, counted from when it has been set.
+    pub(crate) struct Schedule {
+        pub(crate) options: DutyCycle,
+        origin: Instant,
+        /// The motor has been stopped out of the window.
+        pub(crate) paused: bool,
+    }
+
+    impl Schedule {
+        pub(crate) fn new(options: DutyCycle) -> Self {
+            Self {
+                options,
+                origin: Instant::now(),
+                paused: false,
+            }
+        }
+
+        /// Gets the time left until the next window, `None` within one.
+        pub(crate) fn until_window(&self) -> Option<Duration> {
+            if self.options.is_continuous() {
+                return None;
+            }
+            let period = self.options.period.as_nanos();
+            let offset = Duration::from_nanos((self.origin.elapsed().as_nanos() % period) as u64);
+            if offset < self.options.scanning {
+                return None;
+            }
+            Some(self.options.period - offset)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duty(scanning: u64, period: u64) -> DutyCycle {
+        DutyCycle {
+            scanning: Duration::from_secs(scanning),
+            period: Duration::from_secs(period),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn never_stops_without_a_pause() {
+        assert!(duty(10, 10).is_continuous());
+        assert!(duty(10, 5).is_continuous());
+        assert!(!DutyCycle::default().is_continuous());
+    }
+
+    #[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+    #[test]
+    fn starts_within_a_window() {
+        assert_eq!(Schedule::new(duty(60, 120)).until_window(), None);
+        assert_eq!(Schedule::new(duty(10, 5)).until_window(), None);
+
+        // Never scanning, always waiting for the next period.
+        let wait = Schedule::new(duty(0, 60)).until_window().unwrap();
+        assert!(wait <= Duration::from_secs(60) && wait > Duration::from_secs(59));
+    }
+}
@@ -0,0 +1,254 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Driver of the lidar over the `embedded-io-async` traits, enabled by the
+//! `embassy` feature, for Embassy based firmware driving the lidar from the
+//! UART of a microcontroller, e.g. a `BufferedUart` of `embassy-stm32` or
+//! `embassy-rp`.
+//!
+//! The driver does not allocate: the frame buffer lives in the driver and
+//! the readings are returned by value. The crate still links `std` though,
+//! so the firmware must target a chip with a `std` port, e.g. an ESP32
+//! under ESP-IDF.
+//!
+//! ```no_run
+//! # #[cfg(feature = "embassy")]
+//! # mod firmware {
+//! use embedded_io_async::{Read, Write};
+//! use hls_lfcd_lds_driver::embassy::{EmbassyError, EmbassyLaser};
+//!
+//! async fn scan<U: Read + Write>(uart: U) -> Result<(), EmbassyError<U::Error>> {
+//!     let mut laser = EmbassyLaser::new(uart);
+//!     laser.start().await?;
+//!     loop {
+//!         let reading = laser.read().await?;
+//!         if reading.rpms > 0 {
+//!             break;
+//!         }
+//!     }
+//!     laser.stop().await
+//! }
+//! # }
+//! # fn main() {}
+//! ```
+
+use crate::error::SYNC_LIMIT;
+use crate::protocol::{decode_frame, FIRST_INDEX, FRAME_SIZE, START_BYTE, STOP_BYTE, SYNC_BYTE};
+use crate::LaserReading;
+use embedded_io_async::{Read, ReadExactError, Write};
+use std::fmt;
+
+/// Error of an `EmbassyLaser`, `E` being the error of the UART.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EmbassyError<E> {
+    /// The UART failed
+    Io(E),
+    /// The UART has no more data
+    UnexpectedEof,
+    /// No frame header within `SYNC_LIMIT` bytes, e.g. a wrong baud rate
+    SyncLost,
+}
+
+impl<E: fmt::Display> fmt::Display for EmbassyError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbassyError::Io(e) => write!(f, "UART error: {e}"),
+            EmbassyError::UnexpectedEof => write!(f, "The UART closed mid-frame"),
+            EmbassyError::SyncLost => write!(f, "No frame header in {SYNC_LIMIT} bytes"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for EmbassyError<E> {}
+
+impl<E> From<ReadExactError<E>> for EmbassyError<E> {
+    fn from(e: ReadExactError<E>) -> Self {
+        match e {
+            ReadExactError::UnexpectedEof => EmbassyError::UnexpectedEof,
+            ReadExactError::Other(e) => EmbassyError::Io(e),
+        }
+    }
+}
+
+/// Driver of the LDS-01 over an `embedded-io-async` UART at 230400 baud.
+pub struct EmbassyLaser<U> {
+    uart: U,
+    buff: [u8; FRAME_SIZE],
+    rpms: u16,
+    decode_errors: u32,
+}
+
+impl<U: Read + Write> EmbassyLaser<U> {
+    /// Creates a driver over a UART, the lidar is started by `start`.
+    pub fn new(uart: U) -> Self {
+        Self {
+            uart,
+            buff: [0; FRAME_SIZE],
+            rpms: 0,
+            decode_errors: 0,
+        }
+    }
+
+    /// Gets the UART.
+    pub fn uart(&mut self) -> &mut U {
+        &mut self.uart
+    }
+
+    /// Gets the UART back, the lidar keeps its state.
+    pub fn into_inner(self) -> U {
+        self.uart
+    }
+
+    /// Gets the lidars rmp from the last reading
+    pub fn rpms(&self) -> u16 {
+        self.rpms
+    }
+
+    /// Gets the number of packets dropped because of a bad header.
+    pub fn decode_errors(&self) -> u32 {
+        self.decode_errors
+    }
+
+    /// Starts the Lidar
+    ///
+    /// # Errors
+    /// An error variant is returned if the command cannot be written.
+    pub async fn start(&mut self) -> Result<(), EmbassyError<U::Error>> {
+        self.send(START_BYTE).await
+    }
+
+    /// Stops the lidar
+    ///
+    /// # Errors
+    /// An error variant is returned if the command cannot be written.
+    pub async fn stop(&mut self) -> Result<(), EmbassyError<U::Error>> {
+        self.send(STOP_BYTE).await
+    }
+
+    async fn send(&mut self, byte: u8) -> Result<(), EmbassyError<U::Error>> {
+        self.uart
+            .write_all(&[byte])
+            .await
+            .map_err(EmbassyError::Io)?;
+        self.uart.flush().await.map_err(EmbassyError::Io)
+    }
+
+    /// Gets a reading from the lidar, completing when a full revolution is
+    /// available.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read from the UART
+    /// - no frame header found, see `EmbassyError::SyncLost`
+    pub async fn read(&mut self) -> Result<LaserReading, EmbassyError<U::Error>> {
+        // Wait for data sync of frame: 0xFA, 0XA0
+        let mut start_count = 0;
+        let mut skipped = 0;
+        while start_count < 2 {
+            self.uart
+                .read_exact(&mut self.buff[start_count..=start_count])
+                .await?;
+            start_count = match self.buff[start_count] {
+                SYNC_BYTE if start_count == 0 => 1,
+                FIRST_INDEX if start_count == 1 => 2,
+                SYNC_BYTE => {
+                    self.buff[0] = SYNC_BYTE;
+                    1
+                }
+                _ => 0,
+            };
+            skipped += 1;
+            if skipped > SYNC_LIMIT {
+                return Err(EmbassyError::SyncLost);
+            }
+        }
+        self.uart.read_exact(&mut self.buff[2..]).await?;
+
+        let scan = decode_frame(&self.buff, |_| self.decode_errors += 1);
+        self.rpms = scan.rpms;
+        Ok(scan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_scan_eq, fixtures};
+    use std::convert::Infallible;
+
+    /// A UART receiving `input`, in chunks of 100 bytes, and recording the
+    /// bytes sent.
+    #[derive(Default)]
+    struct Uart {
+        input: Vec<u8>,
+        pos: usize,
+        sent: Vec<u8>,
+    }
+
+    impl embedded_io_async::ErrorType for Uart {
+        type Error = Infallible;
+    }
+
+    impl Read for Uart {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            let n = buf.len().min(100).min(self.input.len() - self.pos);
+            buf[..n].copy_from_slice(&self.input[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    impl Write for Uart {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            self.sent.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        async fn flush(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    fn laser(input: Vec<u8>) -> EmbassyLaser<Uart> {
+        EmbassyLaser::new(Uart {
+            input,
+            ..Default::default()
+        })
+    }
+
+    #[::tokio::test]
+    async fn reads_a_frame_after_garbage() {
+        let corrupted = &fixtures()[3];
+        let mut input = vec![0x00, SYNC_BYTE, 0x42, SYNC_BYTE];
+        input.extend_from_slice(&corrupted.frame);
+        let mut laser = laser(input);
+        laser.start().await.unwrap();
+        assert_scan_eq(&laser.read().await.unwrap(), &corrupted.expected);
+        assert_eq!((laser.rpms(), laser.decode_errors()), (300, 1));
+
+        laser.stop().await.unwrap();
+        assert_eq!(laser.into_inner().sent, [START_BYTE, STOP_BYTE]);
+    }
+
+    #[::tokio::test]
+    async fn gives_up_without_a_frame_header() {
+        let mut noise = laser(vec![0x00; SYNC_LIMIT + 1]);
+        assert!(matches!(noise.read().await, Err(EmbassyError::SyncLost)));
+
+        let room = &fixtures()[0];
+        let mut cut = laser(room.frame[..FRAME_SIZE / 2].to_vec());
+        assert!(matches!(cut.read().await, Err(EmbassyError::UnexpectedEof)));
+    }
+}
@@ -0,0 +1,196 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Errors returned by the drivers.
+//!
+//! ```no_run
+//! # #[cfg(feature = "async_tokio")]
+//! # async fn run(lidar: &mut hls_lfcd_lds_driver::tokio::LFCDLaser) {
+//! use hls_lfcd_lds_driver::Error;
+//!
+//! match lidar.read().await {
+//!     Ok(scan) => println!("{} rpm", scan.rpms),
+//!     Err(Error::SyncLost) => eprintln!("check the baud rate"),
+//!     Err(e) => eprintln!("{e}"),
+//! }
+//! # }
+//! ```
+
+use crate::options::OptionsError;
+use crate::DecodeError;
+use std::fmt;
+use std::io;
+
+/// Bytes skipped looking for a frame header before giving up, two frames.
+pub const SYNC_LIMIT: usize = 2 * crate::protocol::FRAME_SIZE;
+
+/// Error returned by the drivers.
+#[derive(Debug)]
+pub enum Error {
+    /// The driver has been closed, `start` opens it again
+    DriverClosed,
+    /// No scan before the deadline, or the serial port timed out
+    Timeout,
+    /// No frame header within `SYNC_LIMIT` bytes, e.g. a wrong baud rate
+    SyncLost,
+    /// The port reached its end in the middle of a frame
+    ShortRead,
+    /// A packet does not start with `0xFA, 0xA0 + index`, only returned by
+    /// the packet decoders, the drivers skip such packets
+    InvalidHeader {
+        /// Index the packet claims to have
+        index: usize,
+    },
+    /// Invalid `OpenOptions`, the port has not been opened
+    InvalidOptions(OptionsError),
+    /// Error of the serial port
+    Io(io::Error),
+}
+
+/// Result of the drivers.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Kind of an `Error`, without its details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    DriverClosed,
+    Timeout,
+    SyncLost,
+    ShortRead,
+    InvalidHeader,
+    InvalidOptions,
+    Io(io::ErrorKind),
+}
+
+impl Error {
+    /// Gets the kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::DriverClosed => ErrorKind::DriverClosed,
+            Error::Timeout => ErrorKind::Timeout,
+            Error::SyncLost => ErrorKind::SyncLost,
+            Error::ShortRead => ErrorKind::ShortRead,
+            Error::InvalidHeader { .. } => ErrorKind::InvalidHeader,
+            Error::InvalidOptions(_) => ErrorKind::InvalidOptions,
+            Error::Io(e) => ErrorKind::Io(e.kind()),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::InvalidHeader { index } => defmt::write!(f, "InvalidHeader({})", index),
+            Error::InvalidOptions(e) => defmt::write!(f, "InvalidOptions({})", e),
+            _ => defmt::write!(f, "{}", self.kind()),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ErrorKind {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            // `io::ErrorKind` does not implement `Format`.
+            ErrorKind::Io(kind) => defmt::write!(f, "Io({})", defmt::Debug2Format(kind)),
+            kind => defmt::write!(f, "{}", defmt::Debug2Format(kind)),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DriverClosed => write!(f, "Driver is closed"),
+            Error::Timeout => write!(f, "No scan before the deadline"),
+            Error::SyncLost => write!(f, "No frame header in {SYNC_LIMIT} bytes"),
+            Error::ShortRead => write!(f, "Port closed in the middle of a frame"),
+            Error::InvalidHeader { index } => write!(f, "Bad header for packet {index}"),
+            Error::InvalidOptions(e) => write!(f, "Invalid options: {e}"),
+            Error::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::InvalidOptions(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::TimedOut => Error::Timeout,
+            io::ErrorKind::UnexpectedEof => Error::ShortRead,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<OptionsError> for Error {
+    fn from(e: OptionsError) -> Self {
+        Error::InvalidOptions(e)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Error::InvalidHeader { index: e.packet }
+    }
+}
+
+// `tokio-serial` and `mio-serial` re-export the error of `serialport`.
+#[cfg(feature = "serialport")]
+impl From<serialport::Error> for Error {
+    fn from(e: serialport::Error) -> Self {
+        io::Error::from(e).into()
+    }
+}
+
+#[cfg(all(feature = "mio-serial", not(feature = "serialport")))]
+impl From<mio_serial::Error> for Error {
+    fn from(e: mio_serial::Error) -> Self {
+        io::Error::from(e).into()
+    }
+}
+
+#[cfg(all(
+    feature = "tokio-serial",
+    not(any(feature = "serialport", feature = "mio-serial"))
+))]
+impl From<tokio_serial::Error> for Error {
+    fn from(e: tokio_serial::Error) -> Self {
+        io::Error::from(e).into()
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        let kind = match e {
+            Error::Io(e) => return e,
+            Error::DriverClosed => io::ErrorKind::NotConnected,
+            Error::Timeout => io::ErrorKind::TimedOut,
+            Error::ShortRead => io::ErrorKind::UnexpectedEof,
+            Error::SyncLost | Error::InvalidHeader { .. } => io::ErrorKind::InvalidData,
+            Error::InvalidOptions(_) => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, e)
+    }
+}
@@ -0,0 +1,158 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! FlatBuffers encoding of scans, enabled by the `flatbuffers` feature.
+//!
+//! The schema is `schema/laser_scan.fbs` in the repository, other languages
+//! can generate their readers from it with `flatc`.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::flatbuffers::{decode, encode};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let bytes = encode(&reading);
+//! let scan = decode(&bytes).unwrap();
+//! assert_eq!(scan.rpms(), reading.rpms);
+//! ```
+
+use crate::LaserReading;
+use ::flatbuffers::{
+    FlatBufferBuilder, Follow, ForwardsUOffset, InvalidFlatbuffer, Table, VOffsetT, Vector,
+    Verifiable, Verifier, WIPOffset,
+};
+
+/// File identifier of the encoded buffers.
+pub const FILE_IDENTIFIER: &str = "LDS1";
+
+/// Zero-copy view of an encoded `LaserScan`, borrowing the buffer.
+#[derive(Copy, Clone, PartialEq)]
+pub struct LaserScan<'a> {
+    table: Table<'a>,
+}
+
+impl<'a> Follow<'a> for LaserScan<'a> {
+    type Inner = LaserScan<'a>;
+
+    #[inline]
+    unsafe fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+        Self {
+            table: Table::new(buf, loc),
+        }
+    }
+}
+
+impl<'a> LaserScan<'a> {
+    const VT_RPMS: VOffsetT = 4;
+    const VT_RANGES: VOffsetT = 6;
+    const VT_INTENSITIES: VOffsetT = 8;
+
+    /// Gets the rotation speed of the lidar.
+    pub fn rpms(&self) -> u16 {
+        // Safety: the buffer has been verified by `decode`.
+        unsafe { self.table.get::<u16>(Self::VT_RPMS, Some(0)).unwrap_or(0) }
+    }
+
+    /// Gets the ranges, in mm.
+    pub fn ranges(&self) -> Option<Vector<'a, u16>> {
+        // Safety: the buffer has been verified by `decode`.
+        unsafe {
+            self.table
+                .get::<ForwardsUOffset<Vector<'a, u16>>>(Self::VT_RANGES, None)
+        }
+    }
+
+    /// Gets the intensities.
+    pub fn intensities(&self) -> Option<Vector<'a, u16>> {
+        // Safety: the buffer has been verified by `decode`.
+        unsafe {
+            self.table
+                .get::<ForwardsUOffset<Vector<'a, u16>>>(Self::VT_INTENSITIES, None)
+        }
+    }
+
+    /// Copies the scan into a reading.
+    ///
+    /// Returns `None` if the scan does not have one range and one intensity
+    /// per degree.
+    pub fn to_reading(&self) -> Option<LaserReading> {
+        let mut reading = LaserReading::new();
+        let (ranges, intensities) = (self.ranges()?, self.intensities()?);
+        if ranges.len() != reading.ranges.len() || intensities.len() != reading.intensities.len() {
+            return None;
+        }
+        for (dst, src) in reading.ranges.iter_mut().zip(ranges.iter()) {
+            *dst = src;
+        }
+        for (dst, src) in reading.intensities.iter_mut().zip(intensities.iter()) {
+            *dst = src;
+        }
+        reading.rpms = self.rpms();
+        Some(reading)
+    }
+}
+
+impl Verifiable for LaserScan<'_> {
+    #[inline]
+    fn run_verifier(v: &mut Verifier, pos: usize) -> Result<(), InvalidFlatbuffer> {
+        v.visit_table(pos)?
+            .visit_field::<u16>("rpms", Self::VT_RPMS, false)?
+            .visit_field::<ForwardsUOffset<Vector<'_, u16>>>("ranges", Self::VT_RANGES, false)?
+            .visit_field::<ForwardsUOffset<Vector<'_, u16>>>(
+                "intensities",
+                Self::VT_INTENSITIES,
+                false,
+            )?
+            .finish();
+        Ok(())
+    }
+}
+
+/// Encodes a reading into `builder`, which is reset first, and returns the
+/// finished buffer.
+///
+/// Reusing the same builder avoids allocating for every scan.
+pub fn encode_into<'b>(builder: &'b mut FlatBufferBuilder, reading: &LaserReading) -> &'b [u8] {
+    builder.reset();
+    let ranges = builder.create_vector(&reading.ranges[..]);
+    let intensities = builder.create_vector(&reading.intensities[..]);
+
+    let start = builder.start_table();
+    builder.push_slot_always::<WIPOffset<_>>(LaserScan::VT_RANGES, ranges);
+    builder.push_slot_always::<WIPOffset<_>>(LaserScan::VT_INTENSITIES, intensities);
+    builder.push_slot::<u16>(LaserScan::VT_RPMS, reading.rpms, 0);
+    let root: WIPOffset<LaserScan> = WIPOffset::new(builder.end_table(start).value());
+
+    builder.finish(root, Some(FILE_IDENTIFIER));
+    builder.finished_data()
+}
+
+/// Encodes a reading into a new buffer.
+pub fn encode(reading: &LaserReading) -> Vec<u8> {
+    // Two vectors of 360 u16, plus the table and the headers.
+    let mut builder = FlatBufferBuilder::with_capacity(1536);
+    encode_into(&mut builder, reading).to_vec()
+}
+
+/// Verifies `buf` and gets a zero-copy view of the scan it contains.
+///
+/// # Errors
+/// An error variant is returned if the buffer is not a valid `LaserScan`.
+pub fn decode(buf: &[u8]) -> Result<LaserScan<'_>, InvalidFlatbuffer> {
+    ::flatbuffers::root::<LaserScan>(buf)
+}
+
+/// Checks if `buf` carries the `LDS1` file identifier.
+pub fn has_identifier(buf: &[u8]) -> bool {
+    ::flatbuffers::buffer_has_identifier(buf, FILE_IDENTIFIER, false)
+}
@@ -0,0 +1,307 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Server of the Foxglove WebSocket protocol (`foxglove.websocket.v1`),
+//! enabled by the `foxglove` feature.
+//!
+//! The server advertises a single channel carrying `foxglove.LaserScan`
+//! messages, protobuf encoded with the types of [`crate::proto`], so Foxglove
+//! Studio can connect directly to it.
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use hls_lfcd_lds_driver::foxglove::{FoxgloveOptions, FoxgloveServer};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let server = FoxgloveServer::bind("0.0.0.0:8765", FoxgloveOptions::default()).await?;
+//! server.publish(&reading);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::proto::{self, LaserScan};
+use crate::queue::{self, Overflow};
+use crate::sink::ScanSink;
+use crate::LaserReading;
+use ::tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use ::tokio::task::JoinHandle;
+use futures::{SinkExt, StreamExt};
+use prost::Message as _;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Subprotocol negotiated with the clients.
+pub const SUBPROTOCOL: &str = "foxglove.websocket.v1";
+
+/// Id of the only channel advertised by the server.
+const CHANNEL_ID: u32 = 1;
+/// Opcode of the binary frames carrying a message.
+const MESSAGE_DATA: u8 = 0x01;
+
+/// Options of the Foxglove server.
+#[derive(Debug, Clone)]
+pub struct FoxgloveOptions {
+    /// Name of the server, shown by Foxglove Studio
+    pub name: String,
+    /// Topic of the advertised channel
+    pub topic: String,
+    /// Frame of the scans
+    pub frame_id: String,
+    /// Number of scans queued for each client
+    pub capacity: usize,
+    /// Handling of a client whose queue is full, with `Block` a slow client
+    /// makes `publish` wait, so it must not be called from the runtime threads
+    pub overflow: Overflow,
+}
+
+impl Default for FoxgloveOptions {
+    fn default() -> Self {
+        Self {
+            name: "hls_lfcd_lds_driver".into(),
+            topic: "/scan".into(),
+            frame_id: "laser".into(),
+            capacity: queue::DEFAULT_CAPACITY,
+            overflow: Overflow::DropOldest,
+        }
+    }
+}
+
+/// A message to be sent to every subscribed client.
+#[derive(Clone)]
+struct Outgoing {
+    log_time: u64,
+    payload: Arc<[u8]>,
+}
+
+/// Operations sent by the clients, the unsupported ones are ignored.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+enum ClientOp {
+    Subscribe {
+        subscriptions: Vec<Subscription>,
+    },
+    Unsubscribe {
+        #[serde(rename = "subscriptionIds")]
+        subscription_ids: Vec<u32>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct Subscription {
+    id: u32,
+    #[serde(rename = "channelId")]
+    channel_id: u32,
+}
+
+/// Server of the Foxglove WebSocket protocol, publishing scans to the
+/// connected clients.
+///
+/// Dropping the server stops accepting new clients.
+pub struct FoxgloveServer {
+    clients: Arc<Mutex<Vec<queue::Sender<Outgoing>>>>,
+    frame_id: String,
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl FoxgloveServer {
+    /// Binds the server to `addr` and starts accepting clients in a new task.
+    ///
+    /// # Errors
+    /// An error variant is returned if the address cannot be bound.
+    pub async fn bind<A: ToSocketAddrs>(addr: A, options: FoxgloveOptions) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+
+        let server_info = json!({
+            "op": "serverInfo",
+            "name": options.name,
+            "capabilities": [],
+            "supportedEncodings": [],
+            "metadata": {},
+        })
+        .to_string();
+        let advertise = json!({
+            "op": "advertise",
+            "channels": [{
+                "id": CHANNEL_ID,
+                "topic": options.topic,
+                "encoding": "protobuf",
+                "schemaName": LaserScan::SCHEMA_NAME,
+                // Base64 of the `FileDescriptorSet` of the schema.
+                "schema": data_encoding::BASE64
+                    .encode(&proto::file_descriptor_set().encode_to_vec()),
+            }],
+        })
+        .to_string();
+        let greeting = Arc::new([server_info, advertise]);
+
+        let accepted = clients.clone();
+        let (capacity, overflow) = (options.capacity, options.overflow);
+        let task = ::tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (tx, rx) = queue::channel(capacity, overflow);
+                lock(&accepted).push(tx);
+                ::tokio::spawn(serve(stream, greeting.clone(), rx));
+            }
+        });
+
+        Ok(Self {
+            clients,
+            frame_id: options.frame_id,
+            local_addr,
+            task,
+        })
+    }
+
+    /// Gets the address the server is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Gets the number of connected clients.
+    pub fn clients(&self) -> usize {
+        let mut clients = lock(&self.clients);
+        clients.retain(|c| !c.is_closed());
+        clients.len()
+    }
+
+    /// Publishes a reading to the subscribed clients, stamped with the current time.
+    ///
+    /// A client whose queue is full is handled according to the `overflow` option.
+    pub fn publish(&self, reading: &LaserReading) {
+        // Sends outside of the lock, so a blocked send does not hold back new clients.
+        let clients = lock(&self.clients).clone();
+        if clients.is_empty() {
+            return;
+        }
+        let now = SystemTime::now();
+        let message = LaserScan::from_reading(reading, now, &self.frame_id);
+
+        let scan = Outgoing {
+            log_time: now
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            payload: message.encode_to_vec().into(),
+        };
+        let mut gone = false;
+        for client in &clients {
+            // Fails only when the client has disconnected.
+            gone |= client.send(scan.clone()).is_err();
+        }
+        if gone {
+            lock(&self.clients).retain(|c| !c.is_closed());
+        }
+    }
+}
+
+impl ScanSink for FoxgloveServer {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        self.publish(reading);
+        Ok(())
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Drop for FoxgloveServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Serves a single client, until it disconnects or the server is dropped.
+// The handshake callback signature, and its error type, is imposed by tungstenite.
+#[allow(clippy::result_large_err)]
+async fn serve(stream: TcpStream, greeting: Arc<[String; 2]>, scans: queue::Receiver<Outgoing>) {
+    let negotiate = |req: &Request, mut resp: Response| {
+        let offered = req
+            .headers()
+            .get_all("Sec-WebSocket-Protocol")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|p| p.trim() == SUBPROTOCOL);
+        if offered {
+            resp.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static(SUBPROTOCOL),
+            );
+        }
+        Ok(resp)
+    };
+    let Ok(ws) = tokio_tungstenite::accept_hdr_async(stream, negotiate).await else {
+        return;
+    };
+    let (mut sink, mut source) = ws.split();
+
+    for op in greeting.iter() {
+        if sink.send(Message::Text(op.clone())).await.is_err() {
+            return;
+        }
+    }
+
+    // Subscription id to channel id.
+    let mut subscriptions = HashMap::new();
+    loop {
+        ::tokio::select! {
+            msg = source.next() => match msg {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(ClientOp::Subscribe { subscriptions: subs }) => {
+                        for s in subs {
+                            subscriptions.insert(s.id, s.channel_id);
+                        }
+                    }
+                    Ok(ClientOp::Unsubscribe { subscription_ids }) => {
+                        for id in subscription_ids {
+                            subscriptions.remove(&id);
+                        }
+                    }
+                    _ => {}
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            scan = scans.recv_async() => match scan {
+                Some(scan) => {
+                    for (id, _) in subscriptions.iter().filter(|(_, ch)| **ch == CHANNEL_ID) {
+                        let mut frame = Vec::with_capacity(13 + scan.payload.len());
+                        frame.push(MESSAGE_DATA);
+                        frame.extend_from_slice(&id.to_le_bytes());
+                        frame.extend_from_slice(&scan.log_time.to_le_bytes());
+                        frame.extend_from_slice(&scan.payload);
+                        if sink.send(Message::Binary(frame)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                None => break,
+            },
+        }
+    }
+}
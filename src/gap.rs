@@ -0,0 +1,184 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Reactive obstacle avoidance with the follow-the-gap method.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::gap::FollowGap;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! if let Some(gap) = FollowGap::default().analyze(&reading) {
+//!     println!("steer towards {} rad", gap.heading);
+//! }
+//! ```
+
+use crate::LaserReading;
+use std::f32::consts::PI;
+
+/// Parameters of the follow-the-gap method.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FollowGap {
+    /// Field of view, in radians, centered on the front of the lidar
+    pub fov: f32,
+    /// Distance, in meters, beyond which a beam is free
+    pub free_distance: f32,
+    /// Radius, in meters, of the bubble blocked around the closest obstacle,
+    /// usually the half width of the robot plus a margin
+    pub safety_radius: f32,
+    /// Considers invalid beams as free, the lidar reports 0 when nothing is in range
+    pub invalid_is_free: bool,
+}
+
+impl Default for FollowGap {
+    fn default() -> Self {
+        Self {
+            fov: PI,
+            free_distance: 1.0,
+            safety_radius: 0.2,
+            invalid_is_free: true,
+        }
+    }
+}
+
+/// A gap between obstacles. Angles are in radians, 0 being the front of
+/// the lidar and positive to the left.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gap {
+    /// Angle of the rightmost free beam
+    pub start: f32,
+    /// Angle of the leftmost free beam
+    pub end: f32,
+    /// Angular width of the gap
+    pub width: f32,
+    /// Recommended heading, the center of the gap
+    pub heading: f32,
+}
+
+impl FollowGap {
+    /// Finds the widest gap in the field of view.
+    ///
+    /// Returns `None` if every beam in the field of view is blocked.
+    pub fn analyze(&self, reading: &LaserReading) -> Option<Gap> {
+        let half = (self.fov.to_degrees() / 2.0).round().clamp(0.0, 180.0) as i32;
+        // Signed degrees from right to left, with their beam.
+        let beams: Vec<(i32, usize)> = (-half..=half)
+            .map(|d| (d, d.rem_euclid(360) as usize))
+            .collect();
+        let range = |i: usize| f32::from(reading.ranges[i]) / 1000.0;
+
+        // Bubble around the closest obstacle.
+        let closest = beams
+            .iter()
+            .filter(|(_, i)| reading.is_valid(*i))
+            .min_by(|a, b| range(a.1).total_cmp(&range(b.1)))
+            .map(|(d, i)| (*d, range(*i)));
+        let bubble = |d: i32| {
+            closest.is_some_and(|(cd, cr)| {
+                let half_width = (self.safety_radius / cr).min(1.0).asin().to_degrees();
+                ((d - cd) as f32).abs() <= half_width
+            })
+        };
+
+        let free: Vec<bool> = beams
+            .iter()
+            .map(|&(d, i)| {
+                let open = if reading.is_valid(i) {
+                    range(i) >= self.free_distance
+                } else {
+                    self.invalid_is_free
+                };
+                open && !bubble(d)
+            })
+            .collect();
+
+        // Widest run of free beams.
+        let mut best: Option<(usize, usize)> = None;
+        let mut start = None;
+        for (k, is_free) in free.iter().chain(std::iter::once(&false)).enumerate() {
+            match (is_free, start) {
+                (true, None) => start = Some(k),
+                (false, Some(s)) => {
+                    if best.is_none_or(|(bs, be)| k - s > be - bs + 1) {
+                        best = Some((s, k - 1));
+                    }
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        best.map(|(s, e)| {
+            let start = (beams[s].0 as f32).to_radians();
+            let end = (beams[e].0 as f32).to_radians();
+            Gap {
+                start,
+                end,
+                width: end - start + 1f32.to_radians(),
+                heading: (start + end) / 2.0,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A wall at `wall` mm all around, with an opening 3 m deep from `from`
+    /// to `to` degrees.
+    fn corridor(wall: u16, from: usize, to: usize) -> LaserReading {
+        let mut reading = LaserReading::new();
+        reading.ranges.fill(wall);
+        reading.ranges[from..=to].fill(3000);
+        reading
+    }
+
+    fn degrees(angle: f32) -> f32 {
+        angle.to_degrees().round()
+    }
+
+    #[test]
+    fn steers_to_the_center_of_the_opening() {
+        let gap = FollowGap::default()
+            .analyze(&corridor(500, 30, 60))
+            .unwrap();
+        assert_eq!((degrees(gap.start), degrees(gap.end)), (30.0, 60.0));
+        assert_eq!(degrees(gap.heading), 45.0);
+        assert_eq!(degrees(gap.width), 31.0);
+    }
+
+    #[test]
+    fn blocks_the_bubble_around_the_closest_obstacle() {
+        let mut reading = corridor(800, 30, 90);
+        reading.ranges[20] = 500;
+        let gap = FollowGap::default().analyze(&reading).unwrap();
+        // asin(0.2 / 0.5) is 23.6 degrees.
+        assert_eq!((degrees(gap.start), degrees(gap.end)), (44.0, 90.0));
+        assert_eq!(degrees(gap.heading), 67.0);
+    }
+
+    #[test]
+    fn finds_no_gap_when_blocked() {
+        let mut reading = LaserReading::new();
+        reading.ranges.fill(500);
+        assert_eq!(FollowGap::default().analyze(&reading), None);
+        // Nothing in range is free, unless told otherwise.
+        let blind = FollowGap {
+            invalid_is_free: false,
+            ..FollowGap::default()
+        };
+        assert_eq!(blind.analyze(&LaserReading::new()), None);
+        assert!(FollowGap::default().analyze(&LaserReading::new()).is_some());
+    }
+}
@@ -0,0 +1,58 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Conversions of scans to `geo-types` geometries, enabled by the `geo` feature.
+//!
+//! The geometries can be used with the algorithms of the `geo` crate, e.g.
+//! convex hull, simplification or intersection tests. Coordinates are in
+//! meters, in the lidar frame.
+//!
+//! ```
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//! let points = reading.to_geo_multipoint();
+//! let contour = reading.to_geo_contour();
+//! assert_eq!(points.0.len(), contour.0.len());
+//! ```
+
+use crate::LaserReading;
+use geo_types::{Coord, LineString, MultiPoint, Point, Polygon};
+
+impl LaserReading {
+    fn geo_coords(&self) -> impl Iterator<Item = Coord<f64>> + '_ {
+        self.points().into_iter().map(|(x, y)| Coord {
+            x: f64::from(x),
+            y: f64::from(y),
+        })
+    }
+
+    /// Gets the valid beams as a `MultiPoint`.
+    pub fn to_geo_multipoint(&self) -> MultiPoint<f64> {
+        self.geo_coords().map(Point::from).collect()
+    }
+
+    /// Gets the contour of the scan, joining the valid beams in angle order.
+    ///
+    /// The line is open, invalid beams are skipped.
+    pub fn to_geo_contour(&self) -> LineString<f64> {
+        self.geo_coords().collect()
+    }
+
+    /// Gets the area seen by the lidar, i.e. the polygon closed by the contour.
+    ///
+    /// Invalid beams are skipped, hence the polygon is an approximation
+    /// when the scan has gaps.
+    pub fn to_geo_polygon(&self) -> Polygon<f64> {
+        Polygon::new(self.to_geo_contour(), vec![])
+    }
+}
@@ -0,0 +1,225 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Callbacks that can be attached to an `LFCDLaser` to observe what the
+//! driver is doing (raw bytes, raw frames, scans, decoding problems, reconnections) without
+//! wrapping every `read` call site.
+
+use crate::state::DriverEvent;
+use crate::LaserReading;
+use std::fmt;
+use std::sync::mpsc;
+
+/// Callback invoked for every chunk of bytes read from the serial port.
+pub type BytesCallback = Box<dyn FnMut(&[u8]) + Send>;
+/// Callback invoked for every raw frame, before it is decoded.
+pub type FrameCallback = Box<dyn FnMut(&[u8]) + Send>;
+/// Callback invoked for every complete scan.
+pub type ScanCallback = Box<dyn FnMut(&LaserReading) + Send>;
+/// Callback invoked for every packet that fails to decode.
+pub type DecodeErrorCallback = Box<dyn FnMut(&DecodeError) + Send>;
+/// Callback invoked after the serial port has been re-opened, gets the port name.
+pub type ReconnectCallback = Box<dyn FnMut(&str) + Send>;
+/// Callback invoked for every change of the state of the driver.
+pub type EventCallback = Box<dyn FnMut(&DriverEvent) + Send>;
+
+/// A packet inside a frame that did not carry the expected header
+/// and has been skipped while decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DecodeError {
+    /// Index of the packet inside the frame, from 0 to 59
+    pub packet: usize,
+    /// The two header bytes that were found instead of `0xFA, 0xA0 + packet`
+    pub header: [u8; 2],
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Bad header for packet {}: {:02X?}",
+            self.packet, self.header
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Set of callbacks registered on a driver.
+#[derive(Default)]
+pub struct Hooks {
+    on_bytes: Vec<BytesCallback>,
+    on_frame: Vec<FrameCallback>,
+    on_scan: Vec<ScanCallback>,
+    on_decode_error: Vec<DecodeErrorCallback>,
+    on_reconnect: Vec<ReconnectCallback>,
+    on_event: Vec<EventCallback>,
+    // Dropped as soon as their receiver is.
+    subscribers: Vec<mpsc::Sender<DriverEvent>>,
+}
+
+impl Hooks {
+    /// Creates an empty set of callbacks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a callback invoked for every chunk of bytes read from the
+    /// serial port, before any parsing.
+    pub fn on_bytes<F>(&mut self, f: F)
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.on_bytes.push(Box::new(f));
+    }
+
+    /// Registers a callback invoked for every raw frame, before it is decoded.
+    pub fn on_frame<F>(&mut self, f: F)
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.on_frame.push(Box::new(f));
+    }
+
+    /// Registers a callback invoked for every complete scan.
+    pub fn on_scan<F>(&mut self, f: F)
+    where
+        F: FnMut(&LaserReading) + Send + 'static,
+    {
+        self.on_scan.push(Box::new(f));
+    }
+
+    /// Registers a callback invoked for every packet that fails to decode.
+    pub fn on_decode_error<F>(&mut self, f: F)
+    where
+        F: FnMut(&DecodeError) + Send + 'static,
+    {
+        self.on_decode_error.push(Box::new(f));
+    }
+
+    /// Registers a callback invoked after the serial port has been re-opened.
+    pub fn on_reconnect<F>(&mut self, f: F)
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        self.on_reconnect.push(Box::new(f));
+    }
+
+    /// Registers a callback invoked for every change of the state of the driver.
+    pub fn on_event<F>(&mut self, f: F)
+    where
+        F: FnMut(&DriverEvent) + Send + 'static,
+    {
+        self.on_event.push(Box::new(f));
+    }
+
+    /// Gets a channel receiving every change of the state of the driver.
+    ///
+    /// The sender is dropped on the first event after the receiver is.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<DriverEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Removes all the registered callbacks.
+    pub fn clear(&mut self) {
+        self.on_bytes.clear();
+        self.on_frame.clear();
+        self.on_scan.clear();
+        self.on_decode_error.clear();
+        self.on_reconnect.clear();
+        self.on_event.clear();
+        self.subscribers.clear();
+    }
+
+    /// Invokes the `on_bytes` callbacks.
+    pub fn emit_bytes(&mut self, bytes: &[u8]) {
+        for cb in self.on_bytes.iter_mut() {
+            cb(bytes);
+        }
+    }
+
+    /// Invokes the `on_frame` callbacks.
+    pub fn emit_frame(&mut self, frame: &[u8]) {
+        for cb in self.on_frame.iter_mut() {
+            cb(frame);
+        }
+    }
+
+    /// Invokes the `on_scan` callbacks.
+    pub fn emit_scan(&mut self, reading: &LaserReading) {
+        for cb in self.on_scan.iter_mut() {
+            cb(reading);
+        }
+    }
+
+    /// Invokes the `on_decode_error` callbacks.
+    pub fn emit_decode_error(&mut self, err: &DecodeError) {
+        for cb in self.on_decode_error.iter_mut() {
+            cb(err);
+        }
+    }
+
+    /// Invokes the `on_reconnect` callbacks.
+    pub fn emit_reconnect(&mut self, port: &str) {
+        for cb in self.on_reconnect.iter_mut() {
+            cb(port);
+        }
+    }
+
+    /// Invokes the `on_event` callbacks.
+    pub fn emit_event(&mut self, event: DriverEvent) {
+        for cb in self.on_event.iter_mut() {
+            cb(&event);
+        }
+        self.subscribers.retain(|tx| tx.send(event).is_ok());
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_bytes", &self.on_bytes.len())
+            .field("on_frame", &self.on_frame.len())
+            .field("on_scan", &self.on_scan.len())
+            .field("on_decode_error", &self.on_decode_error.len())
+            .field("on_reconnect", &self.on_reconnect.len())
+            .field("on_event", &self.on_event.len())
+            .field("subscribers", &self.subscribers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_the_closed_subscribers() {
+        let mut hooks = Hooks::new();
+        let kept = hooks.subscribe();
+        drop(hooks.subscribe());
+        assert_eq!(hooks.subscribers.len(), 2);
+
+        hooks.emit_event(DriverEvent::Started);
+        assert_eq!(hooks.subscribers.len(), 1);
+        assert_eq!(kept.try_recv(), Ok(DriverEvent::Started));
+
+        drop(kept);
+        hooks.emit_event(DriverEvent::Stopped);
+        assert!(hooks.subscribers.is_empty());
+    }
+}
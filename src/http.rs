@@ -0,0 +1,366 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! HTTP server exposing the latest scan, enabled by the `http` feature.
+//!
+//! - `GET /scan`: the latest scan as JSON, ranges in mm
+//! - `GET /health`: `200` while scans keep arriving, `503` otherwise
+//! - `GET /metrics`: counters in the Prometheus text format
+//! - `GET /scan/stream`: server-sent events, one `scan` event with the same
+//!   JSON as `/scan` per scan, decimated to `?rate=` Hz, by default the
+//!   `stream_rate` option, the scans in between are skipped
+//!
+//! ```no_run
+//! # async fn run(hooks: &mut hls_lfcd_lds_driver::Hooks) -> std::io::Result<()> {
+//! use hls_lfcd_lds_driver::http::{HttpOptions, HttpServer};
+//!
+//! let server = HttpServer::bind("0.0.0.0:8080", HttpOptions::default()).await?;
+//! // `hooks` are the ones of the driver, see `LFCDLaser::hooks`.
+//! server.attach(hooks);
+//! // curl http://robot:8080/scan
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::sink::ScanSink;
+use crate::{Hooks, LaserReading};
+use ::tokio::net::{TcpListener, ToSocketAddrs};
+use ::tokio::sync::watch;
+use ::tokio::task::JoinHandle;
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use futures::Stream;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Options of the HTTP server.
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    /// Age of the latest scan after which `/health` reports the lidar as down
+    pub stale_after: Duration,
+    /// Default rate, in Hz, of the scans sent by `/scan/stream`, 0 for every scan
+    pub stream_rate: f32,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            // Five revolutions at 300 rpm.
+            stale_after: Duration::from_secs(1),
+            stream_rate: 1.0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Latest {
+    scan: Option<(SystemTime, Instant, LaserReading)>,
+    scans: u64,
+    decode_errors: u64,
+}
+
+type Stamped = Option<Arc<(SystemTime, LaserReading)>>;
+
+struct Shared {
+    latest: Mutex<Latest>,
+    // Wakes the streams up on every scan.
+    stream: watch::Sender<Stamped>,
+    options: HttpOptions,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Latest> {
+        self.latest.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// HTTP server exposing the latest published scan.
+///
+/// Dropping the server stops it.
+pub struct HttpServer {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl HttpServer {
+    /// Binds the server to `addr` and starts serving in a new task.
+    ///
+    /// # Errors
+    /// An error variant is returned if the address cannot be bound.
+    pub async fn bind<A: ToSocketAddrs>(addr: A, options: HttpOptions) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            latest: Mutex::new(Latest::default()),
+            stream: watch::Sender::new(None),
+            options,
+        });
+
+        let app = Router::new()
+            .route("/scan", get(scan))
+            .route("/health", get(health))
+            .route("/metrics", get(metrics))
+            .route("/scan/stream", get(stream))
+            .with_state(shared.clone());
+        let task = ::tokio::spawn(async move {
+            // Fails only if the listener does, the server is then gone.
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Ok(Self {
+            shared,
+            local_addr,
+            task,
+        })
+    }
+
+    /// Gets the address the server is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Publishes a reading, replacing the previous one, stamped with the current time.
+    pub fn publish(&self, reading: &LaserReading) {
+        publish(&self.shared, reading);
+    }
+
+    /// Registers the callbacks publishing the scans, and counting the decode
+    /// errors, of a driver.
+    pub fn attach(&self, hooks: &mut Hooks) {
+        let shared = self.shared.clone();
+        hooks.on_scan(move |reading| publish(&shared, reading));
+        let shared = self.shared.clone();
+        hooks.on_decode_error(move |_| shared.lock().decode_errors += 1);
+    }
+}
+
+impl ScanSink for HttpServer {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        self.publish(reading);
+        Ok(())
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn publish(shared: &Shared, reading: &LaserReading) {
+    let now = SystemTime::now();
+    {
+        let mut latest = shared.lock();
+        latest.scan = Some((now, Instant::now(), reading.clone()));
+        latest.scans += 1;
+    }
+    if shared.stream.receiver_count() > 0 {
+        shared
+            .stream
+            .send_replace(Some(Arc::new((now, reading.clone()))));
+    }
+}
+
+fn to_json(timestamp: SystemTime, reading: &LaserReading) -> Value {
+    let timestamp = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    json!({
+        "timestamp_ms": timestamp,
+        "rpms": reading.rpms,
+        "ranges": reading.ranges.to_vec(),
+        "intensities": reading.intensities.to_vec(),
+    })
+}
+
+async fn scan(State(shared): State<Arc<Shared>>) -> Response {
+    let latest = shared.lock();
+    let Some((timestamp, _, reading)) = &latest.scan else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "no scan yet").into_response();
+    };
+    Json(to_json(*timestamp, reading)).into_response()
+}
+
+#[derive(Deserialize)]
+struct StreamParams {
+    rate: Option<f32>,
+}
+
+async fn stream(
+    State(shared): State<Arc<Shared>>,
+    Query(params): Query<StreamParams>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let rate = params.rate.unwrap_or(shared.options.stream_rate);
+    let period = if rate > 0.0 {
+        Duration::try_from_secs_f32(1.0 / rate).unwrap_or(Duration::MAX)
+    } else {
+        Duration::ZERO
+    };
+    let scans = shared.stream.subscribe();
+
+    let events = futures::stream::unfold(
+        (scans, None::<::tokio::time::Instant>),
+        move |(mut scans, last)| async move {
+            scans.changed().await.ok()?;
+            if let Some(last) = last {
+                // Waits for the next slot, sending the latest scan at that time.
+                ::tokio::time::sleep_until(last + period).await;
+            }
+            let scan = scans.borrow_and_update().clone()?;
+            let event = Event::default()
+                .event("scan")
+                .data(to_json(scan.0, &scan.1).to_string());
+            Some((Ok(event), (scans, Some(::tokio::time::Instant::now()))))
+        },
+    );
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn health(State(shared): State<Arc<Shared>>) -> Response {
+    let age = shared.lock().scan.as_ref().map(|(_, at, _)| at.elapsed());
+    match age {
+        Some(age) if age <= shared.options.stale_after => Json(json!({
+            "status": "ok",
+            "age_ms": age.as_millis() as u64,
+        }))
+        .into_response(),
+        _ => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "status": "stale",
+                "age_ms": age.map(|a| a.as_millis() as u64),
+            })),
+        )
+            .into_response(),
+    }
+}
+
+async fn metrics(State(shared): State<Arc<Shared>>) -> Response {
+    let latest = shared.lock();
+    let (age, rpms, valid) = match &latest.scan {
+        Some((_, at, reading)) => (
+            at.elapsed().as_secs_f64(),
+            reading.rpms,
+            (0..reading.ranges.len())
+                .filter(|&i| reading.is_valid(i))
+                .count(),
+        ),
+        None => (f64::NAN, 0, 0),
+    };
+    let body = format!(
+        "# TYPE lds_scans_total counter\n\
+         lds_scans_total {}\n\
+         # TYPE lds_decode_errors_total counter\n\
+         lds_decode_errors_total {}\n\
+         # TYPE lds_last_scan_age_seconds gauge\n\
+         lds_last_scan_age_seconds {}\n\
+         # TYPE lds_rpms gauge\n\
+         lds_rpms {}\n\
+         # TYPE lds_valid_beams gauge\n\
+         lds_valid_beams {}\n",
+        latest.scans, latest.decode_errors, age, rpms, valid
+    );
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    /// Sends a `GET` and reads the whole response.
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: lds\r\nConnection: close\r\n\r\n");
+        ::tokio::task::spawn_blocking(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+        .await
+        .unwrap()
+    }
+
+    #[::tokio::test]
+    async fn serves_the_latest_scan() {
+        let server = HttpServer::bind("127.0.0.1:0", HttpOptions::default())
+            .await
+            .unwrap();
+        let addr = server.local_addr();
+        assert!(get(addr, "/scan").await.starts_with("HTTP/1.1 503"));
+        assert!(get(addr, "/health").await.contains("\"stale\""));
+
+        let mut reading = LaserReading::new();
+        reading.rpms = 300;
+        reading.ranges[0] = 1000;
+        server.publish(&reading);
+
+        let scan = get(addr, "/scan").await;
+        assert!(scan.starts_with("HTTP/1.1 200"));
+        let body: Value = serde_json::from_str(scan.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["rpms"], 300);
+        assert_eq!(body["ranges"][0], 1000);
+        assert!(get(addr, "/health").await.contains("\"ok\""));
+
+        let metrics = get(addr, "/metrics").await;
+        assert!(metrics.contains("lds_scans_total 1\n"));
+        assert!(metrics.contains("lds_valid_beams 1\n"));
+    }
+
+    #[::tokio::test]
+    async fn streams_the_scans() {
+        let server = HttpServer::bind("127.0.0.1:0", HttpOptions::default())
+            .await
+            .unwrap();
+        let addr = server.local_addr();
+        let events = ::tokio::task::spawn_blocking(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /scan/stream?rate=0 HTTP/1.1\r\nHost: lds\r\n\r\n")
+                .unwrap();
+            let mut response = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !String::from_utf8_lossy(&response).contains("\"rpms\":300") {
+                let n = stream.read(&mut buf).unwrap();
+                assert!(n > 0, "stream closed");
+                response.extend_from_slice(&buf[..n]);
+            }
+            String::from_utf8(response).unwrap()
+        });
+
+        let mut reading = LaserReading::new();
+        reading.rpms = 300;
+        // Publishes until the stream, subscribed once the request is in, gets a scan.
+        while !events.is_finished() {
+            server.publish(&reading);
+            ::tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let events = events.await.unwrap();
+        assert!(events.contains("text/event-stream"));
+        assert!(events.contains("event: scan\n"));
+    }
+}
@@ -0,0 +1,281 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Stopping the motor of a lidar nobody reads, see `LFCDLaser::set_idle_stop`.
+//!
+//! A thread of the driver stops the motor once no read has been made for
+//! the idle timeout. The next read starts it again and drops the scans of
+//! the warm-up, taken while the motor speeds up, so scanning intermittently
+//! does not need any care from the caller.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "sync", unix))]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use hls_lfcd_lds_driver::idle::IdleOptions;
+//! use hls_lfcd_lds_driver::sync::LFCDLaser;
+//! use std::time::Duration;
+//!
+//! let mut laser = LFCDLaser::new("/dev/ttyUSB0", 230400)?;
+//! laser.set_idle_stop(Some(IdleOptions {
+//!     timeout: Duration::from_secs(30),
+//!     ..Default::default()
+//! }))?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(all(feature = "sync", unix)))]
+//! # fn main() {}
+//! ```
+
+use std::time::Duration;
+
+/// When the motor is stopped and how it is started again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ser_de", derive(serde::Serialize, serde::Deserialize))]
+pub struct IdleOptions {
+    /// Time without reads after which the motor is stopped
+    pub timeout: Duration,
+    /// Scans dropped after the motor has been started again
+    pub warmup_scans: usize,
+}
+
+impl Default for IdleOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            // A second at 300 rpm.
+            warmup_scans: 5,
+        }
+    }
+}
+
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+pub(crate) use self::monitor::{Busy, IdleMonitor};
+
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+mod monitor {
+    use super::IdleOptions;
+    use crate::protocol::STOP_BYTE;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+    use std::thread::{self, JoinHandle};
+    use std::time::Instant;
+
+    struct State {
+        last_read: Instant,
+        busy: bool,
+        stopped: bool,
+        shutdown: bool,
+    }
+
+    struct Shared {
+        state: Mutex<State>,
+        changed: Condvar,
+    }
+
+    impl Shared {
+        fn lock(&self) -> MutexGuard<'_, State> {
+            self.state.lock().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    /// Thread stopping the motor once the driver is idle.
+    pub(crate) struct IdleMonitor {
+        shared: Arc<Shared>,
+        options: IdleOptions,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl IdleMonitor {
+        /// Starts monitoring, the motor is stopped by writing to `port`.
+        pub(crate) fn spawn<W>(mut port: W, options: IdleOptions) -> io::Result<Self>
+        where
+            W: Write + Send + 'static,
+        {
+            let shared = Arc::new(Shared {
+                state: Mutex::new(State {
+                    last_read: Instant::now(),
+                    busy: false,
+                    stopped: false,
+                    shutdown: false,
+                }),
+                changed: Condvar::new(),
+            });
+
+            let monitor = shared.clone();
+            let thread = thread::Builder::new()
+                .name("lds-idle".into())
+                .spawn(move || {
+                    let mut state = monitor.lock();
+                    while !state.shutdown {
+                        if state.busy || state.stopped {
+                            state = monitor
+                                .changed
+                                .wait(state)
+                                .unwrap_or_else(|e| e.into_inner());
+                            continue;
+                        }
+                        let idle = state.last_read.elapsed();
+                        if idle >= options.timeout {
+                            // Retried at the next timeout if it fails.
+                            if port.write_all(&[STOP_BYTE]).is_ok() {
+                                state.stopped = true;
+                            } else {
+                                state.last_read = Instant::now();
+                            }
+                            continue;
+                        }
+                        state = monitor
+                            .changed
+                            .wait_timeout(state, options.timeout - idle)
+                            .unwrap_or_else(|e| e.into_inner())
+                            .0;
+                    }
+                })?;
+
+            Ok(Self {
+                shared,
+                options,
+                thread: Some(thread),
+            })
+        }
+
+        /// Gets the options of the monitor.
+        pub(crate) fn options(&self) -> IdleOptions {
+            self.options
+        }
+
+        /// Marks a read in progress until the returned guard is dropped,
+        /// returns `true` if the motor has been stopped meanwhile.
+        pub(crate) fn enter(&self) -> (Busy, bool) {
+            let mut state = self.shared.lock();
+            state.busy = true;
+            let stopped = std::mem::replace(&mut state.stopped, false);
+            (
+                Busy {
+                    shared: self.shared.clone(),
+                },
+                stopped,
+            )
+        }
+
+        /// Checks if the motor has been stopped for being idle.
+        pub(crate) fn is_stopped(&self) -> bool {
+            self.shared.lock().stopped
+        }
+
+        /// Records that the motor has been started by the driver.
+        pub(crate) fn started(&self) {
+            let mut state = self.shared.lock();
+            state.stopped = false;
+            state.last_read = Instant::now();
+            self.shared.changed.notify_one();
+        }
+    }
+
+    impl Drop for IdleMonitor {
+        fn drop(&mut self) {
+            self.shared.lock().shutdown = true;
+            self.shared.changed.notify_one();
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    /// A read in progress, the motor is not stopped meanwhile.
+    pub(crate) struct Busy {
+        shared: Arc<Shared>,
+    }
+
+    impl Drop for Busy {
+        fn drop(&mut self) {
+            let mut state = self.shared.lock();
+            state.busy = false;
+            state.last_read = Instant::now();
+            self.shared.changed.notify_one();
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "sync", feature = "async_tokio", feature = "async_smol")
+))]
+mod tests {
+    use super::*;
+    use crate::protocol::STOP_BYTE;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    #[derive(Clone, Default)]
+    struct Port(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Port {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn monitor(port: &Port) -> IdleMonitor {
+        let options = IdleOptions {
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        IdleMonitor::spawn(port.clone(), options).unwrap()
+    }
+
+    fn wait_until(f: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !f() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        true
+    }
+
+    #[test]
+    fn stops_the_motor_once_idle() {
+        let port = Port::default();
+        let monitor = monitor(&port);
+        assert!(wait_until(|| monitor.is_stopped()));
+        assert_eq!(*port.0.lock().unwrap(), [STOP_BYTE]);
+
+        // The next read learns the motor has been stopped, once.
+        let (busy, stopped) = monitor.enter();
+        assert!(stopped);
+        drop(busy);
+        monitor.started();
+        assert!(!monitor.enter().1);
+    }
+
+    #[test]
+    fn keeps_the_motor_running_while_reading() {
+        let port = Port::default();
+        let monitor = monitor(&port);
+        let (busy, _) = monitor.enter();
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(!monitor.is_stopped());
+        assert!(port.0.lock().unwrap().is_empty());
+        drop(busy);
+        assert!(wait_until(|| monitor.is_stopped()));
+    }
+}
@@ -0,0 +1,185 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Reads filling a whole buffer out of the partial reads of the serial port.
+//!
+//! Interrupted system calls are retried, and a timeout only fails the read
+//! when no byte at all arrived during the timeout period. On errors
+//! `filled` tells how much of the buffer is valid, so that the frame can be
+//! resumed by the next read.
+//!
+//! The serial crates only take UTF-8 port names, other paths go through
+//! their canonical path, e.g. the device a link points to. On macOS and the
+//! BSDs the dial-in devices, `/dev/tty.*` and `/dev/tty*`, are replaced by
+//! their call-out twin, `/dev/cu.*` and `/dev/cua*`: opening a dial-in
+//! device waits for a carrier the lidar never sends.
+
+#[cfg(any(
+    feature = "sync",
+    feature = "async_tokio",
+    feature = "async_smol",
+    feature = "ydlidar"
+))]
+use std::borrow::Cow;
+use std::io;
+#[cfg(any(
+    feature = "sync",
+    feature = "async_tokio",
+    feature = "async_smol",
+    feature = "ydlidar"
+))]
+use std::path::Path;
+
+/// Gets the name of a port to give to the serial crates.
+///
+/// # Errors
+/// An error variant is returned if neither the path nor its canonical path
+/// are valid UTF-8.
+#[cfg(any(
+    feature = "sync",
+    feature = "async_tokio",
+    feature = "async_smol",
+    feature = "ydlidar"
+))]
+pub(crate) fn port_name(path: &Path) -> io::Result<Cow<'_, str>> {
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))]
+    if let Some(callout) = callout(path) {
+        return Ok(Cow::Owned(callout));
+    }
+    if let Some(name) = path.to_str() {
+        return Ok(Cow::Borrowed(name));
+    }
+    std::fs::canonicalize(path)?
+        .into_os_string()
+        .into_string()
+        .map(Cow::Owned)
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No UTF-8 name for the port {}", path.display()),
+            )
+        })
+}
+
+/// Gets the call-out device of a dial-in one, `None` for the other ports or
+/// if it does not exist.
+#[cfg(all(
+    any(
+        feature = "sync",
+        feature = "async_tokio",
+        feature = "async_smol",
+        feature = "ydlidar"
+    ),
+    any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    )
+))]
+fn callout(path: &Path) -> Option<String> {
+    let (dial_in, call_out) = crate::devices::DIAL_IN_CALL_OUT;
+    let name = path
+        .to_str()?
+        .strip_prefix("/dev/")?
+        .strip_prefix(dial_in)?;
+    let callout = format!("/dev/{call_out}{name}");
+    Path::new(&callout).exists().then_some(callout)
+}
+
+/// Fills `buf[*filled..]` from a blocking reader.
+///
+/// # Errors
+/// An error variant is returned if the reader fails, times out without
+/// receiving anything, or reaches its end.
+pub(crate) fn read_full<R: io::Read + ?Sized>(
+    reader: &mut R,
+    buf: &mut [u8],
+    filled: &mut usize,
+) -> io::Result<()> {
+    let mut progressed = false;
+    while *filled < buf.len() {
+        match reader.read(&mut buf[*filled..]) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                *filled += n;
+                progressed = true;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            // The data is still flowing, just slower than the timeout.
+            Err(e) if e.kind() == io::ErrorKind::TimedOut && progressed => progressed = false,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Fills `buf[*filled..]` from a tokio reader.
+///
+/// # Errors
+/// An error variant is returned if the reader fails or reaches its end.
+#[cfg(feature = "async_tokio")]
+pub(crate) async fn read_full_tokio<R>(
+    reader: &mut R,
+    buf: &mut [u8],
+    filled: &mut usize,
+) -> io::Result<()>
+where
+    R: ::tokio::io::AsyncRead + Unpin + ?Sized,
+{
+    use ::tokio::io::AsyncReadExt;
+
+    while *filled < buf.len() {
+        match reader.read(&mut buf[*filled..]).await {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => *filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Fills `buf[*filled..]` from a `futures` reader.
+///
+/// # Errors
+/// An error variant is returned if the reader fails or reaches its end.
+#[cfg(feature = "async_smol")]
+pub(crate) async fn read_full_async<R>(
+    reader: &mut R,
+    buf: &mut [u8],
+    filled: &mut usize,
+) -> io::Result<()>
+where
+    R: futures::io::AsyncRead + Unpin + ?Sized,
+{
+    use futures::io::AsyncReadExt;
+
+    while *filled < buf.len() {
+        match reader.read(&mut buf[*filled..]).await {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => *filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
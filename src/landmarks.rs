@@ -0,0 +1,347 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Extraction of wall segments, corners and doorways, usable as landmarks
+//! for simple localization.
+//!
+//! Walls are found splitting every cluster of the scan until its points fit
+//! a line, corners are where two walls meet and doorways are openings of a
+//! plausible width between two walls.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::landmarks::LandmarkDetector;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let detector = LandmarkDetector::default();
+//! for corner in detector.corners(&reading) {
+//!     println!("corner at ({}, {})", corner.x, corner.y);
+//! }
+//! for door in detector.doorways(&reading) {
+//!     println!("{} m wide doorway at ({}, {})", door.width, door.x, door.y);
+//! }
+//! ```
+
+use crate::cluster::clusters;
+use crate::LaserReading;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_3, PI};
+
+/// Parameters of the detector, distances in meters and angles in radians.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LandmarkDetector {
+    /// Maximum distance between two points of the same cluster
+    pub cluster_gap: f32,
+    /// Maximum distance of a point from its wall
+    pub line_tolerance: f32,
+    /// Minimum number of beams hitting a wall
+    pub min_points: usize,
+    /// Minimum length of a wall
+    pub min_length: f32,
+    /// Minimum angle between two walls meeting at a corner
+    pub min_corner_angle: f32,
+    /// Minimum width of a doorway
+    pub min_door_width: f32,
+    /// Maximum width of a doorway
+    pub max_door_width: f32,
+}
+
+impl Default for LandmarkDetector {
+    fn default() -> Self {
+        Self {
+            cluster_gap: 0.15,
+            line_tolerance: 0.03,
+            min_points: 5,
+            min_length: 0.2,
+            min_corner_angle: FRAC_PI_3,
+            min_door_width: 0.6,
+            max_door_width: 1.2,
+        }
+    }
+}
+
+/// A wall segment, coordinates in meters in the lidar frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineSegment {
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+    /// First and last beam of the segment
+    pub beams: (usize, usize),
+}
+
+impl LineSegment {
+    /// Gets the length of the segment.
+    pub fn length(&self) -> f32 {
+        (self.end.0 - self.start.0).hypot(self.end.1 - self.start.1)
+    }
+
+    /// Gets the direction of the segment, in radians.
+    pub fn direction(&self) -> f32 {
+        (self.end.1 - self.start.1).atan2(self.end.0 - self.start.0)
+    }
+}
+
+/// A corner between two walls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Corner {
+    pub x: f32,
+    pub y: f32,
+    /// Angle between the two walls, from `min_corner_angle` to 90 degrees
+    pub angle: f32,
+    pub walls: [LineSegment; 2],
+}
+
+/// An opening between two walls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Doorway {
+    /// Center of the opening
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    /// Edges of the opening, in angle order
+    pub edges: [(f32, f32); 2],
+}
+
+impl LandmarkDetector {
+    /// Extracts the wall segments, in angle order.
+    pub fn lines(&self, reading: &LaserReading) -> Vec<LineSegment> {
+        self.walls(reading).into_iter().flatten().collect()
+    }
+
+    /// Extracts the wall segments of every cluster.
+    fn walls(&self, reading: &LaserReading) -> Vec<Vec<LineSegment>> {
+        clusters(reading, self.cluster_gap)
+            .into_iter()
+            .map(|c| {
+                let mut segments = Vec::new();
+                self.split(reading, &c, &mut segments);
+                segments
+            })
+            .collect()
+    }
+
+    /// Splits the beams at the farthest point from their chord until they fit a line.
+    fn split(&self, reading: &LaserReading, beams: &[usize], out: &mut Vec<LineSegment>) {
+        if beams.len() < self.min_points {
+            return;
+        }
+        let a = reading.point(beams[0]);
+        let b = reading.point(beams[beams.len() - 1]);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len = dx.hypot(dy).max(f32::EPSILON);
+
+        let (farthest, distance) = beams
+            .iter()
+            .enumerate()
+            .map(|(k, &i)| {
+                let p = reading.point(i);
+                (k, ((p.0 - a.0) * dy - (p.1 - a.1) * dx).abs() / len)
+            })
+            .max_by(|p, q| p.1.total_cmp(&q.1))
+            .unwrap_or((0, 0.0));
+
+        if distance > self.line_tolerance && farthest > 0 && farthest < beams.len() - 1 {
+            self.split(reading, &beams[..=farthest], out);
+            self.split(reading, &beams[farthest..], out);
+            return;
+        }
+
+        if let Some(segment) = fit(reading, beams).filter(|s| s.length() >= self.min_length) {
+            out.push(segment);
+        }
+    }
+
+    /// Finds the corners between consecutive walls of the same cluster.
+    pub fn corners(&self, reading: &LaserReading) -> Vec<Corner> {
+        let mut corners = Vec::new();
+        for walls in self.walls(reading) {
+            for pair in walls.windows(2) {
+                let (w1, w2) = (pair[0], pair[1]);
+                let mut angle = (w1.direction() - w2.direction()).abs() % PI;
+                if angle > FRAC_PI_2 {
+                    angle = PI - angle;
+                }
+                if angle < self.min_corner_angle {
+                    continue;
+                }
+                let Some((x, y)) = intersection(&w1, &w2) else {
+                    continue;
+                };
+                // Discards intersections far from where the walls meet.
+                let joint = w1.end;
+                if (x - joint.0).hypot(y - joint.1) > self.cluster_gap {
+                    continue;
+                }
+                corners.push(Corner {
+                    x,
+                    y,
+                    angle,
+                    walls: [w1, w2],
+                });
+            }
+        }
+        corners
+    }
+
+    /// Finds the openings between the last wall of a cluster and the first
+    /// wall of the next one, through which the lidar sees farther.
+    pub fn doorways(&self, reading: &LaserReading) -> Vec<Doorway> {
+        let walls: Vec<Vec<LineSegment>> = self
+            .walls(reading)
+            .into_iter()
+            .filter(|w| !w.is_empty())
+            .collect();
+        let n = reading.ranges.len();
+
+        let mut doors = Vec::new();
+        for k in 0..walls.len() {
+            let (Some(left), Some(right)) = (walls[k].last(), walls[(k + 1) % walls.len()].first())
+            else {
+                continue;
+            };
+            // A lone wall cannot be both edges.
+            if std::ptr::eq(left, right) {
+                continue;
+            }
+            let (a, b) = (left.end, right.start);
+            let width = (a.0 - b.0).hypot(a.1 - b.1);
+            if width < self.min_door_width || width > self.max_door_width {
+                continue;
+            }
+
+            // Every beam inside the opening must be invalid or beyond its edges.
+            let near = a.0.hypot(a.1).min(b.0.hypot(b.1));
+            let first = (left.beams.1 + 1) % n;
+            let count = (right.beams.0 + n - first) % n;
+            let open = (0..count)
+                .map(|j| (first + j) % n)
+                .all(|i| !reading.is_valid(i) || f32::from(reading.ranges[i]) / 1000.0 > near);
+            if !open {
+                continue;
+            }
+
+            doors.push(Doorway {
+                x: (a.0 + b.0) / 2.0,
+                y: (a.1 + b.1) / 2.0,
+                width,
+                edges: [a, b],
+            });
+        }
+        doors
+    }
+}
+
+/// Fits a line to the points of the beams, with total least squares, and
+/// gets the segment between the projections of the first and last points.
+fn fit(reading: &LaserReading, beams: &[usize]) -> Option<LineSegment> {
+    let points: Vec<(f32, f32)> = beams.iter().map(|&i| reading.point(i)).collect();
+    let n = points.len() as f32;
+    let (mx, my) = points
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), p| (sx + p.0 / n, sy + p.1 / n));
+    let (sxx, syy, sxy) = points.iter().fold((0.0, 0.0, 0.0), |(xx, yy, xy), p| {
+        let (dx, dy) = (p.0 - mx, p.1 - my);
+        (xx + dx * dx, yy + dy * dy, xy + dx * dy)
+    });
+    let theta = 0.5 * (2.0 * sxy).atan2(sxx - syy);
+    let (dy, dx) = theta.sin_cos();
+    let project = |p: (f32, f32)| {
+        let t = (p.0 - mx) * dx + (p.1 - my) * dy;
+        (mx + t * dx, my + t * dy)
+    };
+
+    Some(LineSegment {
+        start: project(*points.first()?),
+        end: project(*points.last()?),
+        beams: (*beams.first()?, *beams.last()?),
+    })
+}
+
+/// Intersects the lines through two segments, `None` if they are parallel.
+fn intersection(a: &LineSegment, b: &LineSegment) -> Option<(f32, f32)> {
+    let (d1x, d1y) = (a.end.0 - a.start.0, a.end.1 - a.start.1);
+    let (d2x, d2y) = (b.end.0 - b.start.0, b.end.1 - b.start.1);
+    let den = d1x * d2y - d1y * d2x;
+    if den.abs() < f32::EPSILON {
+        return None;
+    }
+    let t = ((b.start.0 - a.start.0) * d2y - (b.start.1 - a.start.1) * d2x) / den;
+    Some((a.start.0 + t * d1x, a.start.1 + t * d1y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Range, in mm, of the beam at `degree` hitting the wall `x = d` or,
+    /// with `along_y`, the wall `y = d`.
+    fn wall(degree: i32, d: f32, along_y: bool) -> u16 {
+        let (sin, cos) = (degree as f32).to_radians().sin_cos();
+        let range = if along_y { d / sin } else { d / cos };
+        (range * 1000.0).round() as u16
+    }
+
+    fn beam(degree: i32) -> usize {
+        degree.rem_euclid(360) as usize
+    }
+
+    #[test]
+    fn finds_the_corner_of_a_room() {
+        let mut reading = LaserReading::new();
+        for degree in -30..=120 {
+            reading.ranges[beam(degree)] = wall(degree, 1.0, degree > 45);
+        }
+
+        let detector = LandmarkDetector::default();
+        assert_eq!(detector.lines(&reading).len(), 2);
+        let corners = detector.corners(&reading);
+        assert_eq!(corners.len(), 1);
+        let corner = corners[0];
+        assert!((corner.x - 1.0).abs() < 0.03 && (corner.y - 1.0).abs() < 0.03);
+        assert!((corner.angle - FRAC_PI_2).abs() < 0.05);
+    }
+
+    #[test]
+    fn finds_a_doorway_in_a_wall() {
+        let mut reading = LaserReading::new();
+        for degree in -40..=40 {
+            reading.ranges[beam(degree)] = if degree.abs() < 15 {
+                // Nothing within range behind the doorway.
+                0
+            } else {
+                wall(degree, 1.5, false)
+            };
+        }
+
+        let doors = LandmarkDetector::default().doorways(&reading);
+        assert_eq!(doors.len(), 1);
+        let door = doors[0];
+        assert!((door.x - 1.5).abs() < 0.03 && door.y.abs() < 0.03);
+        assert!((door.width - 0.8).abs() < 0.03, "{} m wide", door.width);
+    }
+
+    #[test]
+    fn needs_an_opening_behind_a_doorway() {
+        let mut reading = LaserReading::new();
+        for degree in -40..=40 {
+            reading.ranges[beam(degree)] = if degree.abs() < 2 {
+                // Something too small for a wall stands in the opening.
+                1000
+            } else if degree.abs() < 15 {
+                0
+            } else {
+                wall(degree, 1.5, false)
+            };
+        }
+        assert!(LandmarkDetector::default().doorways(&reading).is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Heuristic detection of legs and people, e.g. for follow-me robots.
+//!
+//! The scan is split in clusters, the small ones bulging towards the lidar
+//! are legs, and two legs close enough are a person. With the lidar at the
+//! height of a TurtleBot3 the beams hit the lower part of the legs.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::legs::LegDetector;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! for person in LegDetector::default().detect_people(&reading) {
+//!     println!("person at ({}, {})", person.x, person.y);
+//! }
+//! ```
+
+use crate::cluster::clusters;
+use crate::LaserReading;
+
+/// Parameters of the detector, distances in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LegDetector {
+    /// Maximum distance between two points of the same cluster
+    pub cluster_gap: f32,
+    /// Minimum number of beams hitting a leg
+    pub min_points: usize,
+    /// Minimum width of a leg
+    pub min_width: f32,
+    /// Maximum width of a leg
+    pub max_width: f32,
+    /// Minimum distance between the two legs of a person
+    pub min_separation: f32,
+    /// Maximum distance between the two legs of a person
+    pub max_separation: f32,
+}
+
+impl Default for LegDetector {
+    fn default() -> Self {
+        Self {
+            cluster_gap: 0.1,
+            min_points: 3,
+            min_width: 0.05,
+            max_width: 0.25,
+            min_separation: 0.05,
+            max_separation: 0.5,
+        }
+    }
+}
+
+/// A leg candidate, coordinates in meters in the lidar frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Leg {
+    pub x: f32,
+    pub y: f32,
+    /// Distance between the first and the last point
+    pub width: f32,
+    /// First and last beam of the cluster
+    pub beams: (usize, usize),
+}
+
+/// A person candidate, between two legs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Person {
+    pub x: f32,
+    pub y: f32,
+    pub legs: [Leg; 2],
+}
+
+impl LegDetector {
+    /// Finds the clusters that look like a leg.
+    pub fn detect_legs(&self, reading: &LaserReading) -> Vec<Leg> {
+        clusters(reading, self.cluster_gap)
+            .into_iter()
+            .filter(|c| c.len() >= self.min_points)
+            .filter_map(|c| {
+                let points: Vec<(f32, f32)> = c.iter().map(|&i| reading.point(i)).collect();
+                let (first, last) = (points[0], points[points.len() - 1]);
+                let width = (first.0 - last.0).hypot(first.1 - last.1);
+                if width < self.min_width || width > self.max_width {
+                    return None;
+                }
+
+                // A leg is round, its middle is closer to the lidar than the chord.
+                let chord = ((first.0 + last.0) / 2.0).hypot((first.1 + last.1) / 2.0);
+                let middle = points[points.len() / 2];
+                if middle.0.hypot(middle.1) >= chord {
+                    return None;
+                }
+
+                let n = points.len() as f32;
+                let (sx, sy) = points
+                    .iter()
+                    .fold((0.0, 0.0), |(sx, sy), p| (sx + p.0, sy + p.1));
+                Some(Leg {
+                    x: sx / n,
+                    y: sy / n,
+                    width,
+                    beams: (c[0], c[c.len() - 1]),
+                })
+            })
+            .collect()
+    }
+
+    /// Finds the people, pairing the closest legs first; every leg belongs
+    /// to at most one person.
+    pub fn detect_people(&self, reading: &LaserReading) -> Vec<Person> {
+        let legs = self.detect_legs(reading);
+
+        let mut pairs = Vec::new();
+        for a in 0..legs.len() {
+            for b in a + 1..legs.len() {
+                let d = (legs[a].x - legs[b].x).hypot(legs[a].y - legs[b].y);
+                if d >= self.min_separation && d <= self.max_separation {
+                    pairs.push((d, a, b));
+                }
+            }
+        }
+        pairs.sort_by(|p, q| p.0.total_cmp(&q.0));
+
+        let mut used = vec![false; legs.len()];
+        let mut people = Vec::new();
+        for (_, a, b) in pairs {
+            if used[a] || used[b] {
+                continue;
+            }
+            used[a] = true;
+            used[b] = true;
+            people.push(Person {
+                x: (legs[a].x + legs[b].x) / 2.0,
+                y: (legs[a].y + legs[b].y) / 2.0,
+                legs: [legs[a], legs[b]],
+            });
+        }
+        people
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Puts a leg 1 m away, bulging towards the lidar, from `degree` on.
+    fn leg(reading: &mut LaserReading, degree: usize) {
+        let profile = [1050, 1020, 1000, 1000, 1020, 1050];
+        reading.ranges[degree..degree + profile.len()].copy_from_slice(&profile);
+    }
+
+    #[test]
+    fn pairs_two_legs_into_a_person() {
+        let mut reading = LaserReading::new();
+        leg(&mut reading, 10);
+        leg(&mut reading, 22);
+        // A flat wall and a wide object are not legs.
+        reading.ranges[90..=95].fill(1000);
+        reading.ranges[180..=210].fill(1000);
+
+        let detector = LegDetector::default();
+        let legs = detector.detect_legs(&reading);
+        assert_eq!(
+            legs.iter().map(|l| l.beams).collect::<Vec<_>>(),
+            [(10, 15), (22, 27)]
+        );
+
+        let people = detector.detect_people(&reading);
+        assert_eq!(people.len(), 1);
+        let angle = people[0].y.atan2(people[0].x).to_degrees();
+        assert!((angle - 18.5).abs() < 0.5, "person at {angle} degrees");
+    }
+
+    #[test]
+    fn leaves_a_lone_leg_alone() {
+        let mut reading = LaserReading::new();
+        leg(&mut reading, 10);
+        leg(&mut reading, 100);
+        let detector = LegDetector::default();
+        assert_eq!(detector.detect_legs(&reading).len(), 2);
+        assert!(detector.detect_people(&reading).is_empty());
+    }
+}
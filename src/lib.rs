@@ -14,495 +14,382 @@
 
 //! hls_lfcd_lds_driver provides a rust version of the LDS01 driver from robotis.
 //! This crate facilitates reading information from that specific lidar.
+//!
+//! # Backends
+//!
+//! The driver is available in three flavours, each one behind a feature and
+//! in its own module, so that several of them can be enabled at the same time:
+//! `tokio::LFCDLaser` (`async_tokio`, the default), `smol::LFCDLaser`
+//! (`async_smol`) and `sync::LFCDLaser` (`sync`). When a single backend is
+//! enabled its driver is also available as `LFCDLaser`, and `any::AnyLaser`
+//! picks one of them at runtime.
+//!
+//! Adapters the OS does not expose as a serial port are read by
+//! `transport::TransportLaser`, over any `transport::Transport` byte stream;
+//! the `android_usb` and `nusb` features provide the ones driving the CP2102
+//! adapter from user space.
+//!
+//! # Opening the lidar
+//!
+//! `OpenOptions::new(port, baud_rate).open::<LFCDLaser>()` checks the model,
+//! the baud rate and the timeout before the port is touched, and with
+//! `auto_start(false)` leaves the motor stopped until `start()`.
+//! `devices::resolve` finds the port from a name that survives reboots. On
+//! macOS a `/dev/tty.*` port is opened through its `/dev/cu.*` twin, and on
+//! the BSDs a `/dev/ttyU0` port as `/dev/cuaU0`, since the dial-in devices wait
+//! for a carrier the lidar never sends.
+//!
+//! # Reading the scans
+//!
+//! Every backend returns `Error`, telling a closed driver, a timeout, a lost
+//! synchronization or a port closed mid-frame apart from the other I/O errors.
+//! Once the lidar spins, the read timeouts are three revolutions at the
+//! measured RPMs, so a motor spun down is detected quickly on any unit.
+//!
+//! On top of `read`, the drivers of every backend offer:
+//! - `run`, owning the read loop and recovering from errors, see `run`
+//! - `set_mirrored` for a lidar mounted upside down, and `set_calibration` for
+//!   the per-degree range offset of a unit, see `calibration`
+//! - `set_decimation(n)`, returning one scan out of `n` while still parsing
+//!   every frame, so the scans returned are never late
+//! - `read_latest`, dropping the scans waiting in the serial buffer, and
+//!   `purge_input`, discarding the pending bytes as `start()` does
+//! - `set_idle_stop` (unix), `set_duty_cycle` and `set_watchdog`, managing the
+//!   motor, see `idle`, `duty` and `watchdog`
+//! - `set_tee` and the `Hooks`, observing the raw bytes, frames and scans
+//! - `state` and `subscribe`, telling what the driver is doing, see `state`
+//! - `packet_rpms`, `degree_rpms` and `rpm_stats`, describing the speed of the
+//!   motor, and `beam_times`, the capture time of every beam, see `timing`
+//!
+//! `pipeline::Pipeline` turns any blocking driver into a complete logging or
+//! streaming application, writing to the sinks of `sink`.
+//!
+//! # Optional features
+//!
+//! Most features enable the module of the same name, documented there. The
+//! others are `actor` (`LFCDLaser::spawn`, see `actor`), `cancellation`
+//! (`tokio::LFCDLaser::run_until`), `config_watch` (`config::ConfigWatcher`),
+//! `protobuf` (`proto`), `usb_reset` (`usb`), `android_usb` (`android`),
+//! `test-utils` (`testing`), `ser_de` (serde support of the readings and of the
+//! calibration) and `defmt`. The latter implements `defmt::Format` for the
+//! readings, the errors and the states, and logs the parser through `defmt`;
+//! it is meant for embedded targets and excludes the `sync`, `async_tokio` and
+//! `async_smol` backends.
+
+// The host binaries of the std backends cannot link the defmt logger.
+#[cfg(all(
+    feature = "defmt",
+    any(feature = "sync", feature = "async_tokio", feature = "async_smol")
+))]
+compile_error!(
+    "the `defmt` feature is for embedded targets and excludes the `sync`, `async_tokio` \
+     and `async_smol` backends, disable the default features"
+);
+
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+#[macro_use]
+mod common;
+mod cluster;
+mod io;
+
+#[cfg(all(feature = "actor", any(feature = "sync", feature = "async_tokio")))]
+pub mod actor;
+#[cfg(all(
+    feature = "android_usb",
+    any(target_os = "android", target_os = "linux")
+))]
+pub mod android;
+pub mod angles;
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+pub mod any;
+pub mod ascii;
+pub mod background;
+pub mod blackbox;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod calibration;
+#[cfg(feature = "codec")]
+pub mod codec;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod delta;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+pub mod devices;
+pub mod diagnostics;
+pub mod driver;
+pub mod duty;
+#[cfg(feature = "embassy")]
+pub mod embassy;
+pub mod error;
+#[cfg(feature = "flatbuffers")]
+pub mod flatbuffers;
+#[cfg(feature = "foxglove")]
+pub mod foxglove;
+pub mod gap;
+#[cfg(feature = "geo")]
+pub mod geo;
+pub mod hooks;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod idle;
+pub mod landmarks;
+pub mod legs;
+pub mod metrics;
+#[cfg(feature = "nalgebra")]
+pub mod nalgebra;
+#[cfg(feature = "ndarray")]
+pub mod ndarray;
+#[cfg(feature = "nusb")]
+pub mod nusb;
+pub mod options;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "parry2d")]
+pub mod parry2d;
+pub mod pipeline;
+pub mod ply;
+pub mod pointcloud;
+#[cfg(feature = "polars")]
+pub mod polars;
+pub mod pool;
+#[cfg(feature = "protobuf")]
+pub mod proto;
+pub mod protocol;
+pub mod quantized;
+pub mod queue;
+pub mod raycast;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod resample;
+#[cfg(all(feature = "rfcomm", target_os = "linux"))]
+pub mod rfcomm;
+#[cfg(feature = "rosbag")]
+pub mod rosbag;
+#[cfg(feature = "rosbridge")]
+pub mod rosbridge;
+#[cfg(all(feature = "rpi", target_os = "linux"))]
+pub mod rpi;
+pub mod run;
+pub mod safety;
+pub mod sink;
+pub mod snapshot;
+pub mod spatial;
+pub mod state;
+pub mod stats;
+pub mod svg;
+#[cfg(all(feature = "systemd", unix))]
+pub mod systemd;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+pub mod timing;
+pub mod tracking;
+pub mod transport;
+pub mod watchdog;
 
-#[cfg(feature = "async_tokio")]
-use tokio::io::AsyncReadExt;
-#[cfg(feature = "async_tokio")]
-use tokio_serial::{SerialPortBuilderExt, SerialStream};
-
-#[cfg(feature = "async_smol")]
-use futures::prelude::*;
 #[cfg(feature = "async_smol")]
-use mio_serial::{SerialPortBuilderExt, SerialStream};
-#[cfg(feature = "async_smol")]
-use smol::Async;
+pub mod smol;
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "async_tokio")]
+pub mod tokio;
+#[cfg(all(feature = "usb_reset", target_os = "linux"))]
+pub mod usb;
+#[cfg(feature = "ydlidar")]
+pub mod ydlidar;
+#[cfg(feature = "zones")]
+pub mod zones;
+#[cfg(feature = "zstd")]
+pub mod zstd;
+
+pub use driver::{AsyncLidarDriver, LidarDriver};
+pub use error::Error;
+pub use hooks::{DecodeError, Hooks};
+
+// Keeps `hls_lfcd_lds_driver::LFCDLaser` working when a single backend is enabled.
+#[cfg(all(
+    feature = "async_smol",
+    not(any(feature = "async_tokio", feature = "sync"))
+))]
+pub use crate::smol::LFCDLaser;
+#[cfg(all(
+    feature = "sync",
+    not(any(feature = "async_tokio", feature = "async_smol"))
+))]
+pub use crate::sync::LFCDLaser;
+#[cfg(all(
+    feature = "async_tokio",
+    not(any(feature = "sync", feature = "async_smol"))
+))]
+pub use crate::tokio::LFCDLaser;
 
-#[cfg(feature = "serde")]
+#[cfg(feature = "ser_de")]
 use serde::{Deserialize, Serialize};
-#[cfg(feature = "serde")]
+#[cfg(feature = "ser_de")]
 use serde_big_array::BigArray;
 
-#[cfg(feature = "sync")]
-use std::io::Read;
-
-#[cfg(feature = "sync")]
-use serialport::TTYPort;
-
 /// Default serial port of the lidar
 pub static DEFAULT_PORT: &str = "/dev/ttyUSB0";
 /// Default baud_rate of the lidar
 pub static DEFAULT_BAUD_RATE: &str = "230400";
 
-/// Byte sent to stop the lidar, 101 = ASCII 'e'
-static STOP_BYTE: u8 = 101;
+/// Minimum valid range of the lidar, in mm
+pub const RANGE_MIN: u16 = 120;
+/// Maximum valid range of the lidar, in mm
+pub const RANGE_MAX: u16 = 3500;
 
-/// Byte sent to start the lidar, 98 = ASCII 'b'
-static START_BYTE: u8 = 98;
+/// Number of beams of a scan of the LDS-01, one per degree
+pub const BEAMS: usize = 360;
 
 /// This struct contains the reading from the lidar.
-/// The `ranges` array contains 360 elements, one for each degree,
+/// The `ranges` array contains `N` elements, one for each beam,
 /// with a value from 0 to 1000, indicating the distance.
 ///
-/// The `intensites` array contains 360 elements, one for each degree,
+/// The `intensites` array contains `N` elements, one for each beam,
 /// with a value, indicating accuracy of the reading
 ///
 /// The `rmps` field gets the lidar RPMs
+///
+/// `N` defaults to the 360 beams of the LDS-01, other resolutions are
+/// spread evenly over a revolution.
 #[cfg(feature = "ser_de")]
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct LaserReading {
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LaserReading<const N: usize = BEAMS> {
     #[serde(with = "BigArray")]
-    pub ranges: [u16; 360],
+    pub ranges: [u16; N],
     #[serde(with = "BigArray")]
-    pub intensities: [u16; 360],
+    pub intensities: [u16; N],
     pub rpms: u16,
 }
 
 /// This struct contains the reading from the lidar.
-/// The `ranges` array contains 360 elements, one for each degree,
+/// The `ranges` array contains `N` elements, one for each beam,
 /// with a value from 0 to 1000, indicating the distance.
 ///
-/// The `intensites` array contains 360 elements, one for each degree,
+/// The `intensites` array contains `N` elements, one for each beam,
 /// with a value, indicating accuracy of the reading
 ///
 /// The `rmps` field gets the lidar RPMs
+///
+/// `N` defaults to the 360 beams of the LDS-01, other resolutions are
+/// spread evenly over a revolution.
 #[cfg(not(feature = "ser_de"))]
 #[derive(Debug, Clone)]
-pub struct LaserReading {
-    pub ranges: [u16; 360],
-    pub intensities: [u16; 360],
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LaserReading<const N: usize = BEAMS> {
+    pub ranges: [u16; N],
+    pub intensities: [u16; N],
     pub rpms: u16,
 }
 
 impl LaserReading {
     pub fn new() -> Self {
-        Self {
-            ranges: [0u16; 360],
-            intensities: [0u16; 360],
-            rpms: 0,
-        }
+        Self::empty()
     }
-}
 
-impl Default for LaserReading {
-    fn default() -> Self {
-        Self::new()
+    /// Gets the angle, in radians, of the given beam.
+    /// Angles grow counter-clockwise starting from the front of the lidar.
+    pub fn angle(index: usize) -> f32 {
+        Self::beam_angle(index)
     }
 }
 
-/// This struct allows to read lidar information and to "shutdown" the driver
-
-pub struct LFCDLaser {
-    port: String,
-    baud_rate: u32,
-    shutting_down: bool,
-    motor_speed: u16,
-    rpms: u16,
-    #[cfg(feature = "async_tokio")]
-    serial: SerialStream,
-    #[cfg(feature = "async_smol")]
-    serial: Async<SerialStream>,
-    #[cfg(feature = "sync")]
-    serial: TTYPort,
-    buff: [u8; 2520],
-}
-
-impl LFCDLaser {
-    /// Creates the `LFCDLaser`
-    pub fn close(&mut self) {
-        self.shutting_down = true;
-
-        // Stopping the Lidar, ignoring the result.
-        #[cfg(not(feature = "async_smol"))]
-        std::io::Write::write_all(&mut self.serial, &[STOP_BYTE]).ok();
-        #[cfg(feature = "async_smol")]
-        std::io::Write::write_all(&mut self.serial.get_mut(), &[STOP_BYTE]).ok();
-    }
-
-    /// Gets lidar speed.
-    pub fn speed(&self) -> u16 {
-        self.motor_speed
+impl<const N: usize> LaserReading<N> {
+    /// Creates a scan of any resolution with every beam set to 0.
+    pub fn empty() -> Self {
+        Self {
+            ranges: [0u16; N],
+            intensities: [0u16; N],
+            rpms: 0,
+        }
     }
 
-    /// Gets the configured baud rate
-    pub fn baud_rate(&self) -> u32 {
-        self.baud_rate
+    /// Gets the angle, in radians, of the given beam at this resolution.
+    /// Angles grow counter-clockwise starting from the front of the lidar.
+    pub fn beam_angle(index: usize) -> f32 {
+        (index as f32 * 360.0 / N as f32).to_radians()
     }
 
-    /// Gets the configured serial port
-    pub fn port(&self) -> String {
-        self.port.clone()
+    /// Gets the time of the revolution of this scan, in seconds, derived from
+    /// its RPMs, 0 if they are not known.
+    pub fn scan_time(&self) -> f32 {
+        protocol::scan_time(self.rpms)
     }
 
-    /// Gets the lidars rmp from the last reading
-    pub fn rpms(&self) -> u16 {
-        self.rpms
+    /// Checks if the range of the given beam is within the lidar limits.
+    pub fn is_valid(&self, index: usize) -> bool {
+        (RANGE_MIN..=RANGE_MAX).contains(&self.ranges[index])
     }
 
-    // Starts the Lidar
-    pub fn start(&mut self) {
-        // Starting the Lidar
-        #[cfg(not(feature = "async_smol"))]
-        std::io::Write::write_all(&mut self.serial, &[START_BYTE]).ok();
-
-        #[cfg(feature = "async_smol")]
-        std::io::Write::write_all(&mut self.serial.get_mut(), &[START_BYTE]).ok();
-
-        self.shutting_down = false;
+    /// Gets the cartesian coordinates, in meters, of the given beam.
+    /// `x` points to the front of the lidar and `y` to its left.
+    pub fn point(&self, index: usize) -> (f32, f32) {
+        let range = f32::from(self.ranges[index]) / 1000.0;
+        let (sin, cos) = Self::beam_angle(index).sin_cos();
+        (range * cos, range * sin)
     }
-}
 
-impl Drop for LFCDLaser {
-    fn drop(&mut self) {
-        self.close();
+    /// Gets the cartesian coordinates, in meters, of all the valid beams.
+    pub fn points(&self) -> Vec<(f32, f32)> {
+        (0..N)
+            .filter(|&i| self.is_valid(i))
+            .map(|i| self.point(i))
+            .collect()
     }
-}
-
-#[cfg(feature = "async_tokio")]
-impl LFCDLaser {
-    /// Creates a new `LFCDLaser` with the given parameters.
-    ///
-    /// # Errors
-    /// An error variant is returned in case of:
-    /// - unable to open the specified serial port
-    /// - unable to set the port to non-exclusive (only on unix)
-    pub fn new(port: String, baud_rate: u32) -> tokio_serial::Result<Self> {
-        let mut serial = tokio_serial::new(port.clone(), baud_rate).open_native_async()?;
-
-        #[cfg(unix)]
-        serial.set_exclusive(false)?;
-
-        let mut lidar = Self {
-            port,
-            baud_rate,
-            shutting_down: false,
-            motor_speed: 0,
-            rpms: 0,
-            serial,
-            buff: [0u8; 2520],
-        };
-
-        lidar.start();
 
-        Ok(lidar)
+    /// Gets the cartesian coordinates, in meters, of the closest valid beam,
+    /// `None` if the scan has no valid beam.
+    pub fn nearest_point(&self) -> Option<(f32, f32)> {
+        (0..N)
+            .filter(|&i| self.is_valid(i))
+            .min_by_key(|&i| self.ranges[i])
+            .map(|i| self.point(i))
     }
 
-    /// Gets a reading from the lidar, returing a `LaserReading` object.
-    ///
-    /// # Errors
-    /// An error variant is returned in case of:
-    /// - unable to read form the serial port
-    /// - the driver is closed
-    pub async fn read(&mut self) -> tokio_serial::Result<LaserReading> {
-        let mut start_count: usize = 0;
-        let mut good_sets: u8 = 0;
-
-        let mut scan = LaserReading::new();
-
-        if self.shutting_down {
-            return Err(tokio_serial::Error::new(
-                tokio_serial::ErrorKind::Unknown,
-                "Driver is closed",
-            ));
-        }
-
-        loop {
-            // Wait for data sync of frame: 0xFA, 0XA0
-
-            // Read one byte
-            self.serial
-                .read_exact(std::slice::from_mut(&mut self.buff[start_count]))
-                .await?;
-            //println!("start_count : {start_count} = Read {:02X?}", buff[start_count]);
-            if start_count == 0 {
-                if self.buff[start_count] == 0xFA {
-                    start_count = 1
-                }
-            } else if start_count == 1 {
-                if self.buff[start_count] == 0xA0 {
-                    self.serial.read_exact(&mut self.buff[2..]).await?;
-
-                    //read data in sets of 6
-
-                    for i in (0..self.buff.len()).step_by(42) {
-                        if self.buff[i] == 0xFA && usize::from(self.buff[i + 1]) == (0xA0 + i / 42)
-                        {
-                            good_sets = good_sets.wrapping_add(1);
-
-                            let b_rmp0: u16 = self.buff[i + 3] as u16;
-                            let b_rmp1: u16 = self.buff[i + 2] as u16;
-
-                            // motor_speed = motor_speed.wrapping_add((b_rmp0 as u32) << 8 + (b_rmp1 as u32)); // accumulate count for avg. time increment
-                            let rpms = (b_rmp0 << 8 | b_rmp1) / 10;
-                            scan.rpms = rpms;
-                            self.rpms = rpms;
-
-                            for j in ((i + 4)..(i + 40)).step_by(6) {
-                                let index = 6 * (i / 42) + (j - 4 - i) / 6;
-                                // Four bytes `per reading
-                                let b0: u16 = self.buff[j] as u16;
-                                let b1: u16 = self.buff[j + 1] as u16;
-                                let b2: u16 = self.buff[j + 2] as u16;
-                                let b3: u16 = self.buff[j + 3] as u16;
-
-                                // Remaining bits are the range in mm
-                                let range: u16 = (b3 << 8) + b2;
-
-                                // Last two bytes represents the uncertanity or intensity, might also
-                                // be pixel area of target...
-                                // let intensity = (b3 << 8) + b2;
-                                let intensity: u16 = (b1 << 8) + b0;
-
-                                scan.ranges[359 - index] = range;
-                                scan.intensities[359 - index] = intensity;
-                            }
-                        }
-                    }
-
-                    // self.time_increment = motor_speed/good_sets/1e8;
-                    return Ok(scan);
-                } else {
-                    start_count = 0;
-                }
-            }
-        }
+    /// Gets the cartesian coordinates, in meters, of the `k` closest valid
+    /// beams, the closest first; fewer if the scan has less valid beams.
+    pub fn k_nearest(&self, k: usize) -> Vec<(f32, f32)> {
+        let mut beams: Vec<usize> = (0..N).filter(|&i| self.is_valid(i)).collect();
+        beams.sort_by_key(|&i| self.ranges[i]);
+        beams.into_iter().take(k).map(|i| self.point(i)).collect()
     }
 }
 
-#[cfg(feature = "sync")]
-impl LFCDLaser {
-    /// Creates a new `LFCDLaser` with the given parameters.
-    ///
-    /// # Errors
-    /// An error variant is returned in case of:
-    /// - unable to open the specified serial port
-    /// - unable to set the port to non-exclusive (only on unix)
-    pub fn new(port: String, baud_rate: u32) -> serialport::Result<Self> {
-        let mut serial = serialport::new(port.clone(), baud_rate).open_native()?;
-
-        #[cfg(unix)]
-        serial.set_exclusive(false)?;
-
-        let mut lidar = Self {
-            port,
-            baud_rate,
-            shutting_down: false,
-            motor_speed: 0,
-            rpms: 0,
-            serial,
-            buff: [0u8; 2520],
-        };
-
-        lidar.start();
-
-        Ok(lidar)
-    }
-
-    /// Gets a reading from the lidar, returing a `LaserReading` object.
-    ///
-    /// # Errors
-    /// An error variant is returned in case of:
-    /// - unable to read form the serial port
-    /// - the driver is closed
-    pub fn read(&mut self) -> serialport::Result<LaserReading> {
-        let mut start_count: usize = 0;
-        let mut good_sets: u8 = 0;
-
-        let mut scan = LaserReading::new();
-
-        if self.shutting_down {
-            return Err(serialport::Error::new(
-                serialport::ErrorKind::Unknown,
-                "Driver is closed",
-            ));
-        }
-
-        loop {
-            // Wait for data sync of frame: 0xFA, 0XA0
-
-            // Read one byte
-            self.serial
-                .read_exact(std::slice::from_mut(&mut self.buff[start_count]))?;
-            //println!("start_count : {start_count} = Read {:02X?}", buff[start_count]);
-            if start_count == 0 {
-                if self.buff[start_count] == 0xFA {
-                    start_count = 1
-                }
-            } else if start_count == 1 {
-                if self.buff[start_count] == 0xA0 {
-                    self.serial.read_exact(&mut self.buff[2..])?;
-
-                    //read data in sets of 6
-
-                    for i in (0..self.buff.len()).step_by(42) {
-                        if self.buff[i] == 0xFA && usize::from(self.buff[i + 1]) == (0xA0 + i / 42)
-                        {
-                            good_sets = good_sets.wrapping_add(1);
-
-                            let b_rmp0: u16 = self.buff[i + 3] as u16;
-                            let b_rmp1: u16 = self.buff[i + 2] as u16;
-
-                            // motor_speed = motor_speed.wrapping_add((b_rmp0 as u32) << 8 + (b_rmp1 as u32)); // accumulate count for avg. time increment
-                            let rpms = (b_rmp0 << 8 | b_rmp1) / 10;
-                            scan.rpms = rpms;
-                            self.rpms = rpms;
-
-                            for j in ((i + 4)..(i + 40)).step_by(6) {
-                                let index = 6 * (i / 42) + (j - 4 - i) / 6;
-                                // Four bytes `per reading
-                                let b0: u16 = self.buff[j] as u16;
-                                let b1: u16 = self.buff[j + 1] as u16;
-                                let b2: u16 = self.buff[j + 2] as u16;
-                                let b3: u16 = self.buff[j + 3] as u16;
-
-                                // Remaining bits are the range in mm
-                                let range: u16 = (b3 << 8) + b2;
-
-                                // Last two bytes represents the uncertanity or intensity, might also
-                                // be pixel area of target...
-                                // let intensity = (b3 << 8) + b2;
-                                let intensity: u16 = (b1 << 8) + b0;
-
-                                scan.ranges[359 - index] = range;
-                                scan.intensities[359 - index] = intensity;
-                            }
-                        }
-                    }
-
-                    // self.time_increment = motor_speed/good_sets/1e8;
-                    return Ok(scan);
-                } else {
-                    start_count = 0;
-                }
-            }
-        }
+impl<const N: usize> Default for LaserReading<N> {
+    fn default() -> Self {
+        Self::empty()
     }
 }
 
-#[cfg(feature = "async_smol")]
-impl LFCDLaser {
-    /// Creates a new `LFCDLaser` with the given parameters.
-    ///
-    /// # Errors
-    /// An error variant is returned in case of:
-    /// - unable to open the specified serial port
-    /// - unable to set the port to non-exclusive (only on unix)
-    pub fn new(port: String, baud_rate: u32) -> mio_serial::Result<Self> {
-        let mut serial = mio_serial::new(port.clone(), baud_rate).open_native_async()?;
-
-        #[cfg(unix)]
-        serial.set_exclusive(false)?;
-
-        // Wrapping into smol::Async to make it "async", similar to what tokio-serial does.
-        let serial = Async::new(serial).map_err(|e| {
-            mio_serial::Error::new(
-                mio_serial::ErrorKind::Unknown,
-                format!("Unable to wrap mio-serial in smol::Async: {e}"),
-            )
-        })?;
-
-        let mut lidar = Self {
-            port,
-            baud_rate,
-            shutting_down: false,
-            motor_speed: 0,
-            rpms: 0,
-            serial,
-            buff: [0u8; 2520],
-        };
-
-        lidar.start();
-
-        Ok(lidar)
-    }
-
-    /// Gets a reading from the lidar, returing a `LaserReading` object.
-    ///
-    /// # Errors
-    /// An error variant is returned in case of:
-    /// - unable to read form the serial port
-    /// - the driver is closed
-    pub async fn read(&mut self) -> mio_serial::Result<LaserReading> {
-        let mut start_count: usize = 0;
-        let mut good_sets: u8 = 0;
-
-        let mut scan = LaserReading::new();
-
-        if self.shutting_down {
-            return Err(mio_serial::Error::new(
-                mio_serial::ErrorKind::Unknown,
-                "Driver is closed",
-            ));
-        }
-
-        loop {
-            // Wait for data sync of frame: 0xFA, 0XA0
-
-            // Read one byte
-            self.serial
-                .read_exact(std::slice::from_mut(&mut self.buff[start_count]))
-                .await?;
-            //println!("start_count : {start_count} = Read {:02X?}", buff[start_count]);
-            if start_count == 0 {
-                if self.buff[start_count] == 0xFA {
-                    start_count = 1
-                }
-            } else if start_count == 1 {
-                if self.buff[start_count] == 0xA0 {
-                    self.serial.read_exact(&mut self.buff[2..]).await?;
-
-                    //read data in sets of 6
-
-                    for i in (0..self.buff.len()).step_by(42) {
-                        if self.buff[i] == 0xFA && usize::from(self.buff[i + 1]) == (0xA0 + i / 42)
-                        {
-                            good_sets = good_sets.wrapping_add(1);
-
-                            let b_rmp0: u16 = self.buff[i + 3] as u16;
-                            let b_rmp1: u16 = self.buff[i + 2] as u16;
-
-                            // motor_speed = motor_speed.wrapping_add((b_rmp0 as u32) << 8 + (b_rmp1 as u32)); // accumulate count for avg. time increment
-                            let rpms = (b_rmp0 << 8 | b_rmp1) / 10;
-                            scan.rpms = rpms;
-                            self.rpms = rpms;
-
-                            for j in ((i + 4)..(i + 40)).step_by(6) {
-                                let index = 6 * (i / 42) + (j - 4 - i) / 6;
-                                // Four bytes `per reading
-                                let b0: u16 = self.buff[j] as u16;
-                                let b1: u16 = self.buff[j + 1] as u16;
-                                let b2: u16 = self.buff[j + 2] as u16;
-                                let b3: u16 = self.buff[j + 3] as u16;
-
-                                // Remaining bits are the range in mm
-                                let range: u16 = (b3 << 8) + b2;
-
-                                // Last two bytes represents the uncertanity or intensity, might also
-                                // be pixel area of target...
-                                // let intensity = (b3 << 8) + b2;
-                                let intensity: u16 = (b1 << 8) + b0;
-
-                                scan.ranges[359 - index] = range;
-                                scan.intensities[359 - index] = intensity;
-                            }
-                        }
-                    }
-
-                    // self.time_increment = motor_speed/good_sets/1e8;
-                    return Ok(scan);
-                } else {
-                    start_count = 0;
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_nearest_valid_points() {
+        let mut reading = LaserReading::new();
+        assert_eq!(reading.nearest_point(), None);
+        assert!(reading.k_nearest(3).is_empty());
+
+        // Too close and too far beams are not valid.
+        reading.ranges[0] = RANGE_MIN - 1;
+        reading.ranges[1] = RANGE_MAX + 1;
+        reading.ranges[90] = 1000;
+        reading.ranges[180] = 500;
+        let (x, y) = reading.nearest_point().unwrap();
+        assert!((x + 0.5).abs() < 1e-4 && y.abs() < 1e-4);
+
+        let nearest = reading.k_nearest(5);
+        assert_eq!(nearest.len(), 2);
+        assert_eq!(nearest[0], reading.point(180));
+        assert_eq!(nearest[1], reading.point(90));
+        assert_eq!(reading.k_nearest(1), [reading.point(180)]);
     }
 }
@@ -0,0 +1,142 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Similarity of two scans, e.g. a fresh scan against a recorded reference
+//! in a regression test, or before and after a calibration.
+//!
+//! The beams are compared one to one, only where both scans have a valid
+//! range, so two scans of a static scene are close even if a few beams
+//! dropped out.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::metrics;
+//! use hls_lfcd_lds_driver::LaserReading;
+//!
+//! let mut reference = LaserReading::new();
+//! reference.ranges = [1000; 360];
+//! let mut scan = reference.clone();
+//! scan.ranges[90] = 1040;
+//!
+//! let diff = metrics::compare(&reference, &scan);
+//! assert_eq!(diff.common, 360);
+//! assert!(diff.is_within(1.0, 0.95));
+//! ```
+
+use crate::LaserReading;
+
+/// How close two scans are, see `compare`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScanComparison {
+    /// Beams valid in both scans
+    pub common: usize,
+    /// Beams valid in both scans over the beams valid in either, from 0 to 1
+    pub overlap: f32,
+    /// Mean absolute difference of the ranges of the common beams, in mm,
+    /// `None` without common beams
+    pub mean_abs_error: Option<f32>,
+    /// Pearson correlation of the ranges of the common beams, `None` with
+    /// less than two of them or constant ranges
+    pub correlation: Option<f32>,
+}
+
+impl ScanComparison {
+    /// Checks if the mean absolute error is at most `max_error` mm and the
+    /// overlap at least `min_overlap`.
+    pub fn is_within(&self, max_error: f32, min_overlap: f32) -> bool {
+        self.overlap >= min_overlap && self.mean_abs_error.is_some_and(|e| e <= max_error)
+    }
+}
+
+/// Compares the ranges of `a` and `b`, beam by beam.
+pub fn compare<const N: usize>(a: &LaserReading<N>, b: &LaserReading<N>) -> ScanComparison {
+    let mut either = 0;
+    let mut pairs = Vec::with_capacity(N);
+    for i in 0..N {
+        match (a.is_valid(i), b.is_valid(i)) {
+            (true, true) => pairs.push((f32::from(a.ranges[i]), f32::from(b.ranges[i]))),
+            (false, false) => continue,
+            _ => {}
+        }
+        either += 1;
+    }
+
+    let common = pairs.len();
+    let n = common as f32;
+    let mean_abs_error =
+        (common > 0).then(|| pairs.iter().map(|(x, y)| (x - y).abs()).sum::<f32>() / n);
+
+    let correlation = (common > 1).then(|| {
+        let mean_a = pairs.iter().map(|(x, _)| x).sum::<f32>() / n;
+        let mean_b = pairs.iter().map(|(_, y)| y).sum::<f32>() / n;
+        let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+        for (x, y) in &pairs {
+            cov += (x - mean_a) * (y - mean_b);
+            var_a += (x - mean_a).powi(2);
+            var_b += (y - mean_b).powi(2);
+        }
+        (var_a > 0.0 && var_b > 0.0).then(|| cov / (var_a * var_b).sqrt())
+    });
+
+    ScanComparison {
+        common,
+        overlap: if either > 0 {
+            common as f32 / either as f32
+        } else {
+            0.0
+        },
+        mean_abs_error,
+        correlation: correlation.flatten(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixtures;
+
+    #[test]
+    fn matches_a_fixture_with_itself() {
+        let room = fixtures()[0].decode();
+        let diff = compare(&room, &room);
+        assert_eq!(diff.overlap, 1.0);
+        assert_eq!(diff.mean_abs_error, Some(0.0));
+        assert!((diff.correlation.unwrap() - 1.0).abs() < 1e-4);
+        assert!(diff.is_within(0.0, 1.0));
+    }
+
+    #[test]
+    fn ignores_the_beams_of_the_corrupted_packet() {
+        let fixtures = fixtures();
+        let (room, corrupted) = (fixtures[0].decode(), fixtures[3].decode());
+        let diff = compare(&room, &corrupted);
+        let valid = (0..360).filter(|&i| room.is_valid(i)).count();
+        let lost = (0..360)
+            .filter(|&i| room.is_valid(i) && !corrupted.is_valid(i))
+            .count();
+        assert_eq!(lost, 6);
+        assert_eq!(diff.common, valid - lost);
+        assert_eq!(diff.overlap, (valid - lost) as f32 / valid as f32);
+        assert_eq!(diff.mean_abs_error, Some(0.0));
+    }
+
+    #[test]
+    fn has_no_error_without_common_beams() {
+        let fixtures = fixtures();
+        let diff = compare(&fixtures[0].decode(), &fixtures[1].decode());
+        assert_eq!(diff.common, 0);
+        assert_eq!(diff.overlap, 0.0);
+        assert_eq!(diff.mean_abs_error, None);
+        assert!(!diff.is_within(f32::MAX, 0.0));
+    }
+}
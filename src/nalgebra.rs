@@ -0,0 +1,69 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Conversions of scans to `nalgebra` points, enabled by the `nalgebra` feature.
+//!
+//! ```
+//! use nalgebra::{Isometry2, Vector2};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! // Lidar mounted 10 cm ahead of the robot center, robot at (1, 2) facing left.
+//! let mount = Isometry2::new(Vector2::new(0.1, 0.0), 0.0);
+//! let robot = Isometry2::new(Vector2::new(1.0, 2.0), std::f32::consts::FRAC_PI_2);
+//! let points = reading.to_points_transformed(&(robot * mount));
+//! ```
+
+use crate::LaserReading;
+use ::nalgebra::{Isometry2, Isometry3, Point2, Point3};
+
+impl LaserReading {
+    /// Gets the given beam as a point, in meters, in the lidar frame.
+    pub fn point_nalgebra(&self, index: usize) -> Point2<f32> {
+        let (x, y) = self.point(index);
+        Point2::new(x, y)
+    }
+
+    /// Gets the valid beams as points, in meters, in the lidar frame.
+    pub fn to_points_nalgebra(&self) -> Vec<Point2<f32>> {
+        self.points()
+            .into_iter()
+            .map(|(x, y)| Point2::new(x, y))
+            .collect()
+    }
+
+    /// Gets the valid beams as points, in meters, transformed by `pose`,
+    /// i.e. the pose of the lidar in the target frame.
+    pub fn to_points_transformed(&self, pose: &Isometry2<f32>) -> Vec<Point2<f32>> {
+        self.points()
+            .into_iter()
+            .map(|(x, y)| pose * Point2::new(x, y))
+            .collect()
+    }
+
+    /// Gets the valid beams as 3D points, in meters, transformed by `pose`,
+    /// the scan lying on the XY plane of the lidar.
+    pub fn to_points3_transformed(&self, pose: &Isometry3<f32>) -> Vec<Point3<f32>> {
+        self.points()
+            .into_iter()
+            .map(|(x, y)| pose * Point3::new(x, y, 0.0))
+            .collect()
+    }
+}
+
+/// Transforms the points in place by `pose`.
+pub fn transform_points(points: &mut [Point2<f32>], pose: &Isometry2<f32>) {
+    for p in points {
+        *p = pose * *p;
+    }
+}
@@ -0,0 +1,90 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Conversions of scans to `ndarray` arrays, enabled by the `ndarray` feature.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::ndarray::stack_ranges;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let ranges = reading.as_ndarray();
+//! assert_eq!(ranges.len(), 360);
+//!
+//! let history = stack_ranges([&reading, &reading]);
+//! assert_eq!(history.dim(), (2, 360));
+//! ```
+
+use crate::LaserReading;
+use ::ndarray::{Array2, ArrayView1};
+
+impl LaserReading {
+    /// Gets a view of the ranges, in mm, without copying them.
+    pub fn as_ndarray(&self) -> ArrayView1<'_, u16> {
+        ArrayView1::from(&self.ranges)
+    }
+
+    /// Gets a view of the intensities, without copying them.
+    pub fn intensities_as_ndarray(&self) -> ArrayView1<'_, u16> {
+        ArrayView1::from(&self.intensities)
+    }
+
+    /// Converts the scan into a 360x2 array, every row being the range,
+    /// in meters, and the intensity of a beam.
+    pub fn to_ndarray(&self) -> Array2<f32> {
+        Array2::from_shape_fn((self.ranges.len(), 2), |(i, c)| match c {
+            0 => f32::from(self.ranges[i]) / 1000.0,
+            _ => f32::from(self.intensities[i]),
+        })
+    }
+
+    /// Converts the valid beams into a Nx2 array of (x, y) points, in meters.
+    pub fn points_ndarray(&self) -> Array2<f32> {
+        let points = self.points();
+        Array2::from_shape_fn((points.len(), 2), |(i, c)| match c {
+            0 => points[i].0,
+            _ => points[i].1,
+        })
+    }
+}
+
+/// Stacks the ranges of several scans into a scans x 360 array.
+pub fn stack_ranges<'a, I>(readings: I) -> Array2<u16>
+where
+    I: IntoIterator<Item = &'a LaserReading>,
+{
+    stack(readings, |r| &r.ranges)
+}
+
+/// Stacks the intensities of several scans into a scans x 360 array.
+pub fn stack_intensities<'a, I>(readings: I) -> Array2<u16>
+where
+    I: IntoIterator<Item = &'a LaserReading>,
+{
+    stack(readings, |r| &r.intensities)
+}
+
+fn stack<'a, I, F>(readings: I, field: F) -> Array2<u16>
+where
+    I: IntoIterator<Item = &'a LaserReading>,
+    F: Fn(&LaserReading) -> &[u16; 360],
+{
+    let mut rows = 0;
+    let mut data = Vec::new();
+    for r in readings {
+        data.extend_from_slice(field(r));
+        rows += 1;
+    }
+    // The length always matches the shape.
+    Array2::from_shape_vec((rows, 360), data).unwrap_or_default()
+}
@@ -0,0 +1,196 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Transport talking to the USB adapter of the lidar from user space,
+//! through `nusb`, enabled by the `nusb` feature.
+//!
+//! The CP2102 is driven with its vendor requests and bulk endpoints, without
+//! the kernel serial driver and the tty layer: the reads return as soon as
+//! the adapter has data, several transfers are kept in flight, and on Linux
+//! a udev rule on the USB device is enough, no `dialout` group needed. The
+//! kernel driver is detached from the adapter while it is in use, so the
+//! `/dev/ttyUSB*` port disappears meanwhile.
+//!
+//! ```no_run
+//! # #[cfg(feature = "nusb")]
+//! # fn main() -> Result<(), hls_lfcd_lds_driver::Error> {
+//! use hls_lfcd_lds_driver::nusb::{NusbLaser, NusbSerial};
+//!
+//! let mut laser = NusbLaser::new(NusbSerial::open_first()?);
+//! let scan = laser.read()?;
+//! println!("{} rpm", scan.rpms);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "nusb"))]
+//! # fn main() {}
+//! ```
+
+use crate::transport::{cp210x, Transport, TransportLaser};
+#[cfg(not(target_os = "android"))]
+use crate::transport::{BAUD_RATE, CP2102_ID};
+use ::nusb::io::{EndpointRead, EndpointWrite};
+use ::nusb::transfer::{Bulk, ControlOut, ControlType, In, Out, Recipient};
+#[cfg(not(target_os = "android"))]
+use ::nusb::DeviceInfo;
+use ::nusb::{Device, Interface, MaybeFuture};
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+/// Timeout of a USB transfer, by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Size of the USB reads, rounded up to the packets of the endpoint.
+const READ_SIZE: usize = 512;
+/// Number of reads kept in flight, so that the adapter is never left waiting.
+const READ_TRANSFERS: usize = 4;
+/// Size of the USB writes, a single byte command fits a packet.
+const WRITE_SIZE: usize = 64;
+
+/// Serial port of a CP2102 adapter driven through `nusb`.
+pub struct NusbSerial {
+    interface: Interface,
+    reader: EndpointRead<Bulk>,
+    writer: EndpointWrite<Bulk>,
+    timeout: Duration,
+}
+
+impl NusbSerial {
+    /// Opens the first CP2102 adapter found, at the baud rate of the LDS-01.
+    /// Not on Android, where the app gets the device from `UsbManager`, see
+    /// `from_device` and `nusb::Device::from_fd`.
+    ///
+    /// # Errors
+    /// An error variant is returned if no adapter is found, or it cannot be
+    /// opened and configured, see `from_device`.
+    #[cfg(not(target_os = "android"))]
+    pub fn open_first() -> io::Result<Self> {
+        let info = ::nusb::list_devices()
+            .wait()?
+            .find(|info| (info.vendor_id(), info.product_id()) == CP2102_ID)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No CP2102 adapter found"))?;
+        Self::open(&info, BAUD_RATE)
+    }
+
+    /// Opens an adapter found by `nusb::list_devices` and configures it to
+    /// `baud_rate`, 8N1.
+    ///
+    /// # Errors
+    /// An error variant is returned if the device cannot be opened, e.g. no
+    /// permission, or configured, see `from_device`.
+    #[cfg(not(target_os = "android"))]
+    pub fn open(info: &DeviceInfo, baud_rate: u32) -> io::Result<Self> {
+        Self::from_device(&info.open().wait()?, baud_rate)
+    }
+
+    /// Claims the serial interface of an opened adapter, detaching the kernel
+    /// driver on Linux, and configures it to `baud_rate`, 8N1.
+    ///
+    /// # Errors
+    /// An error variant is returned if the interface cannot be claimed or the
+    /// adapter refuses the configuration.
+    pub fn from_device(device: &Device, baud_rate: u32) -> io::Result<Self> {
+        let interface = device
+            .detach_and_claim_interface(cp210x::INTERFACE)
+            .wait()?;
+        let reader = interface
+            .endpoint::<Bulk, In>(cp210x::BULK_IN)?
+            .reader(READ_SIZE)
+            .with_num_transfers(READ_TRANSFERS)
+            .with_read_timeout(DEFAULT_TIMEOUT);
+        let writer = interface
+            .endpoint::<Bulk, Out>(cp210x::BULK_OUT)?
+            .writer(WRITE_SIZE)
+            .with_write_timeout(DEFAULT_TIMEOUT);
+        let serial = Self {
+            interface,
+            reader,
+            writer,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        serial.control(cp210x::IFC_ENABLE, 1, &[])?;
+        serial.control(cp210x::SET_BAUDRATE, 0, &baud_rate.to_le_bytes())?;
+        serial.control(cp210x::SET_LINE_CTL, cp210x::LINE_8N1, &[])?;
+        Ok(serial)
+    }
+
+    /// Gets the timeout of the USB transfers.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the timeout of the USB transfers, a read receiving nothing for
+    /// this long fails with `io::ErrorKind::TimedOut`.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+        self.reader.set_read_timeout(timeout);
+        self.writer.set_write_timeout(timeout);
+    }
+
+    fn control(&self, request: u8, value: u16, data: &[u8]) -> io::Result<()> {
+        let request = ControlOut {
+            control_type: ControlType::Vendor,
+            recipient: Recipient::Interface,
+            request,
+            value,
+            index: cp210x::INTERFACE.into(),
+            data,
+        };
+        Ok(self.interface.control_out(request, self.timeout).wait()?)
+    }
+}
+
+impl Read for NusbSerial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for NusbSerial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Transport for NusbSerial {
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.control(cp210x::PURGE, cp210x::PURGE_ALL, &[])?;
+        // Drops what the transfers in flight already received, without
+        // waiting for more.
+        self.reader.set_read_timeout(Duration::ZERO);
+        let mut scratch = [0u8; READ_SIZE];
+        let drained = loop {
+            match self.reader.read(&mut scratch) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.reader.set_read_timeout(self.timeout);
+        drained
+    }
+}
+
+impl Drop for NusbSerial {
+    fn drop(&mut self) {
+        self.control(cp210x::IFC_ENABLE, 0, &[]).ok();
+    }
+}
+
+/// Driver of the LDS-01 over a `NusbSerial`.
+pub type NusbLaser = TransportLaser<NusbSerial>;
@@ -0,0 +1,313 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! How a driver opens the lidar, see `OpenOptions::open`.
+//!
+//! The options are checked before the port is touched: a baud rate the
+//! model does not talk at, or a model the driver does not handle, fails
+//! right away with an `OptionsError` instead of a sync lost a few seconds
+//! later.
+//!
+//! By default the motor is started as soon as the port is open, like `new`
+//! does. Without the auto start the port is open but the lidar stays
+//! still until `start` is called, e.g. to run diagnostics or to wait for a
+//! mission before scanning.
+//!
+//! ```no_run
+//! # #[cfg(feature = "sync")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use hls_lfcd_lds_driver::options::{Model, OpenOptions};
+//! use hls_lfcd_lds_driver::sync::LFCDLaser;
+//! use std::time::Duration;
+//!
+//! let mut laser: LFCDLaser = OpenOptions::new("/dev/ttyUSB0", 230400)
+//!     .model(Model::Lds01)
+//!     .timeout(Duration::from_secs(2))
+//!     .exclusive(true)
+//!     .auto_start(false)
+//!     .open()?;
+//! // The motor is still, until the mission begins.
+//! laser.start();
+//! let reading = laser.read()?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "sync"))]
+//! # fn main() {}
+//! ```
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The lidars known to the crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Model {
+    /// Robotis LDS-01, the `sync`, `tokio` and `smol` drivers
+    Lds01,
+    /// YDLIDAR X2, the `ydlidar` driver
+    YdLidarX2,
+    /// YDLIDAR X4, the `ydlidar` driver
+    YdLidarX4,
+}
+
+impl Model {
+    /// Gets the name of the model.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::Lds01 => "LDS-01",
+            Model::YdLidarX2 => "YDLIDAR X2",
+            Model::YdLidarX4 => "YDLIDAR X4",
+        }
+    }
+
+    /// Gets the baud rates the model talks at.
+    pub fn baud_rates(&self) -> &'static [u32] {
+        match self {
+            Model::Lds01 => &[230_400],
+            Model::YdLidarX2 => &[115_200],
+            Model::YdLidarX4 => &[128_000],
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Invalid options, found before opening the port or rendering a scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OptionsError {
+    /// No serial port given
+    EmptyPort,
+    /// The model does not talk at this baud rate
+    UnsupportedBaudRate { model: Model, baud_rate: u32 },
+    /// The driver does not handle this model
+    UnsupportedModel { model: Model, driver: &'static str },
+    /// A zero timeout fails every read
+    ZeroTimeout,
+    /// The range mapped to the border of an image is not positive
+    NonPositiveRange,
+    /// The distance between two range rings is not positive
+    NonPositiveRingStep,
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionsError::EmptyPort => write!(f, "No serial port given"),
+            OptionsError::UnsupportedBaudRate { model, baud_rate } => write!(
+                f,
+                "The {model} does not talk at {baud_rate} baud, only at {:?}",
+                model.baud_rates()
+            ),
+            OptionsError::UnsupportedModel { model, driver } => {
+                write!(f, "The {driver} driver does not handle the {model}")
+            }
+            OptionsError::ZeroTimeout => write!(f, "The timeout must not be zero"),
+            OptionsError::NonPositiveRange => write!(f, "The maximum range must be positive"),
+            OptionsError::NonPositiveRingStep => {
+                write!(f, "The distance between range rings must be positive")
+            }
+        }
+    }
+}
+
+impl std::error::Error for OptionsError {}
+
+/// A driver opened from `OpenOptions`.
+pub trait Open: Sized {
+    /// Error returned when the lidar cannot be opened
+    type Error: From<OptionsError>;
+
+    /// Name of the driver, in the errors
+    const DRIVER: &'static str;
+
+    /// Models handled by the driver
+    const MODELS: &'static [Model];
+
+    /// Opens the lidar without checking the options, see `OpenOptions::open`.
+    ///
+    /// # Errors
+    /// An error variant is returned if the port cannot be opened and configured.
+    fn open_unchecked(options: &OpenOptions) -> Result<Self, Self::Error>;
+}
+
+/// The port of the lidar and how to open it, see the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenOptions {
+    pub(crate) port: PathBuf,
+    pub(crate) baud_rate: u32,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) exclusive: bool,
+    pub(crate) auto_start: bool,
+    pub(crate) model: Model,
+}
+
+impl OpenOptions {
+    /// Creates the options of an LDS-01 on `port`, shared with other
+    /// processes and started once open.
+    pub fn new<P: AsRef<Path>>(port: P, baud_rate: u32) -> Self {
+        Self {
+            port: port.as_ref().to_path_buf(),
+            baud_rate,
+            timeout: None,
+            exclusive: false,
+            auto_start: true,
+            model: Model::Lds01,
+        }
+    }
+
+    /// Sets the time a blocking read waits for data before failing until
+    /// the RPMs are known, by default the one of the driver.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets whether other processes are kept from opening the port, only on unix.
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Sets whether the motor is started once the port is open, if not the
+    /// lidar sends no data until `start` is called.
+    pub fn auto_start(mut self, auto_start: bool) -> Self {
+        self.auto_start = auto_start;
+        self
+    }
+
+    /// Sets the model of the lidar.
+    pub fn model(mut self, model: Model) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Gets the serial port.
+    pub fn port(&self) -> &Path {
+        &self.port
+    }
+
+    /// Gets the baud rate.
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    /// Checks if the motor is started once the port is open.
+    pub fn is_auto_start(&self) -> bool {
+        self.auto_start
+    }
+
+    /// Checks the options, whatever the driver.
+    ///
+    /// # Errors
+    /// An error variant is returned for the first invalid option.
+    pub fn validate(&self) -> Result<(), OptionsError> {
+        if self.port.as_os_str().is_empty() {
+            return Err(OptionsError::EmptyPort);
+        }
+        if !self.model.baud_rates().contains(&self.baud_rate) {
+            return Err(OptionsError::UnsupportedBaudRate {
+                model: self.model,
+                baud_rate: self.baud_rate,
+            });
+        }
+        if self.timeout == Some(Duration::ZERO) {
+            return Err(OptionsError::ZeroTimeout);
+        }
+        Ok(())
+    }
+
+    /// Checks the options, then opens the lidar with the driver `D`.
+    ///
+    /// # Errors
+    /// An error variant is returned if the options are invalid, or the
+    /// driver does not handle the model, without touching the port; or if
+    /// the port cannot be opened and configured.
+    pub fn open<D: Open>(&self) -> Result<D, D::Error> {
+        if !D::MODELS.contains(&self.model) {
+            return Err(OptionsError::UnsupportedModel {
+                model: self.model,
+                driver: D::DRIVER,
+            }
+            .into());
+        }
+        self.validate()?;
+        D::open_unchecked(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A driver of the LDS-01 only, opened without any port.
+    #[derive(Debug)]
+    struct Opened;
+
+    impl Open for Opened {
+        type Error = OptionsError;
+
+        const DRIVER: &'static str = "test";
+
+        const MODELS: &'static [Model] = &[Model::Lds01];
+
+        fn open_unchecked(_: &OpenOptions) -> Result<Self, OptionsError> {
+            Ok(Opened)
+        }
+    }
+
+    #[test]
+    fn validates_the_options() {
+        let options = OpenOptions::new("/dev/ttyUSB0", 230_400);
+        assert_eq!(options.validate(), Ok(()));
+        assert_eq!(
+            OpenOptions::new("", 230_400).validate(),
+            Err(OptionsError::EmptyPort)
+        );
+        assert_eq!(
+            options.clone().model(Model::YdLidarX4).validate(),
+            Err(OptionsError::UnsupportedBaudRate {
+                model: Model::YdLidarX4,
+                baud_rate: 230_400,
+            })
+        );
+        assert_eq!(
+            options.timeout(Duration::ZERO).validate(),
+            Err(OptionsError::ZeroTimeout)
+        );
+    }
+
+    #[test]
+    fn checks_the_model_before_opening() {
+        let options = OpenOptions::new("/dev/ttyUSB0", 115_200).model(Model::YdLidarX2);
+        assert_eq!(
+            options.open::<Opened>().unwrap_err(),
+            OptionsError::UnsupportedModel {
+                model: Model::YdLidarX2,
+                driver: "test",
+            }
+        );
+        assert!(OpenOptions::new("/dev/ttyUSB0", 230_400)
+            .open::<Opened>()
+            .is_ok());
+        assert!(OpenOptions::new("/dev/ttyUSB0", 115_200)
+            .open::<Opened>()
+            .is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Arrow and Parquet export of scans, enabled by the `parquet` feature.
+//!
+//! Scans are buffered and written as Arrow record batches, each batch
+//! becoming a row group of the Parquet file, so long captures can be
+//! queried with DataFusion, DuckDB, pandas and similar tools.
+//!
+//! ```no_run
+//! use hls_lfcd_lds_driver::parquet::{Layout, ParquetWriter};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let mut writer = ParquetWriter::create("scans.parquet", Layout::PerBeam).unwrap();
+//! writer.write(&reading).unwrap();
+//! writer.close().unwrap();
+//! ```
+
+use crate::LaserReading;
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::errors::Result;
+use ::parquet::file::properties::WriterProperties;
+use arrow_array::builder::{ListBuilder, UInt16Builder};
+use arrow_array::{
+    ArrayRef, Float32Array, RecordBatch, TimestampNanosecondArray, UInt16Array, UInt64Array,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default number of scans in every record batch.
+pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// Layout of the rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// One row per scan: `timestamp`, `scan`, `rpms`, `ranges` and `intensities` lists
+    PerScan,
+    /// One row per beam: `timestamp`, `scan`, `beam`, `angle`, `range` and `intensity`
+    #[default]
+    PerBeam,
+}
+
+/// A scan and the time it was taken at.
+#[derive(Debug, Clone)]
+pub struct StampedReading {
+    pub timestamp: SystemTime,
+    pub reading: LaserReading,
+}
+
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()))
+}
+
+/// Gets the Arrow schema of the given layout.
+///
+/// Timestamps are in ns since the epoch, angles in radians and ranges in mm.
+pub fn schema(layout: Layout) -> SchemaRef {
+    let fields = match layout {
+        Layout::PerScan => vec![
+            Field::new("timestamp", timestamp_type(), false),
+            Field::new("scan", DataType::UInt64, false),
+            Field::new("rpms", DataType::UInt16, false),
+            Field::new_list(
+                "ranges",
+                Field::new_list_field(DataType::UInt16, true),
+                false,
+            ),
+            Field::new_list(
+                "intensities",
+                Field::new_list_field(DataType::UInt16, true),
+                false,
+            ),
+        ],
+        Layout::PerBeam => vec![
+            Field::new("timestamp", timestamp_type(), false),
+            Field::new("scan", DataType::UInt64, false),
+            Field::new("beam", DataType::UInt16, false),
+            Field::new("angle", DataType::Float32, false),
+            Field::new("range", DataType::UInt16, false),
+            Field::new("intensity", DataType::UInt16, false),
+        ],
+    };
+    Arc::new(Schema::new(fields))
+}
+
+/// Converts the given scans into a record batch, `first_scan` being the
+/// sequence number of the first one.
+///
+/// # Errors
+/// An error variant is returned if the batch cannot be built.
+pub fn to_record_batch(
+    scans: &[StampedReading],
+    first_scan: u64,
+    layout: Layout,
+) -> std::result::Result<RecordBatch, ArrowError> {
+    let nanos = |t: &SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as i64;
+    let seq = first_scan..;
+
+    let columns: Vec<ArrayRef> = match layout {
+        Layout::PerScan => {
+            let mut ranges = ListBuilder::new(UInt16Builder::new());
+            let mut intensities = ListBuilder::new(UInt16Builder::new());
+            for s in scans {
+                ranges.values().append_slice(&s.reading.ranges);
+                ranges.append(true);
+                intensities.values().append_slice(&s.reading.intensities);
+                intensities.append(true);
+            }
+            vec![
+                Arc::new(
+                    TimestampNanosecondArray::from_iter_values(
+                        scans.iter().map(|s| nanos(&s.timestamp)),
+                    )
+                    .with_timezone("UTC"),
+                ),
+                Arc::new(UInt64Array::from_iter_values(seq.take(scans.len()))),
+                Arc::new(UInt16Array::from_iter_values(
+                    scans.iter().map(|s| s.reading.rpms),
+                )),
+                Arc::new(ranges.finish()),
+                Arc::new(intensities.finish()),
+            ]
+        }
+        Layout::PerBeam => {
+            let beams = |s: &StampedReading| 0..s.reading.ranges.len();
+            let rows = || {
+                scans
+                    .iter()
+                    .zip(seq.clone())
+                    .flat_map(move |(s, n)| beams(s).map(move |i| (s, n, i)))
+            };
+            vec![
+                Arc::new(
+                    TimestampNanosecondArray::from_iter_values(
+                        rows().map(|(s, _, _)| nanos(&s.timestamp)),
+                    )
+                    .with_timezone("UTC"),
+                ),
+                Arc::new(UInt64Array::from_iter_values(rows().map(|(_, n, _)| n))),
+                Arc::new(UInt16Array::from_iter_values(
+                    rows().map(|(_, _, i)| i as u16),
+                )),
+                Arc::new(Float32Array::from_iter_values(
+                    rows().map(|(_, _, i)| LaserReading::angle(i)),
+                )),
+                Arc::new(UInt16Array::from_iter_values(
+                    rows().map(|(s, _, i)| s.reading.ranges[i]),
+                )),
+                Arc::new(UInt16Array::from_iter_values(
+                    rows().map(|(s, _, i)| s.reading.intensities[i]),
+                )),
+            ]
+        }
+    };
+
+    RecordBatch::try_new(schema(layout), columns)
+}
+
+/// Writer of scans to a Parquet file, buffering them in record batches.
+pub struct ParquetWriter<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    layout: Layout,
+    batch_size: usize,
+    pending: Vec<StampedReading>,
+    written: u64,
+}
+
+impl ParquetWriter<File> {
+    /// Creates the Parquet file at `path`.
+    ///
+    /// # Errors
+    /// An error variant is returned if the file cannot be created.
+    pub fn create<P: AsRef<Path>>(path: P, layout: Layout) -> Result<Self> {
+        Self::new(File::create(path)?, layout)
+    }
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    /// Creates a writer on `w`, compressing the data with snappy.
+    ///
+    /// # Errors
+    /// An error variant is returned if the Parquet header cannot be written.
+    pub fn new(w: W, layout: Layout) -> Result<Self> {
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        Ok(Self {
+            writer: ArrowWriter::try_new(w, schema(layout), Some(props))?,
+            layout,
+            batch_size: DEFAULT_BATCH_SIZE,
+            pending: Vec::with_capacity(DEFAULT_BATCH_SIZE),
+            written: 0,
+        })
+    }
+
+    /// Sets the number of scans in every record batch.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Gets the layout of the rows.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Gets the number of scans written so far, including the buffered ones.
+    pub fn scans(&self) -> u64 {
+        self.written + self.pending.len() as u64
+    }
+
+    /// Writes a reading, stamped with the current time.
+    ///
+    /// # Errors
+    /// An error variant is returned if a full batch cannot be written.
+    pub fn write(&mut self, reading: &LaserReading) -> Result<()> {
+        self.write_at(reading, SystemTime::now())
+    }
+
+    /// Writes a reading taken at `timestamp`.
+    ///
+    /// # Errors
+    /// An error variant is returned if a full batch cannot be written.
+    pub fn write_at(&mut self, reading: &LaserReading, timestamp: SystemTime) -> Result<()> {
+        self.pending.push(StampedReading {
+            timestamp,
+            reading: reading.clone(),
+        });
+        if self.pending.len() >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered scans as a new row group.
+    ///
+    /// # Errors
+    /// An error variant is returned if the batch cannot be written.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let batch = to_record_batch(&self.pending, self.written, self.layout)?;
+        self.writer.write(&batch)?;
+        self.writer.flush()?;
+        self.written += self.pending.len() as u64;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Writes the buffered scans and the Parquet footer.
+    ///
+    /// # Errors
+    /// An error variant is returned if the file cannot be completed.
+    pub fn close(mut self) -> Result<()> {
+        self.flush()?;
+        self.writer.close().map(|_| ())
+    }
+}
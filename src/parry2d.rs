@@ -0,0 +1,95 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Collision checking against scans with `parry2d`, enabled by the `parry2d` feature.
+//!
+//! Coordinates are in meters, poses are the ones of the shapes in the lidar frame.
+//!
+//! ```
+//! use parry2d::math::{Isometry, Point};
+//! use parry2d::shape::ConvexPolygon;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! // 40x30 cm robot footprint, 20 cm ahead of the lidar.
+//! let footprint = ConvexPolygon::from_convex_hull(&[
+//!     Point::new(-0.2, -0.15),
+//!     Point::new(0.2, -0.15),
+//!     Point::new(0.2, 0.15),
+//!     Point::new(-0.2, 0.15),
+//! ])
+//! .unwrap();
+//! let pose = Isometry::translation(0.2, 0.0);
+//! assert!(!reading.collides_with(&footprint, &pose, 0.05));
+//! ```
+
+use crate::LaserReading;
+use ::parry2d::math::{Isometry, Point, Real};
+use ::parry2d::shape::{Polyline, Shape};
+
+impl LaserReading {
+    /// Gets the valid beams as `parry2d` points.
+    pub fn to_parry_points(&self) -> Vec<Point<Real>> {
+        self.points()
+            .into_iter()
+            .map(|(x, y)| Point::new(x, y))
+            .collect()
+    }
+
+    /// Gets the contour of the scan as a `Polyline`, with a segment between
+    /// every two adjacent valid beams, so gaps are not bridged.
+    ///
+    /// Returns `None` if no two adjacent beams are valid.
+    pub fn to_parry_polyline(&self) -> Option<Polyline> {
+        let n = self.ranges.len();
+        let mut vertex = vec![None; n];
+        let mut vertices = Vec::new();
+        for (i, v) in vertex
+            .iter_mut()
+            .enumerate()
+            .filter(|(i, _)| self.is_valid(*i))
+        {
+            let (x, y) = self.point(i);
+            *v = Some(vertices.len() as u32);
+            vertices.push(Point::new(x, y));
+        }
+
+        let indices: Vec<[u32; 2]> = (0..n)
+            .filter_map(|i| Some([vertex[i]?, vertex[(i + 1) % n]?]))
+            .filter(|[a, b]| a != b)
+            .collect();
+        if indices.is_empty() {
+            return None;
+        }
+        Some(Polyline::new(vertices, Some(indices)))
+    }
+
+    /// Checks if any valid beam lies inside `shape`, placed at `pose`, or
+    /// closer than `margin` meters to it.
+    pub fn collides_with(&self, shape: &dyn Shape, pose: &Isometry<Real>, margin: Real) -> bool {
+        self.closest_to(shape, pose)
+            .is_some_and(|(_, distance)| distance <= margin)
+    }
+
+    /// Gets the index of the valid beam closest to `shape`, placed at `pose`,
+    /// and its distance in meters, 0 when the beam is inside the shape.
+    pub fn closest_to(&self, shape: &dyn Shape, pose: &Isometry<Real>) -> Option<(usize, Real)> {
+        (0..self.ranges.len())
+            .filter(|&i| self.is_valid(i))
+            .map(|i| {
+                let (x, y) = self.point(i);
+                (i, shape.distance_to_point(pose, &Point::new(x, y), true))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+}
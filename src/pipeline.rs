@@ -0,0 +1,330 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Logging and streaming applications in a few lines: scans are read from a
+//! driver, go through the filters and are handed to the sinks.
+//!
+//! The filters run on the thread reading the lidar, the sinks on a thread of
+//! their own, so that a slow disk or network never delays the serial port.
+//! When the sinks fall behind by more than the backlog, the scans are
+//! dropped and counted in the report.
+//!
+//! ```no_run
+//! # #[cfg(feature = "sync")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use hls_lfcd_lds_driver::pipeline::{Pipeline, RangeClamp, SpeckleFilter};
+//! use hls_lfcd_lds_driver::sink::{CsvSink, UdpSink};
+//! use hls_lfcd_lds_driver::sync::LFCDLaser;
+//!
+//! let laser = LFCDLaser::new("/dev/ttyUSB0", 230400)?;
+//! let report = Pipeline::new(laser)
+//!     .filter(RangeClamp::new(150, 3000))
+//!     .filter(SpeckleFilter::new(100))
+//!     .sink(CsvSink::create("scans.csv")?)
+//!     .sink(UdpSink::connect("192.168.1.10:9000")?)
+//!     .limit(1000)
+//!     .run()?;
+//! println!("{} scans, {} dropped", report.scans, report.dropped);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "sync"))]
+//! # fn main() {}
+//! ```
+
+use crate::sink::ScanSink;
+use crate::{LaserReading, LidarDriver};
+use std::io;
+use std::sync::mpsc::{self, TrySendError};
+use std::thread;
+
+/// Default number of scans waiting for the sinks.
+pub const DEFAULT_BACKLOG: usize = 16;
+
+/// A transformation applied to every scan before the sinks.
+pub trait ScanFilter: Send {
+    /// Modifies the scan in place.
+    fn apply(&mut self, reading: &mut LaserReading);
+}
+
+impl<F> ScanFilter for F
+where
+    F: FnMut(&mut LaserReading) + Send,
+{
+    fn apply(&mut self, reading: &mut LaserReading) {
+        self(reading)
+    }
+}
+
+/// Sets the ranges outside `[min, max]`, in mm, to 0, no return.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeClamp {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl RangeClamp {
+    /// Creates a filter keeping the ranges from `min` to `max` mm.
+    pub fn new(min: u16, max: u16) -> Self {
+        Self { min, max }
+    }
+}
+
+impl ScanFilter for RangeClamp {
+    fn apply(&mut self, reading: &mut LaserReading) {
+        for (range, intensity) in reading.ranges.iter_mut().zip(&mut reading.intensities) {
+            if !(self.min..=self.max).contains(range) {
+                *range = 0;
+                *intensity = 0;
+            }
+        }
+    }
+}
+
+/// Removes the isolated returns, the speckles: the beams more than
+/// `max_difference` mm away from both their neighbours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeckleFilter {
+    pub max_difference: u16,
+}
+
+impl SpeckleFilter {
+    /// Creates a filter of the returns more than `max_difference` mm away
+    /// from both their neighbours.
+    pub fn new(max_difference: u16) -> Self {
+        Self { max_difference }
+    }
+}
+
+impl ScanFilter for SpeckleFilter {
+    fn apply(&mut self, reading: &mut LaserReading) {
+        let n = reading.ranges.len();
+        let original = reading.ranges;
+        let close = |a: u16, b: u16| b != 0 && a.abs_diff(b) <= self.max_difference;
+        for i in (0..n).filter(|&i| original[i] != 0) {
+            let (prev, next) = (original[(i + n - 1) % n], original[(i + 1) % n]);
+            if !close(original[i], prev) && !close(original[i], next) {
+                reading.ranges[i] = 0;
+                reading.intensities[i] = 0;
+            }
+        }
+    }
+}
+
+/// What happened while a pipeline ran.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineReport {
+    /// Scans read from the driver
+    pub scans: u64,
+    /// Scans dropped because the sinks were behind
+    pub dropped: u64,
+    /// Failed writes of the sinks
+    pub sink_errors: u64,
+}
+
+/// A driver, its filters and its sinks, see the module documentation.
+pub struct Pipeline<D: LidarDriver> {
+    driver: D,
+    filters: Vec<Box<dyn ScanFilter>>,
+    sinks: Vec<Box<dyn ScanSink>>,
+    backlog: usize,
+    limit: Option<u64>,
+}
+
+impl<D: LidarDriver> Pipeline<D> {
+    /// Creates a pipeline reading from `driver`.
+    pub fn new(driver: D) -> Self {
+        Self {
+            driver,
+            filters: Vec::new(),
+            sinks: Vec::new(),
+            backlog: DEFAULT_BACKLOG,
+            limit: None,
+        }
+    }
+
+    /// Adds a filter, applied after the ones already added.
+    pub fn filter<F: ScanFilter + 'static>(mut self, filter: F) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Adds a sink, every sink gets every scan.
+    pub fn sink<S: ScanSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Sets the number of scans waiting for the sinks before new ones are dropped.
+    pub fn backlog(mut self, scans: usize) -> Self {
+        self.backlog = scans.max(1);
+        self
+    }
+
+    /// Stops after `scans` scans, by default the pipeline runs until the
+    /// driver fails.
+    pub fn limit(mut self, scans: u64) -> Self {
+        self.limit = Some(scans);
+        self
+    }
+
+    /// Runs the pipeline, the sinks are flushed before returning.
+    ///
+    /// # Errors
+    /// An error variant is returned if the thread of the sinks cannot be
+    /// started, or if the driver fails, after the scans already read are
+    /// handed to the sinks.
+    pub fn run(self) -> Result<PipelineReport, D::Error>
+    where
+        D::Error: From<io::Error>,
+    {
+        let Self {
+            mut driver,
+            mut filters,
+            mut sinks,
+            backlog,
+            limit,
+        } = self;
+
+        let (tx, rx) = mpsc::sync_channel::<LaserReading>(backlog);
+        let writer = thread::Builder::new()
+            .name("lds-sinks".into())
+            .spawn(move || {
+                let mut errors = 0;
+                for reading in rx {
+                    for sink in sinks.iter_mut() {
+                        if sink.consume(&reading).is_err() {
+                            errors += 1;
+                        }
+                    }
+                }
+                for sink in sinks.iter_mut() {
+                    if sink.flush().is_err() {
+                        errors += 1;
+                    }
+                }
+                errors
+            })?;
+
+        let mut report = PipelineReport::default();
+        let result = loop {
+            if limit.is_some_and(|limit| report.scans >= limit) {
+                break Ok(());
+            }
+            let mut reading = match driver.read() {
+                Ok(reading) => reading,
+                Err(e) => break Err(e),
+            };
+            report.scans += 1;
+            for filter in filters.iter_mut() {
+                filter.apply(&mut reading);
+            }
+            match tx.try_send(reading) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => report.dropped += 1,
+                // The sink thread is gone, nothing to write to anymore.
+                Err(TrySendError::Disconnected(_)) => break Ok(()),
+            }
+        };
+
+        drop(tx);
+        report.sink_errors = writer.join().unwrap_or(0);
+        result.map(|()| report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A driver returning `left` scans numbered by their RPMs, then failing.
+    struct Counting {
+        left: u16,
+    }
+
+    impl LidarDriver for Counting {
+        type Error = io::Error;
+
+        fn read(&mut self) -> io::Result<LaserReading> {
+            if self.left == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.left -= 1;
+            let mut reading = LaserReading::new();
+            reading.rpms = self.left;
+            reading.ranges.fill(1000);
+            Ok(reading)
+        }
+
+        fn start(&mut self) {}
+
+        fn close(&mut self) {}
+    }
+
+    #[test]
+    fn clamps_the_ranges() {
+        let mut reading = LaserReading::new();
+        reading.ranges[..3].copy_from_slice(&[100, 1000, 4000]);
+        reading.intensities[..3].fill(50);
+        RangeClamp::new(120, 3500).apply(&mut reading);
+        assert_eq!(reading.ranges[..3], [0, 1000, 0]);
+        assert_eq!(reading.intensities[..3], [0, 50, 0]);
+    }
+
+    #[test]
+    fn removes_the_speckles() {
+        let mut reading = LaserReading::new();
+        reading.ranges[10..15].copy_from_slice(&[1000, 1010, 2000, 1020, 1030]);
+        reading.ranges[100] = 1500;
+        SpeckleFilter::new(50).apply(&mut reading);
+        assert_eq!(reading.ranges[10..15], [1000, 1010, 0, 1020, 1030]);
+        assert_eq!(reading.ranges[100], 0);
+    }
+
+    #[test]
+    fn hands_the_filtered_scans_to_the_sinks() {
+        let (tx, rx) = mpsc::channel();
+        let report = Pipeline::new(Counting { left: 10 })
+            .filter(|r: &mut LaserReading| r.ranges[0] = 0)
+            .sink(tx)
+            .limit(4)
+            .run()
+            .unwrap();
+        assert_eq!(
+            report,
+            PipelineReport {
+                scans: 4,
+                ..Default::default()
+            }
+        );
+        let scans: Vec<_> = rx.iter().collect();
+        assert_eq!(
+            scans.iter().map(|s| s.rpms).collect::<Vec<_>>(),
+            [9, 8, 7, 6]
+        );
+        assert!(scans
+            .iter()
+            .all(|s| s.ranges[0] == 0 && s.ranges[1] == 1000));
+    }
+
+    #[test]
+    fn delivers_the_scans_read_before_failing() {
+        let (tx, rx) = mpsc::channel();
+        let err = Pipeline::new(Counting { left: 2 })
+            .sink(tx)
+            .run()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(rx.iter().count(), 2);
+    }
+}
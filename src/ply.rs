@@ -0,0 +1,152 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! PLY export of scans and accumulated point clouds, as expected by
+//! MeshLab, Open3D, CloudCompare and similar tools.
+
+use crate::LaserReading;
+use std::io::{self, Write};
+
+/// Encoding of the PLY body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlyFormat {
+    /// Human readable, one vertex per line
+    #[default]
+    Ascii,
+    /// Binary, little-endian
+    BinaryLittleEndian,
+}
+
+/// A vertex of the point cloud, coordinates in meters.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PlyPoint {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub intensity: u16,
+}
+
+/// Writes the given points as a PLY file.
+///
+/// # Errors
+/// An error variant is returned if writing to `w` fails.
+pub fn write_ply<W: Write>(mut w: W, points: &[PlyPoint], format: PlyFormat) -> io::Result<()> {
+    let format_name = match format {
+        PlyFormat::Ascii => "ascii",
+        PlyFormat::BinaryLittleEndian => "binary_little_endian",
+    };
+    write!(
+        w,
+        "ply\n\
+         format {format_name} 1.0\n\
+         comment generated by hls_lfcd_lds_driver\n\
+         element vertex {}\n\
+         property float x\n\
+         property float y\n\
+         property float z\n\
+         property ushort intensity\n\
+         end_header\n",
+        points.len()
+    )?;
+
+    match format {
+        PlyFormat::Ascii => {
+            for p in points {
+                writeln!(w, "{} {} {} {}", p.x, p.y, p.z, p.intensity)?;
+            }
+        }
+        PlyFormat::BinaryLittleEndian => {
+            for p in points {
+                w.write_all(&p.x.to_le_bytes())?;
+                w.write_all(&p.y.to_le_bytes())?;
+                w.write_all(&p.z.to_le_bytes())?;
+                w.write_all(&p.intensity.to_le_bytes())?;
+            }
+        }
+    }
+
+    w.flush()
+}
+
+/// A point cloud accumulating several scans, each one taken from a given pose.
+#[derive(Debug, Clone, Default)]
+pub struct PointCloud {
+    points: Vec<PlyPoint>,
+}
+
+impl PointCloud {
+    /// Creates an empty point cloud.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the valid beams of `reading`, taken by a lidar at (`x`, `y`, `z`)
+    /// meters and rotated by `yaw` radians around the vertical axis.
+    pub fn add_scan(&mut self, reading: &LaserReading, x: f32, y: f32, z: f32, yaw: f32) {
+        let (sin, cos) = yaw.sin_cos();
+        self.points.extend(
+            (0..reading.ranges.len())
+                .filter(|&i| reading.is_valid(i))
+                .map(|i| {
+                    let (px, py) = reading.point(i);
+                    PlyPoint {
+                        x: x + px * cos - py * sin,
+                        y: y + px * sin + py * cos,
+                        z,
+                        intensity: reading.intensities[i],
+                    }
+                }),
+        );
+    }
+
+    /// Gets the accumulated points.
+    pub fn points(&self) -> &[PlyPoint] {
+        &self.points
+    }
+
+    /// Gets the number of accumulated points.
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Checks if the point cloud is empty.
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Removes all the points.
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    /// Writes the point cloud as a PLY file.
+    ///
+    /// # Errors
+    /// An error variant is returned if writing to `w` fails.
+    pub fn write_ply<W: Write>(&self, w: W, format: PlyFormat) -> io::Result<()> {
+        write_ply(w, &self.points, format)
+    }
+}
+
+impl LaserReading {
+    /// Writes the valid beams of the scan as a PLY file, in the lidar frame.
+    ///
+    /// # Errors
+    /// An error variant is returned if writing to `w` fails.
+    pub fn write_ply<W: Write>(&self, w: W, format: PlyFormat) -> io::Result<()> {
+        let mut cloud = PointCloud::new();
+        cloud.add_scan(self, 0.0, 0.0, 0.0, 0.0);
+        cloud.write_ply(w, format)
+    }
+}
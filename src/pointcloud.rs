@@ -0,0 +1,179 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Scans as the payload of a `sensor_msgs/PointCloud2`, for the users
+//! publishing through rosbridge or zenoh without `laser_geometry`.
+//!
+//! Every point is four little-endian `float32`: `x`, `y` and `z` in meters,
+//! in the frame of the lidar, and the intensity; 16 bytes per point. The
+//! header of the message is left to the publisher.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::pointcloud::{PointCloud2, POINT_STEP};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let cloud = PointCloud2::from_reading(&reading);
+//! assert_eq!(cloud.data.len(), (cloud.width * POINT_STEP) as usize);
+//! ```
+
+use crate::LaserReading;
+
+/// `sensor_msgs/PointField` datatype of a `float32`.
+pub const FLOAT32: u8 = 7;
+/// Bytes of every point.
+pub const POINT_STEP: u32 = 16;
+
+/// `sensor_msgs/PointField`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ser_de", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointField {
+    pub name: String,
+    /// Offset of the field from the start of the point, in bytes
+    pub offset: u32,
+    pub datatype: u8,
+    /// Number of elements of the field
+    pub count: u32,
+}
+
+/// Gets the fields of the points: `x`, `y`, `z` and `intensity`.
+pub fn fields() -> Vec<PointField> {
+    ["x", "y", "z", "intensity"]
+        .iter()
+        .zip((0..).step_by(4))
+        .map(|(name, offset)| PointField {
+            name: (*name).into(),
+            offset,
+            datatype: FLOAT32,
+            count: 1,
+        })
+        .collect()
+}
+
+/// `sensor_msgs/PointCloud2` without its header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ser_de", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointCloud2 {
+    pub height: u32,
+    pub width: u32,
+    pub fields: Vec<PointField>,
+    pub is_bigendian: bool,
+    pub point_step: u32,
+    pub row_step: u32,
+    pub data: Vec<u8>,
+    /// No point is NaN
+    pub is_dense: bool,
+}
+
+impl PointCloud2 {
+    /// Creates an unorganized cloud of the valid beams.
+    pub fn from_reading<const N: usize>(reading: &LaserReading<N>) -> Self {
+        let mut data = Vec::with_capacity(N * POINT_STEP as usize);
+        write_points(reading, &mut data);
+        Self::with_data(data, true)
+    }
+
+    /// Creates a cloud of all the `N` beams in their order, the invalid ones
+    /// being NaN, for the consumers indexing the points by beam.
+    pub fn from_reading_organized<const N: usize>(reading: &LaserReading<N>) -> Self {
+        let mut data = Vec::with_capacity(N * POINT_STEP as usize);
+        for i in 0..N {
+            let (x, y, intensity) = if reading.is_valid(i) {
+                let (x, y) = reading.point(i);
+                (x, y, f32::from(reading.intensities[i]))
+            } else {
+                (f32::NAN, f32::NAN, f32::NAN)
+            };
+            push_point(&mut data, x, y, intensity);
+        }
+        Self::with_data(data, (0..N).all(|i| reading.is_valid(i)))
+    }
+
+    fn with_data(data: Vec<u8>, is_dense: bool) -> Self {
+        let width = data.len() as u32 / POINT_STEP;
+        Self {
+            height: 1,
+            width,
+            fields: fields(),
+            is_bigendian: false,
+            point_step: POINT_STEP,
+            row_step: width * POINT_STEP,
+            data,
+            is_dense,
+        }
+    }
+}
+
+/// Appends the valid beams of a scan to `data`, in the layout of `fields`,
+/// reusing the buffer of the previous scan.
+pub fn write_points<const N: usize>(reading: &LaserReading<N>, data: &mut Vec<u8>) {
+    for i in (0..N).filter(|&i| reading.is_valid(i)) {
+        let (x, y) = reading.point(i);
+        push_point(data, x, y, f32::from(reading.intensities[i]));
+    }
+}
+
+fn push_point(data: &mut Vec<u8>, x: f32, y: f32, intensity: f32) {
+    for value in [x, y, 0.0, intensity] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(cloud: &PointCloud2, n: usize) -> [f32; 4] {
+        let at = n * POINT_STEP as usize;
+        let mut point = [0f32; 4];
+        for (k, value) in point.iter_mut().enumerate() {
+            let bytes = &cloud.data[at + 4 * k..at + 4 * k + 4];
+            *value = f32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        point
+    }
+
+    fn reading() -> LaserReading {
+        let mut reading = LaserReading::new();
+        reading.ranges[90] = 2000;
+        reading.intensities[90] = 800;
+        reading
+    }
+
+    #[test]
+    fn packs_the_valid_beams() {
+        let cloud = PointCloud2::from_reading(&reading());
+        assert_eq!((cloud.height, cloud.width, cloud.row_step), (1, 1, 16));
+        assert!(cloud.is_dense);
+        let [x, y, z, intensity] = point(&cloud, 0);
+        assert!(x.abs() < 1e-4 && (y - 2.0).abs() < 1e-4);
+        assert_eq!((z, intensity), (0.0, 800.0));
+
+        let offsets: Vec<_> = cloud
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), f.offset))
+            .collect();
+        assert_eq!(offsets, [("x", 0), ("y", 4), ("z", 8), ("intensity", 12)]);
+    }
+
+    #[test]
+    fn keeps_every_beam_when_organized() {
+        let cloud = PointCloud2::from_reading_organized(&reading());
+        assert_eq!(cloud.width, 360);
+        assert_eq!(cloud.data.len(), 360 * POINT_STEP as usize);
+        assert!(!cloud.is_dense);
+        assert!(point(&cloud, 0)[0].is_nan());
+        assert_eq!(point(&cloud, 90)[3], 800.0);
+    }
+}
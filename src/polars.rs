@@ -0,0 +1,118 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Conversion of scans to polars `DataFrame`s, enabled by the `polars` feature.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::polars::ScanCollector;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let mut collector = ScanCollector::new();
+//! collector.push(&reading);
+//! collector.push(&reading);
+//! let df = collector.to_dataframe().unwrap();
+//! assert_eq!(df.height(), 720);
+//! ```
+
+use crate::LaserReading;
+use ::polars::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+impl LaserReading {
+    /// Converts the scan into a `DataFrame` with one row per beam and the
+    /// columns `angle` (radians), `range` (mm) and `intensity`.
+    ///
+    /// # Errors
+    /// An error variant is returned if the `DataFrame` cannot be built.
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let angles: Vec<f32> = (0..self.ranges.len()).map(LaserReading::angle).collect();
+        df!(
+            "angle" => angles,
+            "range" => &self.ranges[..],
+            "intensity" => &self.intensities[..],
+        )
+    }
+}
+
+/// Collector of several scans, converted into a single `DataFrame` with
+/// one row per beam and the columns `timestamp`, `angle`, `range` and `intensity`.
+#[derive(Debug, Clone, Default)]
+pub struct ScanCollector {
+    timestamps: Vec<i64>,
+    angles: Vec<f32>,
+    ranges: Vec<u16>,
+    intensities: Vec<u16>,
+    scans: usize,
+}
+
+impl ScanCollector {
+    /// Creates an empty collector.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a reading, stamped with the current time.
+    pub fn push(&mut self, reading: &LaserReading) {
+        self.push_at(reading, SystemTime::now());
+    }
+
+    /// Adds a reading taken at `timestamp`.
+    pub fn push_at(&mut self, reading: &LaserReading, timestamp: SystemTime) {
+        let nanos = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as i64;
+        let n = reading.ranges.len();
+        self.timestamps.extend(std::iter::repeat_n(nanos, n));
+        self.angles.extend((0..n).map(LaserReading::angle));
+        self.ranges.extend_from_slice(&reading.ranges);
+        self.intensities.extend_from_slice(&reading.intensities);
+        self.scans += 1;
+    }
+
+    /// Gets the number of collected scans.
+    pub fn len(&self) -> usize {
+        self.scans
+    }
+
+    /// Checks if no scan has been collected.
+    pub fn is_empty(&self) -> bool {
+        self.scans == 0
+    }
+
+    /// Removes all the collected scans.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Converts the collected scans into a `DataFrame`, timestamps are
+    /// in ns since the epoch.
+    ///
+    /// # Errors
+    /// An error variant is returned if the `DataFrame` cannot be built.
+    pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
+        let timestamp = Int64Chunked::from_vec("timestamp".into(), self.timestamps.clone())
+            .into_datetime(TimeUnit::Nanoseconds, None)
+            .into_series();
+        DataFrame::new(
+            self.ranges.len(),
+            vec![
+                timestamp.into(),
+                Column::new("angle".into(), &self.angles),
+                Column::new("range".into(), &self.ranges),
+                Column::new("intensity".into(), &self.intensities),
+            ],
+        )
+    }
+}
@@ -0,0 +1,208 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Recycling of the readings sent through channels.
+//!
+//! A `PooledScan` goes back to its `ScanPool` when dropped, so a publisher
+//! copying every scan for its consumers stops allocating once the pool is
+//! warm. For fan-out, wrap it in an `Arc`: the reading is recycled when
+//! the last consumer drops it.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::pool::ScanPool;
+//! use std::sync::{mpsc, Arc};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let pool = ScanPool::new();
+//! let (tx, rx) = mpsc::channel();
+//! for _ in 0..10 {
+//!     tx.send(Arc::new(pool.copy(&reading))).unwrap();
+//!     let scan = rx.recv().unwrap();
+//!     assert_eq!(scan.ranges, reading.ranges);
+//! }
+//! assert_eq!(pool.allocated(), 1);
+//! ```
+
+use crate::LaserReading;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Default number of free readings kept by a pool.
+pub const DEFAULT_RETAINED: usize = 16;
+
+// The readings are boxed so that handing them out moves a pointer, not 1.4 KB.
+type Free = Vec<Box<LaserReading>>;
+
+struct Shared {
+    free: Mutex<Free>,
+    retained: usize,
+    allocated: AtomicU64,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Free> {
+        self.free.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A pool of readings, clones share the same readings.
+#[derive(Clone)]
+pub struct ScanPool {
+    shared: Arc<Shared>,
+}
+
+impl ScanPool {
+    /// Creates a pool keeping up to `DEFAULT_RETAINED` free readings.
+    pub fn new() -> Self {
+        Self::with_retained(DEFAULT_RETAINED)
+    }
+
+    /// Creates a pool keeping up to `retained` free readings, the ones
+    /// returned when it is full are deallocated.
+    pub fn with_retained(retained: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                free: Mutex::new(Vec::with_capacity(retained)),
+                retained,
+                allocated: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Gets a reading from the pool, allocating it if none is free.
+    /// A recycled reading still holds its previous scan.
+    pub fn get(&self) -> PooledScan {
+        let scan = self.shared.lock().pop().unwrap_or_else(|| {
+            self.shared.allocated.fetch_add(1, Ordering::Relaxed);
+            Box::default()
+        });
+        PooledScan {
+            scan: Some(scan),
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Gets a reading from the pool holding a copy of `reading`.
+    pub fn copy(&self, reading: &LaserReading) -> PooledScan {
+        let mut scan = self.get();
+        scan.clone_from(reading);
+        scan
+    }
+
+    /// Gets the number of free readings in the pool.
+    pub fn available(&self) -> usize {
+        self.shared.lock().len()
+    }
+
+    /// Gets the number of readings allocated by the pool since its creation.
+    pub fn allocated(&self) -> u64 {
+        self.shared.allocated.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for ScanPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ScanPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScanPool")
+            .field("available", &self.available())
+            .field("allocated", &self.allocated())
+            .finish()
+    }
+}
+
+/// A reading borrowed from a `ScanPool`, returned to it when dropped.
+pub struct PooledScan {
+    // Only `None` once taken by `detach` or `drop`.
+    scan: Option<Box<LaserReading>>,
+    shared: Arc<Shared>,
+}
+
+impl PooledScan {
+    /// Takes the reading out of the pool, it is not recycled anymore.
+    pub fn detach(mut self) -> Box<LaserReading> {
+        // Always set until dropped.
+        self.scan.take().unwrap()
+    }
+}
+
+impl Deref for PooledScan {
+    type Target = LaserReading;
+
+    fn deref(&self) -> &LaserReading {
+        // Always set until dropped.
+        self.scan.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledScan {
+    fn deref_mut(&mut self) -> &mut LaserReading {
+        // Always set until dropped.
+        self.scan.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledScan {
+    fn drop(&mut self) {
+        if let Some(scan) = self.scan.take() {
+            let mut free = self.shared.lock();
+            if free.len() < self.shared.retained {
+                free.push(scan);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for PooledScan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recycles_the_returned_readings() {
+        let pool = ScanPool::with_retained(1);
+        let mut reading = LaserReading::new();
+        reading.rpms = 300;
+
+        let (a, b) = (pool.copy(&reading), pool.get());
+        assert_eq!((a.rpms, pool.allocated()), (300, 2));
+        drop(a);
+        // Full, `b` is deallocated.
+        drop(b);
+        assert_eq!(pool.available(), 1);
+
+        // The recycled reading keeps its scan.
+        assert_eq!(pool.get().rpms, 300);
+        assert_eq!(pool.allocated(), 2);
+    }
+
+    #[test]
+    fn forgets_the_detached_readings() {
+        let pool = ScanPool::new();
+        let scan = pool.get().detach();
+        drop(scan);
+        assert_eq!((pool.available(), pool.allocated()), (0, 1));
+    }
+}
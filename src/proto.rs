@@ -0,0 +1,619 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Protobuf messages of the foxglove schemas, enabled by the `protobuf` feature.
+//!
+//! The types match the `.proto` files in the `proto/foxglove` directory of the
+//! repository, so scans can be decoded by any foxglove aware tool, and
+//! `file_descriptor_set` describes them to the tools needing the schema. The
+//! unit tests check the types and the descriptors against the `.proto` files.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::proto::LaserScan;
+//! use prost::Message;
+//! use std::time::SystemTime;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let bytes = LaserScan::from_reading(&reading, SystemTime::now(), "laser").encode_to_vec();
+//! let scan = LaserScan::decode(bytes.as_slice()).unwrap();
+//! assert_eq!(scan.to_reading().unwrap().ranges, reading.ranges);
+//! ```
+
+use crate::LaserReading;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+    FileDescriptorProto, FileDescriptorSet, Timestamp,
+};
+use std::f64::consts::TAU;
+use std::time::SystemTime;
+
+/// A vector in 3D space that represents a direction only
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct Vector3 {
+    /// x coordinate length
+    #[prost(double, tag = "1")]
+    pub x: f64,
+    /// y coordinate length
+    #[prost(double, tag = "2")]
+    pub y: f64,
+    /// z coordinate length
+    #[prost(double, tag = "3")]
+    pub z: f64,
+}
+
+/// A quaternion representing a rotation in 3D space
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct Quaternion {
+    /// x value
+    #[prost(double, tag = "1")]
+    pub x: f64,
+    /// y value
+    #[prost(double, tag = "2")]
+    pub y: f64,
+    /// z value
+    #[prost(double, tag = "3")]
+    pub z: f64,
+    /// w value
+    #[prost(double, tag = "4")]
+    pub w: f64,
+}
+
+/// A position and orientation for an object or reference frame in 3D space
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct Pose {
+    /// Point denoting position in 3D space
+    #[prost(message, optional, tag = "1")]
+    pub position: ::core::option::Option<Vector3>,
+    /// Quaternion denoting orientation in 3D space
+    #[prost(message, optional, tag = "2")]
+    pub orientation: ::core::option::Option<Quaternion>,
+}
+
+impl Pose {
+    /// Gets the pose at the origin of the frame, without any rotation.
+    pub fn identity() -> Self {
+        Self {
+            position: Some(Vector3::default()),
+            orientation: Some(Quaternion {
+                w: 1.0,
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// A single scan from a planar laser range-finder
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LaserScan {
+    /// Timestamp of scan
+    #[prost(message, optional, tag = "1")]
+    pub timestamp: ::core::option::Option<Timestamp>,
+    /// Frame of reference
+    #[prost(string, tag = "2")]
+    pub frame_id: ::prost::alloc::string::String,
+    /// Origin of scan relative to frame of reference
+    #[prost(message, optional, tag = "3")]
+    pub pose: ::core::option::Option<Pose>,
+    /// Bearing of first point, in radians
+    #[prost(double, tag = "4")]
+    pub start_angle: f64,
+    /// Bearing of last point, in radians
+    #[prost(double, tag = "5")]
+    pub end_angle: f64,
+    /// Distance of detections from origin; assumed to be at equally-spaced angles
+    /// between `start_angle` and `end_angle`
+    #[prost(double, repeated, tag = "6")]
+    pub ranges: ::prost::alloc::vec::Vec<f64>,
+    /// Intensity of detections
+    #[prost(double, repeated, tag = "7")]
+    pub intensities: ::prost::alloc::vec::Vec<f64>,
+}
+
+/// Nested message and enum types in `PackedElementField`.
+pub mod packed_element_field {
+    /// Numeric type
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+    #[repr(i32)]
+    pub enum NumericType {
+        Unknown = 0,
+        Uint8 = 1,
+        Int8 = 2,
+        Uint16 = 3,
+        Int16 = 4,
+        Uint32 = 5,
+        Int32 = 6,
+        Float32 = 7,
+        Float64 = 8,
+    }
+}
+
+/// A field present within each element in a byte array of packed elements.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PackedElementField {
+    /// Name of the field
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    /// Byte offset from start of data buffer
+    #[prost(fixed32, tag = "2")]
+    pub offset: u32,
+    /// Type of data in the field. Integers are stored using little-endian byte order.
+    #[prost(enumeration = "packed_element_field::NumericType", tag = "3")]
+    pub r#type: i32,
+}
+
+/// A collection of N-dimensional points, which may contain additional fields
+/// with information like normals, intensity, etc.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PointCloud {
+    /// Timestamp of point cloud
+    #[prost(message, optional, tag = "1")]
+    pub timestamp: ::core::option::Option<Timestamp>,
+    /// Frame of reference
+    #[prost(string, tag = "2")]
+    pub frame_id: ::prost::alloc::string::String,
+    /// The origin of the point cloud relative to the frame of reference
+    #[prost(message, optional, tag = "3")]
+    pub pose: ::core::option::Option<Pose>,
+    /// Number of bytes between points in the `data`
+    #[prost(fixed32, tag = "4")]
+    pub point_stride: u32,
+    /// Fields in `data`
+    #[prost(message, repeated, tag = "5")]
+    pub fields: ::prost::alloc::vec::Vec<PackedElementField>,
+    /// Point data, interpreted using `fields`
+    #[prost(bytes = "vec", tag = "6")]
+    pub data: ::prost::alloc::vec::Vec<u8>,
+}
+
+impl LaserScan {
+    /// Name of the schema, as advertised to foxglove.
+    pub const SCHEMA_NAME: &'static str = "foxglove.LaserScan";
+
+    /// Converts a reading into a `LaserScan`, ranges in meters, counter-clockwise,
+    /// starting from the front of the lidar.
+    pub fn from_reading(reading: &LaserReading, timestamp: SystemTime, frame_id: &str) -> Self {
+        let n = reading.ranges.len();
+        Self {
+            timestamp: Some(timestamp.into()),
+            frame_id: frame_id.to_string(),
+            pose: Some(Pose::identity()),
+            start_angle: 0.0,
+            end_angle: TAU - TAU / n as f64,
+            ranges: reading
+                .ranges
+                .iter()
+                .map(|r| f64::from(*r) / 1000.0)
+                .collect(),
+            intensities: reading.intensities.iter().map(|i| f64::from(*i)).collect(),
+        }
+    }
+
+    /// Converts the scan back into a reading, the rpms are not part of the
+    /// message and are set to 0.
+    ///
+    /// Returns `None` if the scan does not have one range and one intensity
+    /// per degree.
+    pub fn to_reading(&self) -> Option<LaserReading> {
+        let mut reading = LaserReading::new();
+        if self.ranges.len() != reading.ranges.len()
+            || self.intensities.len() != reading.intensities.len()
+        {
+            return None;
+        }
+        for (dst, src) in reading.ranges.iter_mut().zip(&self.ranges) {
+            *dst = (src * 1000.0).round() as u16;
+        }
+        for (dst, src) in reading.intensities.iter_mut().zip(&self.intensities) {
+            *dst = src.round() as u16;
+        }
+        Some(reading)
+    }
+}
+
+impl PointCloud {
+    /// Name of the schema, as advertised to foxglove.
+    pub const SCHEMA_NAME: &'static str = "foxglove.PointCloud";
+
+    /// Converts the valid beams of a reading into a `PointCloud`, in the lidar frame.
+    ///
+    /// Every point has `x`, `y`, `z` and `intensity` fields, as little-endian `f32`.
+    pub fn from_reading(reading: &LaserReading, timestamp: SystemTime, frame_id: &str) -> Self {
+        use packed_element_field::NumericType;
+
+        let fields = ["x", "y", "z", "intensity"]
+            .iter()
+            .zip((0..).step_by(4))
+            .map(|(name, offset)| PackedElementField {
+                name: name.to_string(),
+                offset,
+                r#type: NumericType::Float32 as i32,
+            })
+            .collect::<Vec<_>>();
+
+        let mut data = Vec::new();
+        for i in (0..reading.ranges.len()).filter(|&i| reading.is_valid(i)) {
+            let (x, y) = reading.point(i);
+            for v in [x, y, 0.0, f32::from(reading.intensities[i])] {
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+
+        Self {
+            timestamp: Some(timestamp.into()),
+            frame_id: frame_id.to_string(),
+            pose: Some(Pose::identity()),
+            point_stride: 4 * fields.len() as u32,
+            fields,
+            data,
+        }
+    }
+}
+
+/// Gets the descriptors of the `.proto` files of the messages of this
+/// module and of their imports, the schema foxglove expects for protobuf
+/// channels.
+pub fn file_descriptor_set() -> FileDescriptorSet {
+    use Type::{Bytes, Double, Enum, Fixed32, Int32, Int64, Message, String};
+
+    let numeric_type = EnumDescriptorProto {
+        name: Some("NumericType".into()),
+        value: [
+            "UNKNOWN", "UINT8", "INT8", "UINT16", "INT16", "UINT32", "INT32", "FLOAT32", "FLOAT64",
+        ]
+        .iter()
+        .zip(0..)
+        .map(|(name, number)| EnumValueDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            options: None,
+        })
+        .collect(),
+        ..Default::default()
+    };
+    let packed_element_field = DescriptorProto {
+        enum_type: vec![numeric_type],
+        ..message(
+            "PackedElementField",
+            vec![
+                field("name", 1, String, ""),
+                field("offset", 2, Fixed32, ""),
+                field("type", 3, Enum, ".foxglove.PackedElementField.NumericType"),
+            ],
+        )
+    };
+    let xyz = |extra: Option<&str>| {
+        ["x", "y", "z"]
+            .into_iter()
+            .chain(extra)
+            .zip(1..)
+            .map(|(name, number)| field(name, number, Double, ""))
+            .collect()
+    };
+
+    FileDescriptorSet {
+        file: vec![
+            FileDescriptorProto {
+                name: Some("google/protobuf/timestamp.proto".into()),
+                package: Some("google.protobuf".into()),
+                message_type: vec![message(
+                    "Timestamp",
+                    vec![field("seconds", 1, Int64, ""), field("nanos", 2, Int32, "")],
+                )],
+                syntax: Some("proto3".into()),
+                ..Default::default()
+            },
+            file("Vector3", &[], message("Vector3", xyz(None))),
+            file("Quaternion", &[], message("Quaternion", xyz(Some("w")))),
+            file(
+                "Pose",
+                &["foxglove/Quaternion.proto", "foxglove/Vector3.proto"],
+                message(
+                    "Pose",
+                    vec![
+                        field("position", 1, Message, ".foxglove.Vector3"),
+                        field("orientation", 2, Message, ".foxglove.Quaternion"),
+                    ],
+                ),
+            ),
+            file(
+                "LaserScan",
+                &["foxglove/Pose.proto", "google/protobuf/timestamp.proto"],
+                message(
+                    "LaserScan",
+                    vec![
+                        field("timestamp", 1, Message, ".google.protobuf.Timestamp"),
+                        field("frame_id", 2, String, ""),
+                        field("pose", 3, Message, ".foxglove.Pose"),
+                        field("start_angle", 4, Double, ""),
+                        field("end_angle", 5, Double, ""),
+                        repeated(field("ranges", 6, Double, "")),
+                        repeated(field("intensities", 7, Double, "")),
+                    ],
+                ),
+            ),
+            file("PackedElementField", &[], packed_element_field),
+            file(
+                "PointCloud",
+                &[
+                    "foxglove/PackedElementField.proto",
+                    "foxglove/Pose.proto",
+                    "google/protobuf/timestamp.proto",
+                ],
+                message(
+                    "PointCloud",
+                    vec![
+                        field("timestamp", 1, Message, ".google.protobuf.Timestamp"),
+                        field("frame_id", 2, String, ""),
+                        field("pose", 3, Message, ".foxglove.Pose"),
+                        field("point_stride", 4, Fixed32, ""),
+                        repeated(field("fields", 5, Message, ".foxglove.PackedElementField")),
+                        field("data", 6, Bytes, ""),
+                    ],
+                ),
+            ),
+        ],
+    }
+}
+
+/// Gets the descriptor of `foxglove/<name>.proto`, holding `message`.
+fn file(name: &str, dependencies: &[&str], message: DescriptorProto) -> FileDescriptorProto {
+    FileDescriptorProto {
+        name: Some(format!("foxglove/{name}.proto")),
+        package: Some("foxglove".into()),
+        dependency: dependencies.iter().map(|d| d.to_string()).collect(),
+        message_type: vec![message],
+        syntax: Some("proto3".into()),
+        ..Default::default()
+    }
+}
+
+fn message(name: &str, field: Vec<FieldDescriptorProto>) -> DescriptorProto {
+    DescriptorProto {
+        name: Some(name.into()),
+        field,
+        ..Default::default()
+    }
+}
+
+/// Gets the descriptor of a singular field, `type_name` is the full name of
+/// its message or enum type, if any.
+fn field(name: &str, number: i32, r#type: Type, type_name: &str) -> FieldDescriptorProto {
+    FieldDescriptorProto {
+        name: Some(name.into()),
+        number: Some(number),
+        label: Some(Label::Optional as i32),
+        r#type: Some(r#type as i32),
+        type_name: (!type_name.is_empty()).then(|| type_name.into()),
+        ..Default::default()
+    }
+}
+
+fn repeated(field: FieldDescriptorProto) -> FieldDescriptorProto {
+    FieldDescriptorProto {
+        label: Some(Label::Repeated as i32),
+        ..field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixtures;
+    use prost::Message as _;
+    use std::collections::BTreeSet;
+
+    /// Parses the subset of the protobuf language used by the foxglove
+    /// schemas: no nested messages, no options.
+    fn parse(name: &str, text: &str) -> FileDescriptorProto {
+        let mut file = FileDescriptorProto {
+            name: Some(name.into()),
+            ..Default::default()
+        };
+        let mut messages: Vec<DescriptorProto> = Vec::new();
+        let mut enumeration: Option<EnumDescriptorProto> = None;
+        for line in text.lines() {
+            let line = line.split("//").next().unwrap_or_default();
+            let words: Vec<&str> = line
+                .trim()
+                .trim_end_matches([';', '{'])
+                .split_whitespace()
+                .map(|w| w.trim_matches('"'))
+                .collect();
+            match (words.as_slice(), enumeration.as_mut()) {
+                ([], _) => {}
+                (["syntax", "=", syntax], _) => file.syntax = Some(syntax.to_string()),
+                (["import", path], _) => file.dependency.push(path.to_string()),
+                (["package", package], _) => file.package = Some(package.to_string()),
+                (["message", name], _) => messages.push(message(name, Vec::new())),
+                (["enum", name], _) => {
+                    enumeration = Some(EnumDescriptorProto {
+                        name: Some(name.to_string()),
+                        ..Default::default()
+                    })
+                }
+                (["}"], Some(_)) => {
+                    let parent = messages.last_mut().unwrap();
+                    parent.enum_type.extend(enumeration.take());
+                }
+                (["}"], None) => file.message_type.extend(messages.pop()),
+                ([name, "=", number], Some(enumeration)) => {
+                    enumeration.value.push(EnumValueDescriptorProto {
+                        name: Some(name.to_string()),
+                        number: Some(number.parse().unwrap()),
+                        options: None,
+                    })
+                }
+                ([.., r#type, name, "=", number], None) => {
+                    let parent = messages.last_mut().unwrap();
+                    let (r#type, type_name) = match *r#type {
+                        "double" => (Type::Double, String::new()),
+                        "fixed32" => (Type::Fixed32, String::new()),
+                        "int32" => (Type::Int32, String::new()),
+                        "int64" => (Type::Int64, String::new()),
+                        "string" => (Type::String, String::new()),
+                        "bytes" => (Type::Bytes, String::new()),
+                        nested if parent.enum_type.iter().any(|e| e.name() == nested) => (
+                            Type::Enum,
+                            format!(".{}.{}.{nested}", file.package(), parent.name()),
+                        ),
+                        other => (Type::Message, format!(".{other}")),
+                    };
+                    let field = field(name, number.parse().unwrap(), r#type, &type_name);
+                    parent.field.push(match words[0] {
+                        "repeated" => repeated(field),
+                        _ => field,
+                    });
+                }
+                (words, _) => panic!("{name}: unexpected {words:?}"),
+            }
+        }
+        file
+    }
+
+    fn varint(buf: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = buf.split_first().unwrap();
+            *buf = rest;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte < 0x80 {
+                break;
+            }
+        }
+        value
+    }
+
+    /// Gets the numbers and the wire types of the fields of an encoded message.
+    fn wire_fields(mut buf: &[u8]) -> BTreeSet<(i32, u64)> {
+        let mut fields = BTreeSet::new();
+        while !buf.is_empty() {
+            let key = varint(&mut buf);
+            let len = match key & 7 {
+                0 => {
+                    varint(&mut buf);
+                    0
+                }
+                1 => 8,
+                2 => varint(&mut buf) as usize,
+                5 => 4,
+                wire => panic!("unexpected wire type {wire}"),
+            };
+            buf = &buf[len..];
+            fields.insert(((key >> 3) as i32, key & 7));
+        }
+        fields
+    }
+
+    fn described(name: &str) -> DescriptorProto {
+        file_descriptor_set()
+            .file
+            .into_iter()
+            .flat_map(|f| f.message_type)
+            .find(|m| m.name() == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn describes_the_proto_files() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/proto");
+        let set = file_descriptor_set();
+        let mut checked = 0;
+        for entry in std::fs::read_dir(format!("{dir}/foxglove")).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.strip_prefix(dir).unwrap().to_str().unwrap();
+            let parsed = parse(name, &std::fs::read_to_string(&path).unwrap());
+            let described = set.file.iter().find(|f| f.name() == name);
+            assert_eq!(described, Some(&parsed), "{name}");
+            checked += 1;
+        }
+        // Every file but the well-known timestamp one.
+        assert_eq!(checked, set.file.len() - 1);
+    }
+
+    #[test]
+    fn encodes_the_described_fields() {
+        let room = &fixtures()[0].expected;
+        let now = SystemTime::now();
+        let mut scan = LaserScan::from_reading(room, now, "laser");
+        scan.start_angle = 0.5;
+        let vector = Vector3 {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+        let quaternion = Quaternion {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+            w: 4.0,
+        };
+        let messages = [
+            ("Vector3", vector.encode_to_vec()),
+            ("Quaternion", quaternion.encode_to_vec()),
+            (
+                "Pose",
+                Pose {
+                    position: Some(vector),
+                    orientation: Some(quaternion),
+                }
+                .encode_to_vec(),
+            ),
+            ("LaserScan", scan.encode_to_vec()),
+            (
+                "PackedElementField",
+                PackedElementField {
+                    name: "x".into(),
+                    offset: 4,
+                    r#type: packed_element_field::NumericType::Float32 as i32,
+                }
+                .encode_to_vec(),
+            ),
+            (
+                "PointCloud",
+                PointCloud::from_reading(room, now, "laser").encode_to_vec(),
+            ),
+            ("Timestamp", Timestamp::from(now).encode_to_vec()),
+        ];
+
+        for (name, encoded) in messages {
+            let expected = described(name)
+                .field
+                .iter()
+                .map(|f| {
+                    let wire = match (f.r#type(), f.label()) {
+                        (Type::Double, Label::Repeated) => 2,
+                        (Type::Double, _) => 1,
+                        (Type::Fixed32, _) => 5,
+                        (Type::Int32 | Type::Int64 | Type::Enum, _) => 0,
+                        _ => 2,
+                    };
+                    (f.number(), wire)
+                })
+                .collect();
+            assert_eq!(wire_fields(&encoded), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn round_trips_the_scans() {
+        for fixture in fixtures() {
+            let scan = LaserScan::from_reading(&fixture.expected, SystemTime::now(), "laser");
+            let decoded = LaserScan::decode(scan.encode_to_vec().as_slice()).unwrap();
+            let reading = decoded.to_reading().unwrap();
+            assert_eq!(reading.ranges, fixture.expected.ranges);
+            assert_eq!(reading.intensities, fixture.expected.intensities);
+        }
+    }
+}
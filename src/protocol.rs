@@ -0,0 +1,412 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Wire format of the LDS-01, shared by all the backends.
+//!
+//! A frame is a full revolution made of 60 packets of 42 bytes each,
+//! every packet starts with `0xFA, 0xA0 + index` followed by the RPMs
+//! and six readings of six bytes. The last two bytes of every reading,
+//! and the last two of the packet, are not interpreted by the driver and
+//! are exposed as the reserved bytes, for diagnostics.
+
+use crate::calibration::Calibration;
+use crate::{DecodeError, LaserReading};
+use std::time::Duration;
+
+/// Size in bytes of a packet.
+pub const PACKET_SIZE: usize = 42;
+/// Number of packets in a frame.
+pub const PACKETS_PER_FRAME: usize = 60;
+/// Size in bytes of a frame, one full revolution.
+pub const FRAME_SIZE: usize = PACKET_SIZE * PACKETS_PER_FRAME;
+/// First byte of every packet.
+pub const SYNC_BYTE: u8 = 0xFA;
+/// Index byte of the first packet of a frame.
+pub const FIRST_INDEX: u8 = 0xA0;
+
+/// Byte sent to stop the lidar, 101 = ASCII 'e'
+pub const STOP_BYTE: u8 = 101;
+
+/// Byte sent to start the lidar, 98 = ASCII 'b'
+pub const START_BYTE: u8 = 98;
+
+/// Number of readings in a packet.
+pub const READINGS_PER_PACKET: usize = 6;
+
+/// Number of bytes of a packet not interpreted by the driver.
+pub const RESERVED_PER_PACKET: usize = 2 * READINGS_PER_PACKET + 2;
+
+/// Gets the time of a revolution at the given RPMs, in seconds, 0 while the
+/// motor is stopped.
+pub fn scan_time(rpms: u16) -> f32 {
+    if rpms > 0 {
+        60.0 / f32::from(rpms)
+    } else {
+        0.0
+    }
+}
+
+/// Revolutions without data after which a read times out.
+pub const TIMEOUT_REVOLUTIONS: u32 = 3;
+
+/// Shortest read timeout, against the RPMs of a corrupted packet.
+const MIN_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Gets the read timeout at the given RPMs, `TIMEOUT_REVOLUTIONS` revolutions,
+/// `None` while the motor is stopped.
+pub fn read_timeout(rpms: u16) -> Option<Duration> {
+    (rpms > 0).then(|| {
+        (Duration::from_secs(60) * TIMEOUT_REVOLUTIONS / u32::from(rpms)).max(MIN_READ_TIMEOUT)
+    })
+}
+
+/// Options applied while decoding a frame.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Reverses the angular ordering, for a lidar mounted upside down
+    pub mirrored: bool,
+    /// Per-degree correction of the ranges
+    pub calibration: Option<Calibration>,
+}
+
+/// A single packet, six consecutive degrees of a revolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Packet {
+    /// Index of the packet inside the frame, from 0 to 59
+    pub index: u8,
+    /// RPMs reported in the packet
+    pub rpms: u16,
+    /// Ranges, in mm, in the order they are received
+    pub ranges: [u16; READINGS_PER_PACKET],
+    /// Intensities, in the order they are received
+    pub intensities: [u16; READINGS_PER_PACKET],
+    /// Bytes not interpreted by the driver, see `reserved_bytes`
+    pub reserved: [u8; RESERVED_PER_PACKET],
+}
+
+impl Packet {
+    /// Gets the position in `LaserReading::ranges` of the `n`-th reading of this packet.
+    pub fn degree(&self, n: usize) -> usize {
+        359 - (READINGS_PER_PACKET * usize::from(self.index) + n)
+    }
+
+    /// Gets the position of the `n`-th reading of this packet on a lidar
+    /// mounted upside down, where the beams turn the other way.
+    pub fn mirrored_degree(&self, n: usize) -> usize {
+        (360 - self.degree(n)) % 360
+    }
+
+    /// Copies the readings of this packet into `scan`, updating its RPMs.
+    pub fn apply(&self, scan: &mut LaserReading) {
+        self.apply_with(scan, &DecodeOptions::default());
+    }
+
+    /// Like `apply`, placing the readings according to `options`.
+    pub fn apply_with(&self, scan: &mut LaserReading, options: &DecodeOptions) {
+        scan.rpms = self.rpms;
+        for n in 0..READINGS_PER_PACKET {
+            let degree = if options.mirrored {
+                self.mirrored_degree(n)
+            } else {
+                self.degree(n)
+            };
+            scan.ranges[degree] = match &options.calibration {
+                // The table is indexed by the degree reported by the lidar.
+                Some(c) => c.correct(self.degree(n), self.ranges[n]),
+                None => self.ranges[n],
+            };
+            scan.intensities[degree] = self.intensities[n];
+        }
+    }
+}
+
+/// Decodes a single packet, checking that its header is `0xFA, 0xA0 + index`
+/// with an index from 0 to 59.
+///
+/// # Errors
+/// A `DecodeError` is returned when the header is not valid, with `packet` set to
+/// the index the packet claims to have.
+pub fn decode_packet(packet: &[u8; PACKET_SIZE]) -> Result<Packet, DecodeError> {
+    let index = packet[1].wrapping_sub(FIRST_INDEX);
+    if packet[0] != SYNC_BYTE || usize::from(index) >= PACKETS_PER_FRAME {
+        return Err(DecodeError {
+            packet: usize::from(index),
+            header: [packet[0], packet[1]],
+        });
+    }
+
+    let mut decoded = Packet {
+        index,
+        rpms: rpms(packet),
+        reserved: reserved_bytes(packet),
+        ..Default::default()
+    };
+    for (n, reading) in readings(packet).enumerate() {
+        let (range, intensity) = unpack(reading);
+        decoded.ranges[n] = range;
+        decoded.intensities[n] = intensity;
+    }
+
+    Ok(decoded)
+}
+
+/// Gets the RPMs of a packet, sent in tenths of RPM.
+#[inline]
+fn rpms(packet: &[u8; PACKET_SIZE]) -> u16 {
+    u16::from_le_bytes([packet[2], packet[3]]) / 10
+}
+
+/// Gets the six bytes of every reading of a packet.
+#[inline]
+fn readings(packet: &[u8; PACKET_SIZE]) -> impl Iterator<Item = &[u8]> {
+    packet[4..4 + 6 * READINGS_PER_PACKET].chunks_exact(6)
+}
+
+/// Gets the range, in mm, and the intensity of a reading.
+#[inline]
+fn unpack(reading: &[u8]) -> (u16, u16) {
+    // The first two bytes are the intensity, the next two the range.
+    (
+        u16::from_le_bytes([reading[2], reading[3]]),
+        u16::from_le_bytes([reading[0], reading[1]]),
+    )
+}
+
+/// Gets the bytes of a packet not interpreted by the driver, in the order
+/// they are received: the last two bytes of each reading, then the last
+/// two bytes of the packet.
+pub fn reserved_bytes(packet: &[u8; PACKET_SIZE]) -> [u8; RESERVED_PER_PACKET] {
+    let mut reserved = [0u8; RESERVED_PER_PACKET];
+    for (n, j) in (8..40).step_by(6).enumerate() {
+        reserved[2 * n..2 * n + 2].copy_from_slice(&packet[j..j + 2]);
+    }
+    reserved[RESERVED_PER_PACKET - 2..].copy_from_slice(&packet[40..]);
+    reserved
+}
+
+/// Gets the reserved bytes of every packet of a frame, see `reserved_bytes`.
+pub fn frame_reserved_bytes(
+    frame: &[u8; FRAME_SIZE],
+) -> [[u8; RESERVED_PER_PACKET]; PACKETS_PER_FRAME] {
+    let mut reserved = [[0u8; RESERVED_PER_PACKET]; PACKETS_PER_FRAME];
+    for (i, chunk) in frame.chunks_exact(PACKET_SIZE).enumerate() {
+        // chunks_exact always yields PACKET_SIZE long slices.
+        reserved[i] = reserved_bytes(chunk.try_into().unwrap());
+    }
+    reserved
+}
+
+/// Gets the RPMs reported by every packet of a frame, 0 for the packets
+/// with a bad header.
+pub fn frame_rpms(frame: &[u8; FRAME_SIZE]) -> [u16; PACKETS_PER_FRAME] {
+    let mut speeds = [0u16; PACKETS_PER_FRAME];
+    for (i, chunk) in frame.chunks_exact(PACKET_SIZE).enumerate() {
+        // chunks_exact always yields PACKET_SIZE long slices.
+        let chunk: &[u8; PACKET_SIZE] = chunk.try_into().unwrap();
+        if chunk[0] == SYNC_BYTE && chunk[1] == FIRST_INDEX + i as u8 {
+            speeds[i] = rpms(chunk);
+        }
+    }
+    speeds
+}
+
+/// Interpolates the RPMs of every packet, see `frame_rpms`, to every degree,
+/// indexed like `LaserReading::ranges`.
+///
+/// Every packet gives the speed at the middle of its six degrees, the
+/// packets reporting 0 take the speed of the nearest one that does not.
+/// All the degrees are 0 if no packet reports a speed.
+pub fn degree_rpms(packet_rpms: &[u16; PACKETS_PER_FRAME]) -> [f32; 360] {
+    let mut degrees = [0f32; 360];
+    let known: Vec<usize> = (0..PACKETS_PER_FRAME)
+        .filter(|&i| packet_rpms[i] > 0)
+        .collect();
+    if known.is_empty() {
+        return degrees;
+    }
+    let filled: Vec<f32> = (0..PACKETS_PER_FRAME)
+        .map(|i| {
+            // `known` is not empty.
+            let nearest = known.iter().min_by_key(|&&k| k.abs_diff(i)).unwrap();
+            f32::from(packet_rpms[*nearest])
+        })
+        .collect();
+
+    let half = (READINGS_PER_PACKET as f32 - 1.0) / 2.0;
+    for n in 0..360 {
+        // Position of the `n`-th reading of the frame between the middles
+        // of the packets.
+        let at = ((n as f32 - half) / READINGS_PER_PACKET as f32)
+            .clamp(0.0, (PACKETS_PER_FRAME - 1) as f32);
+        let (lower, t) = (at.floor() as usize, at.fract());
+        let upper = (lower + 1).min(PACKETS_PER_FRAME - 1);
+        degrees[359 - n] = filled[lower] * (1.0 - t) + filled[upper] * t;
+    }
+    degrees
+}
+
+/// Decodes a full frame into a `LaserReading`.
+///
+/// Packets with a bad header, or out of place, are skipped, leaving their
+/// six readings to 0, and reported to `on_error`.
+pub fn decode_frame<F>(frame: &[u8; FRAME_SIZE], on_error: F) -> LaserReading
+where
+    F: FnMut(DecodeError),
+{
+    decode_frame_with(frame, &DecodeOptions::default(), on_error)
+}
+
+/// Like `decode_frame`, applying `options` to the readings.
+pub fn decode_frame_with<F>(
+    frame: &[u8; FRAME_SIZE],
+    options: &DecodeOptions,
+    mut on_error: F,
+) -> LaserReading
+where
+    F: FnMut(DecodeError),
+{
+    let mut scan = LaserReading::new();
+
+    // Decodes the readings in place, without going through `Packet`.
+    for (i, chunk) in frame.chunks_exact(PACKET_SIZE).enumerate() {
+        // chunks_exact always yields PACKET_SIZE long slices.
+        let chunk: &[u8; PACKET_SIZE] = chunk.try_into().unwrap();
+        if chunk[0] != SYNC_BYTE || chunk[1] != FIRST_INDEX + i as u8 {
+            #[cfg(feature = "defmt")]
+            defmt::warn!("packet {} skipped, header {=[u8]:#x}", i, chunk[..2]);
+            on_error(DecodeError {
+                packet: i,
+                header: [chunk[0], chunk[1]],
+            });
+            continue;
+        }
+
+        scan.rpms = rpms(chunk);
+        #[cfg(feature = "defmt")]
+        defmt::trace!("packet {} at {} rpm", i, scan.rpms);
+        // Degree of the first reading, the following ones go backwards.
+        let first = 359 - READINGS_PER_PACKET * i;
+        for (n, reading) in readings(chunk).enumerate() {
+            let (mut range, intensity) = unpack(reading);
+            let degree = first - n;
+            if let Some(c) = &options.calibration {
+                range = c.correct(degree, range);
+            }
+            let slot = if options.mirrored {
+                (360 - degree) % 360
+            } else {
+                degree
+            };
+            scan.ranges[slot] = range;
+            scan.intensities[slot] = intensity;
+        }
+    }
+
+    scan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_scan_eq, encode_frame, fixtures};
+
+    #[test]
+    fn decodes_the_fixtures() {
+        for fixture in fixtures() {
+            assert_scan_eq(&fixture.decode(), &fixture.expected);
+        }
+    }
+
+    #[test]
+    fn reports_the_corrupted_packet() {
+        let fixture = &fixtures()[3];
+        let frame: &[u8; FRAME_SIZE] = fixture.frame.as_slice().try_into().unwrap();
+        let mut errors = Vec::new();
+        decode_frame(frame, |e| errors.push(e));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].packet, 17);
+        assert_eq!(errors[0].header, [SYNC_BYTE, 0x00]);
+    }
+
+    #[test]
+    fn decodes_a_packet_of_the_fixtures() {
+        let room = &fixtures()[0];
+        let packet: &[u8; PACKET_SIZE] = room.frame[..PACKET_SIZE].try_into().unwrap();
+        let packet = decode_packet(packet).unwrap();
+        assert_eq!(packet.index, 0);
+        assert_eq!(packet.rpms, room.expected.rpms);
+        for n in 0..READINGS_PER_PACKET {
+            assert_eq!(packet.ranges[n], room.expected.ranges[packet.degree(n)]);
+            assert_eq!(
+                packet.intensities[n],
+                room.expected.intensities[packet.degree(n)]
+            );
+        }
+    }
+
+    #[test]
+    fn mirrors_the_fixtures() {
+        let room = &fixtures()[0];
+        let options = DecodeOptions {
+            mirrored: true,
+            ..Default::default()
+        };
+        let scan = decode_frame_with(&encode_frame(&room.expected), &options, |_| {});
+        for degree in 0..360 {
+            assert_eq!(
+                scan.ranges[(360 - degree) % 360],
+                room.expected.ranges[degree]
+            );
+        }
+    }
+
+    #[test]
+    fn reads_the_rpms_of_every_packet() {
+        let fixture = &fixtures()[2];
+        let frame: &[u8; FRAME_SIZE] = fixture.frame.as_slice().try_into().unwrap();
+        assert!(frame_rpms(frame)
+            .iter()
+            .all(|&rpms| rpms == fixture.expected.rpms));
+    }
+
+    #[test]
+    fn skips_the_rpms_of_the_corrupted_packet() {
+        let fixture = &fixtures()[3];
+        let frame: &[u8; FRAME_SIZE] = fixture.frame.as_slice().try_into().unwrap();
+        let speeds = frame_rpms(frame);
+        assert_eq!(speeds[17], 0);
+        assert_eq!(speeds.iter().filter(|&&rpms| rpms == 0).count(), 1);
+    }
+
+    #[test]
+    fn interpolates_the_rpms_of_every_degree() {
+        let mut speeds = [0u16; PACKETS_PER_FRAME];
+        for (i, rpms) in speeds.iter_mut().enumerate() {
+            *rpms = 300 + i as u16;
+        }
+        let degrees = degree_rpms(&speeds);
+        // The middle of packet 10 falls between its third and fourth readings.
+        assert!((degrees[359 - 62] - (310.0 - 0.5 / 6.0)).abs() < 1e-3);
+        assert!((degrees[359 - 63] - (310.0 + 0.5 / 6.0)).abs() < 1e-3);
+        // Before the middle of the first packet and after the last one.
+        assert_eq!(degrees[359], 300.0);
+        assert_eq!(degrees[0], 359.0);
+
+        // The missing packets take the speed of the nearest one.
+        speeds[10] = 0;
+        assert_eq!(degree_rpms(&speeds)[359 - 62], 309.0);
+        assert_eq!(degree_rpms(&[0; PACKETS_PER_FRAME]), [0.0; 360]);
+    }
+}
@@ -0,0 +1,382 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Lossy fixed-size encoding of scans, for links like LoRa or telemetry
+//! radios where even a compressed scan is too much.
+//!
+//! Every encoded scan starts with a flags byte, the rpms and the range
+//! scale as little-endian `u16`s, then the intensity scale when the scan
+//! carries intensities (`INTENSITIES` flag). One byte per beam follows with
+//! its range in units of the range scale, 0 being no return, then, if any,
+//! one nibble per beam with its intensity in units of the intensity scale,
+//! the low nibble first.
+//!
+//! The default scales fit `RANGE_MAX` in 16 mm steps and intensities up to
+//! 3840: a scan of 360 beams takes 547 bytes, 365 without intensities,
+//! instead of the 2.5 KB of a frame.
+//!
+//! A decoded range is at most `range_scale / 2` mm off. The exceptions are
+//! the returns closer than that, decoded as `range_scale` rather than as no
+//! return, and the ranges beyond `max_range`, decoded as `max_range`.
+//! Intensities are off by at most `intensity_scale / 2` up to 15 steps.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::quantized::{self, QuantizedEncoder};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let encoder = QuantizedEncoder::new().with_range_scale(20);
+//!
+//! let bytes = encoder.encode(&reading);
+//! let decoded = quantized::decode(&bytes).unwrap();
+//! for (decoded, range) in decoded.ranges.iter().zip(reading.ranges) {
+//!     assert!(decoded.abs_diff(range) <= 10);
+//! }
+//! ```
+
+use crate::LaserReading;
+use std::fmt;
+
+/// Flag of a scan carrying the intensities.
+pub const INTENSITIES: u8 = 1;
+/// Default range step, in mm.
+pub const DEFAULT_RANGE_SCALE: u16 = 16;
+/// Default intensity step.
+pub const DEFAULT_INTENSITY_SCALE: u16 = 256;
+
+const MAX_RANGE_STEPS: u16 = u8::MAX as u16;
+const MAX_INTENSITY_STEPS: u16 = 0x0F;
+
+/// Errors while decoding a quantized scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuantizedError {
+    /// The buffer ended before the scan was complete
+    Truncated,
+    /// Unknown flags
+    InvalidFlags(u8),
+    /// A scale is 0, or the buffer is longer than the scan
+    Corrupted,
+}
+
+impl fmt::Display for QuantizedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuantizedError::Truncated => write!(f, "Truncated scan"),
+            QuantizedError::InvalidFlags(flags) => write!(f, "Invalid scan flags: {flags:#04x}"),
+            QuantizedError::Corrupted => write!(f, "Corrupted scan"),
+        }
+    }
+}
+
+impl std::error::Error for QuantizedError {}
+
+/// Encoder of scans into a fixed number of bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantizedEncoder {
+    range_scale: u16,
+    // `None` drops the intensities.
+    intensity_scale: Option<u16>,
+}
+
+impl Default for QuantizedEncoder {
+    fn default() -> Self {
+        Self {
+            range_scale: DEFAULT_RANGE_SCALE,
+            intensity_scale: Some(DEFAULT_INTENSITY_SCALE),
+        }
+    }
+}
+
+impl QuantizedEncoder {
+    /// Creates an encoder with the default scales.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the range step, in mm, 0 is taken as 1.
+    ///
+    /// The error is at most half a step, ranges beyond `max_range` are
+    /// encoded as `max_range`, closer than they are.
+    pub fn with_range_scale(mut self, mm: u16) -> Self {
+        self.range_scale = mm.max(1);
+        self
+    }
+
+    /// Sets the intensity step, 0 is taken as 1. Intensities beyond
+    /// 15 steps are encoded as 15 steps.
+    pub fn with_intensity_scale(mut self, step: u16) -> Self {
+        self.intensity_scale = Some(step.max(1));
+        self
+    }
+
+    /// Drops the intensities, they decode as 0.
+    pub fn without_intensities(mut self) -> Self {
+        self.intensity_scale = None;
+        self
+    }
+
+    /// Gets the farthest range that can be encoded, in mm.
+    pub fn max_range(&self) -> u32 {
+        u32::from(self.range_scale) * u32::from(MAX_RANGE_STEPS)
+    }
+
+    /// Gets the size, in bytes, of an encoded scan of `beams` beams.
+    pub fn encoded_len(&self, beams: usize) -> usize {
+        encoded_len(beams, self.intensity_scale.is_some())
+    }
+
+    /// Encodes a scan into a new buffer.
+    pub fn encode<const N: usize>(&self, reading: &LaserReading<N>) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.encoded_len(N));
+        self.encode_into(reading, &mut out);
+        out
+    }
+
+    /// Encodes a scan, appending it to `out`.
+    pub fn encode_into<const N: usize>(&self, reading: &LaserReading<N>, out: &mut Vec<u8>) {
+        let flags = if self.intensity_scale.is_some() {
+            INTENSITIES
+        } else {
+            0
+        };
+        out.push(flags);
+        out.extend_from_slice(&reading.rpms.to_le_bytes());
+        out.extend_from_slice(&self.range_scale.to_le_bytes());
+        if let Some(scale) = self.intensity_scale {
+            out.extend_from_slice(&scale.to_le_bytes());
+        }
+
+        out.extend(reading.ranges.iter().map(|&range| {
+            match quantize(range, self.range_scale, MAX_RANGE_STEPS) {
+                // A return too close for the scale must not read as none.
+                0 if range > 0 => 1,
+                steps => steps,
+            }
+        }));
+
+        if let Some(scale) = self.intensity_scale {
+            out.extend(reading.intensities.chunks(2).map(|pair| {
+                let low = quantize(pair[0], scale, MAX_INTENSITY_STEPS);
+                let high = pair
+                    .get(1)
+                    .map_or(0, |&i| quantize(i, scale, MAX_INTENSITY_STEPS));
+                low | high << 4
+            }));
+        }
+    }
+}
+
+/// Decodes a scan of `BEAMS` beams.
+///
+/// # Errors
+/// An error variant is returned in case of:
+/// - buffer too short, or too long
+/// - unknown flags
+/// - a scale is 0
+pub fn decode(buf: &[u8]) -> Result<LaserReading, QuantizedError> {
+    decode_beams(buf)
+}
+
+/// Decodes a scan of `N` beams, see `decode`.
+///
+/// # Errors
+/// An error variant is returned in case of:
+/// - buffer too short, or too long
+/// - unknown flags
+/// - a scale is 0
+pub fn decode_beams<const N: usize>(buf: &[u8]) -> Result<LaserReading<N>, QuantizedError> {
+    let (&flags, rest) = buf.split_first().ok_or(QuantizedError::Truncated)?;
+    if flags & !INTENSITIES != 0 {
+        return Err(QuantizedError::InvalidFlags(flags));
+    }
+    let mut words = rest
+        .chunks_exact(2)
+        .map(|w| u16::from_le_bytes([w[0], w[1]]));
+    let mut word = || words.next().ok_or(QuantizedError::Truncated);
+
+    let mut reading = LaserReading::<N>::empty();
+    reading.rpms = word()?;
+    let range_scale = word()?;
+    let intensity_scale = if flags & INTENSITIES != 0 {
+        Some(word()?)
+    } else {
+        None
+    };
+    if range_scale == 0 || intensity_scale == Some(0) {
+        return Err(QuantizedError::Corrupted);
+    }
+
+    let header = header_len(intensity_scale.is_some());
+    let len = encoded_len(N, intensity_scale.is_some());
+    if buf.len() < len {
+        return Err(QuantizedError::Truncated);
+    }
+    if buf.len() > len {
+        return Err(QuantizedError::Corrupted);
+    }
+
+    let (ranges, intensities) = buf[header..].split_at(N);
+    for (range, &steps) in reading.ranges.iter_mut().zip(ranges) {
+        *range = u16::from(steps).saturating_mul(range_scale);
+    }
+    if let Some(scale) = intensity_scale {
+        for (i, intensity) in reading.intensities.iter_mut().enumerate() {
+            let steps = (intensities[i / 2] >> (4 * (i % 2))) & 0x0F;
+            *intensity = u16::from(steps).saturating_mul(scale);
+        }
+    }
+
+    Ok(reading)
+}
+
+fn header_len(intensities: bool) -> usize {
+    if intensities {
+        7
+    } else {
+        5
+    }
+}
+
+fn encoded_len(beams: usize, intensities: bool) -> usize {
+    let nibbles = if intensities { beams.div_ceil(2) } else { 0 };
+    header_len(intensities) + beams + nibbles
+}
+
+/// Rounds `value` to the nearest number of `scale` steps, up to `max`.
+fn quantize(value: u16, scale: u16, max: u16) -> u8 {
+    let steps = (u32::from(value) + u32::from(scale) / 2) / u32::from(scale);
+    steps.min(u32::from(max)) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_scan_close, fixtures};
+    use crate::BEAMS;
+
+    #[test]
+    fn rounds_to_the_nearest_step() {
+        assert_eq!(quantize(0, 16, MAX_RANGE_STEPS), 0);
+        assert_eq!(quantize(7, 16, MAX_RANGE_STEPS), 0);
+        assert_eq!(quantize(8, 16, MAX_RANGE_STEPS), 1);
+        assert_eq!(quantize(23, 16, MAX_RANGE_STEPS), 1);
+        assert_eq!(quantize(24, 16, MAX_RANGE_STEPS), 2);
+        assert_eq!(quantize(u16::MAX, 16, MAX_RANGE_STEPS), 255);
+        assert_eq!(quantize(2, 5, MAX_INTENSITY_STEPS), 0);
+        assert_eq!(quantize(3, 5, MAX_INTENSITY_STEPS), 1);
+        assert_eq!(quantize(1000, 5, MAX_INTENSITY_STEPS), 15);
+    }
+
+    #[test]
+    fn round_trips_the_fixtures() {
+        for scale in [1, 15, DEFAULT_RANGE_SCALE, 40] {
+            let encoder = QuantizedEncoder::new().with_range_scale(scale);
+            for fixture in fixtures() {
+                let bytes = encoder.encode(&fixture.expected);
+                assert_eq!(bytes.len(), encoder.encoded_len(BEAMS));
+                let decoded = decode(&bytes).unwrap();
+                assert_eq!(decoded.rpms, fixture.expected.rpms);
+
+                let mut expected = fixture.expected.clone();
+                for range in expected.ranges.iter_mut() {
+                    *range = (*range).min(encoder.max_range() as u16);
+                }
+                let max_intensity = MAX_INTENSITY_STEPS * DEFAULT_INTENSITY_SCALE;
+                for (decoded, intensity) in decoded.intensities.iter().zip(expected.intensities) {
+                    assert!(
+                        decoded.abs_diff(intensity.min(max_intensity))
+                            <= DEFAULT_INTENSITY_SCALE / 2
+                    );
+                }
+                expected.intensities = decoded.intensities;
+                assert_scan_close(&decoded, &expected, scale / 2);
+                for (decoded, range) in decoded.ranges.iter().zip(fixture.expected.ranges) {
+                    assert_eq!(*decoded == 0, range == 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_close_returns() {
+        let mut scan = fixtures()[1].expected.clone();
+        scan.ranges[0] = 3;
+        scan.ranges[1] = u16::MAX;
+        let decoded = decode(&QuantizedEncoder::new().encode(&scan)).unwrap();
+        assert_eq!(decoded.ranges[..3], [DEFAULT_RANGE_SCALE, 255 * 16, 0]);
+    }
+
+    #[test]
+    fn packs_the_intensities_in_nibbles() {
+        let mut scan = LaserReading::<5>::empty();
+        scan.intensities = [0, 256, 512, 3840, 60000];
+        let encoder = QuantizedEncoder::new();
+        let bytes = encoder.encode(&scan);
+        // Header, 5 ranges, then 3 bytes of nibbles, the last one half used.
+        assert_eq!(bytes.len(), 7 + 5 + 3);
+        assert_eq!(bytes[12..], [0x10, 0xF2, 0x0F]);
+        let decoded = decode_beams::<5>(&bytes).unwrap();
+        assert_eq!(decoded.intensities, [0, 256, 512, 3840, 3840]);
+
+        let bytes = encoder.with_intensity_scale(1000).encode(&scan);
+        let decoded = decode_beams::<5>(&bytes).unwrap();
+        assert_eq!(decoded.intensities, [0, 0, 1000, 4000, 15000]);
+    }
+
+    #[test]
+    fn drops_the_intensities() {
+        let room = &fixtures()[0].expected;
+        let encoder = QuantizedEncoder::new().without_intensities();
+        let bytes = encoder.encode(room);
+        assert_eq!(bytes[0], 0);
+        assert_eq!(bytes.len(), 5 + BEAMS);
+        let decoded = decode(&bytes).unwrap();
+        assert!(decoded.intensities.iter().all(|&i| i == 0));
+    }
+
+    #[test]
+    fn checks_the_length() {
+        let room = &fixtures()[0].expected;
+        for encoder in [
+            QuantizedEncoder::new(),
+            QuantizedEncoder::new().without_intensities(),
+        ] {
+            let mut bytes = encoder.encode(room);
+            assert_eq!(
+                decode(&bytes[..bytes.len() - 1]).unwrap_err(),
+                QuantizedError::Truncated
+            );
+            assert_eq!(decode(&bytes[..3]).unwrap_err(), QuantizedError::Truncated);
+            assert_eq!(
+                decode_beams::<361>(&bytes).unwrap_err(),
+                QuantizedError::Truncated
+            );
+            bytes.push(0);
+            assert_eq!(decode(&bytes).unwrap_err(), QuantizedError::Corrupted);
+        }
+        assert_eq!(decode(&[]).unwrap_err(), QuantizedError::Truncated);
+    }
+
+    #[test]
+    fn checks_the_header() {
+        let mut bytes = QuantizedEncoder::new().encode(&fixtures()[0].expected);
+        bytes[0] = 0x82;
+        assert_eq!(
+            decode(&bytes).unwrap_err(),
+            QuantizedError::InvalidFlags(0x82)
+        );
+        bytes[0] = INTENSITIES;
+        bytes[3..5].copy_from_slice(&[0, 0]);
+        assert_eq!(decode(&bytes).unwrap_err(), QuantizedError::Corrupted);
+    }
+}
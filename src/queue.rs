@@ -0,0 +1,428 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Bounded queues used by the publishers of this crate, with a
+//! configurable behavior when a consumer does not keep up.
+//!
+//! A queue never grows past its capacity: when it is full the publisher
+//! either discards a scan, the oldest or the new one, or waits for the
+//! consumer. Only `Block` lets a slow consumer stall the acquisition.
+//! Both ends work from threads and from async tasks, on any runtime.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::queue::{self, Overflow};
+//!
+//! let (tx, rx) = queue::channel(2, Overflow::DropOldest);
+//! for i in 0..3 {
+//!     tx.send(i).unwrap();
+//! }
+//! assert_eq!(rx.try_recv(), Some(1));
+//! assert_eq!(rx.try_recv(), Some(2));
+//! assert_eq!(rx.dropped(), 1);
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::poll_fn;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Poll, Waker};
+
+/// Default number of items buffered for each consumer.
+pub const DEFAULT_CAPACITY: usize = 16;
+
+/// What a publisher does when the queue of a consumer is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "ser_de", derive(serde::Serialize, serde::Deserialize))]
+pub enum Overflow {
+    /// Discards the oldest queued item, the consumer always gets the latest ones
+    #[default]
+    DropOldest,
+    /// Discards the new item, the consumer gets the ones already queued
+    DropNewest,
+    /// Waits for the consumer to make room, stalling the publisher
+    Block,
+}
+
+/// Error of a send, the receiver is gone; holds the item that was not sent.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Closed<T>(pub T);
+
+impl<T> fmt::Debug for Closed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Closed(..)")
+    }
+}
+
+impl<T> fmt::Display for Closed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("receiver dropped")
+    }
+}
+
+impl<T> std::error::Error for Closed<T> {}
+
+struct State<T> {
+    items: VecDeque<T>,
+    dropped: u64,
+    senders: usize,
+    receiver: bool,
+    recv_waker: Option<Waker>,
+    send_wakers: Vec<Waker>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    overflow: Overflow,
+    readable: Condvar,
+    writable: Condvar,
+}
+
+enum Push<T> {
+    Done,
+    Full(T),
+    Closed(T),
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn push(&self, state: &mut State<T>, item: T) -> Push<T> {
+        if !state.receiver {
+            return Push::Closed(item);
+        }
+        if state.items.len() >= self.capacity {
+            match self.overflow {
+                Overflow::DropOldest => {
+                    state.items.pop_front();
+                    state.dropped += 1;
+                }
+                Overflow::DropNewest => {
+                    state.dropped += 1;
+                    return Push::Done;
+                }
+                Overflow::Block => return Push::Full(item),
+            }
+        }
+        state.items.push_back(item);
+        self.readable.notify_one();
+        if let Some(waker) = state.recv_waker.take() {
+            waker.wake();
+        }
+        Push::Done
+    }
+
+    fn pop(&self, state: &mut State<T>) -> Option<T> {
+        let item = state.items.pop_front()?;
+        self.writable.notify_all();
+        for waker in state.send_wakers.drain(..) {
+            waker.wake();
+        }
+        Some(item)
+    }
+}
+
+/// Publishing side of a queue, clonable.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Consuming side of a queue.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Creates a queue holding up to `capacity` items, at least one.
+pub fn channel<T>(capacity: usize, overflow: Overflow) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            dropped: 0,
+            senders: 1,
+            receiver: true,
+            recv_waker: None,
+            send_wakers: Vec::new(),
+        }),
+        capacity: capacity.max(1),
+        overflow,
+        readable: Condvar::new(),
+        writable: Condvar::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Queues an item, applying the overflow policy if the queue is full.
+    /// With `Block` the calling thread waits for room.
+    ///
+    /// # Errors
+    /// An error variant is returned if the receiver has been dropped.
+    pub fn send(&self, item: T) -> Result<(), Closed<T>> {
+        let mut state = self.shared.lock();
+        let mut item = item;
+        loop {
+            match self.shared.push(&mut state, item) {
+                Push::Done => return Ok(()),
+                Push::Closed(item) => return Err(Closed(item)),
+                Push::Full(back) => {
+                    item = back;
+                    state = self
+                        .shared
+                        .writable
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+            }
+        }
+    }
+
+    /// Like `send`, with `Block` the calling task waits for room instead
+    /// of the thread.
+    ///
+    /// # Errors
+    /// An error variant is returned if the receiver has been dropped.
+    pub async fn send_async(&self, item: T) -> Result<(), Closed<T>> {
+        let mut item = Some(item);
+        poll_fn(|cx| {
+            let mut state = self.shared.lock();
+            // Only polled again once completed if misused.
+            let Some(next) = item.take() else {
+                return Poll::Ready(Ok(()));
+            };
+            match self.shared.push(&mut state, next) {
+                Push::Done => Poll::Ready(Ok(())),
+                Push::Closed(next) => Poll::Ready(Err(Closed(next))),
+                Push::Full(next) => {
+                    item = Some(next);
+                    // Polled again while still full, keep a single waker.
+                    if !state.send_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                        state.send_wakers.push(cx.waker().clone());
+                    }
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Checks if the receiver has been dropped.
+    pub fn is_closed(&self) -> bool {
+        !self.shared.lock().receiver
+    }
+
+    /// Gets the overflow policy of the queue.
+    pub fn overflow(&self) -> Overflow {
+        self.shared.overflow
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.readable.notify_all();
+            if let Some(waker) = state.recv_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Gets the next item, waiting for one.
+    /// Returns `None` once every sender is dropped and the queue is empty.
+    pub fn recv(&self) -> Option<T> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = self.shared.pop(&mut state) {
+                return Some(item);
+            }
+            if state.senders == 0 {
+                return None;
+            }
+            state = self
+                .shared
+                .readable
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Like `recv`, waiting in the calling task instead of the thread.
+    pub async fn recv_async(&self) -> Option<T> {
+        poll_fn(|cx| {
+            let mut state = self.shared.lock();
+            if let Some(item) = self.shared.pop(&mut state) {
+                return Poll::Ready(Some(item));
+            }
+            if state.senders == 0 {
+                return Poll::Ready(None);
+            }
+            state.recv_waker = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Gets the next item if there is one, without waiting.
+    pub fn try_recv(&self) -> Option<T> {
+        let mut state = self.shared.lock();
+        self.shared.pop(&mut state)
+    }
+
+    /// Gets the number of queued items.
+    pub fn len(&self) -> usize {
+        self.shared.lock().items.len()
+    }
+
+    /// Checks if no item is queued.
+    pub fn is_empty(&self) -> bool {
+        self.shared.lock().items.is_empty()
+    }
+
+    /// Gets the number of items discarded by the overflow policy so far.
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.receiver = false;
+        state.items.clear();
+        self.shared.writable.notify_all();
+        for waker in state.send_wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Iterator for Receiver<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.recv()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixtures;
+    use crate::LaserReading;
+    use std::future::Future;
+    use std::thread;
+    use std::time::Duration;
+
+    struct Noop;
+
+    impl std::task::Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn scans() -> Vec<LaserReading> {
+        fixtures().into_iter().map(|f| f.decode()).collect()
+    }
+
+    fn rpms(rx: &Receiver<LaserReading>) -> Vec<u16> {
+        std::iter::from_fn(|| rx.try_recv())
+            .map(|s| s.rpms)
+            .collect()
+    }
+
+    #[test]
+    fn drops_the_oldest_scans() {
+        let scans = scans();
+        let (tx, rx) = channel(2, Overflow::DropOldest);
+        for scan in &scans {
+            tx.send(scan.clone()).unwrap();
+        }
+        assert_eq!(rx.len(), 2);
+        assert_eq!(rx.dropped(), 2);
+        assert_eq!(rpms(&rx), [scans[2].rpms, scans[3].rpms]);
+    }
+
+    #[test]
+    fn drops_the_newest_scans() {
+        let scans = scans();
+        let (tx, rx) = channel(2, Overflow::DropNewest);
+        for scan in &scans {
+            tx.send(scan.clone()).unwrap();
+        }
+        assert_eq!(rx.dropped(), 2);
+        assert_eq!(rpms(&rx), [scans[0].rpms, scans[1].rpms]);
+    }
+
+    #[test]
+    fn blocks_until_there_is_room() {
+        let scans = scans();
+        let (tx, rx) = channel(1, Overflow::Block);
+        let expected: Vec<u16> = scans.iter().map(|s| s.rpms).collect();
+        let publisher = thread::spawn(move || {
+            for scan in scans {
+                tx.send(scan).unwrap();
+            }
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(rx.len(), 1);
+        let received: Vec<u16> = rx.map(|s| s.rpms).collect();
+        publisher.join().unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn keeps_one_waker_per_pending_send() {
+        let scans = scans();
+        let (tx, rx) = channel(1, Overflow::Block);
+        tx.send(scans[0].clone()).unwrap();
+        let mut send = std::pin::pin!(tx.send_async(scans[1].clone()));
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = std::task::Context::from_waker(&waker);
+        for _ in 0..3 {
+            assert!(send.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(tx.shared.lock().send_wakers.len(), 1);
+        assert_eq!(rx.recv().map(|s| s.rpms), Some(scans[0].rpms));
+        assert!(send.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(rx.recv().map(|s| s.rpms), Some(scans[1].rpms));
+    }
+
+    #[test]
+    fn returns_the_scan_once_closed() {
+        let room = fixtures()[0].decode();
+        let (tx, rx) = channel(1, Overflow::Block);
+        drop(rx);
+        assert!(tx.is_closed());
+        let Closed(scan) = tx.send(room).unwrap_err();
+        assert_eq!(scan.rpms, fixtures()[0].expected.rpms);
+    }
+}
@@ -0,0 +1,102 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! How far the robot can go along a heading before hitting a return of the
+//! scan, for vetoing simple motions.
+//!
+//! Angles are in radians counter-clockwise from the front of the lidar,
+//! distances in meters, from the lidar.
+//!
+//! ```
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//! // Straight ahead, with a robot 40 cm wide.
+//! match reading.free_distance_along(0.0, 0.4) {
+//!     Some(free) if free < 0.5 => println!("blocked at {free} m"),
+//!     _ => println!("go"),
+//! }
+//! if let Some(hit) = reading.raycast(std::f32::consts::FRAC_PI_2, 2.0) {
+//!     println!("wall on the left at {hit} m");
+//! }
+//! ```
+
+use crate::angles::AngleFrame;
+use crate::LaserReading;
+
+impl<const N: usize> LaserReading<N> {
+    /// Gets the distance of the return hit by a ray from the lidar at
+    /// `angle`, the beam closest to it, `None` if the beam is not valid or
+    /// farther than `max_range`.
+    pub fn raycast(&self, angle: f32, max_range: f32) -> Option<f32> {
+        self.range_at(angle, &AngleFrame::LIDAR)
+            .filter(|range| *range <= max_range)
+    }
+
+    /// Gets the distance along `heading` to the closest return inside a
+    /// corridor `corridor_width` wide centered on it, the space swept by a
+    /// robot that wide going straight; `None` if the corridor is free as
+    /// far as the lidar sees.
+    ///
+    /// The returns behind the lidar are ignored.
+    pub fn free_distance_along(&self, heading: f32, corridor_width: f32) -> Option<f32> {
+        let (sin, cos) = heading.sin_cos();
+        let half = corridor_width.max(0.0) / 2.0;
+        (0..N)
+            .filter(|&i| self.is_valid(i))
+            .map(|i| self.point(i))
+            .filter_map(|(x, y)| {
+                let along = x * cos + y * sin;
+                let across = y * cos - x * sin;
+                (along >= 0.0 && across.abs() <= half).then_some(along)
+            })
+            .min_by(f32::total_cmp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures;
+    use crate::LaserReading;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    fn close(a: Option<f32>, b: f32) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 0.01)
+    }
+
+    #[test]
+    fn casts_rays_in_the_room() {
+        // Walls at 3 m ahead, 2 m on the left, 1 m behind and on the right.
+        let room = &fixtures()[0].expected;
+        // The beam straight ahead has no return in the fixture.
+        assert_eq!(room.raycast(0.0, 3.5), None);
+        let ahead = 1f32.to_radians();
+        assert!(close(room.raycast(ahead, 3.5), 3.0));
+        assert_eq!(room.raycast(ahead, 2.5), None);
+        assert!(close(room.raycast(-FRAC_PI_2, 3.5), 1.0));
+        assert_eq!(LaserReading::new().raycast(0.0, 3.5), None);
+    }
+
+    #[test]
+    fn measures_the_free_corridor() {
+        let room = &fixtures()[0].expected;
+        assert!(close(room.free_distance_along(0.0, 0.5), 3.0));
+        assert!(close(room.free_distance_along(FRAC_PI_2, 0.5), 2.0));
+        assert!(close(room.free_distance_along(PI, 0.5), 1.0));
+        // Wide enough to touch the wall on the right.
+        assert!(close(room.free_distance_along(0.0, 2.2), 0.0));
+
+        let mut reading = LaserReading::new();
+        reading.ranges[180] = 500;
+        assert_eq!(reading.free_distance_along(0.0, 1.0), None);
+    }
+}
@@ -0,0 +1,172 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Top-down rendering of scans to images, enabled by the `render` feature.
+//!
+//! ```no_run
+//! use hls_lfcd_lds_driver::render::{render_png, RenderOptions};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! render_png(&reading, &RenderOptions::default())
+//!     .unwrap()
+//!     .save("scan.png")
+//!     .unwrap();
+//! ```
+
+use crate::error::Error;
+use crate::options::OptionsError;
+use crate::{LaserReading, RANGE_MAX};
+use image::{ImageBuffer, Rgb, RgbImage};
+
+/// Options used when rendering a scan.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Width and height of the image, in pixels
+    pub size: u32,
+    /// Range, in meters, mapped to the border of the image
+    pub max_range: f32,
+    /// Radius of every point, in pixels
+    pub point_radius: u32,
+    /// Distance, in meters, between two range rings, `None` disables them
+    pub ring_step: Option<f32>,
+    /// Color of the background
+    pub background: Rgb<u8>,
+    /// Color of the points
+    pub points: Rgb<u8>,
+    /// Color of the range rings
+    pub rings: Rgb<u8>,
+    /// Color of the sensor, drawn at the center with a tick to its front
+    pub sensor: Rgb<u8>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            size: 512,
+            max_range: f32::from(RANGE_MAX) / 1000.0,
+            point_radius: 1,
+            ring_step: Some(1.0),
+            background: Rgb([255, 255, 255]),
+            points: Rgb([0, 0, 0]),
+            rings: Rgb([220, 220, 220]),
+            sensor: Rgb([220, 0, 0]),
+        }
+    }
+}
+
+/// Renders the scan seen from above, with the sensor at the center of the image
+/// and its front pointing up.
+///
+/// Invalid beams are not drawn, nor points beyond `max_range`.
+///
+/// # Errors
+/// An error variant is returned if `max_range` or `ring_step` is not positive.
+pub fn render_png(reading: &LaserReading, options: &RenderOptions) -> Result<RgbImage, Error> {
+    if options.max_range.is_nan() || options.max_range <= 0.0 {
+        return Err(OptionsError::NonPositiveRange.into());
+    }
+    if options.ring_step.is_some_and(|s| s.is_nan() || s <= 0.0) {
+        return Err(OptionsError::NonPositiveRingStep.into());
+    }
+    let mut img = ImageBuffer::from_pixel(options.size, options.size, options.background);
+    let center = options.size as f32 / 2.0;
+    let scale = center / options.max_range;
+
+    // Maps a point in meters to the image, x to the front (up), y to the left.
+    let to_pixel = |x: f32, y: f32| (center - y * scale, center - x * scale);
+
+    // Rings closer than a pixel would only fill the image, and take forever.
+    if let Some(step) = options.ring_step.filter(|s| s * scale >= 1.0) {
+        let rings = (options.max_range / step) as u32;
+        for ring in 1..=rings {
+            let radius = step * ring as f32;
+            // One dot every half degree is enough to look continuous.
+            for i in 0..720 {
+                let (sin, cos) = (i as f32 / 2.0).to_radians().sin_cos();
+                let (px, py) = to_pixel(radius * cos, radius * sin);
+                put(&mut img, px, py, 0, options.rings);
+            }
+        }
+    }
+
+    for i in 0..reading.ranges.len() {
+        if !reading.is_valid(i) {
+            continue;
+        }
+        let (x, y) = reading.point(i);
+        if x.hypot(y) > options.max_range {
+            continue;
+        }
+        let (px, py) = to_pixel(x, y);
+        put(&mut img, px, py, options.point_radius, options.points);
+    }
+
+    let sensor = (options.size / 100).max(2);
+    put(&mut img, center, center, sensor, options.sensor);
+    for d in 0..sensor * 3 {
+        put(&mut img, center, center - d as f32, 0, options.sensor);
+    }
+
+    Ok(img)
+}
+
+/// Draws a filled square of the given radius, ignoring the parts outside the image.
+fn put(img: &mut RgbImage, px: f32, py: f32, radius: u32, color: Rgb<u8>) {
+    let (px, py, r) = (px.round() as i64, py.round() as i64, i64::from(radius));
+    for y in (py - r)..=(py + r) {
+        for x in (px - r)..=(px + r) {
+            if x >= 0 && y >= 0 && x < i64::from(img.width()) && y < i64::from(img.height()) {
+                img.put_pixel(x as u32, y as u32, color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn rejects_non_positive_options() {
+        let reading = LaserReading::new();
+        for max_range in [0.0, -1.0, f32::NAN] {
+            let options = RenderOptions {
+                max_range,
+                ..RenderOptions::default()
+            };
+            let err = render_png(&reading, &options).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidOptions);
+        }
+        for step in [0.0, -0.5, f32::NAN] {
+            let options = RenderOptions {
+                ring_step: Some(step),
+                ..RenderOptions::default()
+            };
+            let err = render_png(&reading, &options).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidOptions);
+        }
+    }
+
+    #[test]
+    fn skips_rings_closer_than_a_pixel() {
+        let options = RenderOptions {
+            size: 64,
+            ring_step: Some(1e-9),
+            ..RenderOptions::default()
+        };
+        let img = render_png(&LaserReading::new(), &options).unwrap();
+        assert_eq!(img.get_pixel(0, 0), &options.background);
+    }
+}
@@ -0,0 +1,144 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Resampling of a scan to an arbitrary angular resolution.
+
+use crate::LaserReading;
+
+/// Difference, in mm, between two consecutive beams above which `resample`
+/// stops interpolating between them.
+pub const DEFAULT_MAX_JUMP: u16 = 200;
+
+/// A scan with an arbitrary number of beams, evenly spaced over 360 degrees
+/// starting from the front of the lidar.
+///
+/// As in `LaserReading` ranges are in mm and invalid beams are set to 0.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ResampledScan {
+    pub ranges: Vec<u16>,
+    pub intensities: Vec<u16>,
+    pub rpms: u16,
+}
+
+impl ResampledScan {
+    /// Gets the number of beams.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Checks if the scan has no beams.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Gets the angle, in radians, between two consecutive beams.
+    pub fn angle_increment(&self) -> f32 {
+        std::f32::consts::TAU / self.ranges.len() as f32
+    }
+
+    /// Gets the angle, in radians, of the given beam.
+    pub fn angle(&self, index: usize) -> f32 {
+        index as f32 * self.angle_increment()
+    }
+}
+
+impl LaserReading {
+    /// Resamples the scan to `points` beams, e.g. 720 or 180.
+    ///
+    /// Every beam is linearly interpolated between the two closest original beams.
+    /// Interpolation never crosses invalid beams: when one of the two is invalid the
+    /// closest one is used, so gaps are preserved instead of being filled with
+    /// made up ranges. The same goes for depth discontinuities larger than
+    /// `DEFAULT_MAX_JUMP`, see `resample_with_max_jump`.
+    pub fn resample(&self, points: usize) -> ResampledScan {
+        self.resample_with_max_jump(points, DEFAULT_MAX_JUMP)
+    }
+
+    /// Resamples the scan as `resample`, using the closest beam instead of
+    /// interpolating when two consecutive ranges differ by more than `max_jump` mm.
+    ///
+    /// Interpolating across the edge of an object would put points in the empty
+    /// space between the object and what lies behind it.
+    pub fn resample_with_max_jump(&self, points: usize, max_jump: u16) -> ResampledScan {
+        let n = self.ranges.len();
+        let mut out = ResampledScan {
+            ranges: Vec::with_capacity(points),
+            intensities: Vec::with_capacity(points),
+            rpms: self.rpms,
+        };
+
+        for k in 0..points {
+            let pos = k as f32 * n as f32 / points as f32;
+            let i0 = pos.floor() as usize % n;
+            let i1 = (i0 + 1) % n;
+            let t = pos - pos.floor();
+
+            let (range, intensity) = match (self.is_valid(i0), self.is_valid(i1)) {
+                (true, true) if self.ranges[i0].abs_diff(self.ranges[i1]) <= max_jump => (
+                    lerp(self.ranges[i0], self.ranges[i1], t),
+                    lerp(self.intensities[i0], self.intensities[i1], t),
+                ),
+                (true, _) if t <= 0.5 => (self.ranges[i0], self.intensities[i0]),
+                (_, true) if t >= 0.5 => (self.ranges[i1], self.intensities[i1]),
+                _ => (0, 0),
+            };
+
+            out.ranges.push(range);
+            out.intensities.push(intensity);
+        }
+
+        out
+    }
+}
+
+fn lerp(a: u16, b: u16, t: f32) -> u16 {
+    (f32::from(a) + (f32::from(b) - f32::from(a)) * t).round() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(ranges: &[(usize, u16)]) -> LaserReading {
+        let mut reading = LaserReading::new();
+        for &(i, range) in ranges {
+            reading.ranges[i] = range;
+            reading.intensities[i] = 100;
+        }
+        reading
+    }
+
+    #[test]
+    fn interpolates_close_ranges() {
+        let scan = reading(&[(0, 1000), (1, 1100)]).resample(720);
+        assert_eq!(&scan.ranges[..3], &[1000, 1050, 1100]);
+    }
+
+    #[test]
+    fn does_not_interpolate_across_jumps() {
+        let scan = reading(&[(0, 1000), (1, 3000)]).resample(1440);
+        assert_eq!(&scan.ranges[..5], &[1000, 1000, 1000, 3000, 3000]);
+        let scan = reading(&[(0, 1000), (1, 3000)]).resample_with_max_jump(720, 2000);
+        assert_eq!(&scan.ranges[..3], &[1000, 2000, 3000]);
+    }
+
+    #[test]
+    fn preserves_gaps() {
+        let scan = reading(&[(0, 1000), (2, 1000)]).resample(1440);
+        assert_eq!(
+            &scan.ranges[..9],
+            &[1000, 1000, 1000, 0, 0, 0, 1000, 1000, 1000]
+        );
+    }
+}
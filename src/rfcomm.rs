@@ -0,0 +1,303 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Connection to the lidar through a Bluetooth serial bridge, e.g. an
+//! HC-05 set to 230400 baud wired to the lidar, enabled by the `rfcomm`
+//! feature on Linux.
+//!
+//! The socket connects to the bridge straight through BlueZ, without the
+//! deprecated `rfcomm bind` and its `/dev/rfcomm*` port; the bridge only
+//! has to be paired. A port bound anyway, or the `/dev/cu.*` port of a
+//! paired bridge on macOS, is opened by the `sync` driver with
+//! `SerialTuning::bluetooth`.
+//!
+//! The radio delivers the data in bursts: the socket waits
+//! `DEFAULT_TIMEOUT` for data, and a frame cut by a timeout is completed by
+//! the next read.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "rfcomm", target_os = "linux"))]
+//! # fn main() -> Result<(), hls_lfcd_lds_driver::Error> {
+//! use hls_lfcd_lds_driver::rfcomm::{RfcommLaser, RfcommSocket, DEFAULT_CHANNEL};
+//!
+//! let socket = RfcommSocket::connect("98:D3:31:FB:12:34", DEFAULT_CHANNEL)?;
+//! let mut laser = RfcommLaser::new(socket);
+//! let scan = laser.read()?;
+//! println!("{} rpm", scan.rpms);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(all(feature = "rfcomm", target_os = "linux")))]
+//! # fn main() {}
+//! ```
+
+use crate::transport::{Transport, TransportLaser};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+/// RFCOMM channel of the serial port profile of the HC-05 and HC-06.
+pub const DEFAULT_CHANNEL: u8 = 1;
+
+/// Time a read waits for data, by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// `BTPROTO_RFCOMM` in `bluetooth/bluetooth.h`.
+const BTPROTO_RFCOMM: libc::c_int = 3;
+
+/// `struct sockaddr_rc` in `bluetooth/rfcomm.h`.
+#[repr(C)]
+struct SockaddrRc {
+    family: libc::sa_family_t,
+    address: [u8; 6],
+    channel: u8,
+}
+
+/// Parses a Bluetooth address, e.g. `98:D3:31:FB:12:34`, into the byte
+/// order of BlueZ, the last byte first.
+pub fn parse_address(address: &str) -> Option<[u8; 6]> {
+    let mut bytes = [0u8; 6];
+    let mut parts = address.split(':');
+    for byte in bytes.iter_mut().rev() {
+        let part = parts.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    parts.next().is_none().then_some(bytes)
+}
+
+/// A connection to a Bluetooth serial bridge.
+pub struct RfcommSocket {
+    socket: File,
+    timeout: Duration,
+}
+
+impl RfcommSocket {
+    /// Connects to the bridge at `address`, e.g. `98:D3:31:FB:12:34`, on
+    /// the RFCOMM channel `channel`, usually `DEFAULT_CHANNEL`.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - an invalid address
+    /// - no Bluetooth adapter, or the bridge is not paired or out of range
+    pub fn connect(address: &str, channel: u8) -> io::Result<Self> {
+        let address = parse_address(address).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid Bluetooth address {address}"),
+            )
+        })?;
+        // SAFETY: plain system call, the descriptor is owned right after.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+                BTPROTO_RFCOMM,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the socket was just created, owned by nobody else.
+        let socket = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        let remote = SockaddrRc {
+            family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            address,
+            channel,
+        };
+        // SAFETY: the kernel reads a `SockaddrRc` of the given size.
+        if unsafe {
+            libc::connect(
+                fd,
+                (&remote as *const SockaddrRc).cast(),
+                mem::size_of::<SockaddrRc>() as libc::socklen_t,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let mut socket = Self {
+            socket,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        socket.set_timeout(DEFAULT_TIMEOUT)?;
+        Ok(socket)
+    }
+
+    /// Gets the time a read or a write waits.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Sets the time a read or a write waits, a read receiving nothing for
+    /// this long fails with `io::ErrorKind::TimedOut`.
+    ///
+    /// # Errors
+    /// An error variant is returned if the socket rejects the timeout.
+    pub fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        // A zero timeval would wait forever.
+        let timeout = timeout.max(Duration::from_micros(1));
+        let timeval = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        for option in [libc::SO_RCVTIMEO, libc::SO_SNDTIMEO] {
+            // SAFETY: the kernel reads a `timeval` of the given size.
+            if unsafe {
+                libc::setsockopt(
+                    self.socket.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    option,
+                    (&timeval as *const libc::timeval).cast(),
+                    mem::size_of::<libc::timeval>() as libc::socklen_t,
+                )
+            } != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+/// Reports the expiry of a socket timeout like the serial ports do.
+fn timed_out(e: io::Error) -> io::Error {
+    match e.kind() {
+        io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+        _ => e,
+    }
+}
+
+impl Read for RfcommSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.read(buf).map_err(timed_out)
+    }
+}
+
+impl Write for RfcommSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.write(buf).map_err(timed_out)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for RfcommSocket {
+    fn clear_input(&mut self) -> io::Result<()> {
+        let mut scratch = [0u8; 1024];
+        loop {
+            // SAFETY: the kernel writes up to the length of `scratch`.
+            let n = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    scratch.as_mut_ptr().cast(),
+                    scratch.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if n > 0 {
+                continue;
+            }
+            if n == 0 {
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            return match e.kind() {
+                io::ErrorKind::WouldBlock => Ok(()),
+                io::ErrorKind::Interrupted => continue,
+                _ => Err(e),
+            };
+        }
+    }
+}
+
+impl AsRawFd for RfcommSocket {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+/// Driver of the LDS-01 over an `RfcommSocket`.
+pub type RfcommLaser = TransportLaser<RfcommSocket>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    /// A socket connected to a local stream instead of a bridge.
+    fn socket() -> (RfcommSocket, UnixStream) {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let mut socket = RfcommSocket {
+            socket: File::from(OwnedFd::from(ours)),
+            timeout: DEFAULT_TIMEOUT,
+        };
+        socket.set_timeout(Duration::from_millis(20)).unwrap();
+        (socket, theirs)
+    }
+
+    #[test]
+    fn parses_the_addresses() {
+        // `bdaddr_t` is little endian.
+        assert_eq!(
+            parse_address("98:D3:31:fb:12:34"),
+            Some([0x34, 0x12, 0xFB, 0x31, 0xD3, 0x98])
+        );
+        for address in [
+            "98:D3:31:FB:12",
+            "98:D3:31:FB:12:34:56",
+            "98:D3:31:FB:12:3",
+            "98:D3:31:FB:12:XY",
+        ] {
+            assert_eq!(parse_address(address), None, "{address}");
+        }
+        assert_eq!(
+            RfcommSocket::connect("bridge", DEFAULT_CHANNEL)
+                .err()
+                .map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn matches_the_address_of_the_kernel() {
+        // `struct sockaddr_rc` of `bluetooth/rfcomm.h`.
+        assert_eq!(mem::size_of::<SockaddrRc>(), 10);
+    }
+
+    #[test]
+    fn times_out_and_clears_the_input() {
+        let (mut socket, mut bridge) = socket();
+        let mut buf = [0u8; 4];
+        let err = socket.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        bridge.write_all(b"stale").unwrap();
+        socket.clear_input().unwrap();
+        bridge.write_all(b"LDS").unwrap();
+        assert_eq!(socket.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"LDS");
+
+        socket.write_all(&[0x62]).unwrap();
+        let mut sent = [0u8; 1];
+        bridge.read_exact(&mut sent).unwrap();
+        assert_eq!(sent, [0x62]);
+    }
+}
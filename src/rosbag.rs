@@ -0,0 +1,471 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Import of `sensor_msgs/LaserScan` messages from rosbag2 recordings,
+//! enabled by the `rosbag` feature.
+//!
+//! Both the `sqlite3` and the `mcap` storages are supported, as long as the
+//! messages are CDR serialized and the bag is not compressed. ROS 1 bags
+//! can be converted with `rosbags-convert` first.
+//!
+//! Every beam of a scan goes to the closest degree of the reading, so scans
+//! from other lidars are resampled to 360 beams.
+//!
+//! ```no_run
+//! use hls_lfcd_lds_driver::rosbag::RosbagReader;
+//!
+//! for scan in RosbagReader::open("turtlebot3_bag")?.with_topic("/scan") {
+//!     let (timestamp, reading) = scan?;
+//! }
+//! # Ok::<(), hls_lfcd_lds_driver::rosbag::RosbagError>(())
+//! ```
+
+use crate::LaserReading;
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Type of the messages read from the bag.
+pub const LASER_SCAN_TYPE: &str = "sensor_msgs/msg/LaserScan";
+
+const BATCH_SIZE: i64 = 256;
+
+/// Errors while reading a bag.
+#[derive(Debug)]
+pub enum RosbagError {
+    Io(std::io::Error),
+    Sqlite(rusqlite::Error),
+    Mcap(mcap::McapError),
+    /// The path is neither a `.db3` or `.mcap` file nor a directory containing them
+    NoStorage(PathBuf),
+    /// The messages are not CDR serialized
+    UnsupportedEncoding(String),
+    /// A message is not a valid `LaserScan`
+    Malformed,
+}
+
+impl fmt::Display for RosbagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RosbagError::Io(e) => write!(f, "IO error: {e}"),
+            RosbagError::Sqlite(e) => write!(f, "SQLite error: {e}"),
+            RosbagError::Mcap(e) => write!(f, "MCAP error: {e}"),
+            RosbagError::NoStorage(p) => write!(f, "No rosbag2 storage in {}", p.display()),
+            RosbagError::UnsupportedEncoding(e) => write!(f, "Unsupported encoding: {e}"),
+            RosbagError::Malformed => write!(f, "Malformed LaserScan message"),
+        }
+    }
+}
+
+impl std::error::Error for RosbagError {}
+
+impl From<std::io::Error> for RosbagError {
+    fn from(e: std::io::Error) -> Self {
+        RosbagError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for RosbagError {
+    fn from(e: rusqlite::Error) -> Self {
+        RosbagError::Sqlite(e)
+    }
+}
+
+impl From<mcap::McapError> for RosbagError {
+    fn from(e: mcap::McapError) -> Self {
+        RosbagError::Mcap(e)
+    }
+}
+
+/// Reader of the scans of a rosbag2 recording, iterating over the scans
+/// and the time they were recorded at.
+///
+/// The `sqlite3` files are read in batches, the `mcap` ones are loaded
+/// whole, one at a time.
+pub struct RosbagReader {
+    files: VecDeque<PathBuf>,
+    topic: Option<String>,
+    current: Option<Storage>,
+    pending: VecDeque<(SystemTime, LaserReading)>,
+}
+
+enum Storage {
+    Sqlite {
+        conn: rusqlite::Connection,
+        last_id: i64,
+    },
+    Mcap,
+}
+
+impl RosbagReader {
+    /// Opens a bag, either its directory or a single `.db3` or `.mcap` file.
+    ///
+    /// # Errors
+    /// An error variant is returned if the path cannot be read or contains no storage file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RosbagError> {
+        let path = path.as_ref();
+        let files = if path.is_dir() {
+            let mut files = Vec::new();
+            for entry in std::fs::read_dir(path)? {
+                let file = entry?.path();
+                if is_storage(&file) {
+                    files.push(file);
+                }
+            }
+            // Splits are named `<bag>_<n>`, so `bag_10` comes after `bag_9`.
+            files.sort_by_key(|f| (f.as_os_str().len(), f.clone()));
+            files
+        } else if is_storage(path) {
+            vec![path.to_path_buf()]
+        } else {
+            Vec::new()
+        };
+        if files.is_empty() {
+            return Err(RosbagError::NoStorage(path.to_path_buf()));
+        }
+
+        Ok(Self {
+            files: files.into(),
+            topic: None,
+            current: None,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Reads only the given topic, by default every `LaserScan` topic is read.
+    pub fn with_topic(mut self, topic: &str) -> Self {
+        self.topic = Some(topic.to_string());
+        self
+    }
+
+    /// Reads the next scan and the time it was recorded at, `None` at the end of the bag.
+    ///
+    /// # Errors
+    /// An error variant is returned if the bag cannot be read or a message is malformed,
+    /// the reader then skips to the next file of the bag.
+    pub fn read_scan(&mut self) -> Result<Option<(SystemTime, LaserReading)>, RosbagError> {
+        loop {
+            if let Some(scan) = self.pending.pop_front() {
+                return Ok(Some(scan));
+            }
+            match self.current.take() {
+                Some(Storage::Sqlite { conn, last_id }) => {
+                    let last_id = self.read_batch(&conn, last_id)?;
+                    if !self.pending.is_empty() {
+                        self.current = Some(Storage::Sqlite { conn, last_id });
+                    }
+                }
+                Some(Storage::Mcap) => {}
+                None => {
+                    let Some(file) = self.files.pop_front() else {
+                        return Ok(None);
+                    };
+                    self.current = Some(self.open_file(&file)?);
+                }
+            }
+        }
+    }
+
+    fn open_file(&mut self, file: &Path) -> Result<Storage, RosbagError> {
+        if file.extension().is_some_and(|e| e == "mcap") {
+            self.read_mcap(&std::fs::read(file)?)?;
+            return Ok(Storage::Mcap);
+        }
+
+        let conn = rusqlite::Connection::open_with_flags(
+            file,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?;
+        let formats: Vec<String> = conn
+            .prepare("SELECT DISTINCT serialization_format FROM topics WHERE type = ?1")?
+            .query_map([LASER_SCAN_TYPE], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        if let Some(format) = formats.into_iter().find(|f| f != "cdr") {
+            return Err(RosbagError::UnsupportedEncoding(format));
+        }
+        Ok(Storage::Sqlite { conn, last_id: 0 })
+    }
+
+    /// Reads the messages after `last_id`, returning the id of the last one.
+    fn read_batch(
+        &mut self,
+        conn: &rusqlite::Connection,
+        last_id: i64,
+    ) -> Result<i64, RosbagError> {
+        let mut stmt = conn.prepare_cached(
+            "SELECT m.id, m.timestamp, m.data FROM messages m \
+             JOIN topics t ON m.topic_id = t.id \
+             WHERE t.type = ?1 AND (?2 IS NULL OR t.name = ?2) AND m.id > ?3 \
+             ORDER BY m.id LIMIT ?4",
+        )?;
+        let mut rows = stmt.query(rusqlite::params![
+            LASER_SCAN_TYPE,
+            self.topic,
+            last_id,
+            BATCH_SIZE
+        ])?;
+
+        let mut last_id = last_id;
+        while let Some(row) = rows.next()? {
+            last_id = row.get(0)?;
+            let timestamp: i64 = row.get(1)?;
+            let data: Vec<u8> = row.get(2)?;
+            self.pending
+                .push_back((to_time(timestamp as u64), decode_laser_scan(&data)?));
+        }
+        Ok(last_id)
+    }
+
+    fn read_mcap(&mut self, buf: &[u8]) -> Result<(), RosbagError> {
+        for message in mcap::MessageStream::new(buf)? {
+            let message = message?;
+            let channel = &message.channel;
+            if channel
+                .schema
+                .as_ref()
+                .is_none_or(|s| s.name != LASER_SCAN_TYPE)
+                || self.topic.as_ref().is_some_and(|t| *t != channel.topic)
+            {
+                continue;
+            }
+            if channel.message_encoding != "cdr" {
+                return Err(RosbagError::UnsupportedEncoding(
+                    channel.message_encoding.clone(),
+                ));
+            }
+            self.pending
+                .push_back((to_time(message.log_time), decode_laser_scan(&message.data)?));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for RosbagReader {
+    type Item = Result<(SystemTime, LaserReading), RosbagError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_scan().transpose()
+    }
+}
+
+fn is_storage(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|e| e == "db3" || e == "mcap")
+}
+
+fn to_time(nanos: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+/// Decodes a CDR serialized `sensor_msgs/LaserScan` into a reading.
+///
+/// # Errors
+/// An error variant is returned if the message is truncated or not CDR.
+pub fn decode_laser_scan(data: &[u8]) -> Result<LaserReading, RosbagError> {
+    let mut cdr = Cdr::new(data)?;
+
+    // header: stamp and frame_id
+    cdr.u32()?;
+    cdr.u32()?;
+    let frame_id = cdr.u32()? as usize;
+    cdr.skip(frame_id)?;
+
+    let angle_min = cdr.f32()?;
+    let _angle_max = cdr.f32()?;
+    let angle_increment = cdr.f32()?;
+    let _time_increment = cdr.f32()?;
+    let scan_time = cdr.f32()?;
+    let _range_min = cdr.f32()?;
+    let _range_max = cdr.f32()?;
+    let ranges = cdr.f32_seq()?;
+    let intensities = cdr.f32_seq()?;
+
+    let mut reading = LaserReading::new();
+    let n = reading.ranges.len();
+    if scan_time > 0.0 {
+        reading.rpms = (60.0 / scan_time).round() as u16;
+    }
+    for (k, range) in ranges.iter().enumerate() {
+        let angle = angle_min + k as f32 * angle_increment;
+        let i = (angle.to_degrees().round() as i64).rem_euclid(n as i64) as usize;
+        if !range.is_finite() || *range <= 0.0 {
+            continue;
+        }
+        let mm = (range * 1000.0).round().min(f32::from(u16::MAX)) as u16;
+        // With more beams than degrees, keeps the closest hit.
+        if reading.ranges[i] == 0 || mm < reading.ranges[i] {
+            reading.ranges[i] = mm;
+            reading.intensities[i] = intensities
+                .get(k)
+                .map_or(0, |v| v.round().clamp(0.0, f32::from(u16::MAX)) as u16);
+        }
+    }
+    Ok(reading)
+}
+
+/// Cursor over a CDR buffer, aligning every primitive to its size.
+struct Cdr<'a> {
+    // The buffer after the encapsulation header, alignment is relative to it.
+    buf: &'a [u8],
+    pos: usize,
+    little_endian: bool,
+}
+
+impl<'a> Cdr<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, RosbagError> {
+        let little_endian = match data.get(..4) {
+            Some([0, 0, _, _]) => false,
+            Some([0, 1, _, _]) => true,
+            Some(h) => {
+                return Err(RosbagError::UnsupportedEncoding(format!(
+                    "CDR representation {:#04x}{:02x}",
+                    h[0], h[1]
+                )))
+            }
+            None => return Err(RosbagError::Malformed),
+        };
+        Ok(Self {
+            buf: &data[4..],
+            pos: 0,
+            little_endian,
+        })
+    }
+
+    fn skip(&mut self, n: usize) -> Result<&'a [u8], RosbagError> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + n)
+            .ok_or(RosbagError::Malformed)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, RosbagError> {
+        self.pos = self.pos.next_multiple_of(4);
+        let b: [u8; 4] = self
+            .skip(4)?
+            .try_into()
+            .map_err(|_| RosbagError::Malformed)?;
+        Ok(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    fn f32(&mut self) -> Result<f32, RosbagError> {
+        self.u32().map(f32::from_bits)
+    }
+
+    fn f32_seq(&mut self) -> Result<Vec<f32>, RosbagError> {
+        let len = self.u32()? as usize;
+        if len > self.buf.len() / 4 {
+            return Err(RosbagError::Malformed);
+        }
+        (0..len).map(|_| self.f32()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serializes a little endian `LaserScan` with one beam per degree.
+    fn laser_scan(ranges: &[f32], scan_time: f32) -> Vec<u8> {
+        let mut data = vec![0, 1, 0, 0];
+        let mut put = |v: u32| data.extend_from_slice(&v.to_le_bytes());
+        put(12); // stamp
+        put(0);
+        put(4); // frame_id, "lds\0"
+        put(u32::from_le_bytes(*b"lds\0"));
+        let increment = 1f32.to_radians();
+        for v in [0.0, increment * 359.0, increment, 0.0, scan_time, 0.12, 3.5] {
+            put(f32::to_bits(v));
+        }
+        put(ranges.len() as u32);
+        ranges.iter().for_each(|r| put(r.to_bits()));
+        put(ranges.len() as u32);
+        ranges.iter().for_each(|_| put(100f32.to_bits()));
+        data
+    }
+
+    #[test]
+    fn decodes_a_laser_scan() {
+        let mut ranges = vec![0.0; 360];
+        ranges[0] = 1.0;
+        ranges[90] = 0.25;
+        ranges[180] = f32::INFINITY;
+        let reading = decode_laser_scan(&laser_scan(&ranges, 0.2)).unwrap();
+        assert_eq!(reading.rpms, 300);
+        assert_eq!((reading.ranges[0], reading.intensities[0]), (1000, 100));
+        assert_eq!(reading.ranges[90], 250);
+        assert_eq!((reading.ranges[180], reading.intensities[180]), (0, 0));
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        let data = laser_scan(&[1.0; 360], 0.2);
+        assert!(matches!(
+            decode_laser_scan(&data[..data.len() - 8]),
+            Err(RosbagError::Malformed)
+        ));
+        assert!(matches!(
+            decode_laser_scan(&[0, 1]),
+            Err(RosbagError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn reads_the_scans_of_a_sqlite_bag() {
+        let dir = std::env::temp_dir().join(format!("lds-rosbag-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let conn = rusqlite::Connection::open(dir.join("bag_0.db3")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE topics (id INTEGER PRIMARY KEY, name TEXT, type TEXT, \
+             serialization_format TEXT); \
+             CREATE TABLE messages (id INTEGER PRIMARY KEY, topic_id INTEGER, \
+             timestamp INTEGER, data BLOB);",
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO topics VALUES (1, '/scan', ?1, 'cdr'), (2, '/odom', 'nav_msgs/msg/Odometry', 'cdr')",
+            [LASER_SCAN_TYPE],
+        )
+        .unwrap();
+        for (id, topic, stamp) in [(1, 1, 10), (2, 2, 15), (3, 1, 20)] {
+            conn.execute(
+                "INSERT INTO messages VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![id, topic, stamp, laser_scan(&[1.0; 360], 0.2)],
+            )
+            .unwrap();
+        }
+        drop(conn);
+
+        let scans = RosbagReader::open(&dir)
+            .unwrap()
+            .with_topic("/scan")
+            .collect::<Result<Vec<_>, _>>();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let stamps: Vec<_> = scans.unwrap().into_iter().map(|(t, _)| t).collect();
+        assert_eq!(stamps, [to_time(10), to_time(20)]);
+    }
+
+    #[test]
+    fn needs_a_storage_file() {
+        assert!(matches!(
+            RosbagReader::open(std::env::temp_dir().join("lds-no-such-bag")),
+            Err(RosbagError::NoStorage(_))
+        ));
+    }
+}
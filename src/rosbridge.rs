@@ -0,0 +1,193 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Publishing of scans as `sensor_msgs/LaserScan` to a rosbridge server,
+//! enabled by the `rosbridge` feature.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use hls_lfcd_lds_driver::rosbridge::{RosVersion, RosbridgePublisher};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let mut publisher =
+//!     RosbridgePublisher::connect("ws://localhost:9090", "/scan", "laser", RosVersion::Ros2)
+//!         .await?;
+//! publisher.publish(&reading).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::sink::AsyncScanSink;
+use crate::{LaserReading, RANGE_MAX, RANGE_MIN};
+use roslibrust::{ClientHandle, Publisher, RosLibRustResult};
+use roslibrust_codegen::RosMessageType;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of ROS behind the rosbridge server, they differ in the header layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RosVersion {
+    Ros1,
+    #[default]
+    Ros2,
+}
+
+/// `builtin_interfaces/Time` (ROS 2) or `time` (ROS 1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Time {
+    Ros1 { secs: u32, nsecs: u32 },
+    Ros2 { sec: i32, nanosec: u32 },
+}
+
+impl Time {
+    /// Gets the current time, in the layout used by the given version.
+    pub fn now(version: RosVersion) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        match version {
+            RosVersion::Ros1 => Time::Ros1 {
+                secs: now.as_secs() as u32,
+                nsecs: now.subsec_nanos(),
+            },
+            RosVersion::Ros2 => Time::Ros2 {
+                sec: now.as_secs() as i32,
+                nanosec: now.subsec_nanos(),
+            },
+        }
+    }
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Time::Ros2 { sec: 0, nanosec: 0 }
+    }
+}
+
+/// `std_msgs/Header`, `seq` only exists in ROS 1.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Header {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u32>,
+    pub stamp: Time,
+    pub frame_id: String,
+}
+
+/// `sensor_msgs/LaserScan`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct LaserScan {
+    pub header: Header,
+    pub angle_min: f32,
+    pub angle_max: f32,
+    pub angle_increment: f32,
+    pub time_increment: f32,
+    pub scan_time: f32,
+    pub range_min: f32,
+    pub range_max: f32,
+    pub ranges: Vec<f32>,
+    pub intensities: Vec<f32>,
+}
+
+impl RosMessageType for LaserScan {
+    const ROS_TYPE_NAME: &'static str = "sensor_msgs/LaserScan";
+}
+
+impl LaserScan {
+    /// Converts a reading into a `LaserScan`, with the same conventions
+    /// of the ROBOTIS ROS driver: ranges in meters, counter-clockwise,
+    /// starting from the front of the lidar.
+    pub fn from_reading(reading: &LaserReading, header: Header) -> Self {
+        let n = reading.ranges.len();
+        let angle_increment = TAU / n as f32;
+        let scan_time = reading.scan_time();
+
+        Self {
+            header,
+            angle_min: 0.0,
+            angle_max: TAU - angle_increment,
+            angle_increment,
+            time_increment: scan_time / n as f32,
+            scan_time,
+            range_min: f32::from(RANGE_MIN) / 1000.0,
+            range_max: f32::from(RANGE_MAX) / 1000.0,
+            ranges: reading
+                .ranges
+                .iter()
+                .map(|r| f32::from(*r) / 1000.0)
+                .collect(),
+            intensities: reading.intensities.iter().map(|i| f32::from(*i)).collect(),
+        }
+    }
+}
+
+/// Publisher of scans to a rosbridge websocket server.
+pub struct RosbridgePublisher {
+    // Keeps the connection alive.
+    _client: ClientHandle,
+    publisher: Publisher<LaserScan>,
+    frame_id: String,
+    version: RosVersion,
+    seq: u32,
+}
+
+impl RosbridgePublisher {
+    /// Connects to the rosbridge server at `url` and advertises `topic`.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to connect to the server
+    /// - unable to advertise the topic
+    pub async fn connect(
+        url: &str,
+        topic: &str,
+        frame_id: &str,
+        version: RosVersion,
+    ) -> RosLibRustResult<Self> {
+        let client = ClientHandle::new(url).await?;
+        let publisher = client.advertise::<LaserScan>(topic).await?;
+
+        Ok(Self {
+            _client: client,
+            publisher,
+            frame_id: frame_id.to_string(),
+            version,
+            seq: 0,
+        })
+    }
+
+    /// Publishes a reading, stamped with the current time.
+    ///
+    /// # Errors
+    /// An error variant is returned if the message cannot be sent.
+    pub async fn publish(&mut self, reading: &LaserReading) -> RosLibRustResult<()> {
+        let header = Header {
+            seq: (self.version == RosVersion::Ros1).then_some(self.seq),
+            stamp: Time::now(self.version),
+            frame_id: self.frame_id.clone(),
+        };
+        self.seq = self.seq.wrapping_add(1);
+        self.publisher
+            .publish(LaserScan::from_reading(reading, header))
+            .await
+    }
+}
+
+impl AsyncScanSink for RosbridgePublisher {
+    async fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        self.publish(reading).await.map_err(io::Error::other)
+    }
+}
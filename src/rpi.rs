@@ -0,0 +1,598 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Wiring of the lidar to the hardware UART of a Raspberry Pi, enabled by
+//! the `rpi` feature on Linux.
+//!
+//! The UART on the GPIO header, `/dev/serial0`, is easy to get wrong:
+//! - it is the PL011 (`ttyAMA*`) or the mini UART (`ttyS0`) depending on
+//!   the model and the Bluetooth overlay; the baud rate of the mini UART
+//!   follows the core clock, which only holds still with `enable_uart=1`
+//! - the UART clock must be high enough for 230400 baud
+//! - the kernel console or a login prompt on the port talks over the lidar
+//!
+//! `check` finds all of these before the port is opened, and `open_options`
+//! gives the options of the LDS-01 on `/dev/serial0` once they pass. A
+//! `PowerPin` switches the power of the lidar through a GPIO, e.g. a MOSFET
+//! on the 5V line, to spare the motor while the robot is parked.
+//!
+//! ```no_run
+//! # #[cfg(all(feature = "rpi", feature = "sync", target_os = "linux"))]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use hls_lfcd_lds_driver::rpi::{self, PowerPin};
+//! use hls_lfcd_lds_driver::sync::LFCDLaser;
+//!
+//! // GPIO 17 drives the power of the lidar.
+//! let power = PowerPin::new(17, false)?;
+//! let mut laser: LFCDLaser = rpi::open_options()?.open()?;
+//! let reading = laser.read()?;
+//! laser.close();
+//! power.set(false)?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(all(feature = "rpi", feature = "sync", target_os = "linux")))]
+//! # fn main() {}
+//! ```
+
+use crate::options::{Model, OpenOptions, OptionsError};
+use std::ffi::CStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+/// The UART on the GPIO header, a link to the PL011 or to the mini UART.
+pub const SERIAL0: &str = "/dev/serial0";
+
+/// Largest difference between the baud rate the UART achieves and the one
+/// requested, 2%.
+pub const MAX_BAUD_ERROR: f32 = 0.02;
+
+/// Clock of the PL011 set by the firmware unless `init_uart_clock` says
+/// otherwise, in Hz.
+pub const DEFAULT_UART_CLOCK: u32 = 48_000_000;
+
+/// Core clock the mini UART runs from unless `core_freq` says otherwise,
+/// in Hz.
+pub const DEFAULT_CORE_CLOCK: u32 = 250_000_000;
+
+/// Firmware configuration files, the first one found is read.
+const CONFIG_FILES: [&str; 2] = ["/boot/firmware/config.txt", "/boot/config.txt"];
+
+/// The UARTs of the Raspberry Pi.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Uart {
+    /// ARM PL011, `/dev/ttyAMA*`
+    Pl011,
+    /// Mini UART, `/dev/ttyS0`, clocked by the VPU core
+    Mini,
+}
+
+impl Uart {
+    /// Gets the UART of a device, `None` if not a UART of the Raspberry Pi.
+    pub fn of<P: AsRef<Path>>(device: P) -> Option<Self> {
+        let name = device.as_ref().file_name()?.to_str()?;
+        if name.starts_with("ttyAMA") {
+            Some(Uart::Pl011)
+        } else if name.starts_with("ttyS") {
+            Some(Uart::Mini)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the baud rate the UART achieves when asked for `baud_rate`, with
+    /// its clock at `clock` Hz; `None` if out of the range of its divisor.
+    pub fn actual_baud_rate(&self, clock: u32, baud_rate: u32) -> Option<u32> {
+        if baud_rate == 0 {
+            return None;
+        }
+        let (clock, baud_rate) = (u64::from(clock), u64::from(baud_rate));
+        match self {
+            Uart::Mini => {
+                // baud = clock / (8 * (divisor + 1)), 16 bits divisor.
+                let divisor = ((clock + 4 * baud_rate) / (8 * baud_rate)).checked_sub(1)?;
+                (divisor <= 0xFFFF).then(|| (clock / (8 * (divisor + 1))) as u32)
+            }
+            Uart::Pl011 => {
+                // baud = clock / (16 * divisor), 16.6 fixed point divisor.
+                let divisor = (4 * clock + baud_rate / 2) / baud_rate;
+                (divisor >= 64 && divisor >> 6 <= 0xFFFF).then(|| (4 * clock / divisor) as u32)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Uart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Uart::Pl011 => f.write_str("PL011"),
+            Uart::Mini => f.write_str("mini UART"),
+        }
+    }
+}
+
+/// The settings of the firmware configuration touching the UARTs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootConfig {
+    /// `enable_uart=1`, which also holds the core clock still
+    pub enable_uart: bool,
+    /// `core_freq`, in MHz
+    pub core_freq: Option<u32>,
+    /// `init_uart_clock`, in Hz
+    pub init_uart_clock: Option<u32>,
+}
+
+impl BootConfig {
+    /// Loads the firmware configuration, `/boot/firmware/config.txt` or
+    /// `/boot/config.txt` on older systems.
+    ///
+    /// # Errors
+    /// An error variant is returned if none of the files can be read.
+    pub fn load() -> io::Result<Self> {
+        let mut last = io::ErrorKind::NotFound.into();
+        for path in CONFIG_FILES {
+            match fs::read_to_string(path) {
+                Ok(text) => return Ok(Self::parse(&text)),
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+
+    /// Parses a firmware configuration, the conditional sections are all
+    /// taken into account and the last value of a setting wins.
+    pub fn parse(text: &str) -> Self {
+        let mut config = Self::default();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "enable_uart" => config.enable_uart = value == "1",
+                "core_freq" => config.core_freq = value.parse().ok(),
+                "init_uart_clock" => config.init_uart_clock = value.parse().ok(),
+                _ => {}
+            }
+        }
+        config
+    }
+
+    /// Gets the clock of a UART, in Hz.
+    pub fn clock(&self, uart: Uart) -> u32 {
+        match uart {
+            Uart::Pl011 => self.init_uart_clock.unwrap_or(DEFAULT_UART_CLOCK),
+            Uart::Mini => self
+                .core_freq
+                .map_or(DEFAULT_CORE_CLOCK, |mhz| mhz.saturating_mul(1_000_000)),
+        }
+    }
+}
+
+/// Gets the devices the kernel console writes to, from a kernel command
+/// line such as `/proc/cmdline`, e.g. `serial0` or `ttyAMA0`.
+pub fn console_devices(cmdline: &str) -> Vec<&str> {
+    cmdline
+        .split_whitespace()
+        .filter_map(|arg| arg.strip_prefix("console="))
+        .map(|console| console.split(',').next().unwrap_or_default())
+        .collect()
+}
+
+/// A port found fit for the lidar by `check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UartCheck {
+    /// Device behind the port, e.g. `/dev/ttyAMA0`
+    pub device: PathBuf,
+    /// UART of the device
+    pub uart: Uart,
+    /// Baud rate the UART achieves
+    pub baud_rate: u32,
+}
+
+/// A port not fit for the lidar, found by `check`.
+#[derive(Debug)]
+pub enum RpiError {
+    /// The lidar does not talk at this baud rate
+    Options(OptionsError),
+    /// The port is not a UART of the Raspberry Pi
+    NotUart(PathBuf),
+    /// The kernel console writes to the port, `console=` in `cmdline.txt`
+    KernelConsole(PathBuf),
+    /// A login prompt runs on the port, `serial-getty@` in systemd
+    LoginConsole(PathBuf),
+    /// The mini UART without `enable_uart=1`, its baud rate drifts with the
+    /// core clock
+    UnstableClock,
+    /// The clock of the UART is too slow or too far from a multiple of the
+    /// baud rate
+    BaudRate {
+        uart: Uart,
+        clock: u32,
+        baud_rate: u32,
+    },
+    /// The port or the configuration cannot be read
+    Io(io::Error),
+}
+
+impl fmt::Display for RpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpiError::Options(e) => e.fmt(f),
+            RpiError::NotUart(device) => {
+                write!(f, "{} is not a UART of the Raspberry Pi", device.display())
+            }
+            RpiError::KernelConsole(device) => write!(
+                f,
+                "The kernel console uses {}, remove its console= from cmdline.txt",
+                device.display()
+            ),
+            RpiError::LoginConsole(device) => write!(
+                f,
+                "A login prompt runs on {}, disable the serial console in raspi-config",
+                device.display()
+            ),
+            RpiError::UnstableClock => write!(
+                f,
+                "The mini UART needs enable_uart=1 in config.txt to hold its baud rate"
+            ),
+            RpiError::BaudRate {
+                uart,
+                clock,
+                baud_rate,
+            } => write!(
+                f,
+                "The {uart} cannot talk at {baud_rate} baud with a {clock} Hz clock"
+            ),
+            RpiError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RpiError {}
+
+impl From<io::Error> for RpiError {
+    fn from(e: io::Error) -> Self {
+        RpiError::Io(e)
+    }
+}
+
+impl From<OptionsError> for RpiError {
+    fn from(e: OptionsError) -> Self {
+        RpiError::Options(e)
+    }
+}
+
+/// Checks that the lidar can talk at `baud_rate` on `port`, a UART of the
+/// Raspberry Pi, without touching the port.
+///
+/// # Errors
+/// An error variant is returned for the first problem found, see `RpiError`.
+pub fn check<P: AsRef<Path>>(port: P, baud_rate: u32) -> Result<UartCheck, RpiError> {
+    let model = Model::Lds01;
+    if !model.baud_rates().contains(&baud_rate) {
+        return Err(OptionsError::UnsupportedBaudRate { model, baud_rate }.into());
+    }
+    let device = fs::canonicalize(port)?;
+    let uart = Uart::of(&device).ok_or_else(|| RpiError::NotUart(device.clone()))?;
+
+    let cmdline = fs::read_to_string("/proc/cmdline")?;
+    for console in console_devices(&cmdline) {
+        if fs::canonicalize(Path::new("/dev").join(console)).is_ok_and(|c| c == device) {
+            return Err(RpiError::KernelConsole(device));
+        }
+    }
+    let name = device.file_name().unwrap_or_default().to_string_lossy();
+    let getty = format!("/etc/systemd/system/getty.target.wants/serial-getty@{name}.service");
+    if Path::new(&getty).exists() {
+        return Err(RpiError::LoginConsole(device));
+    }
+
+    // Not every image has a configuration, the firmware defaults apply then.
+    let config = BootConfig::load().unwrap_or_default();
+    if uart == Uart::Mini && !config.enable_uart {
+        return Err(RpiError::UnstableClock);
+    }
+    let clock = config.clock(uart);
+    let error = |actual: u32| (actual as f32 - baud_rate as f32).abs() / baud_rate as f32;
+    match uart.actual_baud_rate(clock, baud_rate) {
+        Some(actual) if error(actual) <= MAX_BAUD_ERROR => Ok(UartCheck {
+            device,
+            uart,
+            baud_rate: actual,
+        }),
+        _ => Err(RpiError::BaudRate {
+            uart,
+            clock,
+            baud_rate,
+        }),
+    }
+}
+
+/// Checks `/dev/serial0`, then gets the options opening the LDS-01 on it.
+///
+/// # Errors
+/// An error variant is returned if the port is not fit for the lidar, see
+/// `check`.
+pub fn open_options() -> Result<OpenOptions, RpiError> {
+    let baud_rate = Model::Lds01.baud_rates()[0];
+    check(SERIAL0, baud_rate)?;
+    Ok(OpenOptions::new(SERIAL0, baud_rate)
+        .model(Model::Lds01)
+        .exclusive(true))
+}
+
+/// `struct gpiochip_info` in `linux/gpio.h`.
+#[repr(C)]
+struct ChipInfo {
+    name: [libc::c_char; 32],
+    label: [libc::c_char; 32],
+    lines: u32,
+}
+
+/// `struct gpiohandle_request` in `linux/gpio.h`.
+#[repr(C)]
+struct HandleRequest {
+    line_offsets: [u32; 64],
+    flags: u32,
+    default_values: [u8; 64],
+    consumer_label: [u8; 32],
+    lines: u32,
+    fd: libc::c_int,
+}
+
+/// `struct gpiohandle_data` in `linux/gpio.h`.
+#[repr(C)]
+struct HandleData {
+    values: [u8; 64],
+}
+
+/// Encodes an ioctl request of the GPIO (0xB4) type, `_IOC` in
+/// `asm-generic/ioctl.h`.
+const fn gpio(dir: libc::c_ulong, nr: libc::c_ulong, size: usize) -> libc::c_ulong {
+    (dir << 30) | ((size as libc::c_ulong) << 16) | (0xB4 << 8) | nr
+}
+
+/// `_IOR(0xB4, 0x01, struct gpiochip_info)`.
+const GPIO_GET_CHIPINFO_IOCTL: libc::c_ulong = gpio(2, 0x01, mem::size_of::<ChipInfo>());
+/// `_IOWR(0xB4, 0x03, struct gpiohandle_request)`.
+const GPIO_GET_LINEHANDLE_IOCTL: libc::c_ulong = gpio(3, 0x03, mem::size_of::<HandleRequest>());
+/// `_IOWR(0xB4, 0x08, struct gpiohandle_data)`.
+const GPIOHANDLE_GET_LINE_VALUES_IOCTL: libc::c_ulong = gpio(3, 0x08, mem::size_of::<HandleData>());
+/// `_IOWR(0xB4, 0x09, struct gpiohandle_data)`.
+const GPIOHANDLE_SET_LINE_VALUES_IOCTL: libc::c_ulong = gpio(3, 0x09, mem::size_of::<HandleData>());
+const GPIOHANDLE_REQUEST_OUTPUT: u32 = 1 << 1;
+const GPIOHANDLE_REQUEST_ACTIVE_LOW: u32 = 1 << 2;
+
+/// Name of the GPIO lines requested, shown by `gpioinfo`.
+const CONSUMER: &[u8] = b"hls_lfcd_lds";
+
+/// Gets the GPIO chip of the header, labelled `pinctrl-bcm*` up to the
+/// Raspberry Pi 4 and `pinctrl-rp1` on the Raspberry Pi 5.
+///
+/// # Errors
+/// An error variant is returned if no such chip is found.
+pub fn header_chip() -> io::Result<PathBuf> {
+    let mut chips: Vec<PathBuf> = fs::read_dir("/dev")?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("gpiochip"))
+        })
+        .collect();
+    chips.sort();
+    for chip in chips {
+        let Ok(file) = File::open(&chip) else {
+            continue;
+        };
+        // SAFETY: `ChipInfo` is plain old data, filled by the kernel.
+        let mut info: ChipInfo = unsafe { mem::zeroed() };
+        // SAFETY: the request writes a `ChipInfo` living through the call.
+        if unsafe { libc::ioctl(file.as_raw_fd(), GPIO_GET_CHIPINFO_IOCTL as _, &mut info) } != 0 {
+            continue;
+        }
+        // SAFETY: the kernel terminates the label with a NUL.
+        let label = unsafe { CStr::from_ptr(info.label.as_ptr()) }.to_string_lossy();
+        if label.starts_with("pinctrl-bcm") || label == "pinctrl-rp1" {
+            return Ok(chip);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "No GPIO chip of the Raspberry Pi header found",
+    ))
+}
+
+/// A GPIO line switching the power of the lidar.
+///
+/// The line stays an output driven by the crate as long as the `PowerPin`
+/// lives, it is released on drop and keeps its last level on the Raspberry Pi.
+pub struct PowerPin {
+    handle: OwnedFd,
+    line: u32,
+}
+
+impl PowerPin {
+    /// Requests the line `line` of the header chip, the GPIO number of the
+    /// BCM pinout, and powers the lidar. `active_low` for a switch powering
+    /// the lidar when the line is low, e.g. a P-channel MOSFET.
+    ///
+    /// # Errors
+    /// An error variant is returned if the chip is not found or the line is
+    /// used by someone else.
+    pub fn new(line: u32, active_low: bool) -> io::Result<Self> {
+        Self::with_chip(header_chip()?, line, active_low)
+    }
+
+    /// Requests the line `line` of the GPIO chip `chip`, e.g.
+    /// `/dev/gpiochip0`, and powers the lidar.
+    ///
+    /// # Errors
+    /// An error variant is returned if the chip cannot be opened or the line
+    /// is used by someone else.
+    pub fn with_chip<P: AsRef<Path>>(chip: P, line: u32, active_low: bool) -> io::Result<Self> {
+        let chip = File::open(chip)?;
+        let mut request = HandleRequest {
+            line_offsets: [0; 64],
+            flags: GPIOHANDLE_REQUEST_OUTPUT,
+            default_values: [0; 64],
+            consumer_label: [0; 32],
+            lines: 1,
+            fd: -1,
+        };
+        request.line_offsets[0] = line;
+        if active_low {
+            request.flags |= GPIOHANDLE_REQUEST_ACTIVE_LOW;
+        }
+        // The kernel applies the polarity to the default value as well.
+        request.default_values[0] = 1;
+        request.consumer_label[..CONSUMER.len()].copy_from_slice(CONSUMER);
+        // SAFETY: the request reads and writes a `HandleRequest` living
+        // through the call.
+        if unsafe {
+            libc::ioctl(
+                chip.as_raw_fd(),
+                GPIO_GET_LINEHANDLE_IOCTL as _,
+                &mut request,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the kernel returned a new descriptor, owned by nobody else.
+        let handle = unsafe { OwnedFd::from_raw_fd(request.fd) };
+        Ok(Self { handle, line })
+    }
+
+    /// Gets the line of the pin.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// Checks if the lidar is powered.
+    ///
+    /// # Errors
+    /// An error variant is returned if the line cannot be read.
+    pub fn is_on(&self) -> io::Result<bool> {
+        let mut data = HandleData { values: [0; 64] };
+        // SAFETY: the request writes a `HandleData` living through the call.
+        if unsafe {
+            libc::ioctl(
+                self.handle.as_raw_fd(),
+                GPIOHANDLE_GET_LINE_VALUES_IOCTL as _,
+                &mut data,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(data.values[0] != 0)
+    }
+
+    /// Powers the lidar on or off.
+    ///
+    /// # Errors
+    /// An error variant is returned if the line cannot be set.
+    pub fn set(&self, on: bool) -> io::Result<()> {
+        let mut data = HandleData { values: [0; 64] };
+        data.values[0] = u8::from(on);
+        // SAFETY: the request reads a `HandleData` living through the call.
+        if unsafe {
+            libc::ioctl(
+                self.handle.as_raw_fd(),
+                GPIOHANDLE_SET_LINE_VALUES_IOCTL as _,
+                &mut data,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_ioctls_of_the_kernel() {
+        // From `linux/gpio.h`.
+        assert_eq!(GPIO_GET_CHIPINFO_IOCTL, 0x8044_B401);
+        assert_eq!(GPIO_GET_LINEHANDLE_IOCTL, 0xC16C_B403);
+        assert_eq!(GPIOHANDLE_GET_LINE_VALUES_IOCTL, 0xC040_B408);
+        assert_eq!(GPIOHANDLE_SET_LINE_VALUES_IOCTL, 0xC040_B409);
+    }
+
+    #[test]
+    fn computes_the_baud_rate_of_the_uarts() {
+        assert_eq!(Uart::of("/dev/ttyAMA0"), Some(Uart::Pl011));
+        assert_eq!(Uart::of("/dev/ttyS0"), Some(Uart::Mini));
+        assert_eq!(Uart::of("/dev/ttyUSB0"), None);
+
+        let config = BootConfig::default();
+        let pl011 = Uart::Pl011.actual_baud_rate(config.clock(Uart::Pl011), 230_400);
+        assert_eq!(pl011, Some(230_492));
+        let mini = Uart::Mini.actual_baud_rate(config.clock(Uart::Mini), 230_400);
+        assert_eq!(mini, Some(229_779));
+        // Too fast for the clock.
+        assert_eq!(Uart::Pl011.actual_baud_rate(1_000_000, 230_400), None);
+        assert_eq!(Uart::Mini.actual_baud_rate(500_000, 230_400), None);
+    }
+
+    #[test]
+    fn parses_the_boot_configuration() {
+        let config = BootConfig::parse(
+            "# serial\n\
+             enable_uart=0\n\
+             [pi4]\n\
+             enable_uart = 1 # for the lidar\n\
+             core_freq=400\n\
+             init_uart_clock=nonsense\n",
+        );
+        assert_eq!(
+            config,
+            BootConfig {
+                enable_uart: true,
+                core_freq: Some(400),
+                init_uart_clock: None,
+            }
+        );
+        assert_eq!(config.clock(Uart::Mini), 400_000_000);
+        assert_eq!(config.clock(Uart::Pl011), DEFAULT_UART_CLOCK);
+    }
+
+    #[test]
+    fn finds_the_kernel_consoles() {
+        let cmdline = "console=serial0,115200 console=tty1 root=/dev/mmcblk0p2";
+        assert_eq!(console_devices(cmdline), ["serial0", "tty1"]);
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_uart() {
+        assert!(matches!(
+            check("/dev/null", 230_400),
+            Err(RpiError::NotUart(_))
+        ));
+        assert!(matches!(
+            check("/dev/null", 115_200),
+            Err(RpiError::Options(_))
+        ));
+    }
+}
@@ -0,0 +1,231 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Error policy of the read loop run by `LFCDLaser::run`.
+//!
+//! Failed reads are retried a few times, then the port is re-opened, and
+//! the loop gives up only after the configured number of reconnections in
+//! a row.
+//!
+//! The loop does not touch the signal handlers of the process unless
+//! `handle_sigint` is set, then the first Ctrl-C stops the loop, closing
+//! the lidar. With the `sync` and `async_smol` backends a second Ctrl-C
+//! kills the program as usual, the `async_tokio` backend waits on
+//! `tokio::signal::ctrl_c`, whose handler stays installed once the loop
+//! returns.
+//!
+//! ```no_run
+//! # #[cfg(feature = "sync")]
+//! # fn main() -> hls_lfcd_lds_driver::error::Result<()> {
+//! use hls_lfcd_lds_driver::run::RunPolicy;
+//! use hls_lfcd_lds_driver::sync::LFCDLaser;
+//!
+//! let laser = LFCDLaser::new("/dev/ttyUSB0", 230400)?;
+//! laser.run(|scan| println!("{} rpm", scan.rpms), RunPolicy::default())?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "sync"))]
+//! # fn main() {}
+//! ```
+
+use std::time::Duration;
+
+/// What the read loop does when reads fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunPolicy {
+    /// Failed reads in a row retried before re-opening the port
+    pub retries: u32,
+    /// Reconnections in a row before giving up, `None` to never give up
+    pub reconnects: Option<u32>,
+    /// Delay before every reconnection
+    pub reconnect_delay: Duration,
+    /// Time a read may take before it counts as failed until the RPMs are
+    /// known, then `protocol::TIMEOUT_REVOLUTIONS` revolutions, the `sync`
+    /// backend uses the timeout of its `SerialTuning` instead
+    pub read_timeout: Duration,
+    /// Stops the loop on SIGINT (Ctrl-C), off by default as it replaces the
+    /// handler of the whole process, only on unix but for `async_tokio`
+    pub handle_sigint: bool,
+}
+
+impl Default for RunPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            reconnects: Some(5),
+            reconnect_delay: Duration::from_secs(1),
+            read_timeout: Duration::from_secs(1),
+            handle_sigint: false,
+        }
+    }
+}
+
+/// Next step of the loop after a failed read.
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Recovery {
+    Retry,
+    Reconnect,
+    GiveUp,
+}
+
+/// Failures counted by the loop.
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+pub(crate) struct Runner {
+    policy: RunPolicy,
+    failures: u32,
+    reconnects: u32,
+}
+
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+impl Runner {
+    pub(crate) fn new(policy: RunPolicy) -> Self {
+        #[cfg(any(feature = "sync", feature = "async_smol"))]
+        if policy.handle_sigint {
+            sigint::install();
+        }
+        Self {
+            policy,
+            failures: 0,
+            reconnects: 0,
+        }
+    }
+
+    pub(crate) fn policy(&self) -> &RunPolicy {
+        &self.policy
+    }
+
+    /// Checks if a shutdown has been requested.
+    #[cfg(any(feature = "sync", feature = "async_smol"))]
+    pub(crate) fn stopped(&self) -> bool {
+        self.policy.handle_sigint && sigint::received()
+    }
+
+    pub(crate) fn succeeded(&mut self) {
+        self.failures = 0;
+        self.reconnects = 0;
+    }
+
+    /// Counts a failed read.
+    pub(crate) fn failed(&mut self) -> Recovery {
+        self.failures += 1;
+        if self.failures <= self.policy.retries {
+            return Recovery::Retry;
+        }
+        self.failures = 0;
+        if self.reconnect() {
+            Recovery::Reconnect
+        } else {
+            Recovery::GiveUp
+        }
+    }
+
+    /// Counts a reconnection, `false` once they are exhausted.
+    pub(crate) fn reconnect(&mut self) -> bool {
+        if self
+            .policy
+            .reconnects
+            .is_some_and(|max| self.reconnects >= max)
+        {
+            return false;
+        }
+        self.reconnects += 1;
+        true
+    }
+}
+
+#[cfg(all(unix, feature = "libc", any(feature = "sync", feature = "async_smol")))]
+mod sigint {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_sigint(_: libc::c_int) {
+        RECEIVED.store(true, Ordering::SeqCst);
+        // A second Ctrl-C kills the program.
+        // SAFETY: `signal` is async-signal-safe.
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+
+    pub(super) fn install() {
+        RECEIVED.store(false, Ordering::SeqCst);
+        // SAFETY: the handler only touches an atomic and calls `signal`.
+        unsafe {
+            libc::signal(libc::SIGINT, on_sigint as *const () as libc::sighandler_t);
+        }
+    }
+
+    pub(super) fn received() -> bool {
+        RECEIVED.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(all(
+    not(all(unix, feature = "libc")),
+    any(feature = "sync", feature = "async_smol")
+))]
+mod sigint {
+    pub(super) fn install() {}
+
+    pub(super) fn received() -> bool {
+        false
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "sync", feature = "async_tokio", feature = "async_smol")
+))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_then_reconnects_then_gives_up() {
+        let mut runner = Runner::new(RunPolicy {
+            retries: 2,
+            reconnects: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(runner.failed(), Recovery::Retry);
+        assert_eq!(runner.failed(), Recovery::Retry);
+        assert_eq!(runner.failed(), Recovery::Reconnect);
+        assert_eq!(runner.failed(), Recovery::Retry);
+        assert_eq!(runner.failed(), Recovery::Retry);
+        assert_eq!(runner.failed(), Recovery::GiveUp);
+    }
+
+    #[test]
+    fn starts_over_after_a_successful_read() {
+        let mut runner = Runner::new(RunPolicy {
+            retries: 0,
+            reconnects: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(runner.failed(), Recovery::Reconnect);
+        runner.succeeded();
+        assert_eq!(runner.failed(), Recovery::Reconnect);
+        assert_eq!(runner.failed(), Recovery::GiveUp);
+    }
+
+    #[test]
+    fn reconnects_forever_without_a_limit() {
+        let mut runner = Runner::new(RunPolicy {
+            reconnects: None,
+            ..Default::default()
+        });
+        assert!((0..1000).all(|_| runner.reconnect()));
+    }
+}
@@ -0,0 +1,565 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Monitoring of warning and protective zones around the lidar.
+//!
+//! This is meant for prototypes, the LDS-01 is not a safety rated sensor.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::safety::{SafetyMonitor, Zone, ZoneKind};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let mut monitor = SafetyMonitor::new()
+//!     .with_zone(Zone::sector("front", ZoneKind::Protective, -0.5, 0.5, 0.4))
+//!     .with_zone(Zone::sector("around", ZoneKind::Warning, 0.0, std::f32::consts::TAU, 1.0))
+//!     .with_min_points(3)
+//!     .with_debounce(2);
+//! monitor.on_event(|event| println!("{event:?}"));
+//!
+//! monitor.evaluate(&reading);
+//! assert!(!monitor.protective_stop());
+//! ```
+//!
+//! As industrial safety scanners do, a zone can also switch between several
+//! fields according to the speed of the robot, the faster the larger:
+//!
+//! ```
+//! use hls_lfcd_lds_driver::safety::{SafetyMonitor, SpeedField, ZoneKind, ZoneShape};
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let field = |radius| ZoneShape::Sector { start: -0.6, end: 0.6, radius };
+//! let mut monitor = SafetyMonitor::new();
+//! let zone = monitor.add_speed_scaled_zone(
+//!     "front",
+//!     ZoneKind::Protective,
+//!     vec![
+//!         SpeedField::new(0.1, field(0.2)),
+//!         SpeedField::new(0.3, field(0.5)),
+//!         SpeedField::new(0.6, field(1.0)),
+//!     ],
+//! );
+//!
+//! monitor.set_speed(0.25);
+//! assert_eq!(monitor.active_field(zone), Some(1));
+//! monitor.evaluate(&reading);
+//! ```
+
+use crate::LaserReading;
+use std::f32::consts::TAU;
+use std::fmt;
+
+/// Callback invoked for every event of the monitor.
+pub type SafetyCallback = Box<dyn FnMut(&SafetyEvent) + Send>;
+
+/// Kind of a zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ZoneKind {
+    /// An intrusion should slow the robot down
+    Warning,
+    /// An intrusion should stop the robot
+    Protective,
+}
+
+/// Shape of a zone, in the lidar frame: meters, x to the front, y to the left.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZoneShape {
+    /// A polygon, given by its vertices
+    Polygon(Vec<(f32, f32)>),
+    /// A circular sector, from `start` to `end` radians counter-clockwise
+    Sector { start: f32, end: f32, radius: f32 },
+}
+
+impl ZoneShape {
+    /// Checks if the point is inside the shape.
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        match self {
+            ZoneShape::Polygon(vertices) => {
+                // Ray casting: counts the edges crossed by a ray going to +x.
+                let mut inside = false;
+                let n = vertices.len();
+                for i in 0..n {
+                    let (xi, yi) = vertices[i];
+                    let (xj, yj) = vertices[(i + n - 1) % n];
+                    if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+            ZoneShape::Sector { start, end, radius } => {
+                if x.hypot(y) > *radius {
+                    return false;
+                }
+                let span = end - start;
+                if span >= TAU {
+                    return true;
+                }
+                (y.atan2(x) - start).rem_euclid(TAU) <= span.rem_euclid(TAU)
+            }
+        }
+    }
+}
+
+/// A field of a speed-scaled zone, active up to `max_speed`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeedField {
+    /// Speed, in m/s, up to which the field is active
+    pub max_speed: f32,
+    pub shape: ZoneShape,
+}
+
+impl SpeedField {
+    /// Creates a field active up to `max_speed` m/s.
+    pub fn new(max_speed: f32, shape: ZoneShape) -> Self {
+        Self { max_speed, shape }
+    }
+}
+
+/// A named zone monitored around the lidar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub name: String,
+    pub kind: ZoneKind,
+    pub shape: ZoneShape,
+}
+
+impl Zone {
+    /// Creates a polygonal zone.
+    pub fn polygon(name: &str, kind: ZoneKind, vertices: Vec<(f32, f32)>) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            shape: ZoneShape::Polygon(vertices),
+        }
+    }
+
+    /// Creates a sector zone, from `start` to `end` radians counter-clockwise
+    /// from the front of the lidar, up to `radius` meters.
+    pub fn sector(name: &str, kind: ZoneKind, start: f32, end: f32, radius: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            shape: ZoneShape::Sector { start, end, radius },
+        }
+    }
+}
+
+/// Event fired by the monitor when the state of a zone changes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SafetyEvent {
+    /// Something entered the zone
+    Intrusion {
+        /// Index of the zone, in insertion order
+        zone: usize,
+        kind: ZoneKind,
+        /// Number of valid beams inside the zone
+        points: usize,
+        /// Range, in meters, of the closest beam inside the zone
+        closest: f32,
+    },
+    /// The zone is clear again
+    Cleared { zone: usize, kind: ZoneKind },
+}
+
+/// Debounced state of a zone.
+#[derive(Debug, Clone)]
+struct ZoneState {
+    zone: Zone,
+    intruded: bool,
+    // Consecutive scans disagreeing with `intruded`.
+    pending: usize,
+    // Fields of a speed-scaled zone sorted by speed, empty for a fixed zone.
+    fields: Vec<SpeedField>,
+    active: usize,
+}
+
+impl ZoneState {
+    /// Selects the field for the given speed, the last one when faster than all.
+    fn select(&mut self, speed: f32) {
+        if self.fields.is_empty() {
+            return;
+        }
+        let speed = speed.abs();
+        self.active = self
+            .fields
+            .iter()
+            .position(|f| speed <= f.max_speed)
+            .unwrap_or(self.fields.len() - 1);
+        self.zone.shape = self.fields[self.active].shape.clone();
+    }
+}
+
+/// Evaluates every scan against a set of zones and fires events when
+/// an intrusion is detected or cleared.
+///
+/// An intrusion needs at least `min_points` beams inside the zone, and is
+/// reported, as well as its clearing, only after `debounce` consecutive scans.
+pub struct SafetyMonitor {
+    zones: Vec<ZoneState>,
+    min_points: usize,
+    debounce: usize,
+    speed: f32,
+    callbacks: Vec<SafetyCallback>,
+}
+
+impl Default for SafetyMonitor {
+    fn default() -> Self {
+        Self {
+            zones: Vec::new(),
+            min_points: 1,
+            debounce: 1,
+            speed: 0.0,
+            callbacks: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for SafetyMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SafetyMonitor")
+            .field("zones", &self.zones)
+            .field("min_points", &self.min_points)
+            .field("debounce", &self.debounce)
+            .field("speed", &self.speed)
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl SafetyMonitor {
+    /// Creates a monitor without zones, an intrusion needs one beam and one scan.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a zone.
+    pub fn with_zone(mut self, zone: Zone) -> Self {
+        self.add_zone(zone);
+        self
+    }
+
+    /// Sets the minimum number of beams inside a zone to consider it intruded.
+    pub fn with_min_points(mut self, min_points: usize) -> Self {
+        self.min_points = min_points.max(1);
+        self
+    }
+
+    /// Sets the number of consecutive scans needed to change the state of a zone.
+    pub fn with_debounce(mut self, scans: usize) -> Self {
+        self.debounce = scans.max(1);
+        self
+    }
+
+    /// Adds a zone and returns its index.
+    pub fn add_zone(&mut self, zone: Zone) -> usize {
+        self.zones.push(ZoneState {
+            zone,
+            intruded: false,
+            pending: 0,
+            fields: Vec::new(),
+            active: 0,
+        });
+        self.zones.len() - 1
+    }
+
+    /// Adds a zone switching between `fields` according to the speed of the
+    /// robot, and returns its index.
+    ///
+    /// The active field is the one with the lowest `max_speed` not below the
+    /// current speed, or the fastest one when the robot exceeds all of them.
+    ///
+    /// # Panics
+    /// Panics if `fields` is empty.
+    pub fn add_speed_scaled_zone(
+        &mut self,
+        name: &str,
+        kind: ZoneKind,
+        mut fields: Vec<SpeedField>,
+    ) -> usize {
+        assert!(!fields.is_empty(), "a speed-scaled zone needs a field");
+        fields.sort_by(|a, b| a.max_speed.total_cmp(&b.max_speed));
+        let mut state = ZoneState {
+            zone: Zone {
+                name: name.to_string(),
+                kind,
+                shape: fields[0].shape.clone(),
+            },
+            intruded: false,
+            pending: 0,
+            fields,
+            active: 0,
+        };
+        state.select(self.speed);
+        self.zones.push(state);
+        self.zones.len() - 1
+    }
+
+    /// Sets the current speed of the robot, in m/s, selecting the active
+    /// field of the speed-scaled zones. The sign is ignored.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+        for state in self.zones.iter_mut() {
+            state.select(speed);
+        }
+    }
+
+    /// Gets the current speed of the robot.
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Gets the index of the active field of a speed-scaled zone,
+    /// `None` for fixed zones.
+    pub fn active_field(&self, index: usize) -> Option<usize> {
+        self.zones
+            .get(index)
+            .filter(|s| !s.fields.is_empty())
+            .map(|s| s.active)
+    }
+
+    /// Gets the zone with the given index, with the shape of the active
+    /// field for speed-scaled zones.
+    pub fn zone(&self, index: usize) -> Option<&Zone> {
+        self.zones.get(index).map(|s| &s.zone)
+    }
+
+    /// Registers a callback invoked for every event.
+    pub fn on_event<F>(&mut self, f: F)
+    where
+        F: FnMut(&SafetyEvent) + Send + 'static,
+    {
+        self.callbacks.push(Box::new(f));
+    }
+
+    /// Checks if the zone with the given index is intruded.
+    pub fn is_intruded(&self, index: usize) -> bool {
+        self.zones.get(index).is_some_and(|s| s.intruded)
+    }
+
+    /// Checks if any warning zone is intruded.
+    pub fn warning(&self) -> bool {
+        self.any_intruded(ZoneKind::Warning)
+    }
+
+    /// Checks if any protective zone is intruded, i.e. the robot should stop.
+    pub fn protective_stop(&self) -> bool {
+        self.any_intruded(ZoneKind::Protective)
+    }
+
+    fn any_intruded(&self, kind: ZoneKind) -> bool {
+        self.zones.iter().any(|s| s.zone.kind == kind && s.intruded)
+    }
+
+    /// Evaluates a scan, invokes the callbacks and returns the fired events.
+    pub fn evaluate(&mut self, reading: &LaserReading) -> Vec<SafetyEvent> {
+        let points: Vec<(f32, f32)> = (0..reading.ranges.len())
+            .filter(|&i| reading.is_valid(i))
+            .map(|i| reading.point(i))
+            .collect();
+
+        let mut events = Vec::new();
+        for (index, state) in self.zones.iter_mut().enumerate() {
+            let inside: Vec<f32> = points
+                .iter()
+                .filter(|(x, y)| state.zone.shape.contains(*x, *y))
+                .map(|(x, y)| x.hypot(*y))
+                .collect();
+            let intruded = inside.len() >= self.min_points;
+
+            if intruded == state.intruded {
+                state.pending = 0;
+                continue;
+            }
+            state.pending += 1;
+            if state.pending < self.debounce {
+                continue;
+            }
+            state.intruded = intruded;
+            state.pending = 0;
+
+            let kind = state.zone.kind;
+            events.push(if intruded {
+                SafetyEvent::Intrusion {
+                    zone: index,
+                    kind,
+                    points: inside.len(),
+                    closest: inside.iter().copied().fold(f32::INFINITY, f32::min),
+                }
+            } else {
+                SafetyEvent::Cleared { zone: index, kind }
+            });
+        }
+
+        for event in &events {
+            for cb in self.callbacks.iter_mut() {
+                cb(event);
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scan with returns at the given degrees and ranges, in mm.
+    fn reading(returns: &[(usize, u16)]) -> LaserReading {
+        let mut reading = LaserReading::new();
+        for &(degree, range) in returns {
+            reading.ranges[degree] = range;
+        }
+        reading
+    }
+
+    #[test]
+    fn contains_the_points_of_a_polygon() {
+        // A concave L shape.
+        let shape = ZoneShape::Polygon(vec![
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+        ]);
+        assert!(shape.contains(0.5, 0.5));
+        assert!(shape.contains(1.5, 0.5));
+        assert!(shape.contains(0.5, 1.5));
+        assert!(!shape.contains(1.5, 1.5));
+        assert!(!shape.contains(-0.5, 0.5));
+        assert!(!shape.contains(2.5, 0.5));
+    }
+
+    #[test]
+    fn contains_the_points_of_a_sector() {
+        let front = ZoneShape::Sector {
+            start: -0.5,
+            end: 0.5,
+            radius: 1.0,
+        };
+        assert!(front.contains(0.9, 0.0));
+        assert!(front.contains(0.5, -0.2));
+        assert!(!front.contains(1.1, 0.0));
+        assert!(!front.contains(0.0, 0.5));
+        assert!(!front.contains(-0.5, 0.0));
+    }
+
+    #[test]
+    fn needs_min_points_inside_the_zone() {
+        let mut monitor = SafetyMonitor::new()
+            .with_zone(Zone::sector("front", ZoneKind::Protective, -0.5, 0.5, 1.0))
+            .with_min_points(3);
+
+        assert!(monitor.evaluate(&reading(&[(0, 500), (1, 500)])).is_empty());
+        assert!(!monitor.protective_stop());
+
+        let events = monitor.evaluate(&reading(&[(0, 500), (1, 400), (359, 600), (90, 500)]));
+        assert_eq!(
+            events,
+            [SafetyEvent::Intrusion {
+                zone: 0,
+                kind: ZoneKind::Protective,
+                points: 3,
+                closest: 0.4,
+            }]
+        );
+        assert!(monitor.protective_stop());
+        assert!(!monitor.warning());
+    }
+
+    #[test]
+    fn debounces_the_stop_and_the_clearing() {
+        let mut monitor = SafetyMonitor::new()
+            .with_zone(Zone::sector("front", ZoneKind::Protective, -0.5, 0.5, 1.0))
+            .with_debounce(2);
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        monitor.on_event(move |event| sink.lock().unwrap().push(event.clone()));
+
+        let intruded = reading(&[(0, 500)]);
+        let clear = reading(&[(180, 500)]);
+
+        // A single scan is a glitch, and restarts the count.
+        monitor.evaluate(&intruded);
+        monitor.evaluate(&clear);
+        monitor.evaluate(&intruded);
+        assert!(!monitor.protective_stop());
+        monitor.evaluate(&intruded);
+        assert!(monitor.protective_stop());
+
+        monitor.evaluate(&clear);
+        assert!(monitor.protective_stop());
+        monitor.evaluate(&clear);
+        assert!(!monitor.protective_stop());
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], SafetyEvent::Intrusion { zone: 0, .. }));
+        assert_eq!(
+            events[1],
+            SafetyEvent::Cleared {
+                zone: 0,
+                kind: ZoneKind::Protective
+            }
+        );
+    }
+
+    #[test]
+    fn switches_the_field_at_the_speed_boundaries() {
+        let field = |radius| ZoneShape::Sector {
+            start: -0.5,
+            end: 0.5,
+            radius,
+        };
+        let mut monitor = SafetyMonitor::new();
+        let fixed = monitor.add_zone(Zone::sector("around", ZoneKind::Warning, 0.0, TAU, 1.0));
+        // Given out of order on purpose.
+        let zone = monitor.add_speed_scaled_zone(
+            "front",
+            ZoneKind::Protective,
+            vec![
+                SpeedField::new(0.6, field(1.0)),
+                SpeedField::new(0.1, field(0.2)),
+                SpeedField::new(0.3, field(0.5)),
+            ],
+        );
+        assert_eq!(monitor.active_field(fixed), None);
+        assert_eq!(monitor.active_field(zone), Some(0));
+
+        for (speed, active) in [
+            (0.1, 0),
+            (0.100_01, 1),
+            (0.3, 1),
+            (-0.3, 1),
+            (0.31, 2),
+            (0.6, 2),
+            (2.0, 2),
+            (0.0, 0),
+        ] {
+            monitor.set_speed(speed);
+            assert_eq!(monitor.active_field(zone), Some(active), "at {speed} m/s");
+        }
+
+        // The shape of the active field is the one evaluated.
+        let obstacle = reading(&[(0, 400)]);
+        monitor.set_speed(0.1);
+        monitor.evaluate(&obstacle);
+        assert!(!monitor.protective_stop());
+        monitor.set_speed(0.3);
+        monitor.evaluate(&obstacle);
+        assert!(monitor.protective_stop());
+        assert_eq!(monitor.zone(zone).unwrap().shape, field(0.5));
+    }
+}
@@ -0,0 +1,450 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Destinations of the scans, the common extension point of the pipeline
+//! and of the publishers.
+//!
+//! A `ScanSink` consumes scans in a blocking fashion, an `AsyncScanSink`
+//! asynchronously; every `ScanSink` is an `AsyncScanSink` as well. Besides
+//! the sinks of this module, the channels, the publishers of the optional
+//! features, the `BlackBox` and the zstd log writer are sinks.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::sink::{CsvSink, ScanSink};
+//! use std::sync::mpsc;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let (mut tx, rx) = mpsc::channel();
+//! tx.consume(&reading)?;
+//! assert_eq!(rx.recv().unwrap().ranges, reading.ranges);
+//!
+//! let mut csv = CsvSink::new(Vec::new());
+//! csv.consume(&reading)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::LaserReading;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufWriter, Write};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A destination of the scans, consuming them in a blocking fashion.
+pub trait ScanSink: Send {
+    /// Handles a scan.
+    ///
+    /// # Errors
+    /// An error variant is returned if the scan cannot be handled.
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()>;
+
+    /// Flushes the buffered scans, e.g. before stopping.
+    ///
+    /// # Errors
+    /// An error variant is returned if the scans cannot be written.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A destination of the scans, consuming them asynchronously.
+pub trait AsyncScanSink: Send {
+    /// Handles a scan.
+    ///
+    /// # Errors
+    /// An error variant is returned if the scan cannot be handled.
+    fn consume(&mut self, reading: &LaserReading) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Flushes the buffered scans, e.g. before stopping.
+    ///
+    /// # Errors
+    /// An error variant is returned if the scans cannot be written.
+    fn flush(&mut self) -> impl Future<Output = io::Result<()>> + Send {
+        async { Ok(()) }
+    }
+}
+
+impl<S: ScanSink> AsyncScanSink for S {
+    fn consume(&mut self, reading: &LaserReading) -> impl Future<Output = io::Result<()>> + Send {
+        std::future::ready(ScanSink::consume(self, reading))
+    }
+
+    fn flush(&mut self) -> impl Future<Output = io::Result<()>> + Send {
+        std::future::ready(ScanSink::flush(self))
+    }
+}
+
+impl<S: ScanSink + ?Sized> ScanSink for &mut S {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        (**self).consume(reading)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+impl<S: ScanSink + ?Sized> ScanSink for Box<S> {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        (**self).consume(reading)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+impl ScanSink for mpsc::Sender<LaserReading> {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        self.send(reading.clone())
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+impl ScanSink for mpsc::SyncSender<LaserReading> {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        self.send(reading.clone())
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+#[cfg(feature = "async_tokio")]
+impl AsyncScanSink for ::tokio::sync::mpsc::Sender<LaserReading> {
+    fn consume(&mut self, reading: &LaserReading) -> impl Future<Output = io::Result<()>> + Send {
+        let reading = reading.clone();
+        async move {
+            self.send(reading)
+                .await
+                .map_err(|_| io::ErrorKind::BrokenPipe.into())
+        }
+    }
+}
+
+/// Writes the scans as CSV, one line per scan: the timestamp in ns since the
+/// epoch, the rpms, the ranges and the intensities.
+#[derive(Debug)]
+pub struct CsvSink<W: Write + Send> {
+    out: W,
+    header: bool,
+}
+
+impl CsvSink<BufWriter<File>> {
+    /// Creates a CSV file, replacing it if it exists.
+    ///
+    /// # Errors
+    /// An error variant is returned if the file cannot be created.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Send> CsvSink<W> {
+    /// Creates a sink writing to `out`, starting with the header line.
+    pub fn new(out: W) -> Self {
+        Self { out, header: true }
+    }
+}
+
+impl<W: Write + Send> ScanSink for CsvSink<W> {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        let n = reading.ranges.len();
+        if std::mem::take(&mut self.header) {
+            write!(self.out, "timestamp,rpms")?;
+            for i in 0..n {
+                write!(self.out, ",range_{i}")?;
+            }
+            for i in 0..n {
+                write!(self.out, ",intensity_{i}")?;
+            }
+            writeln!(self.out)?;
+        }
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        write!(self.out, "{nanos},{}", reading.rpms)?;
+        for v in reading.ranges.iter().chain(&reading.intensities) {
+            write!(self.out, ",{v}")?;
+        }
+        writeln!(self.out)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Sends every scan in a UDP datagram: the rpms, the ranges and the
+/// intensities as little-endian `u16`, 1442 bytes.
+#[derive(Debug)]
+pub struct UdpSink {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+}
+
+impl UdpSink {
+    /// Creates a sink sending to `addr`, from an ephemeral port.
+    ///
+    /// # Errors
+    /// An error variant is returned if the socket cannot be bound or connected.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(addr)?;
+        Ok(Self::new(socket))
+    }
+
+    /// Creates a sink sending through a connected socket.
+    pub fn new(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl ScanSink for UdpSink {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        self.buf.clear();
+        self.buf.extend_from_slice(&reading.rpms.to_le_bytes());
+        for v in reading.ranges.iter().chain(&reading.intensities) {
+            self.buf.extend_from_slice(&v.to_le_bytes());
+        }
+        self.socket.send(&self.buf).map(|_| ())
+    }
+}
+
+/// Writes the scans to the standard output as CSV, see `CsvSink`, e.g. to
+/// pipe them to another program.
+#[derive(Debug)]
+pub struct StdoutSink {
+    csv: CsvSink<io::Stdout>,
+}
+
+impl StdoutSink {
+    /// Creates a sink writing to the standard output, starting with the header line.
+    pub fn new() -> Self {
+        Self {
+            csv: CsvSink::new(io::stdout()),
+        }
+    }
+}
+
+impl Default for StdoutSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScanSink for StdoutSink {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        ScanSink::consume(&mut self.csv, reading)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        ScanSink::flush(&mut self.csv)
+    }
+}
+
+/// Counts the bytes written to a file.
+#[derive(Debug)]
+struct Counted {
+    file: BufWriter<File>,
+    written: u64,
+}
+
+impl Write for Counted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Writes the scans to CSV files of bounded size, see `CsvSink`, keeping
+/// only the most recent ones, e.g. for logging on a robot with a small disk.
+///
+/// The files are `<prefix>-<timestamp>-<n>.csv` in the given directory,
+/// the timestamp being the creation of the sink in seconds since the epoch.
+#[derive(Debug)]
+pub struct RotatingFileSink {
+    dir: PathBuf,
+    prefix: String,
+    stamp: u64,
+    max_bytes: u64,
+    keep: usize,
+    next: u64,
+    files: VecDeque<PathBuf>,
+    current: Option<CsvSink<Counted>>,
+}
+
+impl RotatingFileSink {
+    /// Creates a sink starting a new file once the current one reaches
+    /// `max_bytes`, and deleting the oldest files beyond `keep`.
+    ///
+    /// # Errors
+    /// An error variant is returned if the directory cannot be created.
+    pub fn new<P: AsRef<Path>>(
+        dir: P,
+        prefix: &str,
+        max_bytes: u64,
+        keep: usize,
+    ) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            prefix: prefix.into(),
+            stamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            max_bytes,
+            keep: keep.max(1),
+            next: 0,
+            files: VecDeque::new(),
+            current: None,
+        })
+    }
+
+    /// Gets the files written by this sink, oldest first.
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(PathBuf::as_path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut current) = self.current.take() {
+            ScanSink::flush(&mut current)?;
+        }
+        let path = self
+            .dir
+            .join(format!("{}-{}-{}.csv", self.prefix, self.stamp, self.next));
+        self.next += 1;
+        let file = File::create(&path)?;
+        self.files.push_back(path);
+        while self.files.len() > self.keep {
+            if let Some(old) = self.files.pop_front() {
+                std::fs::remove_file(old)?;
+            }
+        }
+        self.current = Some(CsvSink::new(Counted {
+            file: BufWriter::new(file),
+            written: 0,
+        }));
+        Ok(())
+    }
+}
+
+impl ScanSink for RotatingFileSink {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        let full = match &self.current {
+            Some(csv) => csv.out.written >= self.max_bytes,
+            None => true,
+        };
+        if full {
+            self.rotate()?;
+        }
+        match &mut self.current {
+            Some(csv) => ScanSink::consume(csv, reading),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(csv) => ScanSink::flush(csv),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_csv_header_once() {
+        let mut out = Vec::new();
+        let mut sink = CsvSink::new(&mut out);
+        let mut reading = LaserReading::new();
+        reading.rpms = 300;
+        reading.ranges[0] = 1000;
+        ScanSink::consume(&mut sink, &reading).unwrap();
+        ScanSink::consume(&mut sink, &reading).unwrap();
+
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("timestamp,rpms,range_0,range_1,"));
+        assert!(lines[0].ends_with(",intensity_359"));
+        assert!(lines[1].split(',').skip(1).take(3).eq(["300", "1000", "0"]));
+        assert_eq!(lines[1].split(',').count(), 2 + 2 * 360);
+    }
+
+    #[test]
+    fn sends_the_scans_over_udp() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sink = UdpSink::connect(receiver.local_addr().unwrap()).unwrap();
+        let mut reading = LaserReading::new();
+        reading.rpms = 300;
+        reading.intensities[359] = 7;
+        ScanSink::consume(&mut sink, &reading).unwrap();
+
+        let mut buf = [0u8; 2048];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(n, 2 + 4 * 360);
+        assert_eq!(buf[..2], 300u16.to_le_bytes());
+        assert_eq!(buf[n - 2..n], 7u16.to_le_bytes());
+    }
+
+    #[test]
+    fn rotates_and_deletes_the_oldest_files() {
+        let dir = std::env::temp_dir().join(format!("lds-sink-{}", std::process::id()));
+        // Every scan fills a file.
+        let mut sink = RotatingFileSink::new(&dir, "scans", 1, 2).unwrap();
+        for _ in 0..4 {
+            ScanSink::consume(&mut sink, &LaserReading::new()).unwrap();
+        }
+        ScanSink::flush(&mut sink).unwrap();
+
+        let files: Vec<_> = sink.files().map(Path::to_path_buf).collect();
+        let mut on_disk: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        on_disk.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].to_string_lossy().ends_with("-2.csv"));
+        assert!(files[1].to_string_lossy().ends_with("-3.csv"));
+        assert_eq!(on_disk, files);
+    }
+
+    #[test]
+    fn forwards_to_a_channel() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let mut sink: Box<dyn ScanSink> = Box::new(tx);
+        ScanSink::consume(&mut sink, &LaserReading::new()).unwrap();
+        assert!(rx.try_recv().is_ok());
+        drop(rx);
+        let err = ScanSink::consume(&mut sink, &LaserReading::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
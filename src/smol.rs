@@ -0,0 +1,477 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Driver based on `mio-serial` and `smol`, enabled by the `async_smol` feature.
+
+use crate::common::{Command, Commands, Core, Held};
+use crate::error::{Error, Result};
+use crate::io;
+use crate::options::{Model, Open, OpenOptions};
+use crate::protocol::FRAME_SIZE;
+use crate::run::{Recovery, RunPolicy, Runner};
+use crate::state::{DriverEvent, DriverState};
+use crate::{AsyncLidarDriver, LaserReading};
+use ::smol::Async;
+use futures::lock::{Mutex, MutexGuard};
+use mio_serial::{SerialPort, SerialPortBuilderExt, SerialStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// This struct allows to read lidar information and to "shutdown" the driver
+pub struct LFCDLaser {
+    core: Core,
+    serial: Async<SerialStream>,
+}
+
+impl_common!(LFCDLaser);
+
+/// Period at which `run` checks for a shutdown request.
+const STOP_POLL: Duration = Duration::from_millis(100);
+
+impl LFCDLaser {
+    /// Creates a new `LFCDLaser` with the given parameters.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn new<P: AsRef<Path>>(port: P, baud_rate: u32) -> Result<Self> {
+        Self::open_unchecked(&OpenOptions::new(port, baud_rate))
+    }
+
+    /// Creates a new `LFCDLaser` as the options say, see `OpenOptions::open`.
+    /// The timeout is ignored, see `RunPolicy::read_timeout`.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - invalid options, the port is not touched
+    /// - unable to open the specified serial port
+    /// - unable to set the exclusivity of the port (only on unix)
+    pub fn with_options(options: &OpenOptions) -> Result<Self> {
+        options.open()
+    }
+
+    fn open_with(options: &OpenOptions) -> Result<Self> {
+        let serial = Self::open(&options.port, options.baud_rate, options.exclusive)?;
+
+        let mut core = Core::new(options.port.clone(), options.baud_rate);
+        core.exclusive = options.exclusive;
+        let mut lidar = Self { core, serial };
+
+        if options.auto_start {
+            lidar.start();
+        }
+
+        Ok(lidar)
+    }
+
+    /// Opens the port of a configuration, see `with_config`.
+    #[cfg(feature = "config")]
+    fn open_config(config: &crate::config::Config) -> Result<Self> {
+        Self::new(&config.port, config.baud_rate)
+    }
+
+    /// Re-opens the serial port and starts the lidar again,
+    /// notifying the `on_reconnect` callbacks.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn reconnect(&mut self) -> Result<()> {
+        self.serial = Self::open(&self.core.port, self.core.baud_rate, self.core.exclusive)?;
+        self.start();
+        // The idle stop writes to the previous port.
+        #[cfg(unix)]
+        if let Some(options) = self.idle_stop() {
+            self.set_idle_stop(Some(options))?;
+        }
+        self.core
+            .hooks
+            .emit_reconnect(&self.core.port.to_string_lossy());
+        self.core.hooks.emit_event(DriverEvent::Reconnected);
+
+        Ok(())
+    }
+
+    /// Gets a reading from the lidar, returing a `LaserReading` object.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub async fn read(&mut self) -> Result<LaserReading> {
+        if self.core.closed() {
+            return Err(Error::DriverClosed);
+        }
+
+        let _busy = self.wake_up();
+        loop {
+            self.apply_commands()?;
+            if let Some(wait) = self.pause_duty() {
+                ::smol::Timer::after(wait).await;
+                continue;
+            }
+            self.resume_duty();
+            let scan = self.read_watched().await?;
+            if self.in_window() {
+                return Ok(scan);
+            }
+        }
+    }
+
+    /// Reads a scan, restarting the motor when the watchdog says so.
+    async fn read_watched(&mut self) -> Result<LaserReading> {
+        loop {
+            let Some(deadline) = self.core.watchdog.as_ref().map(|w| w.deadline()) else {
+                return self.read_scan().await;
+            };
+            let read = async { Some(self.read_scan().await) };
+            let expired = async {
+                ::smol::Timer::at(deadline).await;
+                None
+            };
+            match ::smol::future::or(read, expired).await {
+                Some(read) => return read,
+                None => {
+                    if let Some(pause) = self.watchdog_timeout().map_err(|e| self.core.fail(e))? {
+                        ::smol::Timer::after(pause).await;
+                        self.restart_stalled();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads frames until one completes a scan.
+    async fn read_scan(&mut self) -> Result<LaserReading> {
+        loop {
+            self.apply_commands()?;
+            if let Some(scan) = self.read_frame().await.map_err(|e| self.core.fail(e))? {
+                return Ok(scan);
+            }
+        }
+    }
+
+    /// Gets the scan of the newest complete revolution: the scans waiting in
+    /// the serial buffer are dropped, for control loops where acting on old
+    /// data is worse than skipping some. Reads the next one when none is
+    /// waiting.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub async fn read_latest(&mut self) -> Result<LaserReading> {
+        if self.core.closed() {
+            return Err(Error::DriverClosed);
+        }
+
+        let backlog = self
+            .serial
+            .get_ref()
+            .bytes_to_read()
+            .map_err(|e| self.core.fail(e.into()))? as usize;
+        if backlog >= FRAME_SIZE {
+            let mut bytes = vec![0u8; backlog];
+            let mut filled = 0;
+            let read = io::read_full_async(&mut self.serial, &mut bytes, &mut filled).await;
+            self.core.hooks.emit_bytes(&bytes[..filled]);
+            read.map_err(|e| self.core.fail(e.into()))?;
+            if self.core.latest(&bytes) {
+                if let Some(scan) = self.core.decode() {
+                    return Ok(scan);
+                }
+            }
+        }
+        self.read().await
+    }
+
+    /// Reads and decodes a frame, `None` for the scans of the warm-up.
+    async fn read_frame(&mut self) -> Result<Option<LaserReading>> {
+        // A corrupted frame may have left the beginning of this one.
+        let mut start_count = self.core.resume();
+
+        // Wait for data sync of frame: 0xFA, 0XA0
+        while start_count < 2 {
+            // Read one byte
+            let mut filled = start_count;
+            let read = io::read_full_async(
+                &mut self.serial,
+                &mut self.core.buff[..=start_count],
+                &mut filled,
+            )
+            .await;
+            self.core.tee(start_count, filled);
+            read?;
+            self.core.sync(&mut start_count)?;
+        }
+        let mut filled = start_count;
+        let read = io::read_full_async(&mut self.serial, &mut self.core.buff, &mut filled).await;
+        self.core.tee(start_count, filled);
+        if let Err(e) = read {
+            // The next read completes the frame.
+            self.core.keep(filled);
+            return Err(e.into());
+        }
+
+        Ok(self.core.decode())
+    }
+
+    /// Gets a reading from the lidar, failing with `Error::Timeout` if the
+    /// scan is not complete by `deadline`, e.g. to implement a watchdog.
+    ///
+    /// A timed out read discards the partial frame, the next read synchronizes
+    /// again on the following one.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - no scan before the deadline
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub async fn read_deadline(&mut self, deadline: Instant) -> Result<LaserReading> {
+        let timeout = async {
+            ::smol::Timer::at(deadline).await;
+            None
+        };
+        match ::smol::future::or(async { Some(self.read().await) }, timeout).await {
+            Some(read) => read,
+            None => Err(self.core.fail(Error::Timeout)),
+        }
+    }
+
+    /// Gets `n` consecutive readings, amortizing the per-call overhead
+    /// for consumers processing scans in blocks.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    ///
+    /// The readings completed before the error are discarded.
+    pub async fn read_batch(&mut self, n: usize) -> Result<Vec<LaserReading>> {
+        let mut scans = Vec::with_capacity(n);
+        for _ in 0..n {
+            scans.push(self.read().await?);
+        }
+        Ok(scans)
+    }
+
+    /// Reads scans, passing them to `handler`, recovering from failed reads
+    /// as `policy` says, until SIGINT if `policy.handle_sigint` is set.
+    /// Then the lidar is stopped and the serial port closed.
+    ///
+    /// # Errors
+    /// An error variant is returned once the policy gives up, the lidar is
+    /// stopped and the port closed as well.
+    pub async fn run<F>(mut self, mut handler: F, policy: RunPolicy) -> Result<()>
+    where
+        F: FnMut(LaserReading),
+    {
+        let mut runner = Runner::new(policy);
+        loop {
+            // A read may wait for the next window of the duty cycle.
+            let off = self
+                .duty_cycle()
+                .map_or(Duration::ZERO, |d| d.period.saturating_sub(d.scanning));
+            let timeout = self.core.read_timeout(runner.policy().read_timeout) + off;
+            let res = {
+                let read = self.read_deadline(Instant::now() + timeout);
+                let stop = async {
+                    while !runner.stopped() {
+                        ::smol::Timer::after(STOP_POLL).await;
+                    }
+                    None
+                };
+                ::smol::future::or(async { Some(read.await) }, stop).await
+            };
+            let err = match res {
+                None => break,
+                Some(Ok(scan)) => {
+                    runner.succeeded();
+                    handler(scan);
+                    continue;
+                }
+                Some(Err(e)) => e,
+            };
+            match runner.failed() {
+                Recovery::Retry => {}
+                Recovery::GiveUp => return Err(err),
+                Recovery::Reconnect => loop {
+                    ::smol::Timer::after(runner.policy().reconnect_delay).await;
+                    match self.reconnect() {
+                        Ok(()) => break,
+                        Err(e) if !runner.reconnect() => return Err(e),
+                        Err(_) => {}
+                    }
+                },
+            }
+        }
+
+        // Dropping the driver stops the lidar and closes the port.
+        Ok(())
+    }
+
+    fn open(port: &Path, baud_rate: u32, exclusive: bool) -> Result<Async<SerialStream>> {
+        let mut serial = mio_serial::new(io::port_name(port)?, baud_rate).open_native_async()?;
+
+        #[cfg(unix)]
+        serial.set_exclusive(exclusive)?;
+        #[cfg(not(unix))]
+        let _ = exclusive;
+
+        // Wrapping into smol::Async to make it "async", similar to what tokio-serial does.
+        Ok(Async::new(serial)?)
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        std::io::Write::write_all(&mut self.serial.get_mut(), &[byte]).ok();
+    }
+
+    fn clear_input(&mut self) -> std::io::Result<()> {
+        Ok(self
+            .serial
+            .get_ref()
+            .clear(mio_serial::ClearBuffer::Input)?)
+    }
+}
+
+impl Open for LFCDLaser {
+    type Error = Error;
+
+    const DRIVER: &'static str = "smol";
+
+    const MODELS: &'static [Model] = &[Model::Lds01];
+
+    fn open_unchecked(options: &OpenOptions) -> Result<Self> {
+        Self::open_with(options)
+    }
+}
+
+impl AsyncLidarDriver for LFCDLaser {
+    type Error = Error;
+
+    async fn read(&mut self) -> Result<LaserReading> {
+        LFCDLaser::read(self).await
+    }
+
+    fn start(&mut self) {
+        LFCDLaser::start(self)
+    }
+
+    fn close(&mut self) {
+        LFCDLaser::close(self)
+    }
+}
+
+/// A clonable handle to a `LFCDLaser`, that can be shared among tasks.
+///
+/// Reads are serialized. `start` and `stop` do not wait for the ongoing
+/// read, if any: it applies them at the end of the frame being read, within
+/// a revolution, or when the next window opens while paused by a duty
+/// cycle, then fails with `DriverClosed` after a `stop`. The other methods
+/// wait for the ongoing read to complete.
+#[derive(Clone)]
+pub struct LFCDLaserHandle {
+    inner: Arc<Mutex<LFCDLaser>>,
+    commands: Arc<std::sync::Mutex<Commands>>,
+}
+
+impl LFCDLaserHandle {
+    /// Creates a new handle owning the given driver.
+    pub fn new(mut laser: LFCDLaser) -> Self {
+        let commands = laser
+            .core
+            .commands
+            .get_or_insert_with(Default::default)
+            .clone();
+        Self {
+            inner: Arc::new(Mutex::new(laser)),
+            commands,
+        }
+    }
+
+    async fn lock(&self) -> Held<'_, MutexGuard<'_, LFCDLaser>> {
+        Held::new(self.inner.lock().await, &self.commands)
+    }
+
+    /// Has the read apply `command` if one is ongoing, else holds the driver
+    /// to apply it.
+    async fn send(&self, command: Command) {
+        if !Commands::send(&self.commands, command) {
+            // Holding the driver applies the queued commands, if a read took
+            // it first this waits for the read, which applied them already.
+            drop(self.lock().await);
+        }
+    }
+
+    /// Gets a reading from the lidar, waiting for other readers to complete.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub async fn read(&self) -> Result<LaserReading> {
+        self.lock().await.read().await
+    }
+
+    /// Starts the Lidar, without waiting for the ongoing read.
+    pub async fn start(&self) {
+        self.send(Command::Start).await
+    }
+
+    /// Stops the lidar, without waiting for the ongoing read, reads will fail
+    /// until `start` is called.
+    pub async fn stop(&self) {
+        self.send(Command::Stop).await
+    }
+
+    /// Re-opens the serial port and starts the lidar again.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub async fn reconnect(&self) -> Result<()> {
+        self.lock().await.reconnect()
+    }
+
+    /// Gets the lidars rmp from the last reading
+    pub async fn rpms(&self) -> u16 {
+        self.lock().await.rpms()
+    }
+
+    /// Gets the state of the driver.
+    pub async fn state(&self) -> DriverState {
+        self.lock().await.state()
+    }
+
+    /// Gets the configured serial port
+    pub async fn port(&self) -> PathBuf {
+        self.lock().await.port().to_path_buf()
+    }
+
+    /// Runs `f` with exclusive access to the driver.
+    pub async fn with<R>(&self, f: impl FnOnce(&mut LFCDLaser) -> R) -> R {
+        f(&mut *self.lock().await)
+    }
+}
+
+impl LFCDLaser {
+    /// Converts the driver into a clonable `LFCDLaserHandle`.
+    pub fn into_handle(self) -> LFCDLaserHandle {
+        LFCDLaserHandle::new(self)
+    }
+}
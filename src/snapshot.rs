@@ -0,0 +1,155 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Lock-free snapshot of the latest scan, for real-time loops that cannot
+//! block on a mutex held by the reader thread.
+//!
+//! The scans are published alternately in two buffers guarded by a
+//! sequence counter: a read copies the last complete buffer while the
+//! writer fills the other one, so it only retries if two scans are
+//! published while it is copying, which at 5 Hz does not happen. The
+//! writer never waits for the readers.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::snapshot;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let (mut writer, snapshot) = snapshot::channel();
+//! writer.publish(&reading);
+//!
+//! // In the control loop
+//! if let Some(scan) = snapshot.read() {
+//!     assert_eq!(scan.ranges, reading.ranges);
+//! }
+//! ```
+
+use crate::{Hooks, LaserReading, BEAMS};
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A scan stored as atomics, every beam packs its range in the high half
+/// and its intensity in the low half.
+struct Buffer {
+    rpms: AtomicU32,
+    beams: [AtomicU32; BEAMS],
+}
+
+impl Buffer {
+    fn new() -> Self {
+        Self {
+            rpms: AtomicU32::new(0),
+            beams: std::array::from_fn(|_| AtomicU32::new(0)),
+        }
+    }
+}
+
+struct Shared {
+    // Twice the number of published scans, plus one while a scan is being written.
+    seq: AtomicU64,
+    buffers: [Buffer; 2],
+}
+
+/// Publishing side of a snapshot, there is only one per snapshot.
+///
+/// The sequence counter only works with a single writer, hence `publish`
+/// takes it mutably and the writer cannot be cloned.
+pub struct SnapshotWriter {
+    shared: Arc<Shared>,
+}
+
+/// Reading side of a snapshot, clonable and wait-free in practice.
+#[derive(Clone)]
+pub struct ScanSnapshot {
+    shared: Arc<Shared>,
+}
+
+/// Creates an empty snapshot, returning its writer and a reader.
+pub fn channel() -> (SnapshotWriter, ScanSnapshot) {
+    let shared = Arc::new(Shared {
+        seq: AtomicU64::new(0),
+        buffers: [Buffer::new(), Buffer::new()],
+    });
+    (
+        SnapshotWriter {
+            shared: shared.clone(),
+        },
+        ScanSnapshot { shared },
+    )
+}
+
+impl ScanSnapshot {
+    /// Creates a snapshot updated by the `on_scan` callbacks of `hooks`.
+    pub fn attach(hooks: &mut Hooks) -> Self {
+        let (mut writer, snapshot) = channel();
+        hooks.on_scan(move |reading| writer.publish(reading));
+        snapshot
+    }
+
+    /// Gets the number of scans published so far, to detect a new one.
+    pub fn sequence(&self) -> u64 {
+        self.shared.seq.load(Ordering::Acquire) / 2
+    }
+
+    /// Copies the latest scan, `None` if no scan has been published yet.
+    pub fn read(&self) -> Option<LaserReading> {
+        self.read_with_sequence().map(|(_, reading)| reading)
+    }
+
+    /// Copies the latest scan together with its sequence number.
+    pub fn read_with_sequence(&self) -> Option<(u64, LaserReading)> {
+        let mut reading = LaserReading::new();
+        loop {
+            let start = self.shared.seq.load(Ordering::Acquire);
+            let published = start / 2;
+            if published == 0 {
+                return None;
+            }
+
+            let buffer = &self.shared.buffers[(published % 2) as usize];
+            reading.rpms = buffer.rpms.load(Ordering::Relaxed) as u16;
+            for (i, beam) in buffer.beams.iter().enumerate() {
+                let beam = beam.load(Ordering::Relaxed);
+                reading.ranges[i] = (beam >> 16) as u16;
+                reading.intensities[i] = beam as u16;
+            }
+
+            fence(Ordering::Acquire);
+            // The buffer is rewritten only when the writer starts the scan after the next.
+            if self.shared.seq.load(Ordering::Relaxed) <= 2 * published + 2 {
+                return Some((published, reading));
+            }
+        }
+    }
+}
+
+impl SnapshotWriter {
+    /// Publishes a scan, replacing the previous one.
+    pub fn publish(&mut self, reading: &LaserReading) {
+        let seq = self.shared.seq.load(Ordering::Relaxed);
+        let next = seq / 2 + 1;
+        self.shared.seq.store(2 * next - 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        let buffer = &self.shared.buffers[(next % 2) as usize];
+        buffer
+            .rpms
+            .store(u32::from(reading.rpms), Ordering::Relaxed);
+        for (i, beam) in buffer.beams.iter().enumerate() {
+            let packed = u32::from(reading.ranges[i]) << 16 | u32::from(reading.intensities[i]);
+            beam.store(packed, Ordering::Relaxed);
+        }
+
+        self.shared.seq.store(2 * next, Ordering::Release);
+    }
+}
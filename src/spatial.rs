@@ -0,0 +1,175 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Grid index of the points of a scan, for the planners querying the
+//! obstacles around many candidate poses every cycle.
+//!
+//! The points are bucketed in square cells, a radius query only looks at
+//! the cells the circle touches instead of the 360 beams.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::spatial::ScanIndex;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let index = ScanIndex::new(&reading);
+//! for point in index.within(1.0, 0.0, 0.5) {
+//!     println!("beam {} at ({}, {})", point.beam, point.x, point.y);
+//! }
+//! let blocked = index.any_within(0.3, 0.0, 0.2);
+//! ```
+
+use crate::LaserReading;
+use std::collections::HashMap;
+
+/// Default side of the cells, in meters.
+pub const DEFAULT_CELL: f32 = 0.25;
+
+/// A valid beam of the scan, coordinates in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexedPoint {
+    /// Index of the beam in `LaserReading::ranges`
+    pub beam: usize,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Points of a scan bucketed in square cells, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct ScanIndex {
+    cell: f32,
+    points: Vec<IndexedPoint>,
+    /// Positions in `points` of the points of every cell.
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl ScanIndex {
+    /// Creates the index of the valid beams of a scan, with cells of
+    /// `DEFAULT_CELL` m.
+    pub fn new<const N: usize>(reading: &LaserReading<N>) -> Self {
+        Self::with_cell(reading, DEFAULT_CELL)
+    }
+
+    /// Creates the index of the valid beams of a scan, with cells of `cell`
+    /// m. Cells about the radius of the queries are the fastest.
+    pub fn with_cell<const N: usize>(reading: &LaserReading<N>, cell: f32) -> Self {
+        let points = (0..N)
+            .filter(|&i| reading.is_valid(i))
+            .map(|beam| {
+                let (x, y) = reading.point(beam);
+                IndexedPoint { beam, x, y }
+            })
+            .collect();
+        Self::from_points(points, cell)
+    }
+
+    /// Creates the index of any points, e.g. already moved to the frame of
+    /// the robot.
+    pub fn from_points(points: Vec<IndexedPoint>, cell: f32) -> Self {
+        let cell = if cell > 0.0 { cell } else { DEFAULT_CELL };
+        let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (n, p) in points.iter().enumerate() {
+            cells.entry(cell_of(p.x, p.y, cell)).or_default().push(n);
+        }
+        Self {
+            cell,
+            points,
+            cells,
+        }
+    }
+
+    /// Gets the points indexed.
+    pub fn points(&self) -> &[IndexedPoint] {
+        &self.points
+    }
+
+    /// Gets the number of points indexed.
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Checks if no point is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Gets the points within `radius` m of `(x, y)`, in no particular order.
+    pub fn within(&self, x: f32, y: f32, radius: f32) -> impl Iterator<Item = &IndexedPoint> {
+        let (min_x, min_y) = cell_of(x - radius, y - radius, self.cell);
+        let (max_x, max_y) = cell_of(x + radius, y + radius, self.cell);
+        let radius2 = radius * radius;
+        (min_x..=max_x)
+            .flat_map(move |cx| (min_y..=max_y).map(move |cy| (cx, cy)))
+            .filter_map(|c| self.cells.get(&c))
+            .flatten()
+            .map(|&n| &self.points[n])
+            .filter(move |p| (p.x - x).powi(2) + (p.y - y).powi(2) <= radius2)
+    }
+
+    /// Checks if a point is within `radius` m of `(x, y)`.
+    pub fn any_within(&self, x: f32, y: f32, radius: f32) -> bool {
+        self.within(x, y, radius).next().is_some()
+    }
+
+    /// Gets the number of points within `radius` m of `(x, y)`.
+    pub fn count_within(&self, x: f32, y: f32, radius: f32) -> usize {
+        self.within(x, y, radius).count()
+    }
+}
+
+/// Gets the cell holding `(x, y)`.
+fn cell_of(x: f32, y: f32, cell: f32) -> (i32, i32) {
+    ((x / cell).floor() as i32, (y / cell).floor() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixtures;
+
+    #[test]
+    fn finds_the_same_points_as_a_full_search() {
+        let room = &fixtures()[0].expected;
+        let index = ScanIndex::with_cell(room, 0.3);
+        assert_eq!(index.len(), room.points().len());
+
+        for (x, y) in [(1.0, 0.0), (-0.9, -0.9), (0.0, 2.0), (5.0, 5.0)] {
+            for radius in [0.1, 0.5, 1.5] {
+                let mut found: Vec<usize> = index.within(x, y, radius).map(|p| p.beam).collect();
+                found.sort_unstable();
+                let expected: Vec<usize> = index
+                    .points()
+                    .iter()
+                    .filter(|p| (p.x - x).hypot(p.y - y) <= radius)
+                    .map(|p| p.beam)
+                    .collect();
+                assert_eq!(found, expected, "within {radius} m of ({x}, {y})");
+                assert_eq!(index.count_within(x, y, radius), expected.len());
+            }
+        }
+        assert!(index.any_within(-1.0, 0.0, 0.05));
+        assert!(!index.any_within(0.0, 0.0, 0.5));
+    }
+
+    #[test]
+    fn falls_back_to_the_default_cell() {
+        let point = IndexedPoint {
+            beam: 0,
+            x: -0.1,
+            y: 0.1,
+        };
+        let index = ScanIndex::from_points(vec![point], 0.0);
+        assert_eq!(index.within(0.0, 0.0, 0.2).collect::<Vec<_>>(), [&point]);
+        assert!(ScanIndex::new(&LaserReading::new()).is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! What a driver is doing, see `LFCDLaser::state`, and the changes of its
+//! state, see `LFCDLaser::subscribe`.
+//!
+//! The events are delivered apart from the scans, so that supervisory code
+//! notices a lost sync or a reconnection without sitting on the data path.
+//!
+//! ```no_run
+//! # #[cfg(feature = "sync")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use hls_lfcd_lds_driver::state::DriverState;
+//! use hls_lfcd_lds_driver::sync::LFCDLaser;
+//!
+//! let mut laser = LFCDLaser::new("/dev/ttyUSB0", 230400)?;
+//! let _ = laser.read();
+//! match laser.state() {
+//!     DriverState::Scanning => println!("all good"),
+//!     DriverState::Error(kind) => eprintln!("last read failed: {kind:?}"),
+//!     state => println!("{state:?}"),
+//! }
+//!
+//! let events = laser.subscribe();
+//! std::thread::spawn(move || {
+//!     for event in events {
+//!         println!("lidar: {event:?}");
+//!     }
+//! });
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "sync"))]
+//! # fn main() {}
+//! ```
+
+use crate::error::ErrorKind;
+use std::fmt;
+
+/// State of a driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DriverState {
+    /// The port is open but the motor has not been started
+    Stopped,
+    /// The motor has been started, no scan has been read since
+    Starting,
+    /// The last read returned a scan
+    Scanning,
+    /// The idle stop or the duty cycle has stopped the motor, a read
+    /// starts it again
+    Paused,
+    /// The last read failed
+    Error(ErrorKind),
+    /// `close` has been called, `start` opens the driver again
+    Closed,
+}
+
+impl DriverState {
+    /// Checks if the driver can be read, i.e. it is not closed.
+    pub fn is_open(&self) -> bool {
+        *self != DriverState::Closed
+    }
+}
+
+/// A change of the state of a driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DriverEvent {
+    /// The motor has been started, by `start` or by a read after an idle
+    /// stop or at the beginning of a window of the duty cycle
+    Started,
+    /// The motor has been stopped at the end of a window of the duty cycle
+    Paused,
+    /// The driver has been closed and the motor stopped
+    Stopped,
+    /// No frame header could be found, see `Error::SyncLost`
+    SyncLost,
+    /// The serial port has been re-opened by `reconnect`
+    Reconnected,
+    /// A scan had packets failing to decode after a clean one
+    HealthDegraded {
+        /// Packets of the scan that failed to decode
+        bad_packets: usize,
+    },
+    /// The motor has been restarted by the watchdog, see `watchdog`
+    MotorRestarted {
+        /// Restarts since the last decoded frame
+        restarts: u32,
+    },
+}
+
+impl fmt::Display for DriverState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverState::Stopped => write!(f, "stopped"),
+            DriverState::Starting => write!(f, "starting"),
+            DriverState::Scanning => write!(f, "scanning"),
+            DriverState::Paused => write!(f, "paused"),
+            DriverState::Error(kind) => write!(f, "error: {kind:?}"),
+            DriverState::Closed => write!(f, "closed"),
+        }
+    }
+}
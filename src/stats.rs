@@ -0,0 +1,236 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Distribution statistics of the ranges of a scan, over the valid beams.
+//!
+//! ```
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//! let histogram = reading.histogram(100);
+//! if let Some(stats) = reading.stats() {
+//!     println!("{} beams, median {} mm", stats.count, stats.median);
+//! }
+//! // The 30 degrees in front of the lidar.
+//! let front = reading.sector_stats(345, 15);
+//! if front.valid_ratio < 0.5 {
+//!     println!("front mostly blind");
+//! }
+//! ```
+
+use crate::{LaserReading, RANGE_MAX};
+
+/// Histogram of the ranges, bin `k` counting the ranges from `k * bin_width`
+/// included to `(k + 1) * bin_width` excluded, in mm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    pub bin_width: u16,
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    /// Gets the range of the given bin, in mm.
+    pub fn bin_range(&self, bin: usize) -> (u32, u32) {
+        let w = u32::from(self.bin_width);
+        (bin as u32 * w, (bin as u32 + 1) * w)
+    }
+
+    /// Gets the number of counted beams.
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+}
+
+/// Summary statistics of the ranges, in mm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RangeStats {
+    /// Number of valid beams
+    pub count: usize,
+    pub min: u16,
+    pub max: u16,
+    pub mean: f32,
+    pub std_dev: f32,
+    pub median: u16,
+    /// 5th percentile
+    pub p5: u16,
+    /// 95th percentile
+    pub p95: u16,
+}
+
+/// Statistics of the ranges of an angular sector, in mm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SectorStats {
+    /// Number of beams of the sector
+    pub beams: usize,
+    /// Number of valid beams of the sector
+    pub valid: usize,
+    /// Valid beams over the beams of the sector, from 0 to 1
+    pub valid_ratio: f32,
+    /// `None` without valid beams
+    pub min: Option<u16>,
+    /// `None` without valid beams
+    pub mean: Option<f32>,
+    /// `None` without valid beams
+    pub max: Option<u16>,
+}
+
+impl LaserReading {
+    fn valid_ranges(&self) -> Vec<u16> {
+        (0..self.ranges.len())
+            .filter(|&i| self.is_valid(i))
+            .map(|i| self.ranges[i])
+            .collect()
+    }
+
+    /// Gets the histogram of the valid ranges, with bins `bin_width` mm wide
+    /// covering up to `RANGE_MAX`.
+    pub fn histogram(&self, bin_width: u16) -> Histogram {
+        let bin_width = bin_width.max(1);
+        let mut counts = vec![0; usize::from(RANGE_MAX / bin_width) + 1];
+        for r in self.valid_ranges() {
+            counts[usize::from(r / bin_width)] += 1;
+        }
+        Histogram { bin_width, counts }
+    }
+
+    /// Gets the `p`-th percentile, from 0 to 100, of the valid ranges with
+    /// the nearest-rank method.
+    ///
+    /// Returns `None` if the scan has no valid beam.
+    pub fn percentile(&self, p: f32) -> Option<u16> {
+        let mut ranges = self.valid_ranges();
+        ranges.sort_unstable();
+        nearest_rank(&ranges, p)
+    }
+
+    /// Gets the statistics of the sector going counter-clockwise from
+    /// `start_deg` to `end_deg` included, e.g. `sector_stats(345, 15)` for
+    /// the 31 beams in front of the lidar.
+    pub fn sector_stats(&self, start_deg: usize, end_deg: usize) -> SectorStats {
+        let n = self.ranges.len();
+        let (start, end) = (start_deg % n, end_deg % n);
+        let beams = (end + n - start) % n + 1;
+        let ranges: Vec<u16> = (start..start + beams)
+            .map(|i| i % n)
+            .filter(|&i| self.is_valid(i))
+            .map(|i| self.ranges[i])
+            .collect();
+
+        let valid = ranges.len();
+        SectorStats {
+            beams,
+            valid,
+            valid_ratio: valid as f32 / beams as f32,
+            min: ranges.iter().min().copied(),
+            mean: (valid > 0)
+                .then(|| ranges.iter().map(|r| f32::from(*r)).sum::<f32>() / valid as f32),
+            max: ranges.iter().max().copied(),
+        }
+    }
+
+    /// Gets the summary statistics of the valid ranges.
+    ///
+    /// Returns `None` if the scan has no valid beam.
+    pub fn stats(&self) -> Option<RangeStats> {
+        let mut ranges = self.valid_ranges();
+        ranges.sort_unstable();
+        let count = ranges.len();
+        let n = count as f32;
+        let mean = ranges.iter().map(|r| f32::from(*r)).sum::<f32>() / n;
+        let variance = ranges
+            .iter()
+            .map(|r| (f32::from(*r) - mean).powi(2))
+            .sum::<f32>()
+            / n;
+
+        Some(RangeStats {
+            count,
+            min: *ranges.first()?,
+            max: *ranges.last()?,
+            mean,
+            std_dev: variance.sqrt(),
+            median: nearest_rank(&ranges, 50.0)?,
+            p5: nearest_rank(&ranges, 5.0)?,
+            p95: nearest_rank(&ranges, 95.0)?,
+        })
+    }
+}
+
+fn nearest_rank(sorted: &[u16], p: f32) -> Option<u16> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p.clamp(0.0, 100.0) / 100.0 * sorted.len() as f32).ceil() as usize;
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixtures;
+    use crate::{RANGE_MAX, RANGE_MIN};
+
+    #[test]
+    fn summarizes_the_room() {
+        let room = fixtures()[0].decode();
+        let valid: Vec<u16> = (0..360)
+            .filter(|&i| room.is_valid(i))
+            .map(|i| room.ranges[i])
+            .collect();
+
+        let stats = room.stats().unwrap();
+        assert_eq!(stats.count, valid.len());
+        assert_eq!(stats.min, *valid.iter().min().unwrap());
+        assert_eq!(stats.max, *valid.iter().max().unwrap());
+        let mean = valid.iter().map(|&r| f32::from(r)).sum::<f32>() / valid.len() as f32;
+        assert!((stats.mean - mean).abs() < 0.01);
+        assert!(stats.p5 <= stats.median && stats.median <= stats.p95);
+        // The walls at 1 m are the closest.
+        assert!((999..=1000).contains(&stats.min));
+        assert_eq!(room.histogram(100).total(), valid.len());
+    }
+
+    #[test]
+    fn takes_only_the_valid_ranges() {
+        let near_and_far = fixtures()[2].decode();
+        let stats = near_and_far.stats().unwrap();
+        assert_eq!(stats.count, 180);
+        assert_eq!((stats.min, stats.max), (RANGE_MIN, RANGE_MAX));
+        assert_eq!(stats.median, RANGE_MIN);
+        assert_eq!(near_and_far.percentile(51.0), Some(RANGE_MAX));
+
+        let histogram = near_and_far.histogram(500);
+        assert_eq!(histogram.counts[0], 90);
+        assert_eq!(histogram.counts[usize::from(RANGE_MAX / 500)], 90);
+        assert_eq!(histogram.total(), 180);
+    }
+
+    #[test]
+    fn has_no_statistics_without_returns() {
+        let no_returns = fixtures()[1].decode();
+        assert_eq!(no_returns.stats(), None);
+        assert_eq!(no_returns.percentile(50.0), None);
+        assert_eq!(no_returns.histogram(100).total(), 0);
+        let sector = no_returns.sector_stats(350, 9);
+        assert_eq!((sector.beams, sector.valid), (20, 0));
+        assert_eq!(sector.mean, None);
+    }
+
+    #[test]
+    fn wraps_the_sectors() {
+        let near_and_far = fixtures()[2].decode();
+        let sector = near_and_far.sector_stats(358, 1);
+        assert_eq!((sector.beams, sector.valid), (4, 2));
+        assert_eq!(sector.valid_ratio, 0.5);
+        assert_eq!(sector.min, Some(RANGE_MIN));
+        assert_eq!(sector.max, Some(RANGE_MAX));
+    }
+}
@@ -0,0 +1,235 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! SVG export of a scan as a polar plot.
+
+use crate::{LaserReading, RANGE_MAX};
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// Options used when exporting a scan to SVG.
+#[derive(Debug, Clone)]
+pub struct SvgOptions {
+    /// Width and height of the plot, in pixels
+    pub size: u32,
+    /// Range, in meters, mapped to the outer ring
+    pub max_range: f32,
+    /// Distance, in meters, between two range rings, `None` disables them
+    pub ring_step: Option<f32>,
+    /// Degrees between two angle labels, `None` disables them
+    pub label_step: Option<u16>,
+    /// Radius of every point, in pixels
+    pub point_radius: f32,
+    /// Colors the points according to their intensity, from blue (low) to red (high)
+    pub intensity_colors: bool,
+    /// Color of the points, when `intensity_colors` is disabled
+    pub point_color: String,
+    /// Color of the background
+    pub background: String,
+    /// Color of the rings, of the axes and of the labels
+    pub grid_color: String,
+    /// Size of the labels, in pixels
+    pub font_size: u32,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            size: 600,
+            max_range: f32::from(RANGE_MAX) / 1000.0,
+            ring_step: Some(1.0),
+            label_step: Some(30),
+            point_radius: 2.0,
+            intensity_colors: true,
+            point_color: "black".into(),
+            background: "white".into(),
+            grid_color: "#b0b0b0".into(),
+            font_size: 12,
+        }
+    }
+}
+
+/// Maps a value from 0 to 1 to a blue, green, red color ramp.
+fn color_map(v: f32) -> String {
+    let v = v.clamp(0.0, 1.0);
+    let (r, g, b) = if v < 0.5 {
+        let t = v * 2.0;
+        (0.0, t, 1.0 - t)
+    } else {
+        let t = (v - 0.5) * 2.0;
+        (t, 1.0 - t, 0.0)
+    };
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        (r * 255.0) as u8,
+        (g * 255.0) as u8,
+        (b * 255.0) as u8
+    )
+}
+
+/// Escapes a value written in a double quoted attribute.
+fn escape(value: &str) -> Cow<'_, str> {
+    if !value.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+impl LaserReading {
+    /// Exports the scan as an SVG polar plot, seen from above with the
+    /// front of the lidar pointing up.
+    ///
+    /// Invalid beams are not drawn, nor points beyond `max_range`. The colors
+    /// are escaped, so any string ends up in the attributes as is.
+    pub fn to_svg(&self, options: &SvgOptions) -> String {
+        let size = options.size as f32;
+        let center = size / 2.0;
+        // Leaves room for the labels around the plot, but never more than
+        // half of it, so that small images still get a plot.
+        let margin = (options.font_size as f32 * 2.5).min(center / 2.0);
+        let scale = (center - margin) / options.max_range;
+        let to_svg = |x: f32, y: f32| (center - y * scale, center - x * scale);
+
+        let mut svg = String::new();
+        // Writing into a String never fails.
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 {size} {size}">"#
+        );
+        let _ = writeln!(
+            svg,
+            r#"<rect width="100%" height="100%" fill="{}"/>"#,
+            escape(&options.background)
+        );
+
+        let _ = writeln!(
+            svg,
+            r#"<g fill="none" stroke="{}" stroke-width="1">"#,
+            escape(&options.grid_color)
+        );
+        // Rings closer than a pixel would only blur the plot, and take forever.
+        if let Some(step) = options.ring_step.filter(|s| *s > 0.0 && s * scale >= 1.0) {
+            let rings = ((options.max_range + f32::EPSILON) / step) as u32;
+            for ring in 1..=rings {
+                let _ = writeln!(
+                    svg,
+                    r#"<circle cx="{center}" cy="{center}" r="{:.2}"/>"#,
+                    step * ring as f32 * scale
+                );
+            }
+        }
+        let (fx, fy) = to_svg(options.max_range, 0.0);
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{center}" y1="{center}" x2="{fx:.2}" y2="{fy:.2}"/>"#
+        );
+        let _ = writeln!(svg, "</g>");
+
+        if let Some(step) = options.label_step.filter(|s| *s > 0) {
+            let _ = writeln!(
+                svg,
+                r#"<g fill="{}" font-family="sans-serif" font-size="{}" text-anchor="middle" dominant-baseline="middle">"#,
+                escape(&options.grid_color),
+                options.font_size
+            );
+            let radius = options.max_range + margin / 2.0 / scale;
+            for degree in (0..360).step_by(usize::from(step)) {
+                let (sin, cos) = LaserReading::angle(degree).sin_cos();
+                let (x, y) = to_svg(radius * cos, radius * sin);
+                let _ = writeln!(svg, r#"<text x="{x:.2}" y="{y:.2}">{degree}°</text>"#);
+            }
+            let _ = writeln!(svg, "</g>");
+        }
+
+        let max_intensity = (0..self.ranges.len())
+            .filter(|&i| self.is_valid(i))
+            .map(|i| self.intensities[i])
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        let _ = writeln!(
+            svg,
+            r#"<g stroke="none" fill="{}">"#,
+            escape(&options.point_color)
+        );
+        for i in 0..self.ranges.len() {
+            if !self.is_valid(i) {
+                continue;
+            }
+            let (x, y) = self.point(i);
+            if x.hypot(y) > options.max_range {
+                continue;
+            }
+            let (px, py) = to_svg(x, y);
+            if options.intensity_colors {
+                let color = color_map(f32::from(self.intensities[i]) / f32::from(max_intensity));
+                let _ = writeln!(
+                    svg,
+                    r#"<circle cx="{px:.2}" cy="{py:.2}" r="{}" fill="{color}"/>"#,
+                    options.point_radius
+                );
+            } else {
+                let _ = writeln!(
+                    svg,
+                    r#"<circle cx="{px:.2}" cy="{py:.2}" r="{}"/>"#,
+                    options.point_radius
+                );
+            }
+        }
+        let _ = writeln!(svg, "</g>");
+
+        let _ = writeln!(svg, "</svg>");
+        svg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn escapes_colors() {
+        let options = SvgOptions {
+            background: r#"red"/><script/>"#.into(),
+            ..SvgOptions::default()
+        };
+        let svg = testing::fixtures()[0].expected.to_svg(&options);
+        assert!(!svg.contains("<script"));
+        assert!(svg.contains(r#"fill="red&quot;/&gt;&lt;script/&gt;""#));
+    }
+
+    #[test]
+    fn keeps_the_scale_positive_in_small_images() {
+        let options = SvgOptions {
+            size: 20,
+            ..SvgOptions::default()
+        };
+        let svg = testing::fixtures()[0].expected.to_svg(&options);
+        assert!(svg.contains("<circle"));
+        assert!(!svg.contains("r=\"-"));
+    }
+}
@@ -0,0 +1,653 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Blocking driver based on `serialport`, enabled by the `sync` feature.
+//!
+//! `LFCDLaser::with_tuning` changes the read timeout, `VMIN`/`VTIME` and the
+//! low latency mode of the USB adapter, enabled by default.
+//! `SerialTuning::bluetooth` suits a Bluetooth serial bridge, e.g.
+//! `/dev/rfcomm0`: the read timeout leaves room for the data arriving in
+//! bursts instead of taking them for a stopped motor.
+
+use crate::common::{Command, Commands, Core, Held};
+use crate::error::{Error, Result};
+use crate::io;
+use crate::options::{Model, Open, OpenOptions};
+use crate::protocol::FRAME_SIZE;
+use crate::run::{Recovery, RunPolicy, Runner};
+use crate::state::{DriverEvent, DriverState};
+use crate::{LaserReading, LidarDriver};
+use serialport::SerialPort;
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+#[cfg(all(feature = "usb_reset", target_os = "linux"))]
+use std::time::Instant;
+
+/// Serial port of the platform, keeping the file descriptor at hand on unix.
+#[cfg(unix)]
+type NativePort = serialport::TTYPort;
+#[cfg(windows)]
+type NativePort = serialport::COMPort;
+
+/// Low level settings of the serial port, applied every time it is opened.
+/// Only the timeout is applied on Windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialTuning {
+    /// Time a read waits for the first byte before failing with `Error::Timeout`,
+    /// until the RPMs are known, then `protocol::TIMEOUT_REVOLUTIONS` revolutions
+    pub timeout: Duration,
+    /// Minimum number of bytes a read returns (`VMIN`)
+    pub vmin: u8,
+    /// Time a read waits between two bytes once one has arrived, in tenths of a second (`VTIME`)
+    pub vtime: u8,
+    /// Asks the USB adapter to forward the data right away instead of batching it
+    /// for up to 16 ms (`ASYNC_LOW_LATENCY` on Linux, `IOSSDATALAT` on macOS),
+    /// ignored if the adapter does not support it
+    pub low_latency: bool,
+    /// Time added to the read timeout, for links delivering the data in bursts
+    pub jitter: Duration,
+}
+
+impl Default for SerialTuning {
+    fn default() -> Self {
+        Self {
+            // A frame takes 200 ms at 300 rpm, a full second means the lidar is gone.
+            timeout: Duration::from_secs(1),
+            vmin: 1,
+            vtime: 0,
+            low_latency: true,
+            jitter: Duration::ZERO,
+        }
+    }
+}
+
+impl SerialTuning {
+    /// Gets the tuning of a Bluetooth serial bridge, e.g. an HC-05 paired
+    /// through RFCOMM (`/dev/rfcomm0` on Linux, `/dev/cu.HC-05*` on macOS):
+    /// the radio delivers the data in bursts, up to a few hundred
+    /// milliseconds apart, which must not be taken for a stopped motor.
+    pub fn bluetooth() -> Self {
+        Self {
+            timeout: Duration::from_secs(3),
+            // There is no USB adapter to tune.
+            low_latency: false,
+            jitter: Duration::from_millis(500),
+            ..Self::default()
+        }
+    }
+}
+
+/// This struct allows to read lidar information and to "shutdown" the driver
+pub struct LFCDLaser {
+    core: Core,
+    serial: NativePort,
+    tuning: SerialTuning,
+}
+
+impl_common!(LFCDLaser);
+
+impl LFCDLaser {
+    /// Creates a new `LFCDLaser` with the given parameters.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn new<P: AsRef<Path>>(port: P, baud_rate: u32) -> Result<Self> {
+        Self::with_tuning(port, baud_rate, SerialTuning::default())
+    }
+
+    /// Creates a new `LFCDLaser`, opening the serial port with the given tuning.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    /// - unable to apply the tuning
+    pub fn with_tuning<P: AsRef<Path>>(
+        port: P,
+        baud_rate: u32,
+        tuning: SerialTuning,
+    ) -> Result<Self> {
+        Self::open_with(&OpenOptions::new(port, baud_rate), tuning)
+    }
+
+    /// Creates a new `LFCDLaser` as the options say, see `OpenOptions::open`.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - invalid options, the port is not touched
+    /// - unable to open the specified serial port
+    /// - unable to set the exclusivity of the port (only on unix)
+    pub fn with_options(options: &OpenOptions) -> Result<Self> {
+        options.open()
+    }
+
+    fn open_with(options: &OpenOptions, tuning: SerialTuning) -> Result<Self> {
+        let serial = Self::open(&options.port, options.baud_rate, options.exclusive, &tuning)?;
+
+        let mut core = Core::new(options.port.clone(), options.baud_rate);
+        core.exclusive = options.exclusive;
+        let mut lidar = Self {
+            core,
+            serial,
+            tuning,
+        };
+
+        if options.auto_start {
+            lidar.start();
+        }
+
+        Ok(lidar)
+    }
+
+    /// Opens the port of a configuration, see `with_config`.
+    #[cfg(feature = "config")]
+    fn open_config(config: &crate::config::Config) -> Result<Self> {
+        let tuning = SerialTuning {
+            timeout: config.read_timeout(),
+            ..Default::default()
+        };
+        Self::with_tuning(&config.port, config.baud_rate, tuning)
+    }
+
+    /// Re-opens the serial port and starts the lidar again,
+    /// notifying the `on_reconnect` callbacks.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn reconnect(&mut self) -> Result<()> {
+        self.serial = Self::open(
+            &self.core.port,
+            self.core.baud_rate,
+            self.core.exclusive,
+            &self.tuning,
+        )?;
+        self.start();
+        // The idle stop writes to the previous port.
+        #[cfg(unix)]
+        if let Some(options) = self.idle_stop() {
+            self.set_idle_stop(Some(options))?;
+        }
+        self.core
+            .hooks
+            .emit_reconnect(&self.core.port.to_string_lossy());
+        self.core.hooks.emit_event(DriverEvent::Reconnected);
+
+        Ok(())
+    }
+
+    /// Resets the USB adapter of the lidar, see `usb::reset`, and re-opens the
+    /// port once the adapter is back, notifying the `on_reconnect` callbacks.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to reset the adapter
+    /// - the port cannot be re-opened within `timeout`
+    #[cfg(all(feature = "usb_reset", target_os = "linux"))]
+    pub fn reset_usb(&mut self, timeout: Duration) -> Result<()> {
+        crate::usb::reset(&self.core.port)?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Leaves the adapter the time to disappear and enumerate again.
+            std::thread::sleep(Duration::from_millis(500));
+            match self.reconnect() {
+                Ok(()) => return Ok(()),
+                Err(e) if Instant::now() >= deadline => return Err(e),
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Gets a reading from the lidar, returing a `LaserReading` object.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub fn read(&mut self) -> Result<LaserReading> {
+        if self.core.closed() {
+            return Err(Error::DriverClosed);
+        }
+
+        let _busy = self.wake_up();
+        loop {
+            self.apply_commands()?;
+            if let Some(wait) = self.pause_duty() {
+                std::thread::sleep(wait);
+                continue;
+            }
+            self.resume_duty();
+            // Set at each frame, a restarted motor is slow again.
+            let timeout = self.core.read_timeout(self.tuning.timeout) + self.tuning.jitter;
+            self.serial
+                .set_timeout(timeout)
+                .map_err(|e| self.core.fail(e.into()))?;
+            match self.read_frame() {
+                Ok(Some(scan)) if self.in_window() => return Ok(scan),
+                Ok(_) => {}
+                Err(Error::Timeout) => {
+                    if let Some(pause) = self.watchdog_timeout().map_err(|e| self.core.fail(e))? {
+                        std::thread::sleep(pause);
+                        self.restart_stalled();
+                    }
+                }
+                Err(e) => return Err(self.core.fail(e)),
+            }
+        }
+    }
+
+    /// Gets the scan of the newest complete revolution: the scans waiting in
+    /// the serial buffer are dropped, for control loops where acting on old
+    /// data is worse than skipping some. Reads the next one when none is
+    /// waiting.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub fn read_latest(&mut self) -> Result<LaserReading> {
+        if self.core.closed() {
+            return Err(Error::DriverClosed);
+        }
+
+        let backlog = self
+            .serial
+            .bytes_to_read()
+            .map_err(|e| self.core.fail(e.into()))? as usize;
+        if backlog >= FRAME_SIZE {
+            let mut bytes = vec![0u8; backlog];
+            let mut filled = 0;
+            let read = io::read_full(&mut self.serial, &mut bytes, &mut filled);
+            self.core.hooks.emit_bytes(&bytes[..filled]);
+            read.map_err(|e| self.core.fail(e.into()))?;
+            if self.core.latest(&bytes) {
+                if let Some(scan) = self.core.decode() {
+                    return Ok(scan);
+                }
+            }
+        }
+        self.read()
+    }
+
+    /// Reads and decodes a frame, `None` for the scans of the warm-up.
+    fn read_frame(&mut self) -> Result<Option<LaserReading>> {
+        // A corrupted frame may have left the beginning of this one.
+        let mut start_count = self.core.resume();
+
+        // Wait for data sync of frame: 0xFA, 0XA0
+        while start_count < 2 {
+            // Read one byte
+            let mut filled = start_count;
+            let read = io::read_full(
+                &mut self.serial,
+                &mut self.core.buff[..=start_count],
+                &mut filled,
+            );
+            self.core.tee(start_count, filled);
+            read?;
+            self.core.sync(&mut start_count)?;
+        }
+        let mut filled = start_count;
+        let read = io::read_full(&mut self.serial, &mut self.core.buff, &mut filled);
+        self.core.tee(start_count, filled);
+        if let Err(e) = read {
+            // The next read completes the frame.
+            self.core.keep(filled);
+            return Err(e.into());
+        }
+
+        Ok(self.core.decode())
+    }
+
+    /// Gets `n` consecutive readings, amortizing the per-call overhead
+    /// for consumers processing scans in blocks.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    ///
+    /// The readings completed before the error are discarded.
+    pub fn read_batch(&mut self, n: usize) -> Result<Vec<LaserReading>> {
+        let mut scans = Vec::with_capacity(n);
+        for _ in 0..n {
+            scans.push(self.read()?);
+        }
+        Ok(scans)
+    }
+
+    /// Reads scans, passing them to `handler`, recovering from failed reads
+    /// as `policy` says, until SIGINT if `policy.handle_sigint` is set.
+    /// Then the lidar is stopped and the serial port closed.
+    ///
+    /// # Errors
+    /// An error variant is returned once the policy gives up, the lidar is
+    /// stopped and the port closed as well.
+    pub fn run<F>(mut self, mut handler: F, policy: RunPolicy) -> Result<()>
+    where
+        F: FnMut(LaserReading),
+    {
+        let mut runner = Runner::new(policy);
+        while !runner.stopped() {
+            let err = match self.read() {
+                Ok(scan) => {
+                    runner.succeeded();
+                    handler(scan);
+                    continue;
+                }
+                Err(e) => e,
+            };
+            match runner.failed() {
+                Recovery::Retry => {}
+                Recovery::GiveUp => return Err(err),
+                Recovery::Reconnect => loop {
+                    std::thread::sleep(runner.policy().reconnect_delay);
+                    match self.reconnect() {
+                        Ok(()) => break,
+                        Err(e) if !runner.reconnect() => return Err(e),
+                        Err(_) => {}
+                    }
+                },
+            }
+        }
+
+        // Dropping the driver stops the lidar and closes the port.
+        Ok(())
+    }
+
+    /// Gets the tuning of the serial port.
+    pub fn tuning(&self) -> SerialTuning {
+        self.tuning
+    }
+
+    /// Applies a new tuning to the open serial port.
+    ///
+    /// # Errors
+    /// An error variant is returned if the port rejects the settings.
+    pub fn set_tuning(&mut self, tuning: SerialTuning) -> Result<()> {
+        Self::tune(&mut self.serial, &tuning)?;
+        self.tuning = tuning;
+        Ok(())
+    }
+
+    fn open(
+        port: &Path,
+        baud_rate: u32,
+        exclusive: bool,
+        tuning: &SerialTuning,
+    ) -> serialport::Result<NativePort> {
+        let mut serial = serialport::new(io::port_name(port)?, baud_rate).open_native()?;
+
+        #[cfg(unix)]
+        serial.set_exclusive(exclusive)?;
+        #[cfg(not(unix))]
+        let _ = exclusive;
+
+        Self::tune(&mut serial, tuning)?;
+        Ok(serial)
+    }
+
+    fn tune(serial: &mut NativePort, tuning: &SerialTuning) -> serialport::Result<()> {
+        serial.set_timeout(tuning.timeout)?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            let fd = serial.as_raw_fd();
+            // SAFETY: `fd` is open for the lifetime of `serial` and `termios` is plain data.
+            unsafe {
+                let mut termios: libc::termios = std::mem::zeroed();
+                if libc::tcgetattr(fd, &mut termios) != 0 {
+                    return Err(std::io::Error::last_os_error().into());
+                }
+                termios.c_cc[libc::VMIN] = tuning.vmin;
+                termios.c_cc[libc::VTIME] = tuning.vtime;
+                if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+                    return Err(std::io::Error::last_os_error().into());
+                }
+            }
+            // `tcsetattr` resets the speed set through `IOSSIOSPEED` by `serialport`.
+            #[cfg(target_os = "macos")]
+            serial.set_baud_rate(serial.baud_rate()?)?;
+
+            #[cfg(target_os = "linux")]
+            if tuning.low_latency {
+                // Not every USB adapter supports it, the default latency is kept then.
+                let _ = set_low_latency(fd);
+            }
+            #[cfg(target_os = "macos")]
+            if tuning.low_latency {
+                // Not every driver supports it, the default latency is kept then.
+                let _ = set_data_latency(fd, 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        std::io::Write::write_all(&mut self.serial, &[byte]).ok();
+    }
+
+    fn clear_input(&mut self) -> std::io::Result<()> {
+        Ok(self.serial.clear(serialport::ClearBuffer::Input)?)
+    }
+}
+
+/// `struct serial_struct` of `linux/serial.h`.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct SerialStruct {
+    kind: libc::c_int,
+    line: libc::c_int,
+    port: libc::c_uint,
+    irq: libc::c_int,
+    flags: libc::c_int,
+    xmit_fifo_size: libc::c_int,
+    custom_divisor: libc::c_int,
+    baud_base: libc::c_int,
+    close_delay: libc::c_ushort,
+    io_type: libc::c_char,
+    reserved_char: libc::c_char,
+    hub6: libc::c_int,
+    closing_wait: libc::c_ushort,
+    closing_wait2: libc::c_ushort,
+    iomem_base: *mut libc::c_uchar,
+    iomem_reg_shift: libc::c_ushort,
+    port_high: libc::c_uint,
+    iomap_base: libc::c_ulong,
+}
+
+#[cfg(target_os = "linux")]
+const ASYNC_LOW_LATENCY: libc::c_int = 1 << 13;
+
+/// Sets the `ASYNC_LOW_LATENCY` flag of a serial port.
+#[cfg(target_os = "linux")]
+fn set_low_latency(fd: std::os::unix::io::RawFd) -> std::io::Result<()> {
+    // SAFETY: the kernel fills and reads a `serial_struct`, which `SerialStruct` mirrors.
+    unsafe {
+        let mut serial: SerialStruct = std::mem::zeroed();
+        if libc::ioctl(fd, libc::TIOCGSERIAL, &mut serial) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if serial.flags & ASYNC_LOW_LATENCY != 0 {
+            return Ok(());
+        }
+        serial.flags |= ASYNC_LOW_LATENCY;
+        if libc::ioctl(fd, libc::TIOCSSERIAL, &serial) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// `IOSSDATALAT` of `IOKit/serial/ioss.h`, `_IOW('T', 0, unsigned long)`.
+#[cfg(target_os = "macos")]
+const IOSSDATALAT: libc::c_ulong = 0x8008_5400;
+
+/// Sets the time the serial driver of macOS waits before handing the
+/// received data to the reads, in microseconds.
+#[cfg(target_os = "macos")]
+fn set_data_latency(fd: std::os::unix::io::RawFd, micros: libc::c_ulong) -> std::io::Result<()> {
+    // SAFETY: the driver reads an `unsigned long`, which `micros` is.
+    if unsafe { libc::ioctl(fd, IOSSDATALAT, &micros) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Open for LFCDLaser {
+    type Error = Error;
+
+    const DRIVER: &'static str = "sync";
+
+    const MODELS: &'static [Model] = &[Model::Lds01];
+
+    fn open_unchecked(options: &OpenOptions) -> Result<Self> {
+        let defaults = SerialTuning::default();
+        let tuning = SerialTuning {
+            timeout: options.timeout.unwrap_or(defaults.timeout),
+            ..defaults
+        };
+        Self::open_with(options, tuning)
+    }
+}
+
+impl LidarDriver for LFCDLaser {
+    type Error = Error;
+
+    fn read(&mut self) -> Result<LaserReading> {
+        LFCDLaser::read(self)
+    }
+
+    fn start(&mut self) {
+        LFCDLaser::start(self)
+    }
+
+    fn close(&mut self) {
+        LFCDLaser::close(self)
+    }
+}
+
+/// A clonable handle to a `LFCDLaser`, that can be shared among threads.
+///
+/// Reads are serialized. `start` and `stop` do not wait for the ongoing
+/// read, if any: it applies them at the end of the frame being read, within
+/// a revolution, or when the next window opens while paused by a duty
+/// cycle, then fails with `DriverClosed` after a `stop`. The other methods
+/// wait for the ongoing read to complete.
+#[derive(Clone)]
+pub struct LFCDLaserHandle {
+    inner: Arc<Mutex<LFCDLaser>>,
+    commands: Arc<Mutex<Commands>>,
+}
+
+impl LFCDLaserHandle {
+    /// Creates a new handle owning the given driver.
+    pub fn new(mut laser: LFCDLaser) -> Self {
+        let commands = laser
+            .core
+            .commands
+            .get_or_insert_with(Default::default)
+            .clone();
+        Self {
+            inner: Arc::new(Mutex::new(laser)),
+            commands,
+        }
+    }
+
+    // A panic while holding the lock cannot leave the driver in an
+    // inconsistent state, so poisoning is ignored.
+    fn lock(&self) -> Held<'_, MutexGuard<'_, LFCDLaser>> {
+        Held::new(
+            self.inner.lock().unwrap_or_else(|e| e.into_inner()),
+            &self.commands,
+        )
+    }
+
+    /// Has the read apply `command` if one is ongoing, else holds the driver
+    /// to apply it.
+    fn send(&self, command: Command) {
+        if !Commands::send(&self.commands, command) {
+            // Holding the driver applies the queued commands, if a read took
+            // it first this waits for the read, which applied them already.
+            drop(self.lock());
+        }
+    }
+
+    /// Gets a reading from the lidar, waiting for other readers to complete.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub fn read(&self) -> Result<LaserReading> {
+        self.lock().read()
+    }
+
+    /// Starts the Lidar, without waiting for the ongoing read.
+    pub fn start(&self) {
+        self.send(Command::Start)
+    }
+
+    /// Stops the lidar, without waiting for the ongoing read, reads will fail
+    /// until `start` is called.
+    pub fn stop(&self) {
+        self.send(Command::Stop)
+    }
+
+    /// Re-opens the serial port and starts the lidar again.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn reconnect(&self) -> Result<()> {
+        self.lock().reconnect()
+    }
+
+    /// Gets the lidars rmp from the last reading
+    pub fn rpms(&self) -> u16 {
+        self.lock().rpms()
+    }
+
+    /// Gets the state of the driver.
+    pub fn state(&self) -> DriverState {
+        self.lock().state()
+    }
+
+    /// Gets the configured serial port
+    pub fn port(&self) -> PathBuf {
+        self.lock().port().to_path_buf()
+    }
+
+    /// Runs `f` with exclusive access to the driver.
+    pub fn with<R>(&self, f: impl FnOnce(&mut LFCDLaser) -> R) -> R {
+        f(&mut self.lock())
+    }
+}
+
+impl LFCDLaser {
+    /// Converts the driver into a clonable `LFCDLaserHandle`.
+    pub fn into_handle(self) -> LFCDLaserHandle {
+        LFCDLaserHandle::new(self)
+    }
+}
@@ -0,0 +1,218 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Integration with the systemd watchdog, enabled by the `systemd` feature
+//! on unix.
+//!
+//! The watchdog is petted from the thread reading the lidar, and only for
+//! healthy scans, so a read stuck on the serial port, or a lidar that
+//! stopped spinning, gets the service restarted. It needs
+//! `WatchdogSec=` and `Type=notify` in the unit, the messages are sent to
+//! `$NOTIFY_SOCKET` as `sd_notify` does.
+//!
+//! ```no_run
+//! use hls_lfcd_lds_driver::systemd::{self, Watchdog};
+//! # let mut hooks = hls_lfcd_lds_driver::Hooks::new();
+//!
+//! // `hooks` are the ones of the driver, see `LFCDLaser::hooks`.
+//! if let Some(watchdog) = Watchdog::from_env() {
+//!     watchdog.attach(&mut hooks);
+//! }
+//! systemd::notify_ready()?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::{Hooks, LaserReading};
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, Instant};
+
+/// Sends a state, e.g. `READY=1`, to the service manager.
+/// Returns `false` when not running under systemd.
+///
+/// # Errors
+/// An error variant is returned if the notification socket cannot be reached.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let socket = UnixDatagram::unbound()?;
+    let path = path.to_string_lossy();
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Abstract sockets are only supported on Linux",
+            ))
+        }
+        None => {
+            socket.send_to(state.as_bytes(), &*path)?;
+        }
+    }
+    Ok(true)
+}
+
+/// Tells the service manager that the driver is up.
+///
+/// # Errors
+/// An error variant is returned if the notification socket cannot be reached.
+pub fn notify_ready() -> io::Result<bool> {
+    notify("READY=1")
+}
+
+/// Gets the watchdog timeout configured for this process, `None` if the
+/// watchdog is disabled.
+pub fn watchdog_timeout() -> Option<Duration> {
+    // The watchdog may be meant for another process of the service.
+    if let Some(pid) = env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok())
+    {
+        if pid != std::process::id() {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Pets the systemd watchdog while healthy scans keep arriving.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    timeout: Duration,
+    min_valid: usize,
+}
+
+impl Watchdog {
+    /// Creates a watchdog for the given timeout.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            min_valid: 1,
+        }
+    }
+
+    /// Creates a watchdog with the timeout configured by systemd, `None`
+    /// if the watchdog is disabled.
+    pub fn from_env() -> Option<Self> {
+        watchdog_timeout().map(Self::new)
+    }
+
+    /// Sets the number of valid beams a scan needs to be healthy, 1 by default.
+    pub fn with_min_valid(mut self, min_valid: usize) -> Self {
+        self.min_valid = min_valid;
+        self
+    }
+
+    /// Checks if a scan is healthy: the lidar spins and sees something.
+    pub fn is_healthy(&self, reading: &LaserReading) -> bool {
+        reading.rpms > 0
+            && (0..reading.ranges.len())
+                .filter(|&i| reading.is_valid(i))
+                .count()
+                >= self.min_valid
+    }
+
+    /// Pets the watchdog.
+    ///
+    /// # Errors
+    /// An error variant is returned if the notification socket cannot be reached.
+    pub fn pet(&self) -> io::Result<bool> {
+        notify("WATCHDOG=1")
+    }
+
+    /// Registers the callback petting the watchdog on a driver, at most
+    /// twice per timeout as systemd recommends.
+    pub fn attach(self, hooks: &mut Hooks) {
+        let mut last: Option<Instant> = None;
+        hooks.on_scan(move |reading| {
+            if !self.is_healthy(reading) || last.is_some_and(|t| t.elapsed() < self.timeout / 2) {
+                return;
+            }
+            // Errors cannot be reported from a callback, the next scan retries.
+            if self.pet().is_ok() {
+                last = Some(Instant::now());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(rpms: u16, valid: usize) -> LaserReading {
+        let mut reading = LaserReading::new();
+        reading.rpms = rpms;
+        reading.ranges[..valid].fill(1000);
+        reading
+    }
+
+    #[test]
+    fn checks_the_health_of_a_scan() {
+        let watchdog = Watchdog::new(Duration::from_secs(1)).with_min_valid(2);
+        assert!(watchdog.is_healthy(&reading(300, 2)));
+        assert!(!watchdog.is_healthy(&reading(300, 1)));
+        assert!(!watchdog.is_healthy(&reading(0, 360)));
+    }
+
+    // The only test touching the environment, the others would race with it.
+    #[test]
+    fn notifies_through_the_socket_of_the_environment() {
+        let path = env::temp_dir().join(format!("lds-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let received = || {
+            let mut buf = [0u8; 64];
+            let n = socket.recv(&mut buf).ok()?;
+            Some(String::from_utf8_lossy(&buf[..n]).into_owned())
+        };
+
+        env::set_var("NOTIFY_SOCKET", &path);
+        env::set_var("WATCHDOG_USEC", "20000000");
+        env::set_var("WATCHDOG_PID", std::process::id().to_string());
+        assert!(notify_ready().unwrap());
+        assert_eq!(received().as_deref(), Some("READY=1"));
+
+        let watchdog = Watchdog::from_env().unwrap();
+        assert_eq!(watchdog.timeout, Duration::from_secs(20));
+        let mut hooks = Hooks::new();
+        watchdog.attach(&mut hooks);
+        hooks.emit_scan(&reading(0, 0));
+        assert_eq!(received(), None);
+        hooks.emit_scan(&reading(300, 10));
+        hooks.emit_scan(&reading(300, 10));
+        // Petted once, the second scan comes before half the timeout.
+        assert_eq!(received().as_deref(), Some("WATCHDOG=1"));
+        assert_eq!(received(), None);
+
+        env::set_var("WATCHDOG_PID", "1");
+        assert_eq!(watchdog_timeout(), None);
+
+        env::remove_var("NOTIFY_SOCKET");
+        env::remove_var("WATCHDOG_USEC");
+        env::remove_var("WATCHDOG_PID");
+        std::fs::remove_file(&path).unwrap();
+        assert!(!notify_ready().unwrap());
+    }
+}
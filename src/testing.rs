@@ -0,0 +1,276 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Golden fixtures and assertion helpers for decoder-level regression
+//! tests, behind the `test-utils` feature.
+//!
+//! `fixtures` gives frames in the wire format of the LDS-01 next to the
+//! scans they must decode to. They are built from known scenes with
+//! `encode_frame`, including a corrupted packet, so they do not depend on
+//! the decoder they check. Frames recorded on a real lidar come from a
+//! `BlackBox` dump taken `with_raw_frames`, see `Fixture::from_records`.
+//!
+//! ```
+//! # #[cfg(feature = "test-utils")]
+//! # fn main() {
+//! use hls_lfcd_lds_driver::testing::{self, assert_scan_close, encode_frame};
+//!
+//! testing::assert_fixtures(&testing::fixtures());
+//!
+//! let room = &testing::fixtures()[0];
+//! let mut frame = encode_frame(&room.expected);
+//! frame[4 + 2] ^= 0x01; // 1 mm off on a beam
+//! let decoded = hls_lfcd_lds_driver::protocol::decode_frame(&frame, |_| {});
+//! assert_scan_close(&decoded, &room.expected, 1);
+//! # }
+//! # #[cfg(not(feature = "test-utils"))]
+//! # fn main() {}
+//! ```
+
+use crate::blackbox::Record;
+use crate::metrics;
+use crate::protocol::{
+    decode_frame, FIRST_INDEX, FRAME_SIZE, PACKET_SIZE, READINGS_PER_PACKET, SYNC_BYTE,
+};
+use crate::LaserReading;
+
+/// Number of differing beams listed by the assertions.
+const SHOWN_BEAMS: usize = 8;
+
+/// A raw frame and the scan it decodes to.
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    /// Name of the fixture, in the failure messages
+    pub name: String,
+    /// Bytes of the frame, as read from the lidar
+    pub frame: Vec<u8>,
+    /// Scan expected from the decoder
+    pub expected: LaserReading,
+}
+
+impl Fixture {
+    /// Decodes the frame with the default options, skipped packets leaving
+    /// their beams to 0.
+    ///
+    /// # Panics
+    /// If the frame is not `FRAME_SIZE` bytes long.
+    #[track_caller]
+    pub fn decode(&self) -> LaserReading {
+        let frame: &[u8; FRAME_SIZE] = self.frame.as_slice().try_into().unwrap_or_else(|_| {
+            panic!(
+                "fixture {}: frame of {} bytes, expected {FRAME_SIZE}",
+                self.name,
+                self.frame.len()
+            )
+        });
+        decode_frame(frame, |_| {})
+    }
+
+    /// Creates fixtures from the records of a `BlackBox` dump, every raw
+    /// frame paired with the scan recorded right after it.
+    ///
+    /// The scans are the ones handed to the hooks, so the recording driver
+    /// must not mirror nor calibrate them.
+    pub fn from_records(records: &[Record]) -> Vec<Fixture> {
+        records
+            .windows(2)
+            .filter_map(|pair| match pair {
+                [Record::Frame(_, frame), Record::Scan(_, scan)] => Some((frame, scan)),
+                _ => None,
+            })
+            .enumerate()
+            .map(|(n, (frame, scan))| Fixture {
+                name: format!("recorded-{n}"),
+                frame: frame.clone(),
+                expected: (**scan).clone(),
+            })
+            .collect()
+    }
+}
+
+/// Encodes a scan in the wire format of the LDS-01, the reserved bytes set
+/// to 0.
+pub fn encode_frame(reading: &LaserReading) -> [u8; FRAME_SIZE] {
+    let mut frame = [0u8; FRAME_SIZE];
+    let rpms = (reading.rpms.saturating_mul(10)).to_le_bytes();
+    for (i, packet) in frame.chunks_exact_mut(PACKET_SIZE).enumerate() {
+        packet[0] = SYNC_BYTE;
+        packet[1] = FIRST_INDEX + i as u8;
+        packet[2..4].copy_from_slice(&rpms);
+        for n in 0..READINGS_PER_PACKET {
+            let degree = 359 - (READINGS_PER_PACKET * i + n);
+            let reading_bytes = &mut packet[4 + 6 * n..][..6];
+            reading_bytes[0..2].copy_from_slice(&reading.intensities[degree].to_le_bytes());
+            reading_bytes[2..4].copy_from_slice(&reading.ranges[degree].to_le_bytes());
+        }
+    }
+    frame
+}
+
+/// Gets the golden fixtures of the crate:
+/// - `room`: a 4 by 3 m room, the lidar 1 m off its center, at 300 rpm
+/// - `no_returns`: the motor turning in an open space, every range 0
+/// - `near_and_far`: ranges around `RANGE_MIN` and `RANGE_MAX`, high intensities
+/// - `corrupted_packet`: the room with the header of packet 17 broken,
+///   its six beams left to 0
+pub fn fixtures() -> Vec<Fixture> {
+    let room = room();
+
+    let no_returns = LaserReading {
+        rpms: 298,
+        ..LaserReading::new()
+    };
+
+    let mut near_and_far = LaserReading::new();
+    near_and_far.rpms = 302;
+    for degree in 0..360 {
+        let (range, intensity) = match degree % 4 {
+            0 => (crate::RANGE_MIN - 1, 40),
+            1 => (crate::RANGE_MIN, 3000),
+            2 => (crate::RANGE_MAX, 120),
+            _ => (crate::RANGE_MAX + 1, 0),
+        };
+        near_and_far.ranges[degree] = range;
+        near_and_far.intensities[degree] = intensity;
+    }
+
+    let mut corrupted = encode_frame(&room);
+    corrupted[17 * PACKET_SIZE + 1] = 0x00;
+    let mut corrupted_expected = room.clone();
+    for n in 0..READINGS_PER_PACKET {
+        let degree = 359 - (READINGS_PER_PACKET * 17 + n);
+        corrupted_expected.ranges[degree] = 0;
+        corrupted_expected.intensities[degree] = 0;
+    }
+
+    vec![
+        fixture("room", &room),
+        fixture("no_returns", &no_returns),
+        fixture("near_and_far", &near_and_far),
+        Fixture {
+            name: "corrupted_packet".into(),
+            frame: corrupted.to_vec(),
+            expected: corrupted_expected,
+        },
+    ]
+}
+
+fn fixture(name: &str, reading: &LaserReading) -> Fixture {
+    Fixture {
+        name: name.into(),
+        frame: encode_frame(reading).to_vec(),
+        expected: reading.clone(),
+    }
+}
+
+/// A rectangular room, the walls at 3 and 1 m on x and 2 and 1 m on y.
+fn room() -> LaserReading {
+    let mut reading = LaserReading::new();
+    reading.rpms = 300;
+    for degree in 0..360 {
+        let (sin, cos) = LaserReading::angle(degree).sin_cos();
+        let wall_x = if cos > 0.0 { 3.0 / cos } else { -1.0 / cos };
+        let wall_y = if sin > 0.0 { 2.0 / sin } else { -1.0 / sin };
+        let range = wall_x.min(wall_y) * 1000.0;
+        reading.ranges[degree] = range.min(f32::from(crate::RANGE_MAX + 1)) as u16;
+        reading.intensities[degree] = (4000.0 / range * 1000.0) as u16;
+    }
+    reading
+}
+
+/// Describes the beams where `actual` and `expected` differ by more than
+/// `tolerance` mm, or in intensity if `tolerance` is 0.
+fn differences(actual: &LaserReading, expected: &LaserReading, tolerance: u16) -> Vec<String> {
+    (0..actual.ranges.len())
+        .filter(|&i| {
+            actual.ranges[i].abs_diff(expected.ranges[i]) > tolerance
+                || (tolerance == 0 && actual.intensities[i] != expected.intensities[i])
+        })
+        .map(|i| {
+            format!(
+                "beam {i}: {} mm ({}) instead of {} mm ({})",
+                actual.ranges[i],
+                actual.intensities[i],
+                expected.ranges[i],
+                expected.intensities[i]
+            )
+        })
+        .collect()
+}
+
+#[track_caller]
+fn fail(what: &str, diffs: &[String]) -> ! {
+    let mut message = format!("{what}, {} beams differ:", diffs.len());
+    for diff in diffs.iter().take(SHOWN_BEAMS) {
+        message.push_str("\n  ");
+        message.push_str(diff);
+    }
+    if diffs.len() > SHOWN_BEAMS {
+        message.push_str("\n  ...");
+    }
+    panic!("{message}");
+}
+
+/// Asserts that two scans have the same RPMs, ranges and intensities.
+///
+/// # Panics
+/// If they differ, listing the first differing beams.
+#[track_caller]
+pub fn assert_scan_eq(actual: &LaserReading, expected: &LaserReading) {
+    assert_eq!(actual.rpms, expected.rpms, "scans with different rpms");
+    let diffs = differences(actual, expected, 0);
+    if !diffs.is_empty() {
+        fail("scans not equal", &diffs);
+    }
+}
+
+/// Asserts that no range of `actual` is more than `tolerance` mm away from
+/// the one of `expected`, intensities and RPMs are not compared.
+///
+/// # Panics
+/// If they differ, with the mean error and the first differing beams.
+#[track_caller]
+pub fn assert_scan_close(actual: &LaserReading, expected: &LaserReading, tolerance: u16) {
+    let diffs = differences(actual, expected, tolerance);
+    if !diffs.is_empty() {
+        let comparison = metrics::compare(actual, expected);
+        fail(
+            &format!(
+                "scans not within {tolerance} mm, mean error {:?} mm, overlap {:.2}",
+                comparison.mean_abs_error, comparison.overlap
+            ),
+            &diffs,
+        );
+    }
+}
+
+/// Asserts that every fixture decodes to its expected scan.
+///
+/// # Panics
+/// On the first fixture decoding to another scan, with its name.
+#[track_caller]
+pub fn assert_fixtures(fixtures: &[Fixture]) {
+    for fixture in fixtures {
+        let decoded = fixture.decode();
+        assert_eq!(
+            decoded.rpms, fixture.expected.rpms,
+            "fixture {}: different rpms",
+            fixture.name
+        );
+        let diffs = differences(&decoded, &fixture.expected, 0);
+        if !diffs.is_empty() {
+            fail(&format!("fixture {}", fixture.name), &diffs);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Capture time of every beam of a scan, for deskewing and for fusing the
+//! scans with other sensors.
+//!
+//! A revolution lasts 200 ms at 300 rpm, so the beams of a moving robot are
+//! taken from different poses. The lidar sends the beams from the degree
+//! 359 down to 0, the offsets are counted from the first one sent; the
+//! drivers stamp the end of every frame read, see `LFCDLaser::beam_times`.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::timing;
+//! use hls_lfcd_lds_driver::LaserReading;
+//! use std::time::{Duration, SystemTime};
+//!
+//! let mut reading = LaserReading::new();
+//! reading.rpms = 300;
+//! let offsets = timing::beam_offsets(reading.rpms, false);
+//! assert_eq!(offsets[359], Duration::ZERO);
+//! assert!(offsets[0] > Duration::from_millis(199));
+//!
+//! let start = SystemTime::now();
+//! let times = reading.beam_times(start);
+//! assert_eq!(times[359], start);
+//! ```
+
+use crate::LaserReading;
+use std::time::{Duration, SystemTime};
+
+/// Gets the position in the order the lidar sends them of the beam stored
+/// at `slot` of `LaserReading::ranges`.
+fn sent_order(slot: usize, mirrored: bool) -> usize {
+    let degree = if mirrored { (360 - slot) % 360 } else { slot };
+    359 - degree
+}
+
+/// Gets the time elapsed between the first beam of a scan and every beam,
+/// indexed like `LaserReading::ranges`, at constant `rpms`.
+///
+/// `mirrored` tells whether the scan was decoded mirrored, see
+/// `LFCDLaser::set_mirrored`. All the offsets are 0 while the motor is
+/// stopped.
+pub fn beam_offsets(rpms: u16, mirrored: bool) -> [Duration; 360] {
+    let step = crate::protocol::scan_time(rpms) / 360.0;
+    let mut offsets = [Duration::ZERO; 360];
+    for (slot, offset) in offsets.iter_mut().enumerate() {
+        *offset = Duration::from_secs_f32(step * sent_order(slot, mirrored) as f32);
+    }
+    offsets
+}
+
+/// Like `beam_offsets`, with the speed of every degree, see
+/// `protocol::degree_rpms`, so that the offsets follow the motor speeding
+/// up or slowing down within the revolution.
+///
+/// The degrees at 0 rpm take no time.
+pub fn beam_offsets_with(degree_rpms: &[f32; 360], mirrored: bool) -> [Duration; 360] {
+    // Time the lidar spends on every degree, in the order they are sent.
+    let mut elapsed = [0f32; 360];
+    let mut total = 0.0;
+    for (n, slot) in elapsed.iter_mut().enumerate() {
+        *slot = total;
+        let rpms = degree_rpms[359 - n];
+        if rpms > 0.0 {
+            total += 60.0 / rpms / 360.0;
+        }
+    }
+
+    let mut offsets = [Duration::ZERO; 360];
+    for (slot, offset) in offsets.iter_mut().enumerate() {
+        *offset = Duration::from_secs_f32(elapsed[sent_order(slot, mirrored)]);
+    }
+    offsets
+}
+
+impl LaserReading {
+    /// Gets the capture time of every beam of a scan decoded without
+    /// mirroring, whose first beam was taken at `start`, at constant speed.
+    pub fn beam_times(&self, start: SystemTime) -> [SystemTime; 360] {
+        beam_offsets(self.rpms, false).map(|offset| start + offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Duration, b: Duration) -> bool {
+        a.abs_diff(b) < Duration::from_micros(10)
+    }
+
+    #[test]
+    fn times_the_beams_in_the_order_they_are_sent() {
+        // A revolution in 200 ms.
+        let step = Duration::from_secs_f32(0.2 / 360.0);
+        let offsets = beam_offsets(300, false);
+        assert_eq!(offsets[359], Duration::ZERO);
+        assert!(close(offsets[0], step * 359));
+
+        let mirrored = beam_offsets(300, true);
+        assert_eq!(mirrored[1], Duration::ZERO);
+        assert!(close(mirrored[0], step * 359));
+
+        let start = SystemTime::UNIX_EPOCH;
+        let mut reading = LaserReading::new();
+        reading.rpms = 300;
+        assert_eq!(reading.beam_times(start)[359], start);
+    }
+
+    #[test]
+    fn follows_the_speed_within_the_scan() {
+        let constant = beam_offsets_with(&[300.0; 360], false);
+        let offsets = beam_offsets(300, false);
+        assert!((0..360).all(|i| close(constant[i], offsets[i])));
+
+        // Half as fast over the first half of the revolution.
+        let mut rpms = [300.0; 360];
+        rpms[180..].fill(150.0);
+        let offsets = beam_offsets_with(&rpms, false);
+        let step = Duration::from_secs_f32(0.2 / 360.0);
+        assert!(close(offsets[179], step * 360));
+        assert!(close(offsets[0], step * (360 + 179)));
+    }
+}
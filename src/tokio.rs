@@ -0,0 +1,594 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Driver based on `tokio-serial`, enabled by the `async_tokio` feature.
+
+use crate::common::{Command, Commands, Core, Held};
+use crate::error::{Error, Result};
+use crate::io;
+use crate::options::{Model, Open, OpenOptions};
+use crate::protocol::FRAME_SIZE;
+use crate::run::{Recovery, RunPolicy, Runner};
+use crate::state::{DriverEvent, DriverState};
+use crate::{AsyncLidarDriver, Hooks, LaserReading};
+use ::tokio::sync::{watch, Mutex, MutexGuard};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio_serial::{SerialPort, SerialPortBuilderExt, SerialStream};
+
+/// This struct allows to read lidar information and to "shutdown" the driver
+pub struct LFCDLaser {
+    core: Core,
+    serial: SerialStream,
+}
+
+impl_common!(LFCDLaser);
+
+impl LFCDLaser {
+    /// Creates a new `LFCDLaser` with the given parameters.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn new<P: AsRef<Path>>(port: P, baud_rate: u32) -> Result<Self> {
+        Self::open_unchecked(&OpenOptions::new(port, baud_rate))
+    }
+
+    /// Creates a new `LFCDLaser` as the options say, see `OpenOptions::open`.
+    /// The timeout is ignored, see `RunPolicy::read_timeout`.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - invalid options, the port is not touched
+    /// - unable to open the specified serial port
+    /// - unable to set the exclusivity of the port (only on unix)
+    pub fn with_options(options: &OpenOptions) -> Result<Self> {
+        options.open()
+    }
+
+    fn open_with(options: &OpenOptions) -> Result<Self> {
+        let serial = Self::open(&options.port, options.baud_rate, options.exclusive)?;
+
+        let mut core = Core::new(options.port.clone(), options.baud_rate);
+        core.exclusive = options.exclusive;
+        let mut lidar = Self { core, serial };
+
+        if options.auto_start {
+            lidar.start();
+        }
+
+        Ok(lidar)
+    }
+
+    /// Opens the port of a configuration, see `with_config`.
+    #[cfg(feature = "config")]
+    fn open_config(config: &crate::config::Config) -> Result<Self> {
+        Self::new(&config.port, config.baud_rate)
+    }
+
+    /// Re-opens the serial port and starts the lidar again,
+    /// notifying the `on_reconnect` callbacks.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub fn reconnect(&mut self) -> Result<()> {
+        self.serial = Self::open(&self.core.port, self.core.baud_rate, self.core.exclusive)?;
+        self.start();
+        // The idle stop writes to the previous port.
+        #[cfg(unix)]
+        if let Some(options) = self.idle_stop() {
+            self.set_idle_stop(Some(options))?;
+        }
+        self.core
+            .hooks
+            .emit_reconnect(&self.core.port.to_string_lossy());
+        self.core.hooks.emit_event(DriverEvent::Reconnected);
+
+        Ok(())
+    }
+
+    /// Gets a reading from the lidar, returing a `LaserReading` object.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub async fn read(&mut self) -> Result<LaserReading> {
+        if self.core.closed() {
+            return Err(Error::DriverClosed);
+        }
+
+        let _busy = self.wake_up();
+        loop {
+            self.apply_commands()?;
+            if let Some(wait) = self.pause_duty() {
+                ::tokio::time::sleep(wait).await;
+                continue;
+            }
+            self.resume_duty();
+            let scan = self.read_watched().await?;
+            if self.in_window() {
+                return Ok(scan);
+            }
+        }
+    }
+
+    /// Reads a scan, restarting the motor when the watchdog says so.
+    async fn read_watched(&mut self) -> Result<LaserReading> {
+        loop {
+            let Some(deadline) = self.core.watchdog.as_ref().map(|w| w.deadline()) else {
+                return self.read_scan().await;
+            };
+            match ::tokio::time::timeout_at(deadline.into(), self.read_scan()).await {
+                Ok(read) => return read,
+                Err(_) => {
+                    if let Some(pause) = self.watchdog_timeout().map_err(|e| self.core.fail(e))? {
+                        ::tokio::time::sleep(pause).await;
+                        self.restart_stalled();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads frames until one completes a scan.
+    async fn read_scan(&mut self) -> Result<LaserReading> {
+        loop {
+            self.apply_commands()?;
+            if let Some(scan) = self.read_frame().await.map_err(|e| self.core.fail(e))? {
+                return Ok(scan);
+            }
+        }
+    }
+
+    /// Gets the scan of the newest complete revolution: the scans waiting in
+    /// the serial buffer are dropped, for control loops where acting on old
+    /// data is worse than skipping some. Reads the next one when none is
+    /// waiting.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub async fn read_latest(&mut self) -> Result<LaserReading> {
+        if self.core.closed() {
+            return Err(Error::DriverClosed);
+        }
+
+        let backlog = self
+            .serial
+            .bytes_to_read()
+            .map_err(|e| self.core.fail(e.into()))? as usize;
+        if backlog >= FRAME_SIZE {
+            let mut bytes = vec![0u8; backlog];
+            let mut filled = 0;
+            let read = io::read_full_tokio(&mut self.serial, &mut bytes, &mut filled).await;
+            self.core.hooks.emit_bytes(&bytes[..filled]);
+            read.map_err(|e| self.core.fail(e.into()))?;
+            if self.core.latest(&bytes) {
+                if let Some(scan) = self.core.decode() {
+                    return Ok(scan);
+                }
+            }
+        }
+        self.read().await
+    }
+
+    /// Reads and decodes a frame, `None` for the scans of the warm-up.
+    async fn read_frame(&mut self) -> Result<Option<LaserReading>> {
+        // A corrupted frame may have left the beginning of this one.
+        let mut start_count = self.core.resume();
+
+        // Wait for data sync of frame: 0xFA, 0XA0
+        while start_count < 2 {
+            // Read one byte
+            let mut filled = start_count;
+            let read = io::read_full_tokio(
+                &mut self.serial,
+                &mut self.core.buff[..=start_count],
+                &mut filled,
+            )
+            .await;
+            self.core.tee(start_count, filled);
+            read?;
+            self.core.sync(&mut start_count)?;
+        }
+        let mut filled = start_count;
+        let read = io::read_full_tokio(&mut self.serial, &mut self.core.buff, &mut filled).await;
+        self.core.tee(start_count, filled);
+        if let Err(e) = read {
+            // The next read completes the frame.
+            self.core.keep(filled);
+            return Err(e.into());
+        }
+
+        Ok(self.core.decode())
+    }
+
+    /// Gets a reading from the lidar, failing with `Error::Timeout` if the
+    /// scan is not complete by `deadline`, e.g. to implement a watchdog.
+    ///
+    /// A timed out read discards the partial frame, the next read synchronizes
+    /// again on the following one.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - no scan before the deadline
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub async fn read_deadline(&mut self, deadline: Instant) -> Result<LaserReading> {
+        match ::tokio::time::timeout_at(deadline.into(), self.read()).await {
+            Ok(read) => read,
+            Err(_) => Err(self.core.fail(Error::Timeout)),
+        }
+    }
+
+    /// Gets `n` consecutive readings, amortizing the per-call overhead
+    /// for consumers processing scans in blocks.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    ///
+    /// The readings completed before the error are discarded.
+    pub async fn read_batch(&mut self, n: usize) -> Result<Vec<LaserReading>> {
+        let mut scans = Vec::with_capacity(n);
+        for _ in 0..n {
+            scans.push(self.read().await?);
+        }
+        Ok(scans)
+    }
+
+    /// Reads scans, passing them to `on_scan`, until `token` is cancelled.
+    /// Then the lidar is stopped and the serial port closed.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    ///
+    /// In both cases the lidar is stopped and the port closed.
+    #[cfg(feature = "cancellation")]
+    pub async fn run_until<F>(
+        mut self,
+        token: tokio_util::sync::CancellationToken,
+        mut on_scan: F,
+    ) -> Result<()>
+    where
+        F: FnMut(LaserReading),
+    {
+        loop {
+            ::tokio::select! {
+                _ = token.cancelled() => break,
+                res = self.read() => on_scan(res?),
+            }
+        }
+
+        // Dropping the driver stops the lidar and closes the port.
+        Ok(())
+    }
+
+    /// Reads scans, passing them to `handler`, recovering from failed reads
+    /// as `policy` says, until SIGINT if `policy.handle_sigint` is set.
+    /// Then the lidar is stopped and the serial port closed.
+    ///
+    /// SIGINT is awaited through `tokio::signal::ctrl_c`, whose handler
+    /// stays installed once `run` returns.
+    ///
+    /// # Errors
+    /// An error variant is returned once the policy gives up, the lidar is
+    /// stopped and the port closed as well.
+    pub async fn run<F>(mut self, mut handler: F, policy: RunPolicy) -> Result<()>
+    where
+        F: FnMut(LaserReading),
+    {
+        // Ctrl-C is awaited here rather than through the runner.
+        let mut runner = Runner::new(RunPolicy {
+            handle_sigint: false,
+            ..policy
+        });
+        let ctrl_c = async {
+            if policy.handle_sigint && ::tokio::signal::ctrl_c().await.is_ok() {
+                return;
+            }
+            std::future::pending::<()>().await
+        };
+        // Kept across reads so that no Ctrl-C is missed between them.
+        let mut ctrl_c = std::pin::pin!(ctrl_c);
+        loop {
+            // A read may wait for the next window of the duty cycle.
+            let off = self
+                .duty_cycle()
+                .map_or(Duration::ZERO, |d| d.period.saturating_sub(d.scanning));
+            let timeout = self.core.read_timeout(runner.policy().read_timeout) + off;
+            // Whichever completes first, the other is dropped.
+            let res = {
+                let read = self.read_deadline(Instant::now() + timeout);
+                let mut read = std::pin::pin!(read);
+                std::future::poll_fn(|cx| match ctrl_c.as_mut().poll(cx) {
+                    Poll::Ready(()) => Poll::Ready(None),
+                    Poll::Pending => read.as_mut().poll(cx).map(Some),
+                })
+                .await
+            };
+            let err = match res {
+                None => break,
+                Some(Ok(scan)) => {
+                    runner.succeeded();
+                    handler(scan);
+                    continue;
+                }
+                Some(Err(e)) => e,
+            };
+            match runner.failed() {
+                Recovery::Retry => {}
+                Recovery::GiveUp => return Err(err),
+                Recovery::Reconnect => loop {
+                    ::tokio::time::sleep(runner.policy().reconnect_delay).await;
+                    match self.reconnect() {
+                        Ok(()) => break,
+                        Err(e) if !runner.reconnect() => return Err(e),
+                        Err(_) => {}
+                    }
+                },
+            }
+        }
+
+        // Dropping the driver stops the lidar and closes the port.
+        Ok(())
+    }
+
+    fn open(port: &Path, baud_rate: u32, exclusive: bool) -> tokio_serial::Result<SerialStream> {
+        let mut serial = tokio_serial::new(io::port_name(port)?, baud_rate).open_native_async()?;
+
+        #[cfg(unix)]
+        serial.set_exclusive(exclusive)?;
+        #[cfg(not(unix))]
+        let _ = exclusive;
+
+        Ok(serial)
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        std::io::Write::write_all(&mut self.serial, &[byte]).ok();
+    }
+
+    fn clear_input(&mut self) -> std::io::Result<()> {
+        Ok(self.serial.clear(tokio_serial::ClearBuffer::Input)?)
+    }
+}
+
+impl Open for LFCDLaser {
+    type Error = Error;
+
+    const DRIVER: &'static str = "tokio";
+
+    const MODELS: &'static [Model] = &[Model::Lds01];
+
+    fn open_unchecked(options: &OpenOptions) -> Result<Self> {
+        Self::open_with(options)
+    }
+}
+
+impl AsyncLidarDriver for LFCDLaser {
+    type Error = Error;
+
+    async fn read(&mut self) -> Result<LaserReading> {
+        LFCDLaser::read(self).await
+    }
+
+    fn start(&mut self) {
+        LFCDLaser::start(self)
+    }
+
+    fn close(&mut self) {
+        LFCDLaser::close(self)
+    }
+}
+
+/// A clonable handle to a `LFCDLaser`, that can be shared among tasks.
+///
+/// Reads are serialized. `start` and `stop` do not wait for the ongoing
+/// read, if any: it applies them at the end of the frame being read, within
+/// a revolution, or when the next window opens while paused by a duty
+/// cycle, then fails with `DriverClosed` after a `stop`. The other methods
+/// wait for the ongoing read to complete.
+#[derive(Clone)]
+pub struct LFCDLaserHandle {
+    inner: Arc<Mutex<LFCDLaser>>,
+    commands: Arc<std::sync::Mutex<Commands>>,
+}
+
+impl LFCDLaserHandle {
+    /// Creates a new handle owning the given driver.
+    pub fn new(mut laser: LFCDLaser) -> Self {
+        let commands = laser
+            .core
+            .commands
+            .get_or_insert_with(Default::default)
+            .clone();
+        Self {
+            inner: Arc::new(Mutex::new(laser)),
+            commands,
+        }
+    }
+
+    async fn lock(&self) -> Held<'_, MutexGuard<'_, LFCDLaser>> {
+        Held::new(self.inner.lock().await, &self.commands)
+    }
+
+    /// Has the read apply `command` if one is ongoing, else holds the driver
+    /// to apply it.
+    async fn send(&self, command: Command) {
+        if !Commands::send(&self.commands, command) {
+            // Holding the driver applies the queued commands, if a read took
+            // it first this waits for the read, which applied them already.
+            drop(self.lock().await);
+        }
+    }
+
+    /// Gets a reading from the lidar, waiting for other readers to complete.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    /// - the driver is closed
+    pub async fn read(&self) -> Result<LaserReading> {
+        self.lock().await.read().await
+    }
+
+    /// Starts the Lidar, without waiting for the ongoing read.
+    pub async fn start(&self) {
+        self.send(Command::Start).await
+    }
+
+    /// Stops the lidar, without waiting for the ongoing read, reads will fail
+    /// until `start` is called.
+    pub async fn stop(&self) {
+        self.send(Command::Stop).await
+    }
+
+    /// Re-opens the serial port and starts the lidar again.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to open the specified serial port
+    /// - unable to set the port to non-exclusive (only on unix)
+    pub async fn reconnect(&self) -> Result<()> {
+        self.lock().await.reconnect()
+    }
+
+    /// Gets the lidars rmp from the last reading
+    pub async fn rpms(&self) -> u16 {
+        self.lock().await.rpms()
+    }
+
+    /// Gets the state of the driver.
+    pub async fn state(&self) -> DriverState {
+        self.lock().await.state()
+    }
+
+    /// Gets the configured serial port
+    pub async fn port(&self) -> PathBuf {
+        self.lock().await.port().to_path_buf()
+    }
+
+    /// Runs `f` with exclusive access to the driver.
+    pub async fn with<R>(&self, f: impl FnOnce(&mut LFCDLaser) -> R) -> R {
+        f(&mut *self.lock().await)
+    }
+}
+
+impl LFCDLaser {
+    /// Converts the driver into a clonable `LFCDLaserHandle`.
+    pub fn into_handle(self) -> LFCDLaserHandle {
+        LFCDLaserHandle::new(self)
+    }
+}
+
+impl LFCDLaser {
+    /// Gets a `LatestScan` updated with every scan read by this driver.
+    pub fn latest_scan(&mut self) -> LatestScan {
+        LatestScan::attach(&mut self.core.hooks)
+    }
+}
+
+/// The most recent scan of a driver, that many tasks can read without
+/// receiving the whole stream.
+///
+/// Readers never slow the driver down: a scan that nobody read before the
+/// next one arrives is simply replaced.
+#[derive(Debug, Clone)]
+pub struct LatestScan {
+    receiver: watch::Receiver<Option<Arc<LaserReading>>>,
+}
+
+impl LatestScan {
+    /// Creates a `LatestScan` updated by the `on_scan` callbacks of `hooks`.
+    pub fn attach(hooks: &mut Hooks) -> Self {
+        let (sender, receiver) = watch::channel(None);
+        hooks.on_scan(move |reading| {
+            sender.send_replace(Some(Arc::new(reading.clone())));
+        });
+        Self { receiver }
+    }
+
+    /// Gets the most recent scan, `None` if no scan has been read yet.
+    pub fn get(&self) -> Option<Arc<LaserReading>> {
+        self.receiver.borrow().clone()
+    }
+
+    /// Checks if a scan arrived since the last one seen by this `LatestScan`.
+    pub fn has_changed(&self) -> bool {
+        self.receiver.has_changed().unwrap_or(false)
+    }
+
+    /// Waits for a scan newer than the last one seen by this `LatestScan`.
+    ///
+    /// Returns `None` once the driver has been dropped.
+    pub async fn next(&mut self) -> Option<Arc<LaserReading>> {
+        self.receiver.changed().await.ok()?;
+        self.receiver.borrow_and_update().clone()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    /// Opens a pseudo terminal, gets its controller and the path of its port.
+    fn pty() -> (File, String) {
+        // SAFETY: the descriptor is checked before being owned by the `File`,
+        // and `ptsname` is only called from this thread.
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(fd >= 0);
+            let controller = File::from_raw_fd(fd);
+            assert_eq!(libc::grantpt(fd), 0);
+            assert_eq!(libc::unlockpt(fd), 0);
+            let port = CStr::from_ptr(libc::ptsname(fd)).to_str().unwrap().into();
+            (controller, port)
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn stops_while_a_read_waits_for_the_driver() {
+        let (_controller, port) = pty();
+        let handle = LFCDLaser::new(port, 230400).unwrap().into_handle();
+
+        let guard = handle.inner.lock().await;
+        let waiting = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.state().await }
+        });
+        tokio::task::yield_now().await;
+        // The mutex is fair: the driver goes to the waiting task, which only
+        // runs once `stop` yields.
+        drop(guard);
+        handle.stop().await;
+
+        assert_eq!(waiting.await.unwrap(), DriverState::Closed);
+        assert_eq!(handle.state().await, DriverState::Closed);
+    }
+}
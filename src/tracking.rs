@@ -0,0 +1,425 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Nearest-neighbour tracking of the objects around the lidar.
+//!
+//! Every scan is split in clusters, each cluster is paired with the closest
+//! object of the previous scan, the closest pairs first, and keeps its id.
+//! The clusters left become new objects, and the objects not seen for a few
+//! scans are dropped. Works best on the foreground of a `BackgroundModel`,
+//! so that the walls are not tracked.
+//!
+//! The position and the velocity of every object are smoothed by a constant
+//! velocity Kalman filter, the objects are paired with the clusters from
+//! the position predicted for the new scan.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::tracking::Tracker;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let mut tracker = Tracker::new().with_max_distance(0.3);
+//! for object in tracker.update(&reading) {
+//!     println!("object {} at ({}, {})", object.id, object.x, object.y);
+//!     if let Some(t) = object.time_to_collision(0.2) {
+//!         println!("hits the robot in {t} s");
+//!     }
+//! }
+//! ```
+
+use crate::cluster::clusters;
+use crate::LaserReading;
+use std::collections::VecDeque;
+
+/// Constant velocity Kalman filter of a coordinate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Axis {
+    position: f32,
+    velocity: f32,
+    covariance: [[f32; 2]; 2],
+}
+
+impl Axis {
+    fn new(position: f32, measurement_noise: f32) -> Self {
+        Self {
+            position,
+            velocity: 0.0,
+            // Nothing is known about the velocity, up to a few m/s.
+            covariance: [[measurement_noise * measurement_noise, 0.0], [0.0, 4.0]],
+        }
+    }
+
+    /// Moves the state `dt` seconds ahead, `noise` is the standard deviation
+    /// of the acceleration.
+    fn predict(&mut self, dt: f32, noise: f32) {
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        let q = noise * noise;
+        self.position += self.velocity * dt;
+        self.covariance = [
+            [
+                p00 + dt * (p01 + p10) + dt * dt * p11 + q * dt.powi(4) / 4.0,
+                p01 + dt * p11 + q * dt.powi(3) / 2.0,
+            ],
+            [p10 + dt * p11 + q * dt.powi(3) / 2.0, p11 + q * dt * dt],
+        ];
+    }
+
+    /// Corrects the state with a measured position, `noise` is its standard deviation.
+    fn correct(&mut self, measured: f32, noise: f32) {
+        let [[p00, p01], [p10, p11]] = self.covariance;
+        let s = p00 + noise * noise;
+        let (k0, k1) = (p00 / s, p10 / s);
+        let innovation = measured - self.position;
+        self.position += k0 * innovation;
+        self.velocity += k1 * innovation;
+        self.covariance = [
+            [(1.0 - k0) * p00, (1.0 - k0) * p01],
+            [p10 - k1 * p00, p11 - k1 * p01],
+        ];
+    }
+}
+
+/// An object followed across scans, coordinates in meters in the lidar frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedObject {
+    /// Identifier of the object, never reused by a tracker
+    pub id: u64,
+    /// Filtered position, predicted while the object is missed
+    pub x: f32,
+    pub y: f32,
+    /// Filtered velocity, in m/s
+    pub vx: f32,
+    pub vy: f32,
+    /// Beams hitting the object in the last scan it was seen
+    pub points: usize,
+    /// Scans since the object was first seen
+    pub age: usize,
+    /// Scans in a row the object has not been seen, 0 if seen in the last one
+    pub missed: usize,
+    /// Last positions of the object, oldest first
+    pub history: VecDeque<(f32, f32)>,
+    filter: [Axis; 2],
+}
+
+impl TrackedObject {
+    /// Gets the displacement, in meters, between the last two positions.
+    pub fn displacement(&self) -> Option<(f32, f32)> {
+        let n = self.history.len();
+        (n >= 2).then(|| {
+            let (a, b) = (self.history[n - 2], self.history[n - 1]);
+            (b.0 - a.0, b.1 - a.1)
+        })
+    }
+
+    /// Gets the speed of the object, in m/s.
+    pub fn speed(&self) -> f32 {
+        self.vx.hypot(self.vy)
+    }
+
+    /// Gets the time, in seconds, before the object gets within `radius`
+    /// meters of the lidar at its current velocity, `None` if it does not.
+    pub fn time_to_collision(&self, radius: f32) -> Option<f32> {
+        // Smallest t >= 0 with |p + v t| = radius.
+        let a = self.vx * self.vx + self.vy * self.vy;
+        let b = 2.0 * (self.x * self.vx + self.y * self.vy);
+        let c = self.x * self.x + self.y * self.y - radius * radius;
+        if c <= 0.0 {
+            return Some(0.0);
+        }
+        if a <= f32::EPSILON || b >= 0.0 {
+            return None;
+        }
+        let discriminant = b * b - 4.0 * a * c;
+        (discriminant >= 0.0).then(|| (-b - discriminant.sqrt()) / (2.0 * a))
+    }
+
+    fn sync(&mut self) {
+        let [x, y] = self.filter;
+        (self.x, self.y) = (x.position, y.position);
+        (self.vx, self.vy) = (x.velocity, y.velocity);
+    }
+}
+
+/// Associates the clusters of consecutive scans, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct Tracker {
+    cluster_gap: f32,
+    min_points: usize,
+    max_distance: f32,
+    max_missed: usize,
+    history: usize,
+    period: f32,
+    process_noise: f32,
+    measurement_noise: f32,
+    objects: Vec<TrackedObject>,
+    next_id: u64,
+}
+
+impl Tracker {
+    /// Creates a tracker of the clusters of at least 3 beams, moving up to
+    /// 0.5 m between two scans.
+    pub fn new() -> Self {
+        Self {
+            cluster_gap: 0.1,
+            min_points: 3,
+            max_distance: 0.5,
+            max_missed: 5,
+            history: 50,
+            period: 0.2,
+            process_noise: 1.0,
+            measurement_noise: 0.05,
+            objects: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Sets the maximum distance, in meters, between two points of the same cluster.
+    pub fn with_cluster_gap(mut self, gap: f32) -> Self {
+        self.cluster_gap = gap;
+        self
+    }
+
+    /// Sets the minimum number of beams of a tracked cluster.
+    pub fn with_min_points(mut self, min_points: usize) -> Self {
+        self.min_points = min_points;
+        self
+    }
+
+    /// Sets the maximum distance, in meters, an object moves between two scans.
+    pub fn with_max_distance(mut self, distance: f32) -> Self {
+        self.max_distance = distance;
+        self
+    }
+
+    /// Sets the scans in a row an object may be missed before being dropped.
+    pub fn with_max_missed(mut self, scans: usize) -> Self {
+        self.max_missed = scans;
+        self
+    }
+
+    /// Sets the number of positions kept for every object.
+    pub fn with_history(mut self, positions: usize) -> Self {
+        self.history = positions;
+        self
+    }
+
+    /// Sets the time, in seconds, between two scans when it is not known
+    /// from their RPMs.
+    pub fn with_period(mut self, period: f32) -> Self {
+        self.period = period;
+        self
+    }
+
+    /// Sets the standard deviation, in m/s², of the changes of velocity of
+    /// the objects: the higher, the faster the velocity follows the clusters.
+    pub fn with_process_noise(mut self, noise: f32) -> Self {
+        self.process_noise = noise;
+        self
+    }
+
+    /// Sets the standard deviation, in meters, of the position of the clusters.
+    pub fn with_measurement_noise(mut self, noise: f32) -> Self {
+        self.measurement_noise = noise;
+        self
+    }
+
+    /// Gets the objects currently tracked.
+    pub fn objects(&self) -> &[TrackedObject] {
+        &self.objects
+    }
+
+    /// Drops all the objects, the ids are not reused.
+    pub fn clear(&mut self) {
+        self.objects.clear();
+    }
+
+    /// Tracks the clusters of a scan, returns the objects currently tracked.
+    pub fn update(&mut self, reading: &LaserReading) -> &[TrackedObject] {
+        let detections: Vec<((f32, f32), usize)> = clusters(reading, self.cluster_gap)
+            .into_iter()
+            .filter(|c| c.len() >= self.min_points)
+            .map(|c| {
+                let n = c.len() as f32;
+                let (sx, sy) = c
+                    .iter()
+                    .map(|&i| reading.point(i))
+                    .fold((0.0, 0.0), |s, p| (s.0 + p.0, s.1 + p.1));
+                ((sx / n, sy / n), c.len())
+            })
+            .collect();
+        let dt = match reading.scan_time() {
+            t if t > 0.0 => t,
+            _ => self.period,
+        };
+        self.track(&detections, dt)
+    }
+
+    /// Tracks positions detected by other means, e.g. the people of a
+    /// `LegDetector`, `dt` seconds after the previous ones, returns the
+    /// objects currently tracked.
+    pub fn update_positions(&mut self, positions: &[(f32, f32)], dt: f32) -> &[TrackedObject] {
+        let detections: Vec<((f32, f32), usize)> = positions.iter().map(|&p| (p, 1)).collect();
+        self.track(&detections, dt)
+    }
+
+    fn track(&mut self, detections: &[((f32, f32), usize)], dt: f32) -> &[TrackedObject] {
+        for object in self.objects.iter_mut() {
+            for axis in object.filter.iter_mut() {
+                axis.predict(dt, self.process_noise);
+            }
+            object.sync();
+        }
+
+        // Every pair close enough, the closest first.
+        let mut pairs: Vec<(f32, usize, usize)> = Vec::new();
+        for (o, object) in self.objects.iter().enumerate() {
+            for (d, ((x, y), _)) in detections.iter().enumerate() {
+                let distance = (x - object.x).hypot(y - object.y);
+                if distance <= self.max_distance {
+                    pairs.push((distance, o, d));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut matched_objects = vec![false; self.objects.len()];
+        let mut matched_detections = vec![false; detections.len()];
+        for (_, o, d) in pairs {
+            if matched_objects[o] || matched_detections[d] {
+                continue;
+            }
+            matched_objects[o] = true;
+            matched_detections[d] = true;
+
+            let ((x, y), points) = detections[d];
+            let object = &mut self.objects[o];
+            object.filter[0].correct(x, self.measurement_noise);
+            object.filter[1].correct(y, self.measurement_noise);
+            object.sync();
+            object.points = points;
+            object.missed = 0;
+            object.history.push_back((object.x, object.y));
+            if object.history.len() > self.history {
+                object.history.pop_front();
+            }
+        }
+
+        for (object, matched) in self.objects.iter_mut().zip(&matched_objects) {
+            object.age += 1;
+            if !matched {
+                object.missed += 1;
+            }
+        }
+        let max_missed = self.max_missed;
+        self.objects.retain(|o| o.missed <= max_missed);
+
+        for (&((x, y), points), _) in detections
+            .iter()
+            .zip(&matched_detections)
+            .filter(|(_, &matched)| !matched)
+        {
+            let mut history = VecDeque::with_capacity(self.history.min(64));
+            if self.history > 0 {
+                history.push_back((x, y));
+            }
+            self.objects.push(TrackedObject {
+                id: self.next_id,
+                x,
+                y,
+                vx: 0.0,
+                vy: 0.0,
+                points,
+                age: 0,
+                missed: 0,
+                history,
+                filter: [
+                    Axis::new(x, self.measurement_noise),
+                    Axis::new(y, self.measurement_noise),
+                ],
+            });
+            self.next_id += 1;
+        }
+
+        &self.objects
+    }
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{encode_frame, fixtures, Fixture};
+
+    /// The `no_returns` fixture with a 5 beams wide object 1 m away.
+    fn object_at(degree: usize) -> LaserReading {
+        let mut scan = fixtures()[1].expected.clone();
+        for i in degree - 2..=degree + 2 {
+            scan.ranges[i] = 1000;
+        }
+        Fixture {
+            name: format!("object at {degree}"),
+            frame: encode_frame(&scan).to_vec(),
+            expected: scan,
+        }
+        .decode()
+    }
+
+    #[test]
+    fn follows_a_moving_object() {
+        let mut tracker = Tracker::new();
+        for step in 0..10 {
+            let objects = tracker.update(&object_at(10 + 2 * step));
+            assert_eq!(objects.len(), 1);
+            assert_eq!(objects[0].id, 0);
+            assert_eq!(objects[0].points, 5);
+            assert_eq!(objects[0].age, step);
+        }
+
+        let object = &tracker.objects()[0];
+        // 2 degrees at 1 m every scan, counter-clockwise.
+        let expected = 2f32.to_radians() / fixtures()[1].expected.scan_time();
+        assert!(object.vy > 0.0);
+        assert!((object.speed() - expected).abs() < 0.1 * expected);
+        assert_eq!(object.history.len(), 10);
+    }
+
+    #[test]
+    fn drops_the_objects_no_longer_seen() {
+        let mut tracker = Tracker::new().with_max_missed(1);
+        tracker.update(&object_at(90));
+        let empty = fixtures()[1].decode();
+        assert_eq!(tracker.update(&empty)[0].missed, 1);
+        assert!(tracker.update(&empty).is_empty());
+
+        // A new object gets a new id.
+        assert_eq!(tracker.update(&object_at(90))[0].id, 1);
+    }
+
+    #[test]
+    fn keeps_the_ids_of_a_static_scene() {
+        let room = fixtures()[0].decode();
+        let mut tracker = Tracker::new();
+        let first: Vec<u64> = tracker.update(&room).iter().map(|o| o.id).collect();
+        assert!(!first.is_empty());
+        for _ in 0..5 {
+            let objects = tracker.update(&room);
+            assert_eq!(objects.iter().map(|o| o.id).collect::<Vec<_>>(), first);
+            assert!(objects.iter().all(|o| o.speed() < 1e-3));
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Driver of the lidar over any byte transport, for the adapters not
+//! exposed as a serial port by the OS, e.g. the CP2102 of the LDS-01 driven
+//! from user space by the `android_usb` and `nusb` features, or a Bluetooth
+//! serial bridge reached by the `rfcomm` feature.
+//!
+//! A frame cut by an error, e.g. a timeout while the data comes in bursts,
+//! is completed by the next read.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::transport::{Transport, TransportLaser};
+//! use std::io::{self, Cursor, Read, Write};
+//!
+//! /// Replays a recording, ignoring the commands.
+//! struct Replay(Cursor<Vec<u8>>);
+//!
+//! impl Read for Replay {
+//!     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+//!         self.0.read(buf)
+//!     }
+//! }
+//!
+//! impl Write for Replay {
+//!     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+//!         Ok(buf.len())
+//!     }
+//!
+//!     fn flush(&mut self) -> io::Result<()> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! impl Transport for Replay {}
+//!
+//! let mut laser = TransportLaser::new(Replay(Cursor::new(Vec::new())));
+//! assert!(laser.read().is_err());
+//! ```
+
+use crate::protocol::{decode_frame, FIRST_INDEX, FRAME_SIZE, START_BYTE, STOP_BYTE, SYNC_BYTE};
+use crate::{Error, LaserReading, LidarDriver};
+use std::io::{self, Read, Write};
+
+/// Baud rate of the LDS-01.
+pub const BAUD_RATE: u32 = 230_400;
+
+/// Vendor and product ids of the CP2102 adapter of the LDS-01.
+pub const CP2102_ID: (u16, u16) = (0x10C4, 0xEA60);
+
+/// Requests of the CP210x adapters, from the Silicon Labs AN571.
+#[cfg(any(
+    feature = "nusb",
+    all(
+        feature = "android_usb",
+        any(target_os = "android", target_os = "linux")
+    )
+))]
+pub(crate) mod cp210x {
+    /// Interface carrying the serial port.
+    pub(crate) const INTERFACE: u8 = 0;
+    /// Bulk endpoint receiving from the adapter.
+    pub(crate) const BULK_IN: u8 = 0x81;
+    /// Bulk endpoint sending to the adapter.
+    pub(crate) const BULK_OUT: u8 = 0x01;
+
+    pub(crate) const IFC_ENABLE: u8 = 0x00;
+    pub(crate) const SET_LINE_CTL: u8 = 0x03;
+    pub(crate) const PURGE: u8 = 0x12;
+    pub(crate) const SET_BAUDRATE: u8 = 0x1E;
+    /// 8 data bits, no parity, 1 stop bit.
+    pub(crate) const LINE_8N1: u16 = 0x0800;
+    /// Clears the transmit and receive queues.
+    pub(crate) const PURGE_ALL: u16 = 0x000F;
+}
+
+/// A byte stream to and from the lidar.
+pub trait Transport: Read + Write {
+    /// Discards the bytes received and not read yet, nothing by default.
+    ///
+    /// # Errors
+    /// An error variant is returned if the input cannot be cleared.
+    fn clear_input(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Driver of the LDS-01 over a `Transport`.
+pub struct TransportLaser<T: Transport> {
+    transport: T,
+    buff: Box<[u8; FRAME_SIZE]>,
+    /// Bytes of the frame in `buff` read before an error
+    filled: usize,
+    rpms: u16,
+    decode_errors: u64,
+}
+
+impl<T: Transport> TransportLaser<T> {
+    /// Creates a driver over a transport and starts the lidar.
+    pub fn new(transport: T) -> Self {
+        let mut laser = Self {
+            transport,
+            buff: Box::new([0; FRAME_SIZE]),
+            filled: 0,
+            rpms: 0,
+            decode_errors: 0,
+        };
+        laser.start();
+        laser
+    }
+
+    /// Gets the transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Gets the lidars rmp from the last reading
+    pub fn rpms(&self) -> u16 {
+        self.rpms
+    }
+
+    /// Gets the number of packets dropped because of a bad header.
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors
+    }
+
+    /// Gets a reading from the lidar, returing a `LaserReading` object.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read from the transport
+    /// - no data before the timeout of the transport
+    pub fn read(&mut self) -> Result<LaserReading, Error> {
+        // A read cut by an error left the beginning of this frame.
+        let mut filled = std::mem::take(&mut self.filled);
+        if filled < 2 {
+            // Wait for data sync of frame: 0xFA, 0XA0
+            let mut start_count = 0;
+            while start_count < 2 {
+                let mut filled = start_count;
+                crate::io::read_full(
+                    &mut self.transport,
+                    &mut self.buff[..=start_count],
+                    &mut filled,
+                )?;
+                start_count = match self.buff[start_count] {
+                    SYNC_BYTE if start_count == 0 => 1,
+                    FIRST_INDEX if start_count == 1 => 2,
+                    SYNC_BYTE => {
+                        self.buff[0] = SYNC_BYTE;
+                        1
+                    }
+                    _ => 0,
+                };
+            }
+            filled = start_count;
+        }
+        if let Err(e) = crate::io::read_full(&mut self.transport, &mut self.buff[..], &mut filled) {
+            // The next read completes the frame.
+            self.filled = filled;
+            return Err(e.into());
+        }
+
+        let scan = decode_frame(&self.buff, |_| self.decode_errors += 1);
+        self.rpms = scan.rpms;
+        Ok(scan)
+    }
+
+    /// Starts the Lidar
+    pub fn start(&mut self) {
+        // Bytes received before the motor stopped would corrupt the first scan.
+        self.filled = 0;
+        self.transport.clear_input().ok();
+        self.send(START_BYTE);
+    }
+
+    /// Stops the lidar
+    pub fn close(&mut self) {
+        // Stopping the Lidar, ignoring the result.
+        self.send(STOP_BYTE);
+    }
+
+    fn send(&mut self, byte: u8) {
+        // Buffered transports only send on flush.
+        self.transport
+            .write_all(&[byte])
+            .and_then(|_| self.transport.flush())
+            .ok();
+    }
+}
+
+impl<T: Transport> LidarDriver for TransportLaser<T> {
+    type Error = Error;
+
+    fn read(&mut self) -> Result<LaserReading, Error> {
+        TransportLaser::read(self)
+    }
+
+    fn start(&mut self) {
+        TransportLaser::start(self)
+    }
+
+    fn close(&mut self) {
+        TransportLaser::close(self)
+    }
+}
+
+impl<T: Transport> Drop for TransportLaser<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{assert_scan_eq, fixtures};
+    use std::collections::VecDeque;
+
+    /// A wire delivering chunks of bytes, `None` failing a read, and
+    /// recording the bytes sent.
+    #[derive(Default)]
+    struct Wire {
+        chunks: VecDeque<Option<Vec<u8>>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for Wire {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.chunks.pop_front() {
+                None => Ok(0),
+                Some(None) => Err(io::ErrorKind::BrokenPipe.into()),
+                Some(Some(mut chunk)) => {
+                    let n = buf.len().min(chunk.len());
+                    buf[..n].copy_from_slice(&chunk[..n]);
+                    if n < chunk.len() {
+                        self.chunks.push_front(Some(chunk.split_off(n)));
+                    }
+                    Ok(n)
+                }
+            }
+        }
+    }
+
+    impl Write for Wire {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for Wire {}
+
+    fn laser(chunks: Vec<Option<Vec<u8>>>) -> TransportLaser<Wire> {
+        let mut laser = TransportLaser::new(Wire::default());
+        laser.transport().chunks = chunks.into();
+        laser
+    }
+
+    #[test]
+    fn syncs_on_the_frame_header() {
+        let room = &fixtures()[0];
+        let mut bytes = vec![0x00, SYNC_BYTE, SYNC_BYTE];
+        bytes.extend_from_slice(&room.frame);
+        let mut laser = laser(vec![Some(bytes)]);
+        assert_scan_eq(&laser.read().unwrap(), &room.expected);
+        assert_eq!(laser.rpms(), room.expected.rpms);
+
+        laser.close();
+        assert_eq!(laser.transport().sent, [START_BYTE, STOP_BYTE]);
+        assert!(laser.read().is_err());
+    }
+
+    #[test]
+    fn completes_a_frame_cut_by_an_error() {
+        let room = &fixtures()[0];
+        let (head, tail) = room.frame.split_at(1000);
+        let mut laser = laser(vec![Some(head.to_vec()), None, Some(tail.to_vec())]);
+        assert!(laser.read().is_err());
+        assert_scan_eq(&laser.read().unwrap(), &room.expected);
+    }
+
+    #[test]
+    fn counts_the_corrupted_packets() {
+        let corrupted = &fixtures()[3];
+        let mut laser = laser(vec![Some(corrupted.frame.clone())]);
+        assert_scan_eq(&laser.read().unwrap(), &corrupted.expected);
+        assert_eq!(laser.decode_errors(), 1);
+    }
+}
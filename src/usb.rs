@@ -0,0 +1,90 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Reset of the USB adapter of the lidar, enabled by the `usb_reset`
+//! feature on Linux.
+//!
+//! A reset recovers an adapter that stopped answering without rebooting
+//! the robot. It needs write access to the device node in `/dev/bus/usb`,
+//! granted to root or through a udev rule. The serial port disappears
+//! while the adapter enumerates again and must be re-opened after that.
+//!
+//! ```no_run
+//! use hls_lfcd_lds_driver::usb;
+//!
+//! usb::reset("/dev/ttyUSB0")?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// `USBDEVFS_RESET`, `_IO('U', 20)` in `linux/usbdevice_fs.h`.
+const USBDEVFS_RESET: libc::c_ulong = 0x5514;
+
+/// Gets the device node in `/dev/bus/usb` of the adapter behind a serial port.
+///
+/// # Errors
+/// An error variant is returned if the port is not a USB serial port.
+pub fn device_node<P: AsRef<Path>>(port: P) -> io::Result<PathBuf> {
+    // Follows links such as the ones in `/dev/serial/by-id`.
+    let port = fs::canonicalize(port)?;
+    let name = port
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid serial port"))?;
+    let device = fs::canonicalize(Path::new("/sys/class/tty").join(name).join("device"))?;
+
+    // The USB device is the first ancestor with a bus and device number.
+    for dir in device.ancestors() {
+        let (Ok(bus), Ok(dev)) = (
+            fs::read_to_string(dir.join("busnum")),
+            fs::read_to_string(dir.join("devnum")),
+        ) else {
+            continue;
+        };
+        let parse = |s: &str| {
+            s.trim()
+                .parse::<u32>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        };
+        return Ok(PathBuf::from(format!(
+            "/dev/bus/usb/{:03}/{:03}",
+            parse(&bus)?,
+            parse(&dev)?
+        )));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} is not a USB serial port", port.display()),
+    ))
+}
+
+/// Resets the USB adapter behind a serial port.
+///
+/// # Errors
+/// An error variant is returned in case of:
+/// - the port is not a USB serial port
+/// - no permission to open the device node
+/// - the kernel refuses the reset
+pub fn reset<P: AsRef<Path>>(port: P) -> io::Result<()> {
+    let node = device_node(port)?;
+    let device = OpenOptions::new().write(true).open(node)?;
+    // SAFETY: `USBDEVFS_RESET` takes no argument and the descriptor is open.
+    if unsafe { libc::ioctl(device.as_raw_fd(), USBDEVFS_RESET as _) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
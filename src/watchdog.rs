@@ -0,0 +1,182 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Restarting a stalled motor, see `LFCDLaser::set_watchdog`.
+//!
+//! LDS units occasionally stall: the motor stops and so does the data, a
+//! read would wait forever. With the watchdog, once no frame has been
+//! decoded for `WatchdogOptions::interval` the read stops the motor, starts
+//! it again and keeps waiting, reporting a `DriverEvent::MotorRestarted`.
+//! After `max_restarts` restarts in a row without a frame the read fails
+//! with `Error::Timeout` instead, the lidar is likely gone.
+//!
+//! ```no_run
+//! # #[cfg(feature = "sync")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use hls_lfcd_lds_driver::sync::LFCDLaser;
+//! use hls_lfcd_lds_driver::watchdog::WatchdogOptions;
+//! use std::time::Duration;
+//!
+//! let mut laser = LFCDLaser::new("/dev/ttyUSB0", 230400)?;
+//! laser.set_watchdog(Some(WatchdogOptions {
+//!     interval: Duration::from_secs(2),
+//!     ..Default::default()
+//! }));
+//! let reading = laser.read()?;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "sync"))]
+//! # fn main() {}
+//! ```
+
+use std::time::Duration;
+
+/// When the motor is restarted and how.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ser_de", derive(serde::Serialize, serde::Deserialize))]
+pub struct WatchdogOptions {
+    /// Time without a decoded frame after which the motor is restarted
+    pub interval: Duration,
+    /// Time the motor is left stopped before being started again
+    pub pause: Duration,
+    /// Restarts in a row without a frame before the read fails
+    pub max_restarts: u32,
+    /// Scans dropped after the motor has been restarted
+    pub warmup_scans: usize,
+}
+
+impl Default for WatchdogOptions {
+    fn default() -> Self {
+        Self {
+            // 15 revolutions at 300 rpm.
+            interval: Duration::from_secs(3),
+            pause: Duration::from_millis(500),
+            max_restarts: 3,
+            // A second at 300 rpm.
+            warmup_scans: 5,
+        }
+    }
+}
+
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+pub(crate) use self::state::{Verdict, Watchdog};
+
+#[cfg(any(feature = "sync", feature = "async_tokio", feature = "async_smol"))]
+mod state {
+    use super::WatchdogOptions;
+    use std::time::Instant;
+
+    /// What a read does once the watchdog has been checked.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) enum Verdict {
+        /// The interval has not elapsed yet
+        Wait,
+        /// The motor must be restarted
+        Restart,
+        /// Too many restarts in a row, the read fails
+        GiveUp,
+    }
+
+    /// Time of the last decoded frame and the restarts since.
+    pub(crate) struct Watchdog {
+        pub(crate) options: WatchdogOptions,
+        last_frame: Instant,
+        /// Restarts since the last decoded frame.
+        pub(crate) restarts: u32,
+        /// Restarts since the watchdog has been enabled.
+        pub(crate) total: u64,
+    }
+
+    impl Watchdog {
+        pub(crate) fn new(options: WatchdogOptions) -> Self {
+            Self {
+                options,
+                last_frame: Instant::now(),
+                restarts: 0,
+                total: 0,
+            }
+        }
+
+        /// Records a decoded frame.
+        pub(crate) fn fed(&mut self) {
+            self.last_frame = Instant::now();
+            self.restarts = 0;
+        }
+
+        /// Records that the motor has been started, by `start` or after an
+        /// idle stop, the interval starts again.
+        pub(crate) fn started(&mut self) {
+            self.last_frame = Instant::now();
+        }
+
+        /// Gets the time the motor is restarted if no frame is decoded before.
+        pub(crate) fn deadline(&self) -> Instant {
+            self.last_frame + self.options.interval
+        }
+
+        /// Checks whether the motor must be restarted, recording the restart.
+        pub(crate) fn check(&mut self) -> Verdict {
+            if Instant::now() < self.deadline() {
+                return Verdict::Wait;
+            }
+            if self.restarts >= self.options.max_restarts {
+                // The next read gets a whole series of restarts again.
+                self.fed();
+                return Verdict::GiveUp;
+            }
+            self.restarts += 1;
+            self.total += 1;
+            self.last_frame = Instant::now();
+            Verdict::Restart
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "sync", feature = "async_tokio", feature = "async_smol")
+))]
+mod tests {
+    use super::*;
+
+    fn watchdog(interval: Duration) -> Watchdog {
+        Watchdog::new(WatchdogOptions {
+            interval,
+            max_restarts: 2,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn waits_for_the_interval() {
+        let mut watchdog = watchdog(Duration::from_secs(60));
+        assert_eq!(watchdog.check(), Verdict::Wait);
+        assert_eq!((watchdog.restarts, watchdog.total), (0, 0));
+    }
+
+    #[test]
+    fn restarts_then_gives_up() {
+        let mut watchdog = watchdog(Duration::ZERO);
+        assert_eq!(watchdog.check(), Verdict::Restart);
+        assert_eq!(watchdog.check(), Verdict::Restart);
+        assert_eq!(watchdog.check(), Verdict::GiveUp);
+        // The next read starts a new series.
+        assert_eq!(watchdog.check(), Verdict::Restart);
+        assert_eq!((watchdog.restarts, watchdog.total), (1, 3));
+
+        watchdog.fed();
+        assert_eq!(watchdog.restarts, 0);
+        assert_eq!(watchdog.check(), Verdict::Restart);
+    }
+}
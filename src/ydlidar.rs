@@ -0,0 +1,459 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Driver for the YDLIDAR X-series triangulation lidars (X2, X4), enabled
+//! by the `ydlidar` feature.
+//!
+//! The lidar streams packets of samples, each with the angle of its first
+//! and last sample, and flags the packet starting a new revolution. The
+//! samples are binned into a `LaserReading` at the nearest degree, keeping
+//! the closest one, so the rest of the crate works with both lidars
+//! through `LidarDriver`. The X-series measures no intensity, the
+//! intensities are left to 0.
+//!
+//! ```no_run
+//! use hls_lfcd_lds_driver::ydlidar::{YdLidar, X4_BAUD_RATE};
+//! use hls_lfcd_lds_driver::LidarDriver;
+//!
+//! let mut lidar = YdLidar::new("/dev/ttyUSB0", X4_BAUD_RATE)?;
+//! let scan = lidar.read()?;
+//! println!("{} points", scan.points().len());
+//! # Ok::<(), hls_lfcd_lds_driver::Error>(())
+//! ```
+
+use crate::io::{port_name, read_full};
+use crate::options::{Model, Open, OpenOptions};
+use crate::Error;
+use crate::{LaserReading, LidarDriver};
+use serialport::SerialPort;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Baud rate of the X4.
+pub const X4_BAUD_RATE: u32 = 128_000;
+/// Baud rate of the X2.
+pub const X2_BAUD_RATE: u32 = 115_200;
+
+/// Command starting the scan.
+pub const START_SCAN: [u8; 2] = [0xA5, 0x60];
+/// Command stopping the scan.
+pub const STOP_SCAN: [u8; 2] = [0xA5, 0x65];
+/// First bytes of every packet, `0x55AA` little-endian.
+pub const PACKET_HEADER: [u8; 2] = [0xAA, 0x55];
+/// Size of the header of a packet, before the samples.
+pub const HEADER_SIZE: usize = 10;
+
+/// A packet of samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct YdPacket {
+    /// Set on the first packet of a revolution
+    pub start: bool,
+    /// Scan frequency in tenths of Hz, only reported by the first packet of a revolution
+    pub frequency: u8,
+    /// Samples as angle, in degrees clockwise, and distance, in mm
+    pub samples: Vec<(f32, u16)>,
+}
+
+/// Error decoding a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YdError {
+    /// The packet does not start with `PACKET_HEADER`
+    BadHeader,
+    /// The packet is shorter than its header announces
+    Truncated,
+    /// The checksum does not match
+    Checksum,
+}
+
+impl std::fmt::Display for YdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            YdError::BadHeader => write!(f, "bad packet header"),
+            YdError::Truncated => write!(f, "truncated packet"),
+            YdError::Checksum => write!(f, "checksum mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for YdError {}
+
+/// Gets the size of a packet from its header.
+pub fn packet_size(header: &[u8; HEADER_SIZE]) -> usize {
+    HEADER_SIZE + 2 * usize::from(header[3])
+}
+
+/// Decodes a packet, header included.
+///
+/// # Errors
+/// An error variant is returned if the header, the length or the checksum is wrong.
+pub fn decode_packet(packet: &[u8]) -> Result<YdPacket, YdError> {
+    if packet.len() < HEADER_SIZE {
+        return Err(YdError::Truncated);
+    }
+    if packet[..2] != PACKET_HEADER {
+        return Err(YdError::BadHeader);
+    }
+    let word = |i: usize| u16::from_le_bytes([packet[i], packet[i + 1]]);
+    let (ct, count) = (packet[2], usize::from(packet[3]));
+    if packet.len() < HEADER_SIZE + 2 * count {
+        return Err(YdError::Truncated);
+    }
+
+    // XOR of every word but the checksum.
+    let mut checksum = word(0) ^ word(2) ^ word(4) ^ word(6);
+    for n in 0..count {
+        checksum ^= word(HEADER_SIZE + 2 * n);
+    }
+    if checksum != word(8) {
+        return Err(YdError::Checksum);
+    }
+
+    // Angles are in 64ths of degree, shifted by one bit.
+    let first = f32::from(word(4) >> 1) / 64.0;
+    let last = f32::from(word(6) >> 1) / 64.0;
+    let mut span = last - first;
+    if span < 0.0 {
+        span += 360.0;
+    }
+
+    let samples = (0..count)
+        .map(|n| {
+            let distance = word(HEADER_SIZE + 2 * n) / 4;
+            let step = if count > 1 {
+                span * n as f32 / (count - 1) as f32
+            } else {
+                0.0
+            };
+            (
+                (first + step + correction(distance)).rem_euclid(360.0),
+                distance,
+            )
+        })
+        .collect();
+
+    Ok(YdPacket {
+        start: ct & 0x01 != 0,
+        frequency: ct >> 1,
+        samples,
+    })
+}
+
+/// Gets the correction, in degrees, of the angle of a sample due to the
+/// offset between the laser and the camera of the triangulation.
+fn correction(distance: u16) -> f32 {
+    if distance == 0 {
+        return 0.0;
+    }
+    let d = f32::from(distance);
+    (21.8 * (155.3 - d) / (155.3 * d)).atan().to_degrees()
+}
+
+/// Accumulates the packets of a revolution into a `LaserReading`.
+#[derive(Debug, Clone, Default)]
+pub struct ScanAssembler {
+    scan: LaserReading,
+    samples: usize,
+}
+
+impl ScanAssembler {
+    /// Creates an empty assembler.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a packet, returns the previous revolution when it starts a new one.
+    pub fn push(&mut self, packet: &YdPacket) -> Option<LaserReading> {
+        let done = if packet.start {
+            let scan = std::mem::take(&mut self.scan);
+            let samples = std::mem::take(&mut self.samples);
+            // The rpms come with the packet starting the revolution.
+            self.scan.rpms = u16::from(packet.frequency) * 6;
+            (samples > 0).then_some(scan)
+        } else {
+            None
+        };
+
+        for &(angle, distance) in &packet.samples {
+            if distance == 0 {
+                continue;
+            }
+            // Angles grow clockwise, readings counter-clockwise.
+            let degree = (360 - (angle.round() as usize) % 360) % 360;
+            let range = &mut self.scan.ranges[degree];
+            if *range == 0 || distance < *range {
+                *range = distance;
+            }
+            self.samples += 1;
+        }
+
+        done
+    }
+}
+
+/// Driver of a YDLIDAR X-series lidar.
+pub struct YdLidar {
+    port: PathBuf,
+    serial: Box<dyn SerialPort>,
+    assembler: ScanAssembler,
+    decode_errors: u64,
+    rpms: u16,
+}
+
+impl YdLidar {
+    /// Opens the lidar on the given port and starts the scan.
+    ///
+    /// # Errors
+    /// An error variant is returned if the serial port cannot be opened.
+    pub fn new<P: AsRef<Path>>(port: P, baud_rate: u32) -> Result<Self, Error> {
+        let model = if baud_rate == X2_BAUD_RATE {
+            Model::YdLidarX2
+        } else {
+            Model::YdLidarX4
+        };
+        // `serialport` opens the ports exclusively by default.
+        Self::open_unchecked(
+            &OpenOptions::new(port, baud_rate)
+                .model(model)
+                .exclusive(true),
+        )
+    }
+
+    /// Opens the lidar as the options say, see `OpenOptions::open`.
+    ///
+    /// # Errors
+    /// An error variant is returned if the options are invalid, without
+    /// touching the port, or if the serial port cannot be opened.
+    pub fn with_options(options: &OpenOptions) -> Result<Self, Error> {
+        options.open()
+    }
+
+    /// Gets the configured serial port
+    pub fn port(&self) -> &Path {
+        &self.port
+    }
+
+    /// Gets the lidars rmp from the last reading
+    pub fn rpms(&self) -> u16 {
+        self.rpms
+    }
+
+    /// Gets the number of packets dropped because they failed to decode.
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors
+    }
+
+    /// Gets a reading from the lidar, returing a `LaserReading` object.
+    ///
+    /// # Errors
+    /// An error variant is returned in case of:
+    /// - unable to read form the serial port
+    pub fn read(&mut self) -> Result<LaserReading, Error> {
+        loop {
+            let packet = self.read_packet()?;
+            match decode_packet(&packet) {
+                Ok(packet) => {
+                    if let Some(scan) = self.assembler.push(&packet) {
+                        self.rpms = scan.rpms;
+                        return Ok(scan);
+                    }
+                }
+                Err(_) => self.decode_errors += 1,
+            }
+        }
+    }
+
+    fn read_packet(&mut self) -> io::Result<Vec<u8>> {
+        // Skips the answer to the start command, and anything out of sync.
+        let mut header = [0u8; HEADER_SIZE];
+        let mut matched = 0;
+        while matched < 2 {
+            let mut filled = matched;
+            read_full(&mut self.serial, &mut header[..=matched], &mut filled)?;
+            matched = match header[matched] {
+                b if b == PACKET_HEADER[matched] => matched + 1,
+                b if b == PACKET_HEADER[0] => {
+                    header[0] = b;
+                    1
+                }
+                _ => 0,
+            };
+        }
+        let mut filled = 2;
+        read_full(&mut self.serial, &mut header, &mut filled)?;
+
+        let mut packet = vec![0u8; packet_size(&header)];
+        packet[..HEADER_SIZE].copy_from_slice(&header);
+        let mut filled = HEADER_SIZE;
+        read_full(&mut self.serial, &mut packet, &mut filled)?;
+        Ok(packet)
+    }
+
+    /// Starts the scan
+    pub fn start(&mut self) {
+        // Bytes received before the scan stopped would corrupt the first one.
+        self.purge_input().ok();
+        self.serial.write_all(&START_SCAN).ok();
+    }
+
+    /// Discards the bytes received and not read yet, by the OS and by the
+    /// driver, so that the next scan is made of fresh data only. Done by `start`.
+    ///
+    /// # Errors
+    /// An error variant is returned if the OS buffer cannot be cleared.
+    pub fn purge_input(&mut self) -> io::Result<()> {
+        self.assembler = ScanAssembler::new();
+        Ok(self.serial.clear(serialport::ClearBuffer::Input)?)
+    }
+
+    /// Stops the scan
+    pub fn close(&mut self) {
+        // Stopping the Lidar, ignoring the result.
+        self.serial.write_all(&STOP_SCAN).ok();
+    }
+}
+
+impl Open for YdLidar {
+    type Error = Error;
+
+    const DRIVER: &'static str = "ydlidar";
+
+    const MODELS: &'static [Model] = &[Model::YdLidarX2, Model::YdLidarX4];
+
+    fn open_unchecked(options: &OpenOptions) -> Result<Self, Error> {
+        let builder = serialport::new(port_name(&options.port)?, options.baud_rate)
+            .timeout(options.timeout.unwrap_or(Duration::from_secs(1)));
+        #[cfg(unix)]
+        let serial: Box<dyn SerialPort> = {
+            let mut serial = builder.open_native()?;
+            serial.set_exclusive(options.exclusive)?;
+            Box::new(serial)
+        };
+        #[cfg(not(unix))]
+        let serial = builder.open()?;
+        let mut lidar = Self {
+            port: options.port.clone(),
+            serial,
+            assembler: ScanAssembler::new(),
+            decode_errors: 0,
+            rpms: 0,
+        };
+        if options.auto_start {
+            lidar.start();
+        }
+        Ok(lidar)
+    }
+}
+
+impl LidarDriver for YdLidar {
+    type Error = Error;
+
+    fn read(&mut self) -> Result<LaserReading, Error> {
+        YdLidar::read(self)
+    }
+
+    fn start(&mut self) {
+        YdLidar::start(self)
+    }
+
+    fn close(&mut self) {
+        YdLidar::close(self)
+    }
+}
+
+impl Drop for YdLidar {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a packet from `first` to `last` degree, distances in mm.
+    fn packet(start: bool, first: f32, last: f32, distances: &[u16]) -> Vec<u8> {
+        let angle = |a: f32| (((a * 64.0) as u16) << 1) | 1;
+        let mut words = vec![
+            u16::from_le_bytes(PACKET_HEADER),
+            u16::from_le_bytes([u8::from(start) | (10 << 1), distances.len() as u8]),
+            angle(first),
+            angle(last),
+        ];
+        words.extend(distances.iter().map(|d| d * 4));
+        let checksum = words.iter().fold(0, |c, w| c ^ w);
+        words.insert(4, checksum);
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn decodes_a_packet() {
+        let bytes = packet(true, 10.0, 20.0, &[1000, 0, 2000]);
+        let mut header = [0u8; HEADER_SIZE];
+        header.copy_from_slice(&bytes[..HEADER_SIZE]);
+        assert_eq!(packet_size(&header), bytes.len());
+
+        let decoded = decode_packet(&bytes).unwrap();
+        assert!(decoded.start);
+        assert_eq!(decoded.frequency, 10);
+        let expected = [
+            (10.0 + correction(1000), 1000),
+            (15.0, 0),
+            (20.0 + correction(2000), 2000),
+        ];
+        for ((angle, distance), (a, d)) in decoded.samples.into_iter().zip(expected) {
+            assert!((angle - a).abs() < 0.01, "{angle} instead of {a}");
+            assert_eq!(distance, d);
+        }
+    }
+
+    #[test]
+    fn rejects_broken_packets() {
+        let mut bytes = packet(false, 10.0, 20.0, &[1000, 2000]);
+        assert_eq!(
+            decode_packet(&bytes[..bytes.len() - 1]),
+            Err(YdError::Truncated)
+        );
+        bytes[HEADER_SIZE] ^= 0xFF;
+        assert_eq!(decode_packet(&bytes), Err(YdError::Checksum));
+        bytes[0] = 0;
+        assert_eq!(decode_packet(&bytes), Err(YdError::BadHeader));
+    }
+
+    #[test]
+    fn assembles_the_revolutions() {
+        let sample = |angle: f32, distance| YdPacket {
+            start: false,
+            frequency: 0,
+            samples: vec![(angle, distance)],
+        };
+        let mut assembler = ScanAssembler::new();
+        let start = YdPacket {
+            start: true,
+            frequency: 50,
+            samples: Vec::new(),
+        };
+        assert!(assembler.push(&start).is_none());
+        assert!(assembler.push(&sample(90.0, 1500)).is_none());
+        assert!(assembler.push(&sample(90.2, 1200)).is_none());
+        assert!(assembler.push(&sample(0.0, 800)).is_none());
+
+        let scan = assembler.push(&start).unwrap();
+        assert_eq!(scan.rpms, 300);
+        // Clockwise 90 degrees is counter-clockwise 270, keeping the closest.
+        assert_eq!(scan.ranges[270], 1200);
+        assert_eq!(scan.ranges[0], 800);
+        assert_eq!(scan.ranges.iter().filter(|&&r| r != 0).count(), 2);
+    }
+}
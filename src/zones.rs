@@ -0,0 +1,268 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Occupancy of named polygons around the lidar, loaded from a TOML or
+//! JSON file, enabled by the `zones` feature.
+//!
+//! The vertices are in the lidar frame: meters, x to the front, y to the
+//! left. Every scan tells, for every zone, how many valid beams fall
+//! inside it, a building block for area guards.
+//!
+//! ```
+//! use hls_lfcd_lds_driver::zones::ZoneMonitor;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let monitor = ZoneMonitor::from_toml(
+//!     r#"
+//!     min_points = 3
+//!
+//!     [[zones]]
+//!     name = "door"
+//!     vertices = [[0.5, -0.3], [1.5, -0.3], [1.5, 0.3], [0.5, 0.3]]
+//!     "#,
+//! )?;
+//!
+//! for occupancy in monitor.evaluate(&reading) {
+//!     let zone = &monitor.zones()[occupancy.zone];
+//!     println!("{}: {}", zone.name, occupancy.occupied);
+//! }
+//! # Ok::<(), hls_lfcd_lds_driver::zones::ZoneError>(())
+//! ```
+
+use crate::safety::ZoneShape;
+use crate::LaserReading;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// A named polygon, in the lidar frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PolygonZone {
+    /// Name of the zone
+    pub name: String,
+    /// Vertices of the polygon, in meters
+    pub vertices: Vec<(f32, f32)>,
+}
+
+/// Content of a zone file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ZoneConfig {
+    /// Beams inside a zone for it to be occupied, 1 if missing
+    #[serde(default = "default_min_points")]
+    pub min_points: usize,
+    /// The zones
+    pub zones: Vec<PolygonZone>,
+}
+
+fn default_min_points() -> usize {
+    1
+}
+
+/// Occupancy of a zone in a scan.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ZoneOccupancy {
+    /// Index of the zone in the monitor
+    pub zone: usize,
+    /// At least `min_points` beams are inside the zone
+    pub occupied: bool,
+    /// Valid beams inside the zone
+    pub points: usize,
+    /// Range, in meters, of the closest beam inside the zone
+    pub closest: Option<f32>,
+}
+
+/// Errors while loading zones.
+#[derive(Debug)]
+pub enum ZoneError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    /// The file is neither `.toml` nor `.json`
+    UnknownFormat(String),
+    /// A zone has less than three vertices
+    Degenerate(String),
+    /// `min_points` is 0, every zone would be occupied
+    ZeroMinPoints,
+}
+
+impl fmt::Display for ZoneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZoneError::Io(e) => write!(f, "IO error: {e}"),
+            ZoneError::Toml(e) => write!(f, "TOML error: {e}"),
+            ZoneError::Json(e) => write!(f, "JSON error: {e}"),
+            ZoneError::UnknownFormat(p) => write!(f, "Unknown zone file format: {p}"),
+            ZoneError::Degenerate(name) => write!(f, "Zone {name} has less than 3 vertices"),
+            ZoneError::ZeroMinPoints => write!(f, "min_points must be at least 1"),
+        }
+    }
+}
+
+impl std::error::Error for ZoneError {}
+
+impl From<std::io::Error> for ZoneError {
+    fn from(e: std::io::Error) -> Self {
+        ZoneError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ZoneError {
+    fn from(e: toml::de::Error) -> Self {
+        ZoneError::Toml(e)
+    }
+}
+
+impl From<serde_json::Error> for ZoneError {
+    fn from(e: serde_json::Error) -> Self {
+        ZoneError::Json(e)
+    }
+}
+
+/// Evaluates the occupancy of a set of zones.
+#[derive(Debug, Clone)]
+pub struct ZoneMonitor {
+    config: ZoneConfig,
+    shapes: Vec<ZoneShape>,
+}
+
+impl ZoneMonitor {
+    /// Creates a monitor of the given zones.
+    ///
+    /// # Errors
+    /// An error variant is returned if a zone has less than three vertices,
+    /// or if `min_points` is 0.
+    pub fn new(config: ZoneConfig) -> Result<Self, ZoneError> {
+        if config.min_points == 0 {
+            return Err(ZoneError::ZeroMinPoints);
+        }
+        let shapes = config
+            .zones
+            .iter()
+            .map(|zone| {
+                if zone.vertices.len() < 3 {
+                    return Err(ZoneError::Degenerate(zone.name.clone()));
+                }
+                Ok(ZoneShape::Polygon(zone.vertices.clone()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { config, shapes })
+    }
+
+    /// Creates a monitor from the content of a TOML file.
+    ///
+    /// # Errors
+    /// An error variant is returned if the content is not a valid zone file.
+    pub fn from_toml(s: &str) -> Result<Self, ZoneError> {
+        Self::new(toml::from_str(s)?)
+    }
+
+    /// Creates a monitor from the content of a JSON file.
+    ///
+    /// # Errors
+    /// An error variant is returned if the content is not a valid zone file.
+    pub fn from_json(s: &str) -> Result<Self, ZoneError> {
+        Self::new(serde_json::from_str(s)?)
+    }
+
+    /// Creates a monitor from a `.toml` or `.json` file.
+    ///
+    /// # Errors
+    /// An error variant is returned if the file cannot be read, has another
+    /// extension or is not a valid zone file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ZoneError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&content),
+            Some("json") => Self::from_json(&content),
+            _ => Err(ZoneError::UnknownFormat(path.display().to_string())),
+        }
+    }
+
+    /// Gets the zones, in the order of the results of `evaluate`.
+    pub fn zones(&self) -> &[PolygonZone] {
+        &self.config.zones
+    }
+
+    /// Gets the configuration of the monitor.
+    pub fn config(&self) -> &ZoneConfig {
+        &self.config
+    }
+
+    /// Gets the occupancy of every zone in a scan.
+    pub fn evaluate(&self, reading: &LaserReading) -> Vec<ZoneOccupancy> {
+        let mut results: Vec<ZoneOccupancy> = (0..self.shapes.len())
+            .map(|zone| ZoneOccupancy {
+                zone,
+                occupied: false,
+                points: 0,
+                closest: None,
+            })
+            .collect();
+
+        for i in (0..reading.ranges.len()).filter(|&i| reading.is_valid(i)) {
+            let (x, y) = reading.point(i);
+            let range = f32::from(reading.ranges[i]) / 1000.0;
+            for (shape, result) in self.shapes.iter().zip(results.iter_mut()) {
+                if shape.contains(x, y) {
+                    result.points += 1;
+                    result.closest = Some(result.closest.map_or(range, |c| c.min(range)));
+                }
+            }
+        }
+
+        for result in results.iter_mut() {
+            result.occupied = result.points >= self.config.min_points;
+        }
+        results
+    }
+
+    /// Gets the names of the occupied zones in a scan.
+    pub fn occupied(&self, reading: &LaserReading) -> Vec<&str> {
+        self.evaluate(reading)
+            .into_iter()
+            .filter(|r| r.occupied)
+            .map(|r| self.config.zones[r.zone].name.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SQUARE: &str = r#"
+        [[zones]]
+        name = "front"
+        vertices = [[0.5, -0.5], [1.5, -0.5], [1.5, 0.5], [0.5, 0.5]]
+    "#;
+
+    #[test]
+    fn rejects_zero_min_points() {
+        let err = ZoneMonitor::from_toml(&format!("min_points = 0\n{SQUARE}")).unwrap_err();
+        assert!(matches!(err, ZoneError::ZeroMinPoints));
+    }
+
+    #[test]
+    fn leaves_an_empty_zone_free() {
+        let monitor = ZoneMonitor::from_toml(SQUARE).unwrap();
+        assert_eq!(monitor.config().min_points, 1);
+        let mut reading = LaserReading::new();
+        assert!(monitor.occupied(&reading).is_empty());
+
+        reading.ranges[0] = 1000;
+        assert_eq!(monitor.occupied(&reading), ["front"]);
+        assert_eq!(monitor.evaluate(&reading)[0].closest, Some(1.0));
+    }
+}
@@ -0,0 +1,426 @@
+//
+// Copyright (c) 2022 Gabriele Baldoni
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   Gabriele Baldoni, <gabriele@gabrielebaldoni.com>
+//
+
+//! Scan logs compressed with zstd, enabled by the `zstd` feature.
+//!
+//! A log starts with the `LDSZ` magic and a version byte, followed by chunks.
+//! Every chunk is a zstd frame of up to `chunk_size` scans, preceded by a
+//! header that is not compressed: the length of the frame and the number
+//! of scans as `u32`, the first and last timestamp as `u64` ns since the
+//! epoch, all little-endian. A reader can skip whole chunks without
+//! decompressing them.
+//!
+//! Inside a frame, every scan is its timestamp, its rpms, the ranges and
+//! the intensities, all little-endian.
+//!
+//! ```no_run
+//! use hls_lfcd_lds_driver::zstd::{ScanLogReader, ScanLogWriter};
+//! use std::fs::File;
+//! # let reading = hls_lfcd_lds_driver::LaserReading::new();
+//!
+//! let mut writer = ScanLogWriter::new(File::create("scans.ldsz")?)?;
+//! writer.write(&reading)?;
+//! writer.finish()?;
+//!
+//! for scan in ScanLogReader::new(File::open("scans.ldsz")?)? {
+//!     let (timestamp, reading) = scan?;
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::sink::ScanSink;
+use crate::LaserReading;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Magic bytes at the beginning of a log.
+pub const MAGIC: &[u8; 4] = b"LDSZ";
+/// Version of the format.
+pub const VERSION: u8 = 1;
+/// Default number of scans in every chunk.
+pub const DEFAULT_CHUNK_SIZE: usize = 100;
+/// Default zstd compression level.
+pub const DEFAULT_LEVEL: i32 = 3;
+
+const CHUNK_HEADER_SIZE: usize = 24;
+const RECORD_SIZE: usize = 8 + 2 + 360 * 2 * 2;
+
+fn to_nanos(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Writer of a compressed scan log.
+///
+/// Dropping the writer flushes the pending scans, ignoring errors.
+pub struct ScanLogWriter<W: Write> {
+    inner: W,
+    chunk_size: usize,
+    level: i32,
+    pending: Vec<u8>,
+    scans: u32,
+    first: u64,
+    last: u64,
+}
+
+impl<W: Write> ScanLogWriter<W> {
+    /// Creates a writer and writes the header of the log.
+    ///
+    /// # Errors
+    /// An error variant is returned if the header cannot be written.
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&[VERSION])?;
+        Ok(Self {
+            inner,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            level: DEFAULT_LEVEL,
+            pending: Vec::new(),
+            scans: 0,
+            first: 0,
+            last: 0,
+        })
+    }
+
+    /// Sets the number of scans in every chunk, larger chunks compress better
+    /// but make seeking coarser.
+    pub fn with_chunk_size(mut self, scans: usize) -> Self {
+        self.chunk_size = scans.max(1);
+        self
+    }
+
+    /// Sets the zstd compression level.
+    pub fn with_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Writes a reading, stamped with the current time.
+    ///
+    /// # Errors
+    /// An error variant is returned if a full chunk cannot be written.
+    pub fn write(&mut self, reading: &LaserReading) -> io::Result<()> {
+        self.write_at(reading, SystemTime::now())
+    }
+
+    /// Writes a reading taken at `timestamp`.
+    ///
+    /// # Errors
+    /// An error variant is returned if a full chunk cannot be written.
+    pub fn write_at(&mut self, reading: &LaserReading, timestamp: SystemTime) -> io::Result<()> {
+        let nanos = to_nanos(timestamp);
+        if self.scans == 0 {
+            self.first = nanos;
+        }
+        self.last = nanos;
+        self.scans += 1;
+
+        self.pending.extend_from_slice(&nanos.to_le_bytes());
+        self.pending.extend_from_slice(&reading.rpms.to_le_bytes());
+        for r in reading.ranges.iter().chain(reading.intensities.iter()) {
+            self.pending.extend_from_slice(&r.to_le_bytes());
+        }
+
+        if self.scans as usize >= self.chunk_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Compresses and writes the pending scans as a chunk.
+    ///
+    /// # Errors
+    /// An error variant is returned if the chunk cannot be compressed or written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.scans == 0 {
+            return self.inner.flush();
+        }
+        let frame = ::zstd::bulk::compress(&self.pending, self.level)?;
+        let len = u32::try_from(frame.len()).map_err(|_| invalid("chunk too large"))?;
+
+        self.inner.write_all(&len.to_le_bytes())?;
+        self.inner.write_all(&self.scans.to_le_bytes())?;
+        self.inner.write_all(&self.first.to_le_bytes())?;
+        self.inner.write_all(&self.last.to_le_bytes())?;
+        self.inner.write_all(&frame)?;
+        self.inner.flush()?;
+
+        self.pending.clear();
+        self.scans = 0;
+        Ok(())
+    }
+
+    /// Writes the pending scans and completes the log.
+    ///
+    /// # Errors
+    /// An error variant is returned if the last chunk cannot be written.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write + Send> ScanSink for ScanLogWriter<W> {
+    fn consume(&mut self, reading: &LaserReading) -> io::Result<()> {
+        self.write(reading)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        ScanLogWriter::flush(self)
+    }
+}
+
+impl<W: Write> Drop for ScanLogWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Header of a chunk of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ChunkHeader {
+    len: u32,
+    scans: u32,
+    first: u64,
+    last: u64,
+}
+
+/// Reader of a compressed scan log, iterating over the scans and their timestamps.
+pub struct ScanLogReader<R: Read> {
+    inner: R,
+    chunk: Vec<u8>,
+    offset: usize,
+}
+
+impl<R: Read> ScanLogReader<R> {
+    /// Creates a reader and checks the header of the log.
+    ///
+    /// # Errors
+    /// An error variant is returned if the header is missing or not supported.
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0u8; 5];
+        inner.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not a scan log"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid("unsupported scan log version"));
+        }
+        Ok(Self {
+            inner,
+            chunk: Vec::new(),
+            offset: 0,
+        })
+    }
+
+    /// Reads the header of the next chunk, `None` at the end of the log.
+    fn read_header(&mut self) -> io::Result<Option<ChunkHeader>> {
+        let mut buf = [0u8; CHUNK_HEADER_SIZE];
+        match self.inner.read_exact(&mut buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let u32_at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+        let u64_at = |i: usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&buf[i..i + 8]);
+            u64::from_le_bytes(b)
+        };
+        Ok(Some(ChunkHeader {
+            len: u32_at(0),
+            scans: u32_at(4),
+            first: u64_at(8),
+            last: u64_at(16),
+        }))
+    }
+
+    /// Decompresses the chunk following `header`.
+    fn load(&mut self, header: ChunkHeader) -> io::Result<()> {
+        let mut frame = vec![0u8; header.len as usize];
+        self.inner.read_exact(&mut frame)?;
+        let capacity = header.scans as usize * RECORD_SIZE;
+        self.chunk = ::zstd::bulk::decompress(&frame, capacity)?;
+        if self.chunk.len() != capacity {
+            return Err(invalid("corrupted chunk"));
+        }
+        self.offset = 0;
+        Ok(())
+    }
+
+    /// Reads the next scan and its timestamp, `None` at the end of the log.
+    ///
+    /// # Errors
+    /// An error variant is returned if the log cannot be read or is corrupted.
+    pub fn read_scan(&mut self) -> io::Result<Option<(SystemTime, LaserReading)>> {
+        while self.offset >= self.chunk.len() {
+            match self.read_header()? {
+                Some(header) => self.load(header)?,
+                None => return Ok(None),
+            }
+        }
+
+        let record = &self.chunk[self.offset..self.offset + RECORD_SIZE];
+        self.offset += RECORD_SIZE;
+        let u16_at = |i: usize| u16::from_le_bytes([record[i], record[i + 1]]);
+
+        let mut nanos = [0u8; 8];
+        nanos.copy_from_slice(&record[..8]);
+        let timestamp = UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(nanos));
+
+        let mut reading = LaserReading::new();
+        reading.rpms = u16_at(8);
+        let n = reading.ranges.len();
+        for i in 0..n {
+            reading.ranges[i] = u16_at(10 + 2 * i);
+            reading.intensities[i] = u16_at(10 + 2 * (n + i));
+        }
+        Ok(Some((timestamp, reading)))
+    }
+}
+
+impl<R: Read + Seek> ScanLogReader<R> {
+    /// Moves to the first scan taken at or after `timestamp`, skipping the
+    /// chunks before it without decompressing them.
+    ///
+    /// # Errors
+    /// An error variant is returned if the log cannot be read or is corrupted.
+    pub fn seek_time(&mut self, timestamp: SystemTime) -> io::Result<()> {
+        let target = to_nanos(timestamp);
+        self.inner.seek(SeekFrom::Start((MAGIC.len() + 1) as u64))?;
+        self.chunk.clear();
+        self.offset = 0;
+
+        while let Some(header) = self.read_header()? {
+            if header.last < target {
+                self.inner.seek(SeekFrom::Current(i64::from(header.len)))?;
+                continue;
+            }
+            self.load(header)?;
+            while self.offset < self.chunk.len() {
+                let mut nanos = [0u8; 8];
+                nanos.copy_from_slice(&self.chunk[self.offset..self.offset + 8]);
+                if u64::from_le_bytes(nanos) >= target {
+                    break;
+                }
+                self.offset += RECORD_SIZE;
+            }
+            return Ok(());
+        }
+        Ok(())
+    }
+
+    /// Moves to the scan with the given index, skipping the chunks before it
+    /// without decompressing them.
+    ///
+    /// # Errors
+    /// An error variant is returned if the log cannot be read or is corrupted.
+    pub fn seek_scan(&mut self, index: u64) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start((MAGIC.len() + 1) as u64))?;
+        self.chunk.clear();
+        self.offset = 0;
+
+        let mut skipped = 0u64;
+        while let Some(header) = self.read_header()? {
+            if skipped + u64::from(header.scans) <= index {
+                skipped += u64::from(header.scans);
+                self.inner.seek(SeekFrom::Current(i64::from(header.len)))?;
+                continue;
+            }
+            self.load(header)?;
+            self.offset = (index - skipped) as usize * RECORD_SIZE;
+            return Ok(());
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for ScanLogReader<R> {
+    type Item = io::Result<(SystemTime, LaserReading)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_scan().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_scan_eq;
+    use std::io::Cursor;
+
+    fn reading(rpms: u16) -> LaserReading {
+        let mut reading = LaserReading::new();
+        reading.rpms = rpms;
+        reading.ranges[0] = rpms;
+        reading.intensities[359] = rpms;
+        reading
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    /// Writes ten scans, one second apart, in chunks of three.
+    fn log() -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut writer = ScanLogWriter::new(&mut buf).unwrap().with_chunk_size(3);
+        for i in 0..10 {
+            writer
+                .write_at(&reading(300 + i), at(u64::from(i)))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let scans = ScanLogReader::new(Cursor::new(log()))
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(scans.len(), 10);
+        for (i, (timestamp, scan)) in scans.into_iter().enumerate() {
+            assert_eq!(timestamp, at(i as u64));
+            assert_scan_eq(&scan, &reading(300 + i as u16));
+        }
+    }
+
+    #[test]
+    fn seeks_by_time_and_index() {
+        let mut reader = ScanLogReader::new(Cursor::new(log())).unwrap();
+
+        reader.seek_time(at(4)).unwrap();
+        let (timestamp, scan) = reader.read_scan().unwrap().unwrap();
+        assert_eq!((timestamp, scan.rpms), (at(4), 304));
+
+        reader.seek_scan(9).unwrap();
+        assert_eq!(reader.read_scan().unwrap().unwrap().1.rpms, 309);
+        assert!(reader.read_scan().unwrap().is_none());
+
+        reader.seek_time(at(60)).unwrap();
+        assert!(reader.read_scan().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let err = ScanLogReader::new(Cursor::new(b"LDSR\x01".to_vec()))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}